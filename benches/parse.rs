@@ -0,0 +1,61 @@
+use anweb::request_parser::{ParseHttpRequestSettings, Parser};
+use anweb::websocket;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const SMALL_REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+const BROWSER_REQUEST: &[u8] = b"GET /static/css/main.css?v=1.2.3&lang=en HTTP/1.1\r\n\
+Host: localhost:8080\r\n\
+Connection: keep-alive\r\n\
+User-Agent: Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/90.0 Safari/537.36\r\n\
+Accept: text/css,*/*;q=0.1\r\n\
+Referer: http://localhost:8080/\r\n\
+Accept-Encoding: gzip, deflate, br\r\n\
+Accept-Language: en-US,en;q=0.9\r\n\
+Cookie: session=0123456789abcdef; theme=dark\r\n\
+\r\n";
+
+fn request_parsing(c: &mut Criterion) {
+    let settings = ParseHttpRequestSettings::default();
+    let mut group = c.benchmark_group("request_parser");
+
+    for (name, raw) in [("small", SMALL_REQUEST), ("browser", BROWSER_REQUEST)] {
+        group.throughput(Throughput::Bytes(raw.len() as u64));
+        group.bench_function(name, |b| b.iter(|| Parser::new().push(black_box(raw), &settings).is_ok()));
+    }
+
+    // request split to several reads
+    group.throughput(Throughput::Bytes(BROWSER_REQUEST.len() as u64));
+    group.bench_function("browser_in_parts", |b| b.iter(|| {
+        let mut parser = Parser::new();
+        let mut result = None;
+        for part in BROWSER_REQUEST.chunks(64) {
+            result = Some(parser.push(black_box(part), &settings).is_ok());
+        }
+        result
+    }));
+
+    group.finish();
+}
+
+fn websocket_frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("websocket");
+
+    for payload_len in [16, 1024, 65536] {
+        let payload = vec![b'x'; payload_len];
+        let frame = websocket::masked_frame(websocket::BINARY_OPCODE, &payload, [1, 2, 3, 4]);
+
+        group.throughput(Throughput::Bytes(payload_len as u64));
+        group.bench_function(format!("parse_{}", payload_len), |b| b.iter(|| {
+            websocket::Parser::new().parse_yet(black_box(&frame), payload_len).is_ok()
+        }));
+        group.bench_function(format!("build_{}", payload_len), |b| b.iter(|| {
+            websocket::frame(websocket::BINARY_OPCODE, black_box(&payload))
+        }));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, request_parsing, websocket_frames);
+criterion_main!(benches);
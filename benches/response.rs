@@ -0,0 +1,75 @@
+use anweb::static_files::Builder;
+use anweb::testing::TestServer;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+/// Sends keep-alive request and reads response with "Content-Length".
+fn round_trip(tcp_stream: &mut TcpStream, raw_request: &[u8], response: &mut Vec<u8>) {
+    tcp_stream.write_all(raw_request).unwrap();
+
+    response.clear();
+    let mut buf = [0; 65536];
+    loop {
+        let cnt = tcp_stream.read(&mut buf).unwrap();
+        assert!(cnt > 0, "connection closed");
+        response.extend_from_slice(&buf[..cnt]);
+
+        if let Some(head_len) = response.windows(4).position(|window| window == b"\r\n\r\n").map(|index| index + 4) {
+            let head = String::from_utf8_lossy(&response[..head_len]);
+            let content_len = head.split("\r\n")
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .and_then(|len| len.parse::<usize>().ok())
+                .unwrap_or(0);
+
+            if response.len() >= head_len + content_len {
+                return;
+            }
+        }
+    }
+}
+
+fn responses(c: &mut Criterion) {
+    let static_files = Builder::new()
+        .updating_interval(None)
+        .build(concat!(env!("CARGO_MANIFEST_DIR"), "/src"));
+
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        match request.path() {
+            "/hello" => {
+                request.response(200).text("Hello world!").send();
+            }
+            "/headers" => {
+                request.response(200)
+                    .headers("Cache-Control: no-cache\r\nX-Frame-Options: DENY\r\n")
+                    .cookies("Set-Cookie: session=0123456789abcdef; Path=/; HttpOnly\r\n")
+                    .html("<html><body>Hello world!</body></html>")
+                    .send();
+            }
+            path => static_files.send_response(path, &request)?,
+        }
+        Ok(())
+    }).unwrap();
+
+    let mut tcp_stream = TcpStream::connect(server.addr()).unwrap();
+    tcp_stream.set_nodelay(true).unwrap();
+    let mut response = Vec::new();
+
+    let mut group = c.benchmark_group("response");
+    for (name, raw_request) in [
+        ("hello", &b"GET /hello HTTP/1.1\r\nConnection: keep-alive\r\n\r\n"[..]),
+        ("headers", b"GET /headers HTTP/1.1\r\nConnection: keep-alive\r\n\r\n"),
+        ("static_file", b"GET /lib.rs HTTP/1.1\r\nConnection: keep-alive\r\n\r\n"),
+        ("static_file_gzip", b"GET /lib.rs HTTP/1.1\r\nConnection: keep-alive\r\nAccept-Encoding: gzip\r\n\r\n"),
+    ] {
+        group.bench_function(name, |b| b.iter(|| round_trip(&mut tcp_stream, raw_request, &mut response)));
+    }
+    group.finish();
+
+    drop(tcp_stream);
+    server.stop();
+}
+
+criterion_group!(benches, responses);
+criterion_main!(benches);
@@ -21,7 +21,7 @@ fn on_request(request: Request) -> Result<(), Box<dyn std::error::Error>> {
     let cookie_name = "test";
 
     // if cookie with "test" name are already installed on the client (browser)
-    if let Some(_) = request.cookies().iter().find(|cookie| cookie.name == cookie_name) {
+    if request.cookies().iter().any(|cookie| cookie.name == cookie_name) {
         request.response(200).html(HTML_WHEN_COOKIE_RECEIVED).send();
     } else {
         let cookie = Cookie {
@@ -0,0 +1,112 @@
+// Simple load generator like wrk: keep-alive connections in threads send the same GET request
+// as fast as possible for a while and print the number of responses per second.
+//
+// Usage: cargo run --release --example load -- [address] [path] [connections] [seconds]
+// For example, against the hello-world example:
+//   cargo run --release --example load -- 127.0.0.1:8080 / 64 10
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let addr = args.get(1).cloned().unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let path = args.get(2).cloned().unwrap_or_else(|| "/".to_string());
+    let connections: usize = args.get(3).map_or(Ok(64), |arg| arg.parse())?;
+    let duration = Duration::from_secs(args.get(4).map_or(Ok(10), |arg| arg.parse())?);
+
+    let raw_request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: keep-alive\r\n\r\n", path, addr).into_bytes();
+
+    let responses = Arc::new(AtomicU64::new(0));
+    let errors = Arc::new(AtomicU64::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let threads: Vec<_> = (0..connections).map(|_| {
+        let addr = addr.clone();
+        let raw_request = raw_request.clone();
+        let responses = responses.clone();
+        let errors = errors.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                // reconnect if the server closed connection
+                if let Err(err) = load_connection(&addr, &raw_request, &responses, &stop) {
+                    errors.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("{}", err);
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            }
+        })
+    }).collect();
+
+    let begin = Instant::now();
+    std::thread::sleep(duration);
+    stop.store(true, Ordering::Relaxed);
+    let elapsed = begin.elapsed().as_secs_f64();
+
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    let responses = responses.load(Ordering::Relaxed);
+    println!("{} connections, {:.1} s", connections, elapsed);
+    println!("{} responses, {:.0} responses/s", responses, responses as f64 / elapsed);
+    println!("{} connection errors", errors.load(Ordering::Relaxed));
+
+    Ok(())
+}
+
+/// Sends requests in one connection one after another until stop or error.
+fn load_connection(addr: &str, raw_request: &[u8], responses: &AtomicU64, stop: &AtomicBool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tcp_stream = TcpStream::connect(addr)?;
+    tcp_stream.set_nodelay(true)?;
+
+    let mut buf = vec![0; 65536];
+    let mut response = Vec::new();
+    while !stop.load(Ordering::Relaxed) {
+        tcp_stream.write_all(raw_request)?;
+
+        response.clear();
+        loop {
+            let cnt = tcp_stream.read(&mut buf)?;
+            if cnt == 0 {
+                return Err("connection closed by server".into());
+            }
+            response.extend_from_slice(&buf[..cnt]);
+
+            if response_is_complete(&response) {
+                break;
+            }
+        }
+
+        responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+/// Response with "Content-Length" or without content is received completely.
+fn response_is_complete(response: &[u8]) -> bool {
+    let head_len = match response.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(index) => index + 4,
+        None => return false,
+    };
+
+    let content_len = String::from_utf8_lossy(&response[..head_len])
+        .split("\r\n")
+        .find_map(|line| {
+            let mut name_value = line.splitn(2, ':');
+            let name = name_value.next()?;
+            if name.eq_ignore_ascii_case("Content-Length") {
+                name_value.next()?.trim().parse::<usize>().ok()
+            } else {
+                None
+            }
+        })
+        .unwrap_or(0);
+
+    response.len() >= head_len + content_len
+}
@@ -1,6 +1,4 @@
 use anweb::server::{Event, Server};
-use std::sync::Arc;
-use std::sync::Mutex;
 use std::thread::sleep;
 use std::time::Duration;
 
@@ -8,18 +6,14 @@ use std::time::Duration;
 // a long time to execute or blocking input/output.
 // If during the processing of the request event to carry out lengthy operations,
 // then other clients requiring a small time will wait for the end of this long operation.
-// To solve this problem, you can use, for example, a thread pool.
-// Attention, if you want to have support for clients with HTTP pipelining,
-// you must ensure order of responses in requests order.
+// To solve this problem, run them in the blocking pool of the server by `Request::spawn_blocking`.
+// Responses of pipelined requests keep order of requests.
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let pool = Arc::new(Mutex::new(threadpool::ThreadPool::new(num_cpus::get())));
-
     let addr = ([0, 0, 0, 0], 8080).into();
 
-    let server = Server::new(&addr)?;
+    let server = Server::new(&addr)?.with_blocking_pool(num_cpus::get());
     server.run(move |server_event| {
         if let Event::Incoming(tcp_session) = server_event {
-            let pool = pool.clone();
             tcp_session.to_http(move |http_result| {
                 let request = http_result?;
                 match request.path() {
@@ -27,12 +21,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         request.response(200).html(INDEX_HTML).send();
                     }
                     "/long" => {
-                        let pool = pool.lock().unwrap();
-
-                        pool.execute(move || {
+                        request.spawn_blocking(|request| {
                             // emitting long operation using sleep
                             sleep(Duration::from_secs(10));
                             request.response(200).html("Complete").send();
+                            Ok(())
                         });
                     }
                     _ => {
@@ -17,23 +17,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 fn on_request(request: Request) -> Result<(), Box<dyn std::error::Error>> {
-    let path = request.path().clone();
-    match path {
-        "/" => {
-            if request.method() == "GET" {
-                request.response(200).html(INDEX_HTML).send();
-                return Ok(());
-            }
+    match (request.method(), request.path()) {
+        ("GET", "/") => {
+            request.response(200).html(INDEX_HTML).send();
+            return Ok(());
         }
-        "/form" => {
-            if request.method() == "POST" {
-                request.form(|form, request| {
-                    let response_body = format!("Form: {:?}", form);
-                    request.response(200).text(&response_body).send();
-                    Ok(())
-                });
-                return Ok(());
-            }
+        ("POST", "/form") => {
+            request.form(|form, request| {
+                let response_body = format!("Form: {:?}", form);
+                request.response(200).text(&response_body).send();
+                Ok(())
+            });
+            return Ok(());
         }
         _ => {
         }
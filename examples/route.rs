@@ -8,6 +8,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             tcp_session.to_http(|http_result| {
                 let request = http_result?;
 
+                // Segments of path are compared, so "/api/v1/users/" and "//api/v1/users" are the same.
+                if let Some(tail) = request.tail_after(&["api", "v1"]) {
+                    let body = format!("api v1, resource: {}", tail.join("/"));
+                    request.response(200).text(&body).send();
+                    return Ok(());
+                }
+
                 // Routing is done manually in any way.
                 match request.path() {
                     "/" => {
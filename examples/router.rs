@@ -0,0 +1,48 @@
+use anweb::extract::{Path, Query};
+use anweb::guard;
+use anweb::router::{Route, Router};
+use anweb::server::{Event, Server};
+use std::collections::HashMap;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Handlers declare their inputs as extractors. If extraction fails (for example "/users/abc")
+    // the client receives "400 Bad Request" and handler is not called.
+    let router = Router::new()
+        .get("/", |request, ()| {
+            request.response(200).html(INDEX_HTML).send();
+            Ok(())
+        })
+        .get("/users/:id", |request, (Path(id), Query(query)): (Path<u64>, Query<HashMap<String, String>>)| {
+            let body = format!("User id: {}, query: {:?}", id, query);
+            request.response(200).text(&body).send();
+            Ok(())
+        })
+        .route(Route::new("/admin").guard(guard::header("Authorization", |value| value == "Bearer secret")), |request, ()| {
+            request.response(200).text("Welcome, admin").send();
+            Ok(())
+        });
+
+    let addr = ([0, 0, 0, 0], 8080).into();
+    let server = Server::new(&addr)?;
+    server.run(move |server_event| {
+        if let Event::Incoming(tcp_session) = server_event {
+            let router = router.clone();
+            tcp_session.to_http(move |request| {
+                router.dispatch(request?)
+            });
+        }
+    })?;
+
+    Ok(())
+}
+
+const INDEX_HTML: &str = r#"
+<html>
+    <body>
+        <h3>Router example</h3>
+        <a href="/users/1?sort=name">user 1</a> <br>
+        <a href="/users/abc">wrong user id</a> <br>
+        <a href="/admin">admin (without authorization)</a>
+    </body>
+</html>
+"#;
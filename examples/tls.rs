@@ -1,4 +1,4 @@
-use anweb::redirect_server::run_redirect_server;
+use anweb::redirect_server::RedirectServer;
 use anweb::server;
 use anweb::server::Server;
 use anweb::tls::{load_certs, load_private_key};
@@ -18,7 +18,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     server.settings.tls_config = Some(Arc::new(tls_config));
 
     let redirect_addr = ([0, 0, 0, 0], 8080).into();
-    run_redirect_server("https://127.0.0.1:8443/", redirect_addr, 4)?;
+    // http://host:8080/path?query -> https://host:8443/path?query
+    RedirectServer::https(8443).num_threads(4).run(&redirect_addr)?;
 
     server.run(|server_event| match server_event {
         server::Event::Incoming(tcp_session) => {
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// first byte is length of pieces of data
+fuzz_target!(|data: &[u8]| {
+    if let Some((chunk_len, data)) = data.split_first() {
+        anweb::fuzz::request_parser(data, *chunk_len as usize);
+    }
+});
@@ -0,0 +1,64 @@
+use crate::request::Request;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Path prefix of ACME HTTP-01 challenge requests (RFC 8555, 8.3).
+pub const CHALLENGE_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Store of tokens of pending ACME HTTP-01 challenges, answered by the server before the http callback
+/// (see `Server::acme_challenges` and `RedirectServer::acme_challenges`). ACME client adds token with key authorization
+/// before asking the CA for validation and removes it after, then installs the issued certificate by `ReloadableCert`.
+/// Can be used in multi-threaded environment after clone, all clones share tokens.
+#[derive(Clone, Default)]
+pub struct ChallengeStore {
+    /// Key authorization by token.
+    tokens: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl ChallengeStore {
+    /// Creates empty store.
+    pub fn new() -> Self {
+        ChallengeStore::default()
+    }
+
+    /// Adds token of challenge with key authorization ("token.thumbprint of account key") sent in response.
+    pub fn add(&self, token: &str, key_authorization: &str) {
+        if let Ok(mut tokens) = self.tokens.write() {
+            tokens.insert(token.to_string(), key_authorization.to_string());
+        }
+    }
+
+    /// Removes token when challenge is validated or failed.
+    pub fn remove(&self, token: &str) {
+        if let Ok(mut tokens) = self.tokens.write() {
+            tokens.remove(token);
+        }
+    }
+
+    /// Key authorization of token.
+    pub fn key_authorization(&self, token: &str) -> Option<String> {
+        self.tokens.read().ok()?.get(token).cloned()
+    }
+
+    /// Answers challenge request. Returns request back if it's not challenge request.
+    /// Unknown tokens are answered with "404 Not Found".
+    pub(crate) fn respond(&self, request: Request) -> Option<Request> {
+        let token = match request.path().strip_prefix(CHALLENGE_PATH_PREFIX) {
+            Some(token) if request.method() == "GET" => token,
+            _ => return Some(request),
+        };
+
+        // tokens are base64url (RFC 8555, 8.1)
+        let valid = !token.is_empty() && token.bytes().all(|ch| ch.is_ascii_alphanumeric() || ch == b'-' || ch == b'_');
+        match self.key_authorization(token).filter(|_| valid) {
+            Some(key_authorization) => {
+                request.response(200).content("Content-Type: application/octet-stream\r\n", key_authorization.as_bytes()).send();
+            }
+            None => {
+                request.response(404).text("404 Not Found").send();
+            }
+        }
+
+        None
+    }
+}
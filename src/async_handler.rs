@@ -0,0 +1,221 @@
+use crate::handler_error::HandlerError;
+use crate::log::{log, Level};
+use crate::request::Request;
+use crate::response::{http_status_code_with_name, Response};
+use crate::router::HandlerResult;
+use crate::tcp_session::TcpSession;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+/// Threads executing async handlers, a facade for `async`/`await` syntax over the callback API.
+/// The event loop of workers stays the same, handlers are polled on threads of the executor.
+/// Can be used in multi-threaded environment after clone, all clones share threads.
+/// # Example
+/// `let handler = executor.handler(1_000_000, handle); router.get("/", move |request, ()| handler(request));` where
+/// `async fn handle(request: AsyncRequest) -> HandlerResult { request.into_request().response(200).text("ok").send_async().await?; Ok(()) }`
+#[derive(Clone)]
+pub struct Executor {
+    /// Queue of tasks ready for polling.
+    queue: mpsc::Sender<Arc<Task>>,
+}
+
+impl Executor {
+    /// Starts `threads` threads (at least one). Threads are stopped when the executor and all unfinished tasks are dropped.
+    pub fn new(threads: usize) -> Self {
+        let (queue, receiver) = mpsc::channel::<Arc<Task>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            std::thread::spawn(move || loop {
+                let task = match receiver.lock().map(|receiver| receiver.recv()) {
+                    Ok(Ok(task)) => task,
+                    _ => return,
+                };
+
+                task.poll();
+            });
+        }
+
+        Executor { queue }
+    }
+
+    /// Runs future on threads of the executor. Panic of the future drops it, the thread keeps working.
+    pub fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
+        self.spawn_task(future, None);
+    }
+
+    fn spawn_task(&self, future: impl Future<Output = ()> + Send + 'static, tcp_session: Option<TcpSession>) {
+        let task = Arc::new(Task { future: Mutex::new(Some(Box::pin(future))), queue: self.queue.clone(), tcp_session });
+        let _ = self.queue.send(task);
+    }
+
+    /// Http handler that receives content of request up to `body_limit` bytes in the worker, then runs `handler` on the executor.
+    /// Bigger content is answered with "413 Payload Too Large". If `handler` returns error, the connection is closed
+    /// as after error of synchronous handler, panic of `handler` is answered with "500 Internal Server Error".
+    pub fn handler<F, Fut>(&self, body_limit: usize, handler: F) -> impl Fn(Request) -> HandlerResult + Send + Sync + Clone + 'static
+    where
+        F: Fn(AsyncRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let executor = self.clone();
+        let handler = Arc::new(handler);
+        move |request: Request| {
+            if !request.has_content() {
+                executor.run(handler.as_ref(), AsyncRequest { request, content: Vec::new() });
+                return Ok(());
+            }
+
+            let executor = executor.clone();
+            let handler = handler.clone();
+            let mut content = Vec::new();
+            request.read_content(move |data, complete| {
+                if content.len() + data.len() > body_limit {
+                    return Err(HandlerError::new(413, "Content is too large").into());
+                }
+                content.extend_from_slice(data);
+
+                if let Some(request) = complete {
+                    executor.run(handler.as_ref(), AsyncRequest { request, content: std::mem::take(&mut content) });
+                }
+                Ok(())
+            });
+            Ok(())
+        }
+    }
+
+    /// Spawns future of handler, closes the connection if it returns error.
+    fn run<Fut: Future<Output = HandlerResult> + Send + 'static>(&self, handler: &dyn Fn(AsyncRequest) -> Fut, request: AsyncRequest) {
+        let tcp_session = request.request.tcp_session().clone();
+        let future = handler(request);
+        let session = tcp_session.clone();
+        self.spawn_task(async move {
+            if let Err(err) = future.await {
+                session.close_by_handler_error(err.as_ref());
+            }
+        }, Some(tcp_session));
+    }
+}
+
+/// Request with received content, passed to async handler by `Executor::handler`.
+pub struct AsyncRequest {
+    request: Request,
+    content: Vec<u8>,
+}
+
+impl AsyncRequest {
+    /// Content of request. It's already received, next calls return empty content.
+    pub async fn body(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.content)
+    }
+
+    /// Request for building of response.
+    pub fn into_request(self) -> Request {
+        self.request
+    }
+}
+
+impl Deref for AsyncRequest {
+    type Target = Request;
+
+    fn deref(&self) -> &Request {
+        &self.request
+    }
+}
+
+/// Sending of response with future of completion.
+pub trait SendAsync {
+    /// Sends response, the future is ready when the response is written to the socket or writing failed.
+    fn send_async(&self) -> Sent;
+}
+
+impl SendAsync for Response<'_, '_, '_, '_, '_> {
+    fn send_async(&self) -> Sent {
+        let state = Arc::new(Mutex::new(SentState { result: None, waker: None }));
+        let callback_state = state.clone();
+        self.try_send(move |result| {
+            if let Ok(mut state) = callback_state.lock() {
+                state.result = Some(result);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+
+        Sent { state }
+    }
+}
+
+/// Future of completion of sending, see `SendAsync`.
+pub struct Sent {
+    state: Arc<Mutex<SentState>>,
+}
+
+struct SentState {
+    result: Option<Result<(), std::io::Error>>,
+    waker: Option<Waker>,
+}
+
+impl Future for Sent {
+    type Output = Result<(), std::io::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Poll::Ready(Err(std::io::Error::other("lock of sending state is poisoned"))),
+        };
+
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Spawned future, it's queued again by its waker.
+struct Task {
+    /// None when the future is completed.
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    queue: mpsc::Sender<Arc<Task>>,
+    /// Connection of handler task, it's answered with "500 Internal Server Error" if the future panics.
+    tcp_session: Option<TcpSession>,
+}
+
+impl Task {
+    fn poll(self: Arc<Self>) {
+        let mut future = match self.future.lock() {
+            Ok(future) => future,
+            Err(_) => return,
+        };
+
+        if let Some(mut pending) = future.take() {
+            let waker = Waker::from(self.clone());
+            let polled = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| pending.as_mut().poll(&mut Context::from_waker(&waker))));
+            match polled {
+                Ok(Poll::Pending) => *future = Some(pending),
+                Ok(Poll::Ready(())) => {}
+                Err(_) => {
+                    drop(pending);
+                    match &self.tcp_session {
+                        Some(tcp_session) => {
+                            log(Level::Error, format_args!("async handler of session {} panicked", tcp_session.id()));
+                            tcp_session.close_by_handler_error(&HandlerError::new(500, http_status_code_with_name(500)));
+                        }
+                        None => log(Level::Error, format_args!("task of executor panicked")),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        let _ = self.queue.send(self.clone());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::guard::Guard;
+use crate::request::Request;
+use ring::hmac;
+
+/// Compares secrets (tokens, signatures, passwords hashes) in time that doesn't depend on their content,
+/// so position of first mismatched byte can't be found by measuring of response time. Length is not secret.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    // keeps the compiler from turning the fold into early return
+    std::hint::black_box(diff) == 0
+}
+
+/// Guard that passes request with "Authorization: Bearer <token>" header with one of `tokens`.
+/// Tokens are compared in constant time.
+pub fn bearer(tokens: &[&str]) -> Bearer {
+    Bearer { tokens: tokens.iter().map(|token| token.to_string()).collect() }
+}
+
+/// See `bearer` function.
+pub struct Bearer {
+    tokens: Vec<String>,
+}
+
+impl Guard for Bearer {
+    fn check(&self, request: &Request) -> bool {
+        let token = match request.header_value("Authorization").and_then(bearer_token) {
+            Some(token) => token,
+            None => return false,
+        };
+
+        // all tokens are compared, so time doesn't depend on which one matched
+        self.tokens.iter().fold(false, |found, expected| constant_time_eq(token.as_bytes(), expected.as_bytes()) | found)
+    }
+}
+
+/// Token of "Authorization" header value with "Bearer" scheme.
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Bearer") {
+        return None;
+    }
+
+    Some(token.trim()).filter(|token| !token.is_empty())
+}
+
+/// Signs values with HMAC-SHA256, for example session cookies that the client must not forge.
+/// Signed value is "value.signature" with base64url signature. Verification is constant-time.
+/// Can be used in multi-threaded environment after clone.
+#[derive(Clone)]
+pub struct Signer {
+    key: hmac::Key,
+}
+
+impl Signer {
+    /// Creates signer with secret key. Key should be random and at least 32 bytes long.
+    pub fn new(secret: &[u8]) -> Self {
+        Signer { key: hmac::Key::new(hmac::HMAC_SHA256, secret) }
+    }
+
+    /// Returns "value.signature".
+    pub fn sign(&self, value: &str) -> String {
+        let tag = hmac::sign(&self.key, value.as_bytes());
+        format!("{}.{}", value, base64::encode_config(tag.as_ref(), base64::URL_SAFE_NO_PAD))
+    }
+
+    /// Returns value of "value.signature" if the signature is valid.
+    pub fn verify<'a>(&self, signed: &'a str) -> Option<&'a str> {
+        let (value, signature) = signed.rsplit_once('.')?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        let expected = hmac::sign(&self.key, value.as_bytes());
+        if constant_time_eq(expected.as_ref(), &signature) {
+            return Some(value);
+        }
+
+        None
+    }
+}
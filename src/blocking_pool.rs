@@ -0,0 +1,66 @@
+use crate::handler_error::HandlerError;
+use crate::log::{log, Level};
+use crate::request::Request;
+use crate::response::http_status_code_with_name;
+use crate::router::HandlerResult;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Mutex};
+
+/// Threads for long or blocking handlers, so they don't delay other sessions of workers. See `Request::spawn_blocking`.
+/// Set to `web_session::Settings::blocking_pool` or by `Server::with_blocking_pool`.
+/// Can be used in multi-threaded environment after clone, all clones share threads, the threads are stopped when all clones are dropped.
+#[derive(Clone)]
+pub struct BlockingPool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl BlockingPool {
+    /// Starts `threads` threads (at least one).
+    pub fn new(threads: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            std::thread::spawn(move || loop {
+                let job = match receiver.lock().map(|receiver| receiver.recv()) {
+                    Ok(Ok(job)) => job,
+                    _ => return,
+                };
+
+                job();
+            });
+        }
+
+        BlockingPool { jobs }
+    }
+
+    /// Runs `f` with the request on one of threads. Next pipelined requests of the connection are not parsed until `f` returns,
+    /// so responses keep order of requests. Error of `f` closes the connection as error of the http callback,
+    /// panic of `f` is answered with "500 Internal Server Error" and the connection is closed.
+    pub(crate) fn spawn(&self, request: Request, f: impl FnOnce(Request) -> HandlerResult + Send + 'static) {
+        let tcp_session = request.tcp_session().clone();
+        tcp_session.inner.blocking_jobs.fetch_add(1, Ordering::SeqCst);
+
+        let job: Job = Box::new(move || {
+            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(request))) {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => tcp_session.close_by_handler_error(err.as_ref()),
+                Err(_) => {
+                    log(Level::Error, format_args!("blocking job of session {} panicked", tcp_session.id()));
+                    tcp_session.close_by_handler_error(&HandlerError::new(500, http_status_code_with_name(500)));
+                }
+            }
+
+            // response of the job is already in the outbox, the worker continues parsing after writing of it
+            tcp_session.inner.blocking_jobs.fetch_sub(1, Ordering::SeqCst);
+            tcp_session.wake();
+        });
+
+        if let Err(err) = self.jobs.send(job) {
+            // threads are stopped, not expected because jobs don't panic
+            (err.0)();
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
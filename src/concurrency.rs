@@ -0,0 +1,259 @@
+use crate::handler_error::HandlerError;
+use crate::request::Request;
+use crate::response::http_status_code_with_name;
+use crate::router::HandlerResult;
+use crate::tcp_session::TcpSession;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Limits the number of simultaneous in-flight requests.
+/// A request is in flight from the moment it's passed to `run` until the request (or the response
+/// made from it) is dropped, so the requests processed in other threads are also counted.
+/// Excess requests are queued up to the bound, if queue is full the client receives
+/// "503 Service Unavailable" with "Retry-After" header.
+/// Content of queued request is received before it's queued, up to `queued_content_limit`, and parsing of next
+/// pipelined requests of the connection waits until the queued request is processed, so responses keep order of requests.
+/// Can be used in multi-threaded environment after clone.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    inner: Arc<InnerConcurrencyLimit>,
+}
+
+struct InnerConcurrencyLimit {
+    /// Maximum number of in-flight requests.
+    max_in_flight: usize,
+    /// Maximum number of requests waiting for the processing.
+    queue_limit: usize,
+    /// Value of "Retry-After" header in seconds for rejected requests.
+    retry_after_secs: u64,
+    /// Maximum content length of request that can be queued.
+    queued_content_limit: usize,
+    /// Counters and queue.
+    state: Mutex<State>,
+}
+
+/// Request waiting in queue with the function that will process it.
+type QueuedRequest = (Request, Box<dyn FnOnce(Request) -> HandlerResult + Send>, Paused);
+
+struct State {
+    in_flight: usize,
+    queue: VecDeque<QueuedRequest>,
+    /// Requests which content is being received before queueing, they take places in queue.
+    receiving: usize,
+}
+
+impl ConcurrencyLimit {
+    /// Creates limit of simultaneous in-flight requests without queue.
+    pub fn new(max_in_flight: usize) -> Self {
+        ConcurrencyLimit {
+            inner: Arc::new(InnerConcurrencyLimit {
+                max_in_flight,
+                queue_limit: 0,
+                retry_after_secs: 1,
+                queued_content_limit: 1024 * 1024,
+                state: Mutex::new(State { in_flight: 0, queue: VecDeque::new(), receiving: 0 }),
+            }),
+        }
+    }
+
+    /// Maximum number of requests waiting for the processing when limit is reached.
+    /// Must be set before the limit is cloned.
+    pub fn queue_limit(mut self, queue_limit: usize) -> Self {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.queue_limit = queue_limit;
+        }
+        self
+    }
+
+    /// Value of "Retry-After" header for rejected requests, rounded up to seconds. Must be set before the limit is cloned.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        }
+        self
+    }
+
+    /// Maximum content length of request that can be queued, content is kept in memory while request waits.
+    /// Request with bigger content is rejected with 503 when limit is reached,
+    /// chunked content exceeding it closes the connection with "413 Payload Too Large". Default 1 MiB.
+    /// Must be set before the limit is cloned.
+    pub fn queued_content_limit(mut self, bytes: usize) -> Self {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.queued_content_limit = bytes;
+        }
+        self
+    }
+
+    /// Calls `f` with request if limit isn't reached, otherwise queues request or responds 503.
+    /// Queued request is processed in thread where the previous request is released.
+    pub fn run(&self, mut request: Request, f: impl FnOnce(Request) -> HandlerResult + Send + 'static) -> HandlerResult {
+        if let Ok(mut state) = self.inner.state.lock() {
+            if state.in_flight >= self.inner.max_in_flight {
+                if state.queue.len() + state.receiving < self.inner.queue_limit && request.content_len() <= self.inner.queued_content_limit {
+                    let paused = Paused::new(request.tcp_session().clone());
+                    if request.has_content() {
+                        state.receiving += 1;
+                        drop(state); // unlock, content can be already received
+                        self.receive_and_queue(request, Box::new(f), paused);
+                    } else {
+                        state.queue.push_back((request, Box::new(f), paused));
+                    }
+                } else {
+                    drop(state); // unlock before response, it can release other permits
+                    let retry_after = Duration::from_secs(self.inner.retry_after_secs);
+                    request.response(503).retry_after(retry_after).text("503 Service Unavailable").send();
+                }
+
+                return Ok(());
+            }
+
+            state.in_flight += 1;
+        }
+
+        request.concurrency_permits.push(Permit { limit: self.inner.clone() });
+        f(request)
+    }
+
+    /// Receives content of request into memory, then queues it or runs it if a place is released meanwhile.
+    fn receive_and_queue(&self, request: Request, f: Box<dyn FnOnce(Request) -> HandlerResult + Send>, paused: Paused) {
+        let limit = self.clone();
+        let mut receiving = Some(Receiving(self.inner.clone()));
+        let mut queued = Some((f, paused));
+        let mut content = Vec::new();
+        request.read_raw_content(move |data, complete| {
+            if content.len() + data.len() > limit.inner.queued_content_limit {
+                return Err(HandlerError::new(413, http_status_code_with_name(413)).into());
+            }
+
+            content.extend_from_slice(data);
+            if let Some(mut request) = complete {
+                drop(receiving.take());
+                request.received_content = Some(std::mem::take(&mut content));
+                if let Some((f, paused)) = queued.take() {
+                    limit.queue_received(request, f, paused)?;
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    /// Queues request with received content or runs it if limit isn't reached.
+    fn queue_received(&self, mut request: Request, f: Box<dyn FnOnce(Request) -> HandlerResult + Send>, paused: Paused) -> HandlerResult {
+        if let Ok(mut state) = self.inner.state.lock() {
+            if state.in_flight >= self.inner.max_in_flight {
+                state.queue.push_back((request, f, paused));
+                return Ok(());
+            }
+
+            state.in_flight += 1;
+        }
+
+        request.concurrency_permits.push(Permit { limit: self.inner.clone() });
+        let result = f(request);
+        drop(paused);
+        result
+    }
+
+    /// Current number of in-flight requests.
+    pub fn in_flight(&self) -> usize {
+        self.inner.state.lock().map(|state| state.in_flight).unwrap_or(0)
+    }
+
+    /// Current number of requests waiting in queue.
+    pub fn queued(&self) -> usize {
+        self.inner.state.lock().map(|state| state.queue.len()).unwrap_or(0)
+    }
+}
+
+/// Owned by request, releases the place of in-flight request when dropped.
+pub(crate) struct Permit {
+    limit: Arc<InnerConcurrencyLimit>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let next = match self.limit.state.lock() {
+            Ok(mut state) => {
+                let next = state.queue.pop_front();
+                if next.is_none() {
+                    state.in_flight = state.in_flight.saturating_sub(1);
+                }
+                next
+            }
+            Err(_) => None,
+        };
+
+        // the place passes to the next queued request
+        if let Some(next) = next {
+            PASSED.with(|passed| passed.borrow_mut().push_back((next, self.limit.clone())));
+            run_passed();
+        }
+    }
+}
+
+/// Runs queued requests that got places of released permits. Requests handled synchronously release their permits
+/// while running, the permits only add next requests to `PASSED`, so the queue is processed in loop, not by recursion.
+fn run_passed() {
+    if RUNNING_PASSED.with(|running| running.replace(true)) {
+        // processed by loop of outer call
+        return;
+    }
+
+    // the flag is reset even if handler panics
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            RUNNING_PASSED.with(|running| running.set(false));
+        }
+    }
+    let _reset = Reset;
+
+    while let Some(((mut request, f, paused), limit)) = PASSED.with(|passed| passed.borrow_mut().pop_front()) {
+        let tcp_session = request.tcp_session().clone();
+        request.concurrency_permits.push(Permit { limit });
+        if let Err(err) = f(request) {
+            tcp_session.close_by_handler_error(err.as_ref());
+        }
+        drop(paused);
+    }
+}
+
+/// Pauses parsing of next requests of the connection while its request waits in queue, so responses keep order of requests.
+struct Paused(TcpSession);
+
+impl Paused {
+    fn new(tcp_session: TcpSession) -> Self {
+        tcp_session.inner.blocking_jobs.fetch_add(1, Ordering::SeqCst);
+        Paused(tcp_session)
+    }
+}
+
+impl Drop for Paused {
+    fn drop(&mut self) {
+        // the response is already in the outbox, the worker continues parsing after writing of it
+        self.0.inner.blocking_jobs.fetch_sub(1, Ordering::SeqCst);
+        self.0.wake();
+    }
+}
+
+/// Takes a place in queue while content of request is received, released when content is complete or the connection is closed.
+struct Receiving(Arc<InnerConcurrencyLimit>);
+
+impl Drop for Receiving {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.0.state.lock() {
+            state.receiving = state.receiving.saturating_sub(1);
+        }
+    }
+}
+
+thread_local! {
+    /// Queued requests that got places of permits released in this thread and wait for `run_passed`.
+    static PASSED: RefCell<VecDeque<(QueuedRequest, Arc<InnerConcurrencyLimit>)>> = const { RefCell::new(VecDeque::new()) };
+    /// `run_passed` is running in this thread.
+    static RUNNING_PASSED: Cell<bool> = const { Cell::new(false) };
+}
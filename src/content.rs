@@ -0,0 +1,298 @@
+use crate::handler_error::HandlerError;
+use flate2::write::{GzDecoder, ZlibDecoder};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Content of request received by `Request::read_content_spilled`.
+/// Small content is kept in memory, content bigger than the limit is written to temporary file.
+#[derive(Debug)]
+pub enum Content {
+    /// Content is not bigger than memory limit.
+    Memory(Vec<u8>),
+    /// Content is bigger than memory limit.
+    File(SpilledFile),
+}
+
+impl Content {
+    /// Length of content in bytes.
+    pub fn len(&self) -> u64 {
+        match self {
+            Content::Memory(data) => data.len() as u64,
+            Content::File(file) => file.len(),
+        }
+    }
+
+    /// Returns true if content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reader of content from the beginning, for example for multipart parsing by parts.
+    pub fn reader(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        match self {
+            Content::Memory(data) => Ok(Box::new(&data[..])),
+            Content::File(file) => Ok(Box::new(file.open()?)),
+        }
+    }
+
+    /// Content for first data part, written to file at once if the expected length is bigger than memory limit.
+    pub(crate) fn new(expected_len: usize, memory_limit: usize) -> std::io::Result<Self> {
+        if expected_len > memory_limit {
+            Ok(Content::File(SpilledFile::create()?))
+        } else {
+            Ok(Content::Memory(Vec::new()))
+        }
+    }
+
+    /// Appends part of content, moves it to file when memory limit is exceeded.
+    pub(crate) fn push(&mut self, data: &[u8], memory_limit: usize) -> std::io::Result<()> {
+        match self {
+            Content::Memory(content) => {
+                if content.len() + data.len() > memory_limit {
+                    let mut file = SpilledFile::create()?;
+                    file.write(content)?;
+                    file.write(data)?;
+                    *self = Content::File(file);
+                } else {
+                    content.extend_from_slice(data);
+                }
+            }
+            Content::File(file) => file.write(data)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Temporary file with content of request. The file is removed when this value is dropped, unless it is persisted.
+#[derive(Debug)]
+pub struct SpilledFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    /// File is moved by `persist` and must not be removed.
+    persisted: bool,
+}
+
+impl SpilledFile {
+    /// Path of temporary file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Length of content in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Opens the file for reading from the beginning.
+    pub fn open(&self) -> std::io::Result<File> {
+        let mut file = self.file.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    }
+
+    /// Moves the file to the path, so it will not be removed. Both paths must be on the same file system.
+    pub fn persist(mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::rename(&self.path, path)?;
+        self.persisted = true;
+        Ok(())
+    }
+
+    fn create() -> std::io::Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "anweb-{}-{}-{}.tmp",
+            std::process::id(),
+            SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos()),
+            COUNTER.fetch_add(1, Ordering::SeqCst),
+        ));
+
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        Ok(SpilledFile { path, file, len: 0, persisted: false })
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(data)?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+}
+
+impl Drop for SpilledFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Checker of request content against "Content-MD5" (RFC 1864), "Digest" (RFC 3230) or "Content-Digest" (RFC 9530)
+/// headers, see `Settings::verify_content_digest`. Digests are of content as received, before decompression.
+/// Supported algorithms are MD5, SHA-256 and SHA-512, others are ignored.
+pub(crate) struct ContentVerifier {
+    /// Hashing of received content and expected digest.
+    checks: Vec<(DigestContext, Vec<u8>)>,
+}
+
+enum DigestContext {
+    Md5(md5::Context),
+    Sha(ring::digest::Context),
+}
+
+impl ContentVerifier {
+    /// Verifier by values of headers. None if there is no digest of supported algorithm.
+    pub(crate) fn new(content_md5: Option<&str>, digest: Option<&str>, content_digest: Option<&str>) -> Option<Self> {
+        let mut checks = Vec::new();
+        let mut add = |algorithm: &str, value: &str| {
+            let context = if algorithm.eq_ignore_ascii_case("md5") {
+                DigestContext::Md5(md5::Context::new())
+            } else if algorithm.eq_ignore_ascii_case("sha-256") {
+                DigestContext::Sha(ring::digest::Context::new(&ring::digest::SHA256))
+            } else if algorithm.eq_ignore_ascii_case("sha-512") {
+                DigestContext::Sha(ring::digest::Context::new(&ring::digest::SHA512))
+            } else {
+                return;
+            };
+            // malformed digest never matches
+            checks.push((context, base64::decode(value.trim()).unwrap_or_default()));
+        };
+
+        if let Some(value) = content_md5 {
+            add("md5", value);
+        }
+        for item in digest.into_iter().flat_map(|digest| digest.split(',')) {
+            if let Some((algorithm, value)) = item.split_once('=') {
+                add(algorithm.trim(), value);
+            }
+        }
+        for item in content_digest.into_iter().flat_map(|digest| digest.split(',')) {
+            if let Some((algorithm, value)) = item.split_once('=') {
+                add(algorithm.trim(), value.trim().trim_matches(':'));
+            }
+        }
+
+        if checks.is_empty() {
+            return None;
+        }
+
+        Some(ContentVerifier { checks })
+    }
+
+    /// Hashes part of content.
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        for (context, _) in &mut self.checks {
+            match context {
+                DigestContext::Md5(context) => context.consume(data),
+                DigestContext::Sha(context) => context.update(data),
+            }
+        }
+    }
+
+    /// Compares digests after receiving of the last part. Error is "400 Bad Request" on mismatch.
+    pub(crate) fn verify(&mut self) -> Result<(), HandlerError> {
+        for (context, expected) in std::mem::take(&mut self.checks) {
+            let matches = match context {
+                DigestContext::Md5(context) => context.compute().0[..] == expected[..],
+                DigestContext::Sha(context) => context.finish().as_ref() == &expected[..],
+            };
+            if !matches {
+                return Err(HandlerError::bad_request("Content digest mismatch"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decoder of request content with "Content-Encoding" gzip or deflate, see `Settings::content_decompression_limit`.
+pub(crate) enum ContentDecoder {
+    Gzip(GzDecoder<LimitedBuf>),
+    Deflate(ZlibDecoder<LimitedBuf>),
+}
+
+impl ContentDecoder {
+    /// Decoder for "Content-Encoding" value. None if encoding is not supported.
+    pub(crate) fn new(encoding: &str, limit: usize) -> Option<Self> {
+        let buf = LimitedBuf { data: Vec::new(), len: 0, limit };
+        let encoding = encoding.trim();
+        if encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip") {
+            Some(ContentDecoder::Gzip(GzDecoder::new(buf)))
+        } else if encoding.eq_ignore_ascii_case("deflate") {
+            Some(ContentDecoder::Deflate(ZlibDecoder::new(buf)))
+        } else {
+            None
+        }
+    }
+
+    /// Decodes part of content. Returns decoded data which is available yet.
+    pub(crate) fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, HandlerError> {
+        let res = match self {
+            ContentDecoder::Gzip(decoder) => decoder.write_all(data),
+            ContentDecoder::Deflate(decoder) => decoder.write_all(data),
+        };
+
+        self.check(res)?;
+        Ok(std::mem::take(&mut self.buf().data))
+    }
+
+    /// Decodes rest of content after receiving of the last part.
+    pub(crate) fn finish(&mut self) -> Result<Vec<u8>, HandlerError> {
+        let res = match self {
+            ContentDecoder::Gzip(decoder) => decoder.try_finish(),
+            ContentDecoder::Deflate(decoder) => decoder.try_finish(),
+        };
+
+        self.check(res)?;
+        Ok(std::mem::take(&mut self.buf().data))
+    }
+
+    fn check(&mut self, res: std::io::Result<()>) -> Result<(), HandlerError> {
+        let buf = self.buf();
+        if buf.len > buf.limit {
+            return Err(HandlerError::new(413, "Decompressed content is too large"));
+        }
+
+        res.map_err(|_| HandlerError::bad_request("Wrong compressed content"))
+    }
+
+    fn buf(&mut self) -> &mut LimitedBuf {
+        match self {
+            ContentDecoder::Gzip(decoder) => decoder.get_mut(),
+            ContentDecoder::Deflate(decoder) => decoder.get_mut(),
+        }
+    }
+}
+
+/// Output of decoder, fails writing when the limit of decoded length is exceeded.
+pub(crate) struct LimitedBuf {
+    data: Vec<u8>,
+    /// Length of all written data.
+    len: usize,
+    limit: usize,
+}
+
+impl Write for LimitedBuf {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.len += data.len();
+        if self.len > self.limit {
+            return Err(std::io::Error::other("limit of decompressed content length is exceeded"));
+        }
+
+        self.data.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
 /// Cookie that the server sends to the client.
 #[derive(Debug)]
-pub struct Cookie<'a, 'b, 'c, 'd, 'e> {
+pub struct Cookie<'a, 'b, 'c, 'd> {
     /// Cookie name. Can't be empty.
     pub name: &'a str,
     /// Cookie value. Can be empty.
@@ -10,8 +13,8 @@ pub struct Cookie<'a, 'b, 'c, 'd, 'e> {
     pub path: Option<&'c str>,
     /// Domain attribute specifies those hosts to which the cookie will be sent. If not specified, defaults to the host portion of the current document location (but not including subdomains).
     pub domain: Option<&'d str>,
-    /// Expires attribute indicates cookie expiration date.
-    pub expires: Option<&'e str>,
+    /// Expires attribute indicates cookie expiration date. Rendered as "Wed, 21 Oct 2015 07:28:00 GMT".
+    pub expires: Option<DateTime<Utc>>,
 
     // Max-Age attribute indicates the maximum lifetime of the cookie in seconds.
     pub max_age: Option<i32>,
@@ -21,7 +24,7 @@ pub struct Cookie<'a, 'b, 'c, 'd, 'e> {
     pub secure: bool,
 }
 
-impl<'a> Cookie<'a, '_, '_, '_, '_> {
+impl<'a> Cookie<'a, '_, '_, '_> {
     /// Prepared cookie for remove on the browser side.
     pub fn remove(name: &'a str) -> Self {
         Cookie {
@@ -36,6 +39,21 @@ impl<'a> Cookie<'a, '_, '_, '_, '_> {
         }
     }
 
+    /// Set expiration date, accepts `SystemTime` or `chrono::DateTime<Utc>`.
+    pub fn expires_at(mut self, time: impl Into<DateTime<Utc>>) -> Self {
+        self.expires = Some(time.into());
+        self
+    }
+
+    /// Set both Expires and Max-Age attributes to the same moment after `duration` from now.
+    pub fn expires_in(mut self, duration: Duration) -> Self {
+        let duration = chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::seconds(i32::MAX as i64));
+        let max_age = duration.num_seconds().min(i32::MAX as i64);
+        self.expires = Some(Utc::now() + chrono::Duration::seconds(max_age));
+        self.max_age = Some(max_age as i32);
+        self
+    }
+
     /// Return string with value prepared for "Set-Cookie" header.
     pub fn header_value(&self) -> String {
         format!("{}={}{}{}{}{}{}{}",
@@ -51,18 +69,9 @@ impl<'a> Cookie<'a, '_, '_, '_, '_> {
     }
 }
 
-impl std::fmt::Display for Cookie<'_, '_, '_, '_, '_> {
+impl std::fmt::Display for Cookie<'_, '_, '_, '_> {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        fmt.write_str(&format!("Set-Cookie: {}={}{}{}{}{}{}{}\r\n",
-            self.name,
-            self.value,
-            cookie_path_str(self.path),
-            cookie_domain_str(self.domain),
-            cookie_expires_str(self.expires),
-            cookie_max_age_str(self.max_age),
-            if self.secure { "; Secure" } else { "" },
-            if self.http_only { "; HttpOnly" } else { "" },
-        ))?;
+        fmt.write_str(&format!("Set-Cookie: {}\r\n", self.header_value()))?;
         Ok(())
     }
 }
@@ -83,9 +92,9 @@ fn cookie_domain_str(domain: Option<&str>) -> String {
     String::new()
 }
 
-fn cookie_expires_str(expires: Option<&str>) -> String {
+fn cookie_expires_str(expires: Option<DateTime<Utc>>) -> String {
     if let Some(expires) = expires {
-        return format!("; Expires={:?}", expires);
+        return format!("; Expires={}", cookie_date(&expires));
     }
 
     String::new()
@@ -99,6 +108,11 @@ fn cookie_max_age_str(max_age: Option<i32>) -> String {
     String::new()
 }
 
+/// Date in format of RFC 1123 (RFC 6265, 5.1.1), for example "Wed, 21 Oct 2015 07:28:00 GMT".
+pub fn cookie_date(time: &DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
 /// Cookie that the received from client.
 #[derive(Debug)]
 pub struct CookieOfRequst<'a> {
@@ -109,30 +123,110 @@ pub struct CookieOfRequst<'a> {
 }
 
 /// Convert cookie string from http header to the struct.
-pub fn parse_cookie(cookies_header_value: &str) -> Vec<CookieOfRequst> {
+/// Spaces around names and values are trimmed (RFC 6265, 5.2), surrounding double quotes of values are removed.
+/// Pairs with control characters and empty names are skipped. See also `parse_cookie_strict`.
+pub fn parse_cookie(cookies_header_value: &str) -> Vec<CookieOfRequst<'_>> {
     let mut result = Vec::new();
 
-    let cookies = cookies_header_value.split(|ch| ch == ';');
-    for cookie in cookies {
-        let begin_idx = cookie.bytes().position(|ch| ch != b' ');
-        if let Some(begin_idx) = begin_idx {
-            let cookie = &cookie[begin_idx..];
-            let assignment_pos = cookie.bytes().position(|ch| ch == b'=');
-            if let Some(assignment_pos) = assignment_pos {
-                // name found
-                if assignment_pos > 0 {
-                    let name = &cookie[..assignment_pos];
-                    let value = &cookie[assignment_pos + 1..];
-                    result.push(CookieOfRequst { name, value })
-                }
-            } else {
-                // only name found "abc" or "abc="
-                let name = &cookie[..];
-                let value = "";
-                result.push(CookieOfRequst { name, value })
-            }
+    for pair in cookies_header_value.split(';') {
+        if let Ok(Some(cookie)) = parse_pair(pair, false) {
+            result.push(cookie);
         }
     }
 
     result
 }
+
+/// Convert cookie string from http header to the struct, only pairs valid by RFC 6265, 4.1.1 are returned.
+/// # Arguments
+/// * `on_malformed` - called with each dropped pair and the reason.
+pub fn parse_cookie_strict<'a>(cookies_header_value: &'a str, mut on_malformed: impl FnMut(&'a str, MalformedCookie)) -> Vec<CookieOfRequst<'a>> {
+    let mut result = Vec::new();
+
+    for pair in cookies_header_value.split(';') {
+        match parse_pair(pair, true) {
+            Ok(Some(cookie)) => result.push(cookie),
+            Ok(None) => {}
+            Err(reason) => on_malformed(pair, reason),
+        }
+    }
+
+    result
+}
+
+/// Reason why cookie pair was dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MalformedCookie {
+    /// Name is empty.
+    EmptyName,
+    /// There is no '=' after name (only in strict mode).
+    NoValue,
+    /// Name has a character that is not allowed in token (only in strict mode).
+    InvalidName,
+    /// Value has a character that is not allowed (only in strict mode).
+    InvalidValue,
+    /// Name or value has a control character.
+    ControlCharacter,
+}
+
+impl std::fmt::Display for MalformedCookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for MalformedCookie {}
+
+/// Parses one "name=value" pair. Ok(None) for empty pair.
+fn parse_pair(pair: &str, strict: bool) -> Result<Option<CookieOfRequst<'_>>, MalformedCookie> {
+    let pair = trim_whitespace(pair);
+    if pair.is_empty() {
+        return Ok(None);
+    }
+
+    let (name, value) = match pair.find('=') {
+        Some(assignment_pos) => (trim_whitespace(&pair[..assignment_pos]), trim_whitespace(&pair[assignment_pos + 1..])),
+        None if strict => return Err(MalformedCookie::NoValue),
+        // only name found "abc"
+        None => (pair, ""),
+    };
+
+    if name.is_empty() {
+        return Err(MalformedCookie::EmptyName);
+    }
+
+    if name.bytes().chain(value.bytes()).any(|ch| ch.is_ascii_control()) {
+        return Err(MalformedCookie::ControlCharacter);
+    }
+
+    let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    };
+
+    if strict {
+        if !name.bytes().all(is_token_char) {
+            return Err(MalformedCookie::InvalidName);
+        }
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(MalformedCookie::InvalidValue);
+        }
+    }
+
+    Ok(Some(CookieOfRequst { name, value }))
+}
+
+fn trim_whitespace(s: &str) -> &str {
+    s.trim_matches(|ch| ch == ' ' || ch == '\t')
+}
+
+/// Character of token, RFC 7230, 3.2.6.
+fn is_token_char(ch: u8) -> bool {
+    ch.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&ch)
+}
+
+/// Character allowed in cookie value, RFC 6265, 4.1.1.
+fn is_cookie_octet(ch: u8) -> bool {
+    matches!(ch, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
@@ -0,0 +1,184 @@
+use crate::request::Request;
+use std::time::Duration;
+
+/// Cross-origin resource sharing policy of routes, see `Router::cors` and `Route::cors`.
+/// Preflight requests ("OPTIONS" with "Origin" and "Access-Control-Request-Method") are answered by the router
+/// with "204 No Content", other requests from allowed origins get CORS headers in all their responses.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    /// Allowed origins, any origin if empty and credentials aren't allowed.
+    origins: Vec<String>,
+    methods: Vec<String>,
+    /// Headers allowed in requests, "*" allows any header.
+    headers: Vec<String>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+    private_network: bool,
+}
+
+impl Cors {
+    /// Policy allowing "GET", "HEAD" and "POST" requests without custom headers from any origin.
+    pub fn new() -> Self {
+        Cors {
+            origins: Vec::new(),
+            methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            headers: Vec::new(),
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+            private_network: false,
+        }
+    }
+
+    /// Allows origin like "https://example.com". If no origin is added, any origin is allowed
+    /// unless credentials are allowed, see `allow_credentials`.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.to_string());
+        self
+    }
+
+    /// Sets methods allowed in preflight requests.
+    pub fn allow_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = methods.iter().map(|method| method.to_string()).collect();
+        self
+    }
+
+    /// Sets headers allowed in preflight requests, case-insensitive. "*" allows any header.
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /// Sets headers of responses available to scripts ("Access-Control-Expose-Headers").
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.expose_headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /// Allows requests with cookies or authorization ("Access-Control-Allow-Credentials: true").
+    /// Requires origins added by `allow_origin`, without them no origin is allowed, so any site can't read
+    /// responses with credentials of the user.
+    pub fn allow_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
+    /// Time for which browsers cache result of preflight request ("Access-Control-Max-Age"),
+    /// so scripts send less "OPTIONS" requests. Browsers limit it, for example Chrome to 2 hours. Default None.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Allows requests from public sites to the server in private network: preflight request with
+    /// "Access-Control-Request-Private-Network: true" gets "Access-Control-Allow-Private-Network: true".
+    pub fn allow_private_network(mut self) -> Self {
+        self.private_network = true;
+        self
+    }
+
+    /// Returns true if request is CORS preflight request.
+    pub fn is_preflight(request: &Request) -> bool {
+        request.method() == "OPTIONS"
+            && request.header_value("Origin").is_some()
+            && request.header_value("Access-Control-Request-Method").is_some()
+    }
+
+    /// Answers preflight request with "204 No Content" and CORS headers if origin, method and headers are allowed,
+    /// otherwise with "403 Forbidden".
+    pub(crate) fn respond_preflight(&self, request: Request) {
+        let headers = match self.preflight_headers(&request) {
+            Some(headers) => headers,
+            None => {
+                request.response(403).text("403 Forbidden").send();
+                return;
+            }
+        };
+
+        request.response(204).headers(&headers).send();
+    }
+
+    /// Adds CORS headers to responses of request from allowed origin.
+    pub(crate) fn apply(&self, request: &mut Request) {
+        let origin = match request.header_value("Origin") {
+            Some(origin) if self.allows_origin(origin) => origin.to_string(),
+            _ => return,
+        };
+
+        for (name, value) in self.origin_headers(&origin) {
+            request.add_default_response_header(name, &value);
+        }
+        if !self.expose_headers.is_empty() {
+            request.add_default_response_header("Access-Control-Expose-Headers", &self.expose_headers.join(", "));
+        }
+    }
+
+    /// Header lines of response to preflight request, None if the request isn't allowed.
+    fn preflight_headers(&self, request: &Request) -> Option<String> {
+        let origin = request.header_value("Origin").filter(|origin| self.allows_origin(origin))?;
+        let method = request.header_value("Access-Control-Request-Method")?;
+        if !self.methods.iter().any(|allowed| allowed == method) {
+            return None;
+        }
+
+        let requested_headers = request.header_value("Access-Control-Request-Headers").unwrap_or("")
+            .split(',')
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .collect::<Vec<_>>();
+        let any_header = self.headers.iter().any(|header| header == "*");
+        if !any_header && !requested_headers.iter().all(|requested| self.headers.iter().any(|header| header.eq_ignore_ascii_case(requested))) {
+            return None;
+        }
+
+        let private_network = request.header_value("Access-Control-Request-Private-Network") == Some("true");
+        if private_network && !self.private_network {
+            return None;
+        }
+
+        let mut headers = String::new();
+        for (name, value) in self.origin_headers(origin) {
+            headers.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        headers.push_str(&format!("Access-Control-Allow-Methods: {}\r\n", self.methods.join(", ")));
+        if !requested_headers.is_empty() {
+            headers.push_str(&format!("Access-Control-Allow-Headers: {}\r\n", requested_headers.join(", ")));
+        }
+        if let Some(max_age) = self.max_age {
+            headers.push_str(&format!("Access-Control-Max-Age: {}\r\n", max_age.as_secs()));
+        }
+        if private_network {
+            headers.push_str("Access-Control-Allow-Private-Network: true\r\n");
+        }
+
+        Some(headers)
+    }
+
+    /// "Access-Control-Allow-Origin" and related headers. Origin is echoed if it's not any origin,
+    /// "Vary: Origin" tells caches that response depends on it.
+    fn origin_headers(&self, origin: &str) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if self.origins.is_empty() {
+            headers.push(("Access-Control-Allow-Origin", "*".to_string()));
+        } else {
+            headers.push(("Access-Control-Allow-Origin", origin.to_string()));
+            headers.push(("Vary", "Origin".to_string()));
+        }
+        if self.credentials {
+            headers.push(("Access-Control-Allow-Credentials", "true".to_string()));
+        }
+
+        headers
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        (self.origins.is_empty() && !self.credentials) || self.origins.iter().any(|allowed| allowed == origin)
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
@@ -0,0 +1,309 @@
+use crate::query::{decode_query_component, parse_query};
+use crate::request::Request;
+use std::collections::HashMap;
+
+/// Something that can be taken from request before the handler is called.
+/// If extraction fails, the handler is not called and the client receives response with error code (400 by default).
+pub trait FromRequest: Sized {
+    /// Extracts value from request, path parameters matched by router and request content.
+    /// `content` is empty if no extractor of handler needs content.
+    fn from_request(request: &Request, params: &Params, content: &[u8]) -> Result<Self, Rejection>;
+
+    /// Returns true if extraction requires the content of request.
+    /// In this case the content will be read before the handler is called.
+    fn needs_content() -> bool {
+        false
+    }
+}
+
+/// Reason why the value could not be extracted from request. It will be sent to the client.
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    /// HTTP response code.
+    pub code: u16,
+    /// Text of response.
+    pub message: String,
+}
+
+impl Rejection {
+    /// Rejection with code 400 Bad Request.
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Rejection { code: 400, message: message.into() }
+    }
+
+    /// Rejection with code 415 Unsupported Media Type.
+    pub fn unsupported_media_type(message: impl Into<String>) -> Self {
+        Rejection { code: 415, message: message.into() }
+    }
+
+    /// Send response with rejection code and message.
+    pub fn send(&self, request: Request) {
+        request.response(self.code).text(&self.message).send();
+    }
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Path parameters matched by router, like "id" in "/users/:id".
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    params: Vec<(String, String)>,
+}
+
+impl Params {
+    /// Value of parameter by name.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(param_name, _)| param_name == name)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Value of parameter by name converted to `T`.
+    pub fn get<T: FromParam>(&self, name: &str) -> Result<T, Rejection> {
+        let value = self.value(name)
+            .ok_or_else(|| Rejection::bad_request(format!("No path parameter {:?}", name)))?;
+
+        T::from_param(value)
+            .map_err(|err| Rejection::bad_request(format!("Wrong path parameter {:?}: {}", name, err)))
+    }
+
+    /// Names and values of all parameters in order of them in path pattern.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(name, value)| (&name[..], &value[..]))
+    }
+
+    /// Number of parameters.
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns true if there are no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, name: &str, value: &str) {
+        self.params.push((name.to_string(), value.to_string()));
+    }
+}
+
+impl FromRequest for Params {
+    fn from_request(_request: &Request, params: &Params, _content: &[u8]) -> Result<Self, Rejection> {
+        Ok(params.clone())
+    }
+}
+
+/// Value that can be converted from path parameter.
+pub trait FromParam: Sized {
+    /// Converts parameter value. Error is description for the client.
+    fn from_param(param: &str) -> Result<Self, String>;
+}
+
+macro_rules! from_param_by_from_str {
+    ($($t:ty),*) => {
+        $(
+            impl FromParam for $t {
+                fn from_param(param: &str) -> Result<Self, String> {
+                    param.parse().map_err(|err| format!("{}", err))
+                }
+            }
+        )*
+    };
+}
+
+from_param_by_from_str!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char, String);
+
+/// Values that can be converted from all path parameters in order: single value or tuple.
+pub trait FromParams: Sized {
+    /// Converts parameters.
+    fn from_params(params: &Params) -> Result<Self, Rejection>;
+}
+
+impl<T: FromParam> FromParams for T {
+    fn from_params(params: &Params) -> Result<Self, Rejection> {
+        param_at(params, 0)
+    }
+}
+
+macro_rules! from_params_tuple {
+    ($($t:ident $i:tt),*) => {
+        impl<$($t: FromParam),*> FromParams for ($($t,)*) {
+            fn from_params(params: &Params) -> Result<Self, Rejection> {
+                Ok(($(param_at::<$t>(params, $i)?,)*))
+            }
+        }
+    };
+}
+
+from_params_tuple!(A 0, B 1);
+from_params_tuple!(A 0, B 1, C 2);
+from_params_tuple!(A 0, B 1, C 2, D 3);
+
+fn param_at<T: FromParam>(params: &Params, index: usize) -> Result<T, Rejection> {
+    let (name, value) = params.params.get(index)
+        .ok_or_else(|| Rejection::bad_request("Not enough path parameters"))?;
+
+    T::from_param(value)
+        .map_err(|err| Rejection::bad_request(format!("Wrong path parameter {:?}: {}", name, err)))
+}
+
+/// Typed path parameters. For pattern "/users/:id" it's `Path<u64>`,
+/// for pattern "/users/:id/posts/:post" it's `Path<(u64, String)>`.
+#[derive(Debug)]
+pub struct Path<T>(pub T);
+
+impl<T: FromParams> FromRequest for Path<T> {
+    fn from_request(_request: &Request, params: &Params, _content: &[u8]) -> Result<Self, Rejection> {
+        Ok(Path(T::from_params(params)?))
+    }
+}
+
+/// Value that can be made from parsed query or url-encoded form.
+pub trait FromQuery: Sized {
+    /// Converts parsed query. Error is description for the client.
+    fn from_query(query: &crate::query::Query) -> Result<Self, String>;
+}
+
+impl FromQuery for HashMap<String, String> {
+    fn from_query(query: &crate::query::Query) -> Result<Self, String> {
+        Ok(decoded_pairs(query)?.into_iter().collect())
+    }
+}
+
+impl FromQuery for Vec<(String, String)> {
+    fn from_query(query: &crate::query::Query) -> Result<Self, String> {
+        decoded_pairs(query)
+    }
+}
+
+fn decoded_pairs(query: &crate::query::Query) -> Result<Vec<(String, String)>, String> {
+    let mut result = Vec::with_capacity(query.len());
+    for part in query.iter() {
+        let name = decode_component(part.name)?;
+        let value = decode_component(part.value)?;
+        result.push((name, value));
+    }
+
+    Ok(result)
+}
+
+fn decode_component(component: &[u8]) -> Result<String, String> {
+    decode_query_component(component).map_err(|err| format!("{}", err))
+}
+
+/// Query or form mapped into user type with serde, as `Query<Urlencoded<Search>>` or `Form<Urlencoded<SignUp>>`.
+/// See `urlencoded::from_query`. Requires "urlencoded" feature.
+#[cfg(feature = "urlencoded")]
+#[derive(Debug)]
+pub struct Urlencoded<T>(pub T);
+
+#[cfg(feature = "urlencoded")]
+impl<T: serde::de::DeserializeOwned> FromQuery for Urlencoded<T> {
+    fn from_query(query: &crate::query::Query) -> Result<Self, String> {
+        query.deserialize().map(Urlencoded).map_err(|err| err.to_string())
+    }
+}
+
+/// Typed query of request.
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+impl<T: FromQuery> FromRequest for Query<T> {
+    fn from_request(request: &Request, _params: &Params, _content: &[u8]) -> Result<Self, Rejection> {
+        T::from_query(&request.query())
+            .map(Query)
+            .map_err(|err| Rejection::bad_request(format!("Wrong query: {}", err)))
+    }
+}
+
+/// Typed "application/x-www-form-urlencoded" content of request.
+#[derive(Debug)]
+pub struct Form<T>(pub T);
+
+impl<T: FromQuery> FromRequest for Form<T> {
+    fn from_request(request: &Request, _params: &Params, content: &[u8]) -> Result<Self, Rejection> {
+        if !request.has_post_form() {
+            return Err(Rejection::unsupported_media_type("Expected application/x-www-form-urlencoded content"));
+        }
+
+        T::from_query(&parse_query(content))
+            .map(Form)
+            .map_err(|err| Rejection::bad_request(format!("Wrong form: {}", err)))
+    }
+
+    fn needs_content() -> bool {
+        true
+    }
+}
+
+/// Typed "application/json" content of request. Requires "json" feature.
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<T: serde::de::DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request, _params: &Params, content: &[u8]) -> Result<Self, Rejection> {
+        let is_json = request.header_value("Content-Type")
+            .is_some_and(|value| crate::guard::media_type(value).eq_ignore_ascii_case("application/json"));
+
+        if !is_json {
+            return Err(Rejection::unsupported_media_type("Expected application/json content"));
+        }
+
+        serde_json::from_slice(content)
+            .map(Json)
+            .map_err(|err| Rejection::bad_request(format!("Wrong json: {}", err)))
+    }
+
+    fn needs_content() -> bool {
+        true
+    }
+}
+
+/// Raw content of request.
+#[derive(Debug)]
+pub struct Content(pub Vec<u8>);
+
+impl FromRequest for Content {
+    fn from_request(_request: &Request, _params: &Params, content: &[u8]) -> Result<Self, Rejection> {
+        Ok(Content(content.to_vec()))
+    }
+
+    fn needs_content() -> bool {
+        true
+    }
+}
+
+impl FromRequest for () {
+    fn from_request(_request: &Request, _params: &Params, _content: &[u8]) -> Result<Self, Rejection> {
+        Ok(())
+    }
+}
+
+macro_rules! from_request_tuple {
+    ($($t:ident),*) => {
+        impl<$($t: FromRequest),*> FromRequest for ($($t,)*) {
+            fn from_request(request: &Request, params: &Params, content: &[u8]) -> Result<Self, Rejection> {
+                Ok(($($t::from_request(request, params, content)?,)*))
+            }
+
+            fn needs_content() -> bool {
+                $($t::needs_content())||*
+            }
+        }
+    };
+}
+
+from_request_tuple!(A);
+from_request_tuple!(A, B);
+from_request_tuple!(A, B, C);
+from_request_tuple!(A, B, C, D);
+from_request_tuple!(A, B, C, D, E);
+from_request_tuple!(A, B, C, D, E, F);
@@ -0,0 +1,139 @@
+//! Invariants of parsers checked by fuzz targets in "fuzz" directory and by property tests: no panics,
+//! bounded buffering and same result for data passed in one piece and in small pieces.
+//! Functions panic if an invariant is broken. Requires "fuzz" feature.
+
+use crate::multipart::{MultipartParser, MultipartParserEvent, DISPOSITION_LEN_LIMIT};
+use crate::request::{RequestData, RequestError};
+use crate::request_parser::{ParseHttpRequestSettings, Parser};
+use crate::websocket;
+
+/// Limit of payload of frames parsed by `websocket_parser`.
+const PAYLOAD_LIMIT: usize = 1024;
+/// Maximum length of frame head: 2 bytes, 8 bytes of extended payload length and 4 bytes of masking key.
+const FRAME_HEAD_LIMIT: usize = 14;
+
+/// Parses requests (content is parsed as next request) from `data`.
+pub fn request_parser(data: &[u8], chunk_len: usize) {
+    let settings = ParseHttpRequestSettings::default();
+    assert_eq!(parse_requests(data, data.len(), &settings), parse_requests(data, chunk_len, &settings));
+}
+
+/// Parses frames received by server and by client from `data`.
+pub fn websocket_parser(data: &[u8], chunk_len: usize) {
+    for client in [false, true] {
+        assert_eq!(parse_frames(data, data.len(), client), parse_frames(data, chunk_len, client));
+    }
+}
+
+/// Parses multipart content with boundary "xyz" from `data`.
+pub fn multipart_parser(data: &[u8], chunk_len: usize) {
+    assert_eq!(parse_multipart(data, data.len()), parse_multipart(data, chunk_len));
+}
+
+/// Parsed requests and error at the end, if any.
+fn parse_requests(data: &[u8], chunk_len: usize, settings: &ParseHttpRequestSettings) -> Vec<String> {
+    // request line with "\r\n" and headers with ": " and "\r\n", then empty line
+    let head_limit = settings.method_len_limit as usize + settings.uri_len_limit as usize + 16
+        + settings.headers_count_limit as usize * (settings.header_name_len_limit as usize + settings.header_value_len_limit as usize + 4) + 2;
+
+    let mut parser = Parser::new();
+    let mut result = Vec::new();
+    for chunk in data.chunks(chunk_len.max(1)) {
+        let mut input = chunk.to_vec();
+        loop {
+            match parser.push(&input, settings) {
+                Ok((request, surplus)) => {
+                    result.push(request_summary(&request));
+                    if surplus.is_empty() {
+                        break;
+                    }
+                    input = surplus;
+                }
+                Err(RequestError::Partial) => {
+                    assert!(parser.buffered_len() <= head_limit, "{} bytes are buffered", parser.buffered_len());
+                    break;
+                }
+                Err(err) => {
+                    result.push(format!("{:?}", err));
+                    return result;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+fn request_summary(request: &RequestData) -> String {
+    format!("{} {:?} {:?} {:?} {:?} {} {}", request.method(), request.raw_path(), request.raw_query(), request.version(), request.headers(), request.content_len(), request.is_chunked())
+}
+
+/// Parsed frames and error at the end, if any.
+fn parse_frames(data: &[u8], chunk_len: usize, client: bool) -> Vec<String> {
+    let mut parser = if client { websocket::Parser::client() } else { websocket::Parser::new() };
+    let mut result = Vec::new();
+    for chunk in data.chunks(chunk_len.max(1)) {
+        let mut input = chunk.to_vec();
+        loop {
+            match parser.parse_yet(&input, PAYLOAD_LIMIT) {
+                Ok(Some((frame, surplus))) => {
+                    assert!(frame.payload().len() <= PAYLOAD_LIMIT);
+                    result.push(format!("{} {} {:?}", frame.fin(), frame.opcode(), frame.payload()));
+                    if surplus.is_empty() {
+                        break;
+                    }
+                    input = surplus;
+                }
+                Ok(None) => {
+                    assert!(parser.buffered_len() < FRAME_HEAD_LIMIT + PAYLOAD_LIMIT, "{} bytes are buffered", parser.buffered_len());
+                    break;
+                }
+                Err(err) => {
+                    result.push(format!("{:?}", err));
+                    return result;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Dispositions, data of parts joined regardless of pieces, end of content and error, if any.
+fn parse_multipart(data: &[u8], chunk_len: usize) -> Vec<Vec<u8>> {
+    let mut parser = match MultipartParser::with_boundary("xyz") {
+        Ok(parser) => parser,
+        Err(err) => panic!("{:?}", err),
+    };
+
+    let mut result: Vec<Vec<u8>> = Vec::new();
+    let mut in_data = false;
+    for chunk in data.chunks(chunk_len.max(1)) {
+        let pushed = parser.push(chunk, |event| match event {
+            MultipartParserEvent::Disposition(disposition) => {
+                result.push([b"disposition: ", disposition.raw()].concat());
+                in_data = false;
+            }
+            MultipartParserEvent::Data { data_part, .. } => {
+                match result.last_mut() {
+                    Some(data) if in_data => data.extend_from_slice(data_part),
+                    _ => result.push([b"data: ", data_part].concat()),
+                }
+                in_data = true;
+            }
+            MultipartParserEvent::Finished => {
+                result.push(b"finished".to_vec());
+                in_data = false;
+            }
+        });
+
+        if let Err(err) = pushed {
+            result.push(format!("{:?}", err).into_bytes());
+            return result;
+        }
+
+        assert!(parser.buffered_len() <= DISPOSITION_LEN_LIMIT, "{} bytes are buffered", parser.buffered_len());
+    }
+
+    result
+}
@@ -0,0 +1,122 @@
+use crate::request::{Request, Scheme};
+
+/// Condition that request must satisfy so that the route is selected by `Router`.
+/// Guards can be combined with `and`, `or` and `not`.
+pub trait Guard: Send + Sync {
+    /// Returns true if request satisfies the condition.
+    fn check(&self, request: &Request) -> bool;
+
+    /// Both guards must be satisfied.
+    fn and<G: Guard>(self, other: G) -> And<Self, G> where Self: Sized {
+        And(self, other)
+    }
+
+    /// At least one of guards must be satisfied.
+    fn or<G: Guard>(self, other: G) -> Or<Self, G> where Self: Sized {
+        Or(self, other)
+    }
+}
+
+/// Any function that checks request is a guard.
+impl<F: Fn(&Request) -> bool + Send + Sync> Guard for F {
+    fn check(&self, request: &Request) -> bool {
+        self(request)
+    }
+}
+
+/// Guard that passes request with specified method.
+pub fn method(method: &'static str) -> Method {
+    Method(method)
+}
+
+/// Guard that passes request which has header with `name` and value satisfying `predicate`.
+pub fn header<P: Fn(&str) -> bool + Send + Sync>(name: &'static str, predicate: P) -> Header<P> {
+    Header { name, predicate }
+}
+
+/// Guard that passes request with "Content-Type" header of specified media type.
+/// Parameters like "; charset=utf-8" are ignored when comparing.
+pub fn content_type(media_type: &'static str) -> ContentType {
+    ContentType(media_type)
+}
+
+/// Guard that passes request with specified scheme (plain HTTP or HTTP over TLS), see `Request::scheme`.
+pub fn scheme(scheme: Scheme) -> SchemeGuard {
+    SchemeGuard(scheme)
+}
+
+/// Guard that inverts result of other guard.
+pub fn not<G: Guard>(guard: G) -> Not<G> {
+    Not(guard)
+}
+
+/// See `method` function.
+pub struct Method(&'static str);
+
+impl Guard for Method {
+    fn check(&self, request: &Request) -> bool {
+        request.method() == self.0
+    }
+}
+
+/// See `header` function.
+pub struct Header<P> {
+    name: &'static str,
+    predicate: P,
+}
+
+impl<P: Fn(&str) -> bool + Send + Sync> Guard for Header<P> {
+    fn check(&self, request: &Request) -> bool {
+        request.header_value(self.name).is_some_and(|value| (self.predicate)(value))
+    }
+}
+
+/// See `content_type` function.
+pub struct ContentType(&'static str);
+
+impl Guard for ContentType {
+    fn check(&self, request: &Request) -> bool {
+        request.header_value("Content-Type").is_some_and(|value| media_type(value).eq_ignore_ascii_case(self.0))
+    }
+}
+
+/// See `scheme` function.
+pub struct SchemeGuard(Scheme);
+
+impl Guard for SchemeGuard {
+    fn check(&self, request: &Request) -> bool {
+        request.scheme() == self.0
+    }
+}
+
+/// See `Guard::and` function.
+pub struct And<A, B>(A, B);
+
+impl<A: Guard, B: Guard> Guard for And<A, B> {
+    fn check(&self, request: &Request) -> bool {
+        self.0.check(request) && self.1.check(request)
+    }
+}
+
+/// See `Guard::or` function.
+pub struct Or<A, B>(A, B);
+
+impl<A: Guard, B: Guard> Guard for Or<A, B> {
+    fn check(&self, request: &Request) -> bool {
+        self.0.check(request) || self.1.check(request)
+    }
+}
+
+/// See `not` function.
+pub struct Not<G>(G);
+
+impl<G: Guard> Guard for Not<G> {
+    fn check(&self, request: &Request) -> bool {
+        !self.0.check(request)
+    }
+}
+
+/// Returns media type of "Content-Type" header value without parameters.
+pub(crate) fn media_type(content_type: &str) -> &str {
+    content_type.split(';').next().unwrap_or("").trim()
+}
@@ -0,0 +1,91 @@
+use crate::extract::Rejection;
+use crate::response::http_status_code_with_name;
+
+/// Error of request handler with HTTP status and message for the client.
+/// If callback of `TcpSession::to_http` or `Request::read_content` returns this error (boxed),
+/// the server sends error response with the status and message and closes the connection.
+/// Other errors close the connection without response.
+/// Must be returned only if the response to the request has not been sent yet.
+#[derive(Debug)]
+pub struct HandlerError {
+    /// HTTP response code.
+    pub code: u16,
+    /// Text of response.
+    pub message: String,
+    /// Cause of error, not sent to the client.
+    pub source: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl HandlerError {
+    /// Creates error with HTTP response code and message for the client.
+    pub fn new(code: u16, message: impl Into<String>) -> Self {
+        HandlerError { code, message: message.into(), source: None }
+    }
+
+    /// Error with code 400 Bad Request.
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        HandlerError::new(400, message)
+    }
+
+    /// Error with code 404 Not Found.
+    pub fn not_found(message: impl Into<String>) -> Self {
+        HandlerError::new(404, message)
+    }
+
+    /// Error with code 500 Internal Server Error. Cause is not sent to the client.
+    pub fn internal(source: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        HandlerError {
+            code: 500,
+            message: http_status_code_with_name(500).to_string(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// Raw HTTP response with the error, connection will be closed after it.
+    /// `http_version` is version of the request, for example "HTTP/1.0", see `TcpSession::http_version`.
+    pub(crate) fn response(&self, http_version: &str, rfc7231_date: &str) -> Vec<u8> {
+        self.response_with_connection(http_version, rfc7231_date, "close")
+    }
+
+    /// Raw HTTP response with the error, connection is kept alive after it.
+    pub(crate) fn keep_alive_response(&self, http_version: &str, rfc7231_date: &str) -> Vec<u8> {
+        self.response_with_connection(http_version, rfc7231_date, "keep-alive")
+    }
+
+    fn response_with_connection(&self, http_version: &str, rfc7231_date: &str, connection: &str) -> Vec<u8> {
+        let mut response = Vec::from(format!(
+            "{} {}\r\n\
+             Date: {}\r\n\
+             Connection: {}\r\n\
+             Content-Length: {}\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             \r\n",
+            http_version,
+            http_status_code_with_name(self.code),
+            rfc7231_date,
+            connection,
+            self.message.len(),
+        ));
+
+        response.extend_from_slice(self.message.as_bytes());
+        response
+    }
+}
+
+impl From<Rejection> for HandlerError {
+    fn from(rejection: Rejection) -> Self {
+        HandlerError::new(rejection.code, rejection.message)
+    }
+}
+
+impl std::fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for HandlerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|source| source.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
@@ -0,0 +1,82 @@
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Environment variable with number of descriptor of listener inherited from previous process.
+pub const LISTEN_FD_VAR: &str = "ANWEB_LISTEN_FD";
+/// Environment variable with id of process that passed the listener, the listener is taken only by its direct child.
+pub const LISTEN_PARENT_PID_VAR: &str = "ANWEB_LISTEN_PARENT_PID";
+
+/// Phase of handoff of listener to new process, reported by `server::Event::Handoff`. See `Stopper::handoff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandoffPhase {
+    /// New process with the listener is started, its id.
+    Spawned(u32),
+    /// The server stopped accepting, new connections are accepted by new process.
+    Draining,
+    /// Responses of the server are written (true) or timeout of draining expired (false), the server can be stopped.
+    Drained(bool),
+}
+
+/// Starts `command` with inherited `listener`, new process takes it by `inherited_listener` or `Server::from_handoff`.
+pub fn spawn_with_listener(command: &mut Command, listener: &impl AsRawFd) -> Result<Child, std::io::Error> {
+    let fd = listener.as_raw_fd();
+    command.env(LISTEN_FD_VAR, fd.to_string());
+    command.env(LISTEN_PARENT_PID_VAR, std::process::id().to_string());
+    keep_on_exec(command, fd);
+    command.spawn()
+}
+
+/// Takes listener passed by previous process by `spawn_with_listener`. None if nothing is passed.
+/// The listener is taken once, child processes ignore the inherited variables by `LISTEN_PARENT_PID_VAR`.
+/// Error if the descriptor isn't TCP listener, then it isn't taken.
+pub fn inherited_listener() -> Result<Option<TcpListener>, std::io::Error> {
+    let fd = inherited_fd(
+        std::env::var(LISTEN_FD_VAR).ok().as_deref(),
+        std::env::var(LISTEN_PARENT_PID_VAR).ok().as_deref(),
+        std::os::unix::process::parent_id(),
+    );
+
+    let fd = match fd {
+        Some(fd) if !LISTENER_TAKEN.swap(true, Ordering::SeqCst) => fd,
+        _ => return Ok(None),
+    };
+
+    if !crate::systemd::is_tcp_listener(fd) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "inherited descriptor is not TCP listener"));
+    }
+
+    // SAFETY: the descriptor is listening socket passed by the parent process, taken once by LISTENER_TAKEN.
+    #[allow(unsafe_code)]
+    let listener = unsafe { crate::systemd::adopt_fd(fd) };
+    Ok(Some(listener))
+}
+
+/// Descriptor passed to the process with parent `parent_pid` by values of `LISTEN_FD_VAR` and `LISTEN_PARENT_PID_VAR`.
+pub(crate) fn inherited_fd(listen_fd: Option<&str>, listen_parent_pid: Option<&str>, parent_pid: u32) -> Option<RawFd> {
+    let from_parent = listen_parent_pid.and_then(|pid| pid.trim().parse::<u32>().ok()) == Some(parent_pid);
+    if !from_parent {
+        return None;
+    }
+
+    listen_fd.and_then(|fd| fd.trim().parse::<RawFd>().ok()).filter(|fd| *fd >= 0)
+}
+
+#[allow(unsafe_code)]
+fn keep_on_exec(command: &mut Command, fd: RawFd) {
+    // SAFETY: fcntl is async-signal-safe, it changes only flags of descriptor in the table of the child.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// Listener is taken by `inherited_listener`.
+static LISTENER_TAKEN: AtomicBool = AtomicBool::new(false);
@@ -0,0 +1,81 @@
+use crate::request::Request;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Built-in responder of health check requests. Requests are answered by the server before the http callback,
+/// so they are served even if the application handlers are overloaded.
+/// Liveness path ("/healthz" by default) always answers "200 OK" while the event loop runs.
+/// Readiness path ("/readyz" by default) answers "200 OK" if the server is ready and "503 Service Unavailable" otherwise,
+/// so deployment orchestrators can drain traffic before shutdown.
+/// Can be used in multi-threaded environment after clone, all clones share the readiness state.
+#[derive(Clone)]
+pub struct HealthCheck {
+    /// Path of liveness probe.
+    liveness_path: String,
+    /// Path of readiness probe.
+    readiness_path: String,
+    /// Readiness state.
+    ready: Arc<AtomicBool>,
+}
+
+impl HealthCheck {
+    /// Creates health check with "/healthz" and "/readyz" paths. Server is ready initially.
+    pub fn new() -> Self {
+        HealthCheck {
+            liveness_path: "/healthz".to_string(),
+            readiness_path: "/readyz".to_string(),
+            ready: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Sets path of liveness probe.
+    pub fn liveness_path(mut self, path: &str) -> Self {
+        self.liveness_path = path.to_string();
+        self
+    }
+
+    /// Sets path of readiness probe.
+    pub fn readiness_path(mut self, path: &str) -> Self {
+        self.readiness_path = path.to_string();
+        self
+    }
+
+    /// Sets readiness state. Can be called from any thread.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    /// Current readiness state.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Answers health check request. Returns request back if it's not health check request.
+    pub(crate) fn respond(&self, request: Request) -> Option<Request> {
+        if request.method() != "GET" {
+            return Some(request);
+        }
+
+        if request.path() == self.liveness_path {
+            request.response(200).text("ok").send();
+            return None;
+        }
+
+        if request.path() == self.readiness_path {
+            if self.is_ready() {
+                request.response(200).text("ready").send();
+            } else {
+                request.response(503).text("not ready").send();
+            }
+            return None;
+        }
+
+        Some(request)
+    }
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck::new()
+    }
+}
@@ -9,6 +9,8 @@ pub enum HttpError {
     ParseRequestError(RequestError),
     /// Register in poll error.
     PollRegisterError(std::io::Error),
+    /// Write to sock error. Connection is closed, data sent before may be not delivered.
+    WriteError(std::io::Error),
 }
 
 impl From<std::io::Error> for HttpError {
@@ -19,7 +21,7 @@ impl From<std::io::Error> for HttpError {
 
 impl std::fmt::Display for HttpError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
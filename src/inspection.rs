@@ -0,0 +1,49 @@
+use crate::handler_error::HandlerError;
+use crate::request::Request;
+use std::sync::Arc;
+
+/// Check of requests before the http callback, for example simple WAF rules, bot filtering or auth gating.
+/// If the check returns error, the client gets response with its code and message, the connection is closed
+/// and the http callback is not called. Set it in `web_session::Settings::inspector`.
+/// Can be used in multi-threaded environment after clone.
+#[derive(Clone)]
+pub struct Inspector {
+    check: Arc<InspectFn>,
+    /// Max length of content that is received before the check and passed to it.
+    body_limit: usize,
+}
+
+type InspectFn = dyn Fn(&Request, Option<&[u8]>) -> Result<(), HandlerError> + Send + Sync;
+
+impl Inspector {
+    /// Creates inspector with check of request head and content (if it's buffered, see `body_limit`).
+    pub fn new(check: impl Fn(&Request, Option<&[u8]>) -> Result<(), HandlerError> + Send + Sync + 'static) -> Self {
+        Inspector { check: Arc::new(check), body_limit: 0 }
+    }
+
+    /// Content not longer than the limit is received before the check and passed to it as is (not decompressed),
+    /// then it's passed to `Request::read_content` of the handler. Longer content is not passed to the check.
+    /// Chunked content is always received before the check, the client gets 413 if it's longer than the limit.
+    /// Default 0, only head of request is checked.
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    /// Content of the length or chunked content is received before the check.
+    pub(crate) fn buffers(&self, content_len: usize, chunked: bool) -> bool {
+        if chunked {
+            return self.body_limit > 0;
+        }
+
+        content_len > 0 && content_len <= self.body_limit
+    }
+
+    pub(crate) fn max_buffered_len(&self) -> usize {
+        self.body_limit
+    }
+
+    pub(crate) fn check(&self, request: &Request, content: Option<&[u8]>) -> Result<(), HandlerError> {
+        (self.check)(request, content)
+    }
+}
@@ -1,20 +1,59 @@
-#![forbid(unsafe_code)]
+// unsafe code is allowed only for passing of listening descriptors between processes
+#![deny(unsafe_code)]
 
+pub mod acme;
+#[cfg(feature = "async")]
+pub mod async_handler;
+pub mod auth;
+pub mod blocking_pool;
 pub mod tcp_session;
 pub mod http_error;
+pub mod handler_error;
+#[cfg(unix)]
+pub mod handoff;
 pub mod cookie;
 pub mod tls;
+#[cfg(unix)]
+pub mod tls_handshake;
+pub mod micro_cache;
 pub mod mime;
+pub mod mirror;
 pub mod multipart;
 pub mod query;
 pub mod redirect_server;
+pub mod router;
+pub mod guard;
+pub mod extract;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+pub mod concurrency;
+pub mod content;
+pub mod cors;
+pub mod health;
+pub mod inspection;
+pub mod log;
 pub mod request;
 pub mod response;
+pub mod security_headers;
 pub mod server;
+pub mod stats;
 pub mod static_files;
+#[cfg(unix)]
+pub mod systemd;
+pub mod testing;
+pub mod throttle;
+pub mod timing;
+pub mod transform;
+#[cfg(feature = "urlencoded")]
+pub mod urlencoded;
+pub mod webdav;
 pub mod websocket;
+pub mod websocket_client;
 pub mod worker;
 mod web_session;
+#[cfg(feature = "bench")]
+pub mod request_parser;
+#[cfg(not(feature = "bench"))]
 mod request_parser;
 
 #[cfg(test)]
@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Level of diagnostic message of the library.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Internal state is inconsistent, for example code that must be unreachable is reached.
+    Error,
+    /// Unexpected but handled condition.
+    Warn,
+    /// Details for debugging.
+    Debug,
+}
+
+/// Receiver of diagnostic messages, see `set_logger`.
+pub type Logger = Arc<dyn Fn(Level, &str) + Send + Sync>;
+
+/// Sets receiver of diagnostic messages for all servers of the process. Messages are dropped if None (default).
+/// With feature "tracing" messages are also emitted as tracing events.
+pub fn set_logger(logger: Option<Logger>) {
+    if let Ok(mut current) = LOGGER.write() {
+        LOGGER_SET.store(logger.is_some(), Ordering::SeqCst);
+        *current = logger;
+    }
+}
+
+/// Passes message to the logger. Message is formatted only if the logger is set.
+pub(crate) fn log(level: Level, message: std::fmt::Arguments) {
+    #[cfg(feature = "tracing")]
+    match level {
+        Level::Error => tracing::error!("{}", message),
+        Level::Warn => tracing::warn!("{}", message),
+        Level::Debug => tracing::debug!("{}", message),
+    }
+
+    if !LOGGER_SET.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if let Ok(logger) = LOGGER.read() {
+        if let Some(logger) = &*logger {
+            logger(level, &message.to_string());
+        }
+    }
+}
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+/// Logger is set, allows to skip locking and formatting.
+static LOGGER_SET: AtomicBool = AtomicBool::new(false);
@@ -0,0 +1,256 @@
+use crate::request::Request;
+use crate::response::etag_matches;
+use crate::router::HandlerResult;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// In-memory cache of rendered responses of dynamic endpoints for a short time.
+/// Key is method, path, query and values of selected headers (see `vary`). Only GET and HEAD responses with code 200 are cached.
+/// Request with "Cache-Control: no-cache" is rendered again, with "no-store" is rendered and not stored.
+/// With `etag` responses get weak "ETag" from hash of content and repeated requests with "If-None-Match" get 304.
+/// Clones share the cache.
+/// # Example
+/// `router.get("/page", move |request, ()| cache.serve(request, |_| Ok(CachedResponse::html(render_page()))))`
+#[derive(Clone)]
+pub struct MicroCache {
+    entries: Arc<Mutex<Entries>>,
+    /// Time of life of entry.
+    ttl: Duration,
+    max_entries: usize,
+    /// Limit of summary content length of entries.
+    max_bytes: usize,
+    /// Names of headers which values are a part of key.
+    vary: Vec<String>,
+    /// Compute weak "ETag" from hash of content.
+    etag: bool,
+}
+
+impl MicroCache {
+    /// Cache with time of life of entries. Limits by default: 1000 entries, 16 MB of content.
+    pub fn new(ttl: Duration) -> Self {
+        MicroCache {
+            entries: Arc::new(Mutex::new(Entries { map: HashMap::new(), bytes: 0 })),
+            ttl,
+            max_entries: 1000,
+            max_bytes: 16_000_000,
+            vary: Vec::new(),
+            etag: false,
+        }
+    }
+
+    /// Set limit of number of entries.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Set limit of summary content length of entries.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Add header which value is a part of key, for example "Accept-Language".
+    pub fn vary(mut self, header_name: &str) -> Self {
+        self.vary.push(header_name.to_string());
+        self
+    }
+
+    /// Add weak "ETag" header computed from hash of content to responses with code 200 and answer 304
+    /// if it matches "If-None-Match" header of request.
+    pub fn etag(mut self) -> Self {
+        self.etag = true;
+        self
+    }
+
+    /// Sends cached response if it is fresh, otherwise renders it by `render`, sends and stores.
+    pub fn serve(&self, request: Request, render: impl FnOnce(&Request) -> Result<CachedResponse, Box<dyn std::error::Error>>) -> HandlerResult {
+        if request.method() != "GET" && request.method() != "HEAD" {
+            let response = render(&request)?;
+            response.send(request, None);
+            return Ok(());
+        }
+
+        let cache_control = request.header_value("Cache-Control").unwrap_or("").to_ascii_lowercase();
+        let no_cache = cache_control.contains("no-cache");
+        let no_store = cache_control.contains("no-store");
+
+        let key = self.key(&request);
+        if !no_cache && !no_store {
+            if let Some((response, age)) = self.get(&key) {
+                response.send(request, Some(age));
+                return Ok(());
+            }
+        }
+
+        let mut response = render(&request)?;
+        if self.etag && response.code == 200 {
+            response.etag = Some(format!("W/\"{:x}\"", md5::compute(&response.content)));
+        }
+
+        let response = Arc::new(response);
+        if response.code == 200 && !no_store {
+            self.insert(key, response.clone());
+        }
+
+        response.send(request, None);
+        Ok(())
+    }
+
+    /// Number of entries including expired.
+    pub fn len(&self) -> usize {
+        self.entries.lock().map_or(0, |entries| entries.map.len())
+    }
+
+    /// Returns true if there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all entries.
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.map.clear();
+            entries.bytes = 0;
+        }
+    }
+
+    fn key(&self, request: &Request) -> String {
+        let mut key = format!("{} {}?{}", request.method(), String::from_utf8_lossy(request.raw_path()), String::from_utf8_lossy(request.raw_query()));
+        for name in &self.vary {
+            key.push('\n');
+            key.push_str(request.request_data().headers().iter()
+                .find(|header| header.name.eq_ignore_ascii_case(name))
+                .map_or("", |header| &header.value));
+        }
+
+        key
+    }
+
+    /// Fresh entry with its age.
+    fn get(&self, key: &str) -> Option<(Arc<CachedResponse>, Duration)> {
+        let entries = self.entries.lock().ok()?;
+        let entry = entries.map.get(key)?;
+        let age = entry.stored.elapsed();
+        if age < self.ttl {
+            Some((entry.response.clone(), age))
+        } else {
+            None
+        }
+    }
+
+    fn insert(&self, key: String, response: Arc<CachedResponse>) {
+        let len = response.content.len();
+        if len > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+
+        if let Ok(mut entries) = self.entries.lock() {
+            if let Some(old) = entries.map.insert(key, Entry { response, stored: Instant::now() }) {
+                entries.bytes -= old.response.content.len();
+            }
+            entries.bytes += len;
+
+            if entries.map.len() > self.max_entries || entries.bytes > self.max_bytes {
+                entries.evict(self.ttl, self.max_entries, self.max_bytes);
+            }
+        }
+    }
+}
+
+/// Response stored in `MicroCache`.
+pub struct CachedResponse {
+    code: u16,
+    /// "Content-Type" header line.
+    content_type: String,
+    /// Extra header lines.
+    headers: String,
+    content: Vec<u8>,
+    /// Value of "ETag" header, see `MicroCache::etag`.
+    etag: Option<String>,
+}
+
+impl CachedResponse {
+    /// Response with code and content, `content_type` is value of "Content-Type" header.
+    pub fn new(code: u16, content_type: &str, content: impl Into<Vec<u8>>) -> Self {
+        CachedResponse {
+            code,
+            content_type: format!("Content-Type: {}\r\n", content_type),
+            headers: String::new(),
+            content: content.into(),
+            etag: None,
+        }
+    }
+
+    /// Response 200 with "text/html; charset=utf-8" content.
+    pub fn html(html: impl Into<String>) -> Self {
+        CachedResponse::new(200, "text/html; charset=utf-8", html.into())
+    }
+
+    /// Response 200 with "text/plain; charset=utf-8" content.
+    pub fn text(text: impl Into<String>) -> Self {
+        CachedResponse::new(200, "text/plain; charset=utf-8", text.into())
+    }
+
+    /// Adds header.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push_str(&format!("{}: {}\r\n", name, value));
+        self
+    }
+
+    /// Sends 304 without content if "If-None-Match" header of request matches ETag, otherwise the response.
+    fn send(&self, request: Request, age: Option<Duration>) {
+        let mut headers = self.headers.clone();
+        if let Some(age) = age {
+            headers.push_str(&format!("Age: {}\r\n", age.as_secs()));
+        }
+
+        if let Some(etag) = &self.etag {
+            headers.push_str(&format!("ETag: {}\r\n", etag));
+            if request.header_value("If-None-Match").is_some_and(|if_none_match| etag_matches(if_none_match, etag)) {
+                request.response(304).headers(&headers).send();
+                return;
+            }
+        }
+
+        request.response(self.code).content(&self.content_type, &self.content).headers(&headers).send();
+    }
+}
+
+struct Entries {
+    map: HashMap<String, Entry>,
+    /// Summary content length.
+    bytes: usize,
+}
+
+impl Entries {
+    /// Removes expired entries, then the oldest until limits are satisfied.
+    fn evict(&mut self, ttl: Duration, max_entries: usize, max_bytes: usize) {
+        let mut bytes = self.bytes;
+        self.map.retain(|_, entry| {
+            let fresh = entry.stored.elapsed() < ttl;
+            if !fresh {
+                bytes -= entry.response.content.len();
+            }
+            fresh
+        });
+        self.bytes = bytes;
+
+        while self.map.len() > max_entries || self.bytes > max_bytes {
+            let oldest = match self.map.iter().min_by_key(|(_, entry)| entry.stored) {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+
+            if let Some(entry) = self.map.remove(&oldest) {
+                self.bytes -= entry.response.content.len();
+            }
+        }
+    }
+}
+
+struct Entry {
+    response: Arc<CachedResponse>,
+    stored: Instant,
+}
@@ -0,0 +1,119 @@
+use crate::request::Request;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+/// Duplication of selected requests (head and content as received) to a sink, for capture of traffic or shadow testing.
+/// The http callback gets requests as usual. Set it in `web_session::Settings::mirror`.
+/// Can be used in multi-threaded environment after clone.
+#[derive(Clone)]
+pub struct Mirror {
+    filter: Arc<FilterFn>,
+    sink: Arc<SinkFn>,
+    /// Number of requests not written by `to_writer`.
+    dropped: Arc<AtomicU64>,
+}
+
+type FilterFn = dyn Fn(&Request) -> bool + Send + Sync;
+type SinkFn = dyn Fn(u64, MirrorEvent) + Send + Sync;
+
+/// Part of mirrored request passed to the sink of `Mirror` with id of connection (`TcpSession::id`).
+/// Events of one connection come in order, events of different connections are interleaved.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MirrorEvent<'a> {
+    /// Head of request as received, with the empty line at the end.
+    Head(&'a [u8]),
+    /// Piece of content as received, chunked content is with its chunk sizes.
+    Content(&'a [u8]),
+    /// The request is received completely.
+    End,
+    /// The connection is closed before end of the request.
+    Aborted,
+}
+
+impl Mirror {
+    /// Mirrors requests for which `filter` returns true to `sink`. The sink is called in the worker,
+    /// so it must not block, for example it can pass data to other thread.
+    pub fn new(filter: impl Fn(&Request) -> bool + Send + Sync + 'static, sink: impl Fn(u64, MirrorEvent) + Send + Sync + 'static) -> Self {
+        Mirror { filter: Arc::new(filter), sink: Arc::new(sink), dropped: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// Mirrors requests for which `filter` returns true to `writer` in other thread. Request is written after its end,
+    /// so requests of different connections aren't mixed up, aborted requests aren't written. For example file
+    /// for capture of traffic or `TcpStream` to other server for shadow testing (the application must read its responses).
+    /// Requests longer than `WRITER_REQUEST_LEN_LIMIT` and requests received while `WRITER_QUEUE_LIMIT` requests
+    /// wait for slow writer are dropped, see `dropped`. Writing stops after first error.
+    pub fn to_writer(filter: impl Fn(&Request) -> bool + Send + Sync + 'static, writer: impl Write + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(WRITER_QUEUE_LIMIT);
+        std::thread::spawn(move || {
+            let mut writer = writer;
+            for request in receiver {
+                if writer.write_all(&request).and_then(|_| writer.flush()).is_err() {
+                    return;
+                }
+            }
+        });
+
+        // requests being received, None if the request is dropped
+        let requests: Mutex<HashMap<u64, Option<Vec<u8>>>> = Mutex::new(HashMap::new());
+        let dropped = Arc::new(AtomicU64::new(0));
+        let sink_dropped = dropped.clone();
+        let mut mirror = Self::new(filter, move |id, event| {
+            let mut requests = match requests.lock() {
+                Ok(requests) => requests,
+                Err(_) => return,
+            };
+
+            match event {
+                MirrorEvent::Head(data) | MirrorEvent::Content(data) => {
+                    let request = requests.entry(id).or_insert_with(|| Some(Vec::new()));
+                    if let Some(buffer) = request {
+                        if buffer.len() + data.len() > WRITER_REQUEST_LEN_LIMIT {
+                            *request = None;
+                            sink_dropped.fetch_add(1, Ordering::SeqCst);
+                        } else {
+                            buffer.extend_from_slice(data);
+                        }
+                    }
+                }
+                MirrorEvent::End => {
+                    if let Some(Some(request)) = requests.remove(&id) {
+                        if sender.try_send(request).is_err() {
+                            sink_dropped.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+                MirrorEvent::Aborted => {
+                    requests.remove(&id);
+                }
+            }
+        });
+        mirror.dropped = dropped;
+        mirror
+    }
+
+    /// Number of requests not written by `to_writer` because of limits, zero for other sinks.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    /// Passes head of request to the sink if the request is selected.
+    pub(crate) fn start(&self, id: u64, request: &Request) -> bool {
+        if !(self.filter)(request) {
+            return false;
+        }
+
+        (self.sink)(id, MirrorEvent::Head(request.raw()));
+        true
+    }
+
+    pub(crate) fn send(&self, id: u64, event: MirrorEvent) {
+        (self.sink)(id, event)
+    }
+}
+
+/// Maximum of requests waiting for writer of `Mirror::to_writer`.
+pub const WRITER_QUEUE_LIMIT: usize = 256;
+/// Maximum of bytes of request (head and content) mirrored by `Mirror::to_writer`.
+pub const WRITER_REQUEST_LEN_LIMIT: usize = 1_048_576;
@@ -1,4 +1,7 @@
 use crate::request::Request;
+use crate::response::ChunkedResponse;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 
 pub struct MultipartParser {
     state: ParseState,
@@ -17,7 +20,12 @@ impl MultipartParser {
         let boundary_index = content_type_val.find("boundary=")
             .ok_or(MultipartError::NoBoundaryInContentTypeHeader)?;
 
-        let boundary = Vec::from(&content_type_val[boundary_index + 9..]);
+        Self::with_boundary(&content_type_val[boundary_index + 9..])
+    }
+
+    /// Returns new multipart parser of content with boundary from "Content-Type" header.
+    pub fn with_boundary(boundary: &str) -> Result<Self, MultipartError> {
+        let boundary = Vec::from(boundary);
         if boundary.is_empty() {
             return Err(MultipartError::EmptyBoundaryInHeader);
         }
@@ -56,6 +64,7 @@ impl MultipartParser {
                             // This is not explicitly defined in the RFC 2046, but browsers send
                             // closing boundary delimiter when multiform not contains parts at all
                             f(MultipartParserEvent::Finished);
+                            self.state = ParseState::Finished;
                             self.buf.clear();
                             break;
                         }
@@ -73,7 +82,8 @@ impl MultipartParser {
                 ParseState::Disposition => {
                     if self.buf.len() > 4 {
                         if let Some(pos) = self.buf.windows(4).position(|win| win == b"\r\n\r\n") {
-                            let left = if &self.buf[0..2] != b"\r\n" { 0 } else { 2 };
+                            // part without headers starts with empty line
+                            let left = if &self.buf[0..2] != b"\r\n" { 0 } else { 2.min(pos) };
                             let raw_disposition = &self.buf[left..pos];
                             f(MultipartParserEvent::Disposition(&Disposition { raw: raw_disposition }));
                             self.buf = Vec::from(&self.buf[pos + 4..]);
@@ -82,6 +92,10 @@ impl MultipartParser {
                         }
                     }
 
+                    if self.buf.len() > DISPOSITION_LEN_LIMIT {
+                        return Err(MultipartError::DispositionLenLimit);
+                    }
+
                     break; // need more data
                 }
                 ParseState::ReadData => {
@@ -95,6 +109,7 @@ impl MultipartParser {
 
                         if closing_boundary {
                             f(MultipartParserEvent::Finished);
+                            self.state = ParseState::Finished;
                             self.buf.clear();
                             break; // Finish
                         }
@@ -103,39 +118,59 @@ impl MultipartParser {
                         continue;
                     }
 
-                    let data_part = &self.buf;
-                    f(MultipartParserEvent::Data { data_part, end: false });
-                    self.buf.clear();
+                    // the end can be beginning of boundary delimiter, it's kept until next data
+                    let kept_len = self.buf.len().min(boundary_detect_len - 1);
+                    let data_len = self.buf.len() - kept_len;
+                    if data_len > 0 {
+                        f(MultipartParserEvent::Data { data_part: &self.buf[..data_len], end: false });
+                        self.buf.drain(..data_len);
+                    }
                     break; // need more data
                 }
+                ParseState::Finished => {
+                    // data after closing boundary is ignored (RFC 2046)
+                    self.buf.clear();
+                    break;
+                }
             }
         }
 
         Ok(())
     }
+
+    /// Length of data kept until next `push`.
+    #[cfg(any(test, feature = "fuzz"))]
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
 }
 
 fn find_boundary(buf: &[u8], boundary: &[u8]) -> Option<(usize, bool/*closing boundary*/)> {
-    if buf.len() >= boundary.len() + 4 {
-        if let Some(pos) = buf.windows(2).position(|win| win == b"--") {
-            let boundary_pos = pos + 2;
-            if buf.len() >= boundary_pos + boundary.len() + 2 {
-                if &buf[boundary_pos..boundary_pos + boundary.len()] == boundary {
-                    if &buf[boundary_pos + boundary.len()..boundary_pos + boundary.len() + 2] == b"\r\n" {
-                        // --BOUNDARY\r\n
-                        return Some((boundary_pos, false));
-                    } else if &buf[boundary_pos + boundary.len()..boundary_pos + boundary.len() + 2] == b"--" {
-                        // --BOUNDARY--
-                        return Some((boundary_pos, true));
-                    }
+    if buf.len() < boundary.len() + 4 {
+        return None;
+    }
+
+    // "--" can be in data before the boundary delimiter
+    for pos in buf.windows(2).enumerate().filter(|(_, win)| *win == b"--").map(|(pos, _)| pos) {
+        let boundary_pos = pos + 2;
+        if buf.len() >= boundary_pos + boundary.len() + 2
+            && &buf[boundary_pos..boundary_pos + boundary.len()] == boundary {
+                if &buf[boundary_pos + boundary.len()..boundary_pos + boundary.len() + 2] == b"\r\n" {
+                    // --BOUNDARY\r\n
+                    return Some((boundary_pos, false));
+                } else if &buf[boundary_pos + boundary.len()..boundary_pos + boundary.len() + 2] == b"--" {
+                    // --BOUNDARY--
+                    return Some((boundary_pos, true));
                 }
             }
-        }
     }
 
     None
 }
 
+/// Limit of length of headers of part.
+pub(crate) const DISPOSITION_LEN_LIMIT: usize = 16384;
+
 /// Disposition of multipart part.
 #[derive(Debug)]
 pub struct Disposition<'a> {
@@ -144,7 +179,7 @@ pub struct Disposition<'a> {
 
 impl<'a> Disposition<'a>  {
     pub fn raw(&self) -> &[u8] {
-        &self.raw
+        self.raw
     }
 }
 
@@ -162,6 +197,7 @@ enum ParseState {
     FindFirstBoundary,
     Disposition,
     ReadData,
+    Finished,
 }
 
 #[derive(Debug)]
@@ -174,11 +210,118 @@ pub enum MultipartError {
     EmptyBoundaryInHeader,
     /// By RFC 2046, boundary must be no longer than 70 characters.
     BoundaryLenLimit { len: usize },
+    /// Headers of part are longer than 16 KB.
+    DispositionLenLimit,
 }
 
 impl std::fmt::Display for MultipartError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 impl std::error::Error for MultipartError {}
+
+/// Builder of multipart content of response, for example "multipart/byteranges" for requests with several ranges
+/// or "multipart/x-mixed-replace" for MJPEG streams. Headers of parts are header lines, each ending with "\r\n".
+/// # Examples
+/// Whole content: `let multipart = MultipartBuilder::new("mixed").part(headers, data); request.response(200).content(&multipart.content_type(), &multipart.build()).send();`
+/// Stream: `let stream = MultipartBuilder::new("x-mixed-replace").start(request, 200); stream.send_part("Content-Type: image/jpeg\r\n", &frame);`
+pub struct MultipartBuilder {
+    /// Subtype of media type "multipart".
+    subtype: String,
+    boundary: String,
+    /// Encoded parts.
+    content: Vec<u8>,
+}
+
+impl MultipartBuilder {
+    /// Builder of "multipart/{subtype}" content with generated boundary.
+    pub fn new(subtype: &str) -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let seed = format!("{:?}{}", SystemTime::now(), COUNTER.fetch_add(1, Ordering::Relaxed));
+        MultipartBuilder::with_boundary(subtype, &format!("anweb{:x}", md5::compute(seed)))
+    }
+
+    /// Builder with given boundary, it must not occur in data of parts.
+    pub fn with_boundary(subtype: &str, boundary: &str) -> Self {
+        MultipartBuilder { subtype: subtype.to_string(), boundary: boundary.to_string(), content: Vec::new() }
+    }
+
+    /// Boundary of parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// "Content-Type" header line of response, for example "Content-Type: multipart/byteranges; boundary=xyz\r\n".
+    pub fn content_type(&self) -> String {
+        format!("Content-Type: multipart/{}; boundary={}\r\n", self.subtype, self.boundary)
+    }
+
+    /// Adds part with header lines and data.
+    pub fn part(mut self, headers: &str, data: &[u8]) -> Self {
+        self.content.extend_from_slice(&self.encode_part(headers, data));
+        self
+    }
+
+    /// Adds part of "multipart/byteranges" with "Content-Type" and "Content-Range" headers, `end` is inclusive.
+    pub fn byte_range(self, content_type: &str, start: u64, end: u64, total_len: u64, data: &[u8]) -> Self {
+        let headers = format!("Content-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n", content_type, start, end, total_len);
+        self.part(&headers, data)
+    }
+
+    /// Returns content with all parts and closing boundary.
+    pub fn build(mut self) -> Vec<u8> {
+        self.content.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        self.content
+    }
+
+    /// Sends head of response with the code and multipart "Content-Type", parts are sent by returned stream.
+    /// Parts added before are sent at once.
+    pub fn start(self, request: Request, code: u16) -> MultipartStream {
+        let content_type = self.content_type();
+        let chunked = request.response(code).content(&content_type, &[]).chunked();
+        self.stream(chunked)
+    }
+
+    /// Stream of parts over chunked response, its "Content-Type" must be `content_type`.
+    pub fn stream(self, chunked: ChunkedResponse) -> MultipartStream {
+        chunked.send(&self.content);
+        MultipartStream { chunked, builder: MultipartBuilder { content: Vec::new(), ..self } }
+    }
+
+    /// Encodes delimiter, header lines, empty line and data of part.
+    fn encode_part(&self, headers: &str, data: &[u8]) -> Vec<u8> {
+        let mut part = Vec::with_capacity(self.boundary.len() + headers.len() + data.len() + 8);
+        part.extend_from_slice(format!("--{}\r\n{}\r\n", self.boundary, headers).as_bytes());
+        part.extend_from_slice(data);
+        part.extend_from_slice(b"\r\n");
+        part
+    }
+}
+
+/// Multipart content sent part by part, see `MultipartBuilder::start`.
+/// Must be ended by `end`, otherwise the client will wait for the rest of content.
+pub struct MultipartStream {
+    chunked: ChunkedResponse,
+    builder: MultipartBuilder,
+}
+
+impl MultipartStream {
+    /// Sends part with header lines and data.
+    pub fn send_part(&self, headers: &str, data: &[u8]) {
+        self.try_send_part(headers, data, |_| {});
+    }
+
+    /// Sends part with header lines and data.
+    /// # Arguments
+    /// * `res_callback` - function that will be called when the write is finished or socket writing error.
+    pub fn try_send_part(&self, headers: &str, data: &[u8], res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) {
+        self.chunked.try_send(&self.builder.encode_part(headers, data), res_callback);
+    }
+
+    /// Sends closing boundary and the end of content.
+    pub fn end(self) {
+        self.chunked.send(format!("--{}--\r\n", self.builder.boundary).as_bytes());
+        self.chunked.end();
+    }
+}
@@ -1,4 +1,5 @@
 use percent_encoding::percent_decode;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
 #[derive(Debug)]
@@ -16,12 +17,12 @@ pub struct QueryNameValue <'a, 'b> {
 }
 
 impl Query<'_, '_> {
-    /// Return first value by name.
+    /// Return first value by name. Decoded, '+' is space.
     pub fn value(&self, name: &str) -> Option<String> {
         for query_part in self.iter() {
             if query_part.name == name.as_bytes() {
-                if let Ok(decoded_value) = percent_decode(query_part.value).decode_utf8() {
-                    return Some(decoded_value.to_string());
+                if let Ok(decoded_value) = decode_query_component(query_part.value) {
+                    return Some(decoded_value);
                 }
             }
         }
@@ -29,16 +30,173 @@ impl Query<'_, '_> {
         None
     }
 
-    /// Return first value by index.
+    /// Return first value by index. Decoded, '+' is space.
     pub fn value_at(&self, index: usize) -> Option<String> {
         if let Some(query_part) = self.parts.get(index) {
-            if let Ok(decoded_value) = percent_decode(query_part.value).decode_utf8() {
-                return Some(decoded_value.replace('+', " "));
+            if let Ok(decoded_value) = decode_query_component(query_part.value) {
+                return Some(decoded_value);
             }
         }
 
         None
     }
+
+    /// Maps parameters into user type with serde, see `urlencoded::from_query`. Requires "urlencoded" feature.
+    #[cfg(feature = "urlencoded")]
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self) -> Result<T, crate::urlencoded::UrlencodedError> {
+        crate::urlencoded::from_query(self)
+    }
+
+    /// Decoded parameters as structured values by names convention. Parameters that can't be decoded are skipped.
+    /// # Examples
+    /// "a=1&a=2&b=3" with `Flat` gives {"a": ["1", "2"], "b": "3"},
+    /// "items[]=1&items[]=2&user[name]=x" with `Brackets` gives {"items": ["1", "2"], "user": {"name": "x"}}.
+    pub fn structured(&self, convention: KeyConvention) -> BTreeMap<String, QueryValue> {
+        let mut root = QueryValue::Map(BTreeMap::new());
+
+        for part in self.iter() {
+            let (name, value) = match (decode_query_component(part.name), decode_query_component(part.value)) {
+                (Ok(name), Ok(value)) => (name, value),
+                _ => continue,
+            };
+
+            match convention {
+                KeyConvention::Flat => {
+                    if let QueryValue::Map(map) = &mut root {
+                        match map.get_mut(&name) {
+                            Some(QueryValue::Array(values)) => values.push(QueryValue::String(value)),
+                            Some(existing) => {
+                                let first = std::mem::replace(existing, QueryValue::Array(Vec::new()));
+                                *existing = QueryValue::Array(vec![first, QueryValue::String(value)]);
+                            }
+                            None => {
+                                map.insert(name, QueryValue::String(value));
+                            }
+                        }
+                    }
+                }
+                KeyConvention::Brackets => match bracket_segments(&name) {
+                    Some(segments) if segments.len() <= MAX_NESTING => insert_nested(&mut root, &segments, value),
+                    Some(_) => {}
+                    None => insert_nested(&mut root, &[name.as_str()], value),
+                },
+            }
+        }
+
+        match root {
+            QueryValue::Map(map) => map,
+            _ => BTreeMap::new(),
+        }
+    }
+}
+
+/// Convention of names of query parameters for `Query::structured`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyConvention {
+    /// Names are used as is, values of repeated names are collected to array.
+    Flat,
+    /// PHP/Rails-style names: "items[]" appends to array, "user[name]" sets key of nested map.
+    /// Repeated name without "[]" takes last value, names that are not well-formed are used as is.
+    Brackets,
+}
+
+/// Structured value of query parameter, see `Query::structured`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryValue {
+    String(String),
+    Array(Vec<QueryValue>),
+    Map(BTreeMap<String, QueryValue>),
+}
+
+impl QueryValue {
+    /// String value, None for array or map.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            QueryValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Items of array, None for string or map.
+    pub fn as_array(&self) -> Option<&[QueryValue]> {
+        match self {
+            QueryValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Value of nested map by key, None for string or array.
+    pub fn get(&self, key: &str) -> Option<&QueryValue> {
+        match self {
+            QueryValue::Map(map) => map.get(key),
+            _ => None,
+        }
+    }
+}
+
+/// Limit of number of segments in bracket name, parameters with deeper names are skipped.
+const MAX_NESTING: usize = 32;
+
+/// Splits "a[b][]" to ["a", "b", ""]. None if name is not well-formed.
+fn bracket_segments(name: &str) -> Option<Vec<&str>> {
+    let open = name.find('[')?;
+    if open == 0 {
+        return None;
+    }
+
+    let mut segments = vec![&name[..open]];
+    let mut rest = &name[open..];
+    while !rest.is_empty() {
+        let close = rest.find(']')?;
+        if !rest.starts_with('[') || rest[1..close].contains('[') {
+            return None;
+        }
+        segments.push(&rest[1..close]);
+        rest = &rest[close + 1..];
+    }
+
+    Some(segments)
+}
+
+/// Puts value by path of segments, empty segment appends to array.
+fn insert_nested(node: &mut QueryValue, segments: &[&str], value: String) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            *node = QueryValue::String(value);
+            return;
+        }
+    };
+
+    if segment.is_empty() {
+        if !matches!(node, QueryValue::Array(_)) {
+            *node = QueryValue::Array(Vec::new());
+        }
+
+        if let QueryValue::Array(values) = node {
+            // "users[][name]=a&users[][age]=1" fill the same item until its key repeats
+            let continues_last = rest.first().is_some_and(|key| !key.is_empty())
+                && values.last().is_some_and(|last| matches!(last, QueryValue::Map(map) if !map.contains_key(rest[0])));
+
+            if !continues_last {
+                values.push(QueryValue::Map(BTreeMap::new()));
+            }
+
+            if let Some(last) = values.last_mut() {
+                insert_nested(last, rest, value);
+            }
+        }
+        return;
+    }
+
+    if !matches!(node, QueryValue::Map(_)) {
+        *node = QueryValue::Map(BTreeMap::new());
+    }
+
+    if let QueryValue::Map(map) = node {
+        let child = map.entry(segment.to_string()).or_insert_with(|| QueryValue::Map(BTreeMap::new()));
+        insert_nested(child, rest, value);
+    }
 }
 
 impl<'a, 'b> std::ops::Deref for Query<'a, 'b> {
@@ -55,8 +213,16 @@ impl std::ops::DerefMut for Query<'_, '_> {
     }
 }
 
+/// Decodes name or value of query or url-encoded form: '+' is space, then percent-decoding.
+pub fn decode_query_component(component: &[u8]) -> Result<String, std::str::Utf8Error> {
+    let component = component.iter().map(|ch| if *ch == b'+' { b' ' } else { *ch }).collect::<Vec<u8>>();
+    percent_decode(&component)
+        .decode_utf8()
+        .map(|decoded| decoded.to_string())
+}
+
 /// Parse raw query. Splits to names and values array.
-pub fn parse_query(query: &[u8]) -> Query {
+pub fn parse_query(query: &[u8]) -> Query<'_, '_> {
     let mut result = Query { parts: Vec::new() };
     let mut token_index = 0;
 
@@ -96,13 +262,13 @@ pub fn parse_query(query: &[u8]) -> Query {
 impl Debug for QueryNameValue<'_, '_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut f = f.debug_struct("QueryNameValue");
-        let f = if let Ok(decoded_name) = percent_decode(&self.name).decode_utf8() {
+        let f = if let Ok(decoded_name) = percent_decode(self.name).decode_utf8() {
             f.field("name", &decoded_name)
         } else {
             f.field("name", &self.name)
         };
 
-        let f = if let Ok(decoded_name) = percent_decode(&self.value).decode_utf8() {
+        let f = if let Ok(decoded_name) = percent_decode(self.value).decode_utf8() {
             f.field("value", &decoded_name)
         } else {
             f.field("value", &self.value)
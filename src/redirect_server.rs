@@ -1,34 +1,187 @@
-use crate::server;
-use crate::worker::Worker;
-use mio::net::TcpListener;
+use crate::acme::ChallengeStore;
+use crate::request::Request;
+use crate::server::{Event, Server, Stopper};
 use std::net::SocketAddr;
-use std::thread::spawn;
-use crate::server::Stopper;
+use std::sync::Arc;
 
 /// Run http server in own thread. Send redirect response to any request.
 pub fn run_redirect_server(path: &'static str, server_addr: SocketAddr, num_thread: usize) -> Result<(), std::io::Error> {
-    let tcp_listener = TcpListener::bind(&server_addr)?;
+    RedirectServer::fixed(path).code(303).num_threads(num_thread).run(&server_addr)?;
+    Ok(())
+}
+
+/// HTTP server that redirects all requests, for example from HTTP to HTTPS.
+/// Runs on `Server` in own thread.
+/// # Example
+/// `RedirectServer::https(443).hsts(31536000).run(&([0, 0, 0, 0], 80).into())?;`
+pub struct RedirectServer {
+    target: Target,
+    code: u16,
+    /// Value of "Strict-Transport-Security" header.
+    hsts: Option<String>,
+    health_check: bool,
+    acme_challenges: Option<ChallengeStore>,
+    num_threads: Option<usize>,
+}
+
+/// Where to redirect.
+enum Target {
+    /// Base URL, path and query of request are appended.
+    Base(String),
+    /// HTTPS on same host as in "Host" header and the port, path and query of request are appended.
+    Https(u16),
+    /// Same location for any request.
+    Fixed(String),
+}
+
+impl RedirectServer {
+    /// Redirects to the base URL like "https://example.com:8443" with path and query of request.
+    pub fn new(base: &str) -> Self {
+        RedirectServer::with_target(Target::Base(base.trim_end_matches('/').to_string()))
+    }
+
+    /// Redirects to HTTPS on the host from "Host" header of request with the port, path and query of request.
+    /// Port is omitted in location if it is 443.
+    pub fn https(port: u16) -> Self {
+        RedirectServer::with_target(Target::Https(port))
+    }
+
+    /// Redirects any request to the same location.
+    pub fn fixed(location: &str) -> Self {
+        RedirectServer::with_target(Target::Fixed(location.to_string()))
+    }
+
+    /// Status code of redirect, 301, 302, 303, 307 or 308. Default 301.
+    pub fn code(mut self, code: u16) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// Add "Strict-Transport-Security" header with max-age in seconds to redirect responses.
+    pub fn hsts(mut self, max_age: u64) -> Self {
+        self.hsts = Some(format!("Strict-Transport-Security: max-age={}\r\n", max_age));
+        self
+    }
+
+    /// Answer "/healthz" and "/readyz" requests instead of redirecting them, see `Server::health_check`.
+    pub fn health_check(mut self) -> Self {
+        self.health_check = true;
+        self
+    }
+
+    /// Answer ACME HTTP-01 challenges from the store instead of redirecting them, so certificates can be issued
+    /// for the HTTPS server without separate web server on port 80.
+    pub fn acme_challenges(mut self, store: ChallengeStore) -> Self {
+        self.acme_challenges = Some(store);
+        self
+    }
+
+    /// Number of worker threads. By default as in `Server`.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = Some(num_threads);
+        self
+    }
 
-    let stopper = Stopper::new();
+    /// Starts listening on address and runs the server in own thread.
+    pub fn run(self, addr: &SocketAddr) -> Result<Stopper, std::io::Error> {
+        Ok(self.run_server(Server::new(addr)?))
+    }
 
-    for _ in 0..num_thread {
-        let cloned_tcp_listener = tcp_listener.try_clone()?;
-        let path = path.to_string();
+    /// Runs the server in own thread. Server can be prepared before, for example with TLS settings.
+    pub fn run_server(self, mut server: Server) -> Stopper {
+        if let Some(num_threads) = self.num_threads {
+            server.num_threads = num_threads;
+        }
+        if self.health_check {
+            server.health_check();
+        }
+        if let Some(acme_challenges) = &self.acme_challenges {
+            server.settings.web_settings.acme_challenges = Some(acme_challenges.clone());
+        }
 
-        let mut server = Worker::new_from_listener(cloned_tcp_listener, stopper.clone())?;
+        let stopper = server.stopper();
+        let redirect = Arc::new(self);
 
-        spawn(move || {
-            server.run(&mut |server_event| {
-                if let server::Event::Incoming(tcp_session) = server_event {
-                    let path = path.clone();
-                    tcp_session.to_http(move |http_request| {
-                        http_request?.response(303).location(&path).close().send();
+        std::thread::spawn(move || {
+            let _ = server.run(move |server_event| {
+                if let Event::Incoming(tcp_session) = server_event {
+                    let redirect = redirect.clone();
+                    tcp_session.to_http(move |request| {
+                        redirect.respond(request?);
                         Ok(())
                     });
                 }
             });
         });
+
+        stopper
     }
 
-    Ok(())
+    fn with_target(target: Target) -> Self {
+        RedirectServer { target, code: 301, hsts: None, health_check: false, acme_challenges: None, num_threads: None }
+    }
+
+    fn respond(&self, request: Request) {
+        let location = match self.location(&request) {
+            Some(location) => location,
+            None => {
+                request.response(400).text("Bad request").close().send();
+                return;
+            }
+        };
+
+        let hsts = self.hsts.as_deref().unwrap_or("");
+        request.response(self.code).location(&location).headers(hsts).close().send();
+    }
+
+    /// Location for request, None if request has no host or path can't be put in header.
+    fn location(&self, request: &Request) -> Option<String> {
+        let path_and_query = || path_and_query(request);
+
+        match &self.target {
+            Target::Fixed(location) => Some(location.clone()),
+            Target::Base(base) => Some(format!("{}{}", base, path_and_query()?)),
+            Target::Https(port) => {
+                let host = host_without_port(request.header_value("Host")?)?;
+                match port {
+                    443 => Some(format!("https://{}{}", host, path_and_query()?)),
+                    port => Some(format!("https://{}:{}{}", host, port, path_and_query()?)),
+                }
+            }
+        }
+    }
+}
+
+/// Raw path and query of request for location of redirect. None if they have chars that can't be put in header.
+pub(crate) fn path_and_query(request: &Request) -> Option<String> {
+    let raw_path = request.raw_path();
+    let raw_query = request.raw_query();
+    let printable = |raw: &[u8]| raw.iter().all(|ch| ch.is_ascii_graphic());
+    if !printable(raw_path) || !printable(raw_query) {
+        return None;
+    }
+
+    let mut path_and_query = String::from_utf8_lossy(raw_path).to_string();
+    if !raw_query.is_empty() {
+        path_and_query.push('?');
+        path_and_query.push_str(&String::from_utf8_lossy(raw_query));
+    }
+    Some(path_and_query)
+}
+
+/// Host from "Host" header value without port. None if it's empty or has wrong chars.
+pub(crate) fn host_without_port(host: &str) -> Option<&str> {
+    let host = if host.starts_with('[') {
+        // IPv6
+        &host[..=host.find(']')?]
+    } else {
+        host.split(':').next()?
+    };
+
+    let valid = !host.is_empty() && host.bytes().all(|ch| ch.is_ascii_alphanumeric() || b".-[]:".contains(&ch));
+    if valid {
+        Some(host)
+    } else {
+        None
+    }
 }
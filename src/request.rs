@@ -1,15 +1,58 @@
 use crate::cookie::{parse_cookie, CookieOfRequst};
-use crate::query::{parse_query, Query};
+use crate::query::{decode_query_component, parse_query, Query};
 use std::str::from_utf8;
-use crate::tcp_session::{ContentIsComplite, TcpSession};
-use crate::websocket::{Websocket, WebsocketHandshakeError, frame};
+use crate::tcp_session::{ContentIsComplite, SendStatus, TcpSession};
+use crate::websocket::{HandshakePending, Websocket, WebsocketHandshakeError, frame};
 use crate::websocket;
-use crate::response::Response;
+use crate::response::{http_status_code_with_name, need_close_by_request, PreparedResponse, Response};
+use crate::blocking_pool::BlockingPool;
+use crate::concurrency::Permit;
+use crate::content::{Content, ContentDecoder, ContentVerifier};
+use crate::log::{log, Level};
+use crate::request_parser::decode_path;
+use crate::handler_error::HandlerError;
+use crate::redirect_server::{host_without_port, path_and_query};
+use crate::timing::{RequestTimes, Timing, TimingCallback};
+use crate::transform::TransformFactory;
+use std::sync::{Arc, OnceLock};
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::{Duration, Instant, SystemTime};
+use std::sync::atomic::Ordering;
+use percent_encoding::percent_decode;
 
 /// Received request.
 pub struct Request {
     request_data: RequestData,
     tcp_session: TcpSession,
+    /// Places of in-flight request in concurrency limits, released when request is dropped.
+    pub(crate) concurrency_permits: Vec<Permit>,
+    /// Number of request in tcp session.
+    pub(crate) id: u64,
+    /// Headers that are added to responses unless overridden, from server settings.
+    default_response_headers: Arc<Vec<(String, String)>>,
+    /// Limit of length of decompressed content, see `Settings::content_decompression_limit`.
+    decompression_limit: Option<usize>,
+    /// See `Settings::verify_content_digest`.
+    verify_content_digest: bool,
+    /// Moments of receiving of request.
+    pub(crate) times: RequestTimes,
+    /// See `Settings::on_timing`.
+    pub(crate) on_timing: Option<TimingCallback>,
+    /// See `Settings::response_transforms`.
+    pub(crate) response_transforms: Arc<Vec<TransformFactory>>,
+    /// See `Settings::trust_forwarded_proto`.
+    trust_forwarded_proto: bool,
+    /// See `Settings::blocking_pool`.
+    blocking_pool: Option<BlockingPool>,
+    /// Counts the request as not finished in its tcp session.
+    _in_flight: InFlight,
+    /// Path and query received from the client and number of internal forwards, see `forward_to`.
+    forwarded: Option<(String, usize)>,
+    /// Interim "100 Continue" response is sent, see `expects_continue`.
+    continue_sent: bool,
+    /// Content received before the handler, passed to `read_content` at once, see `ConcurrencyLimit::run`.
+    pub(crate) received_content: Option<Vec<u8>>,
 }
 
 impl Request {
@@ -24,10 +67,20 @@ impl Request {
     }
 
     /// The parsed query to names and values array.
-    pub fn query(&self) -> Query {
+    pub fn query(&self) -> Query<'_, '_> {
         self.request_data.query()
     }
 
+    /// Decoded names and values of query, parsed once and cached.
+    pub fn query_params(&self) -> &[(String, String)] {
+        self.request_data.query_params()
+    }
+
+    /// First decoded value of query parameter by name, without repeated parsing and decoding.
+    pub fn query_value(&self, name: &str) -> Option<&str> {
+        self.request_data.query_value(name)
+    }
+
     /// Header value by name.
     pub fn header_value(&self, name: &str) -> Option<&str> {
         self.request_data.header_value(name)
@@ -37,22 +90,77 @@ impl Request {
     pub fn version(&self) -> &HttpVersion {
         self.request_data.version()
     }
+
+    /// Number of "Host" headers, see `RequestData::host_validation`.
+    pub fn host_validation(&self) -> HostValidation {
+        self.request_data.host_validation()
+    }
+
+    /// "Host" headers are valid for version of request, see `RequestData::has_valid_host`.
+    pub fn has_valid_host(&self) -> bool {
+        self.request_data.has_valid_host()
+    }
+
+    /// Counters of request parser of the connection including this request, see `RequestData::parser_stats`.
+    pub fn parser_stats(&self) -> ParserStats {
+        self.request_data.parser_stats()
+    }
     /// Headers.
     pub fn headers(&self) -> &Vec<Header> {
-        &self.request_data.headers()
+        self.request_data.headers()
     }
 
     /// Value of header "Connection: keep-alive/close", if no header then None
     pub fn connection_type(&self) -> &Option<ConnectionType> {
-        &self.request_data.connection_type()
+        self.request_data.connection_type()
+    }
+
+    /// Lowercase tokens of all "Connection" headers, for example ["keep-alive", "upgrade"] for "Connection: Keep-Alive, Upgrade".
+    pub fn connection_options(&self) -> &[String] {
+        self.request_data.connection_options()
+    }
+
+    /// "Connection" header has the option, case-insensitive.
+    pub fn has_connection_option(&self, option: &str) -> bool {
+        self.request_data.has_connection_option(option)
     }
     /// Value of header "Content-length", if no header then None.
     pub fn content_len(&self) -> usize {
         self.request_data.content_len()
     }
 
+    /// Content is sent with "Transfer-Encoding: chunked", its length is unknown until it's received.
+    pub fn is_chunked(&self) -> bool {
+        self.request_data.is_chunked()
+    }
+
+    /// Request has content, chunked or with "Content-Length" more than 0.
+    pub fn has_content(&self) -> bool {
+        self.request_data.has_content()
+    }
+
+    /// Trailer fields received after chunked content. Available in the request passed to `read_content` callback
+    /// when content is complete.
+    pub fn trailers(&self) -> &[Header] {
+        self.request_data.trailers()
+    }
+
+    /// Trailer field value by name, see `trailers`.
+    pub fn trailer_value(&self, name: &str) -> Option<&str> {
+        self.request_data.trailer_value(name)
+    }
+
+    /// The client accepts trailers in chunked response, "TE" header has "trailers". See `ChunkedResponse::end_with_trailers`.
+    pub fn accepts_trailers(&self) -> bool {
+        self.request_data.accepts_trailers()
+    }
+
+    pub(crate) fn set_trailers(&mut self, trailers: Vec<Header>) {
+        self.request_data.trailers = trailers;
+    }
+
     /// Cookies FROM FIRST HEADER "Cookie". RFC 6265, 5.4. "The Cookie Header: When the user agent generates an HTTP request, the user agent MUST NOT attach more than one Cookie header field".
-    pub fn cookies(&self) -> Vec<CookieOfRequst> {
+    pub fn cookies(&self) -> Vec<CookieOfRequst<'_>> {
         self.request_data.cookies()
     }
 
@@ -72,24 +180,171 @@ impl Request {
         Response::new(code, self)
     }
 
+    /// Sends "103 Early Hints" informational response with "Link" headers before the final response,
+    /// for example `request.early_hints(&["</style.css>; rel=preload; as=style"])`. Not sent for HTTP/1.0 requests because HTTP/1.0 has no informational responses.
+    pub fn early_hints(&self, links: &[&str]) {
+        if *self.version() != HttpVersion::Http1_1 || links.is_empty() {
+            return;
+        }
+
+        let mut response = "HTTP/1.1 103 Early Hints\r\n".to_string();
+        for link in links {
+            response.push_str("Link: ");
+            response.push_str(link);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+
+        self.tcp_session.send(response.as_bytes());
+    }
+
     /// Read raw http content (this is what is after headers).
+    /// Must be called before return from the http callback, otherwise content is discarded or the connection is closed
+    /// after the response, see `Settings::discard_unread_content_limit`.
+    /// If `Settings::content_decompression_limit` is set, content with "Content-Encoding" gzip or deflate is decompressed.
+    /// If `Settings::verify_content_digest` is set and the digest of content doesn't match "Content-MD5", "Digest" or
+    /// "Content-Digest" header, the last part is not passed, "400 Bad Request" is sent and the connection is closed.
+    /// Chunked content is passed decoded, trailers are in the request passed when content is complete.
     pub fn read_content(self, mut callback: impl FnMut(&[u8], ContentIsComplite) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) {
+        let mut verifier = match self.verify_content_digest {
+            true => ContentVerifier::new(self.header_value("Content-MD5"), self.header_value("Digest"), self.header_value("Content-Digest")),
+            false => None,
+        };
+
+        let mut decoder = match (self.decompression_limit, self.content_encoding()) {
+            (Some(limit), Some(encoding)) if self.has_content() => ContentDecoder::new(encoding, limit),
+            _ => None,
+        };
+
+        if verifier.is_none() && decoder.is_none() {
+            return self.read_raw_content(callback);
+        }
+
+        self.read_raw_content(move |data, complete| {
+            if let Some(verifier) = &mut verifier {
+                verifier.push(data);
+                if complete.is_some() {
+                    verifier.verify()?;
+                }
+            }
+
+            match &mut decoder {
+                Some(decoder) => {
+                    let mut decoded = decoder.push(data)?;
+                    if complete.is_some() {
+                        decoded.extend_from_slice(&decoder.finish()?);
+                    }
+
+                    callback(&decoded, complete)
+                }
+                None => callback(data, complete),
+            }
+        });
+    }
+
+    /// Sends response serialized once, see `response::PreparedResponse`.
+    pub fn send_prepared(self, prepared: &PreparedResponse) -> SendStatus {
+        let data = prepared.bytes_for(&self);
+        if need_close_by_request(self.request_data()) {
+            self.tcp_session.close_after_send();
+        }
+
+        self.tcp_session.send(&data)
+    }
+
+    /// The client sent "Expect: 100-continue" and waits for interim response before sending of content (RFC 7231, 5.1.1).
+    /// Interim "100 Continue" is sent when the handler starts reading of content, so a handler that responds without
+    /// reading, for example "401 Unauthorized" or "413 Payload Too Large", rejects the upload before it's transmitted.
+    /// See also `Route::expect_continue`. Always false for HTTP/1.0.
+    pub fn expects_continue(&self) -> bool {
+        self.request_data.expects_continue()
+    }
+
+    /// Sends interim "100 Continue" response once if the client waits for it.
+    pub(crate) fn send_continue_if_expected(&mut self) {
+        if self.expects_continue() && !self.continue_sent {
+            self.continue_sent = true;
+            self.tcp_session.send(b"HTTP/1.1 100 Continue\r\n\r\n");
+        }
+    }
+
+    /// Value of "Content-Encoding" header, original encoding of content even if it is decompressed by `read_content`.
+    pub fn content_encoding(&self) -> Option<&str> {
+        self.header_value("Content-Encoding")
+    }
+
+    /// Read content as is, without decompression.
+    pub(crate) fn read_raw_content(mut self, mut callback: impl FnMut(&[u8], ContentIsComplite) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) {
         let tcp_session = self.tcp_session.clone();
 
-        if self.content_len() == 0 {
-            if callback(&[], Some(self)).is_err() {
-                tcp_session.close();
+        if let Some(content) = self.received_content.take() {
+            if let Err(err) = callback(&content, Some(self)) {
+                tcp_session.close_by_handler_error(err.as_ref());
+            }
+            return;
+        }
+
+        if !self.has_content() {
+            if let Err(err) = callback(&[], Some(self)) {
+                tcp_session.close_by_handler_error(err.as_ref());
             }
             return;
         }
 
+        self.send_continue_if_expected();
+
         if let Ok(mut content_callback) = tcp_session.inner.content_callback.lock() {
             *content_callback = Some((Box::new(callback), Some(self)));
         }
         drop(tcp_session);
     }
 
-    /// Read content and parse it as form.
+    /// Read content keeping it in memory up to `memory_limit` bytes, bigger content is written to temporary file.
+    /// Protects memory on endpoints that accept big uploads. The file is removed when `Content` is dropped unless it is persisted.
+    /// Multipart content can be parsed from `Content::reader`.
+    pub fn read_content_spilled(self, memory_limit: usize, mut callback: impl FnMut(Content, Request) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) {
+        let content_len = self.content_len();
+        let mut content = None;
+        self.read_content(move |data, complete| {
+            let received = match content.as_mut() {
+                Some(received) => received,
+                None => content.insert(Content::new(content_len, memory_limit)?),
+            };
+
+            received.push(data, memory_limit)?;
+            if let Some(request) = complete {
+                if let Some(received) = content.take() {
+                    return callback(received, request);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Writes content to `writer`, for example file, hasher or pipe, then flushes it and passes it with the request
+    /// to `on_complete`. If writing fails, the client gets "500 Internal Server Error" and the connection is closed.
+    pub fn pipe_content_to<W: Write + Send + 'static>(self, writer: W, on_complete: impl FnOnce(W, Request) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) {
+        let mut writer_and_callback = Some((writer, on_complete));
+        self.read_content(move |data, complete| {
+            let writer = match writer_and_callback.as_mut() {
+                Some((writer, _)) => writer,
+                None => return Ok(()),
+            };
+
+            writer.write_all(data).map_err(HandlerError::internal)?;
+            if let Some(request) = complete {
+                writer.flush().map_err(HandlerError::internal)?;
+                if let Some((writer, on_complete)) = writer_and_callback.take() {
+                    return on_complete(writer, request);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Read content and parse it as form. Form is accumulated in memory, use `read_content_spilled` for big uploads.
     pub fn form(self, mut callback: impl FnMut(&Query, Request) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) {
         if self.has_post_form() {
             let mut content = vec![];
@@ -106,6 +361,13 @@ impl Request {
         }
     }
 
+    /// Same as `form` but maps the form into user type with serde, see `urlencoded::from_query`.
+    /// Requires "urlencoded" feature.
+    #[cfg(feature = "urlencoded")]
+    pub fn form_into<T: serde::de::DeserializeOwned>(self, mut callback: impl FnMut(Result<T, crate::urlencoded::UrlencodedError>, Request) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) {
+        self.form(move |form, request| callback(form.deserialize(), request))
+    }
+
     /// Begin work with websocket.
     /// Makes handshake response to upgrade websocket request from browser.
     /// Returns object for work with websocket or error if no "Sec-WebSocket-Key" header in request.
@@ -129,32 +391,76 @@ impl Request {
 
         let accept = websocket::accept_key(key)?;
 
-        let protocol = if let Some(protocol) = self.header_value("Sec-WebSocket-Protocol") {
-            format!("Sec-WebSocket-Protocol: {}\r\n", &protocol)
+        let protocol = self.header_value("Sec-WebSocket-Protocol");
+        let mut response = websocket::handshake_response(&accept, protocol, &self.rfc7231_date_string());
+
+        for (opcode, payload) in extra_frames {
+            response.extend_from_slice(&frame(*opcode, payload));
+        }
+
+        self.tcp_session.send(&response);
+
+        Ok(Websocket::new(self.tcp_session.clone()))
+    }
+
+    /// Begin work with websocket after decision of the handler that can be made later and in other thread,
+    /// see `HandshakePending`. Returns error if no "Sec-WebSocket-Key" header in request, does not make response in this case.
+    pub fn accept_websocket_deferred(self) -> Result<HandshakePending, WebsocketHandshakeError> {
+        let key = self.header_value("Sec-WebSocket-Key")
+            .ok_or(WebsocketHandshakeError::NoSecWebSocketKeyHeader)?;
+
+        let accept = websocket::accept_key(key)?;
+        let protocols = self.header_value("Sec-WebSocket-Protocol")
+            .map(|protocols| protocols.split(',').map(|protocol| protocol.trim().to_string()).filter(|protocol| !protocol.is_empty()).collect())
+            .unwrap_or_default();
+
+        Ok(HandshakePending::new(self.tcp_session.clone(), accept, protocols))
+    }
+
+    /// Switches the connection to other protocol by "Upgrade" header (RFC 7230, 6.7), for example MQTT over HTTP upgrade.
+    /// Checks that "Upgrade" header of request has the protocol and "Connection" header has "upgrade", sends "101 Switching Protocols"
+    /// and switches the connection to raw mode with the callback, see `TcpSession::into_raw_mode`.
+    /// If the check fails, sends "426 Upgrade Required" and closes the connection.
+    /// # Arguments
+    /// * `protocol` - protocol name, matches "name" and "name/version" in request.
+    /// * `headers` - extra header lines of 101 response, each ends with "\r\n".
+    pub fn upgrade(self, protocol: &str, headers: &str, callback: impl FnMut(&[u8], &TcpSession) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) -> Result<TcpSession, UpgradeError> {
+        let has_token = |value: Option<&str>, matches: &dyn Fn(&str) -> bool| {
+            value.is_some_and(|value| value.split(',').map(str::trim).any(matches))
+        };
+
+        let result = if self.header_value("Upgrade").is_none() {
+            Err(UpgradeError::NoUpgradeHeader)
+        } else if !has_token(self.header_value("Upgrade"), &|token| token.eq_ignore_ascii_case(protocol)
+            || token.split('/').next().is_some_and(|name| name.eq_ignore_ascii_case(protocol))) {
+            Err(UpgradeError::UnsupportedProtocol)
+        } else if !self.has_connection_option("upgrade") {
+            Err(UpgradeError::NoConnectionUpgrade)
         } else {
-            String::new()
+            Ok(())
         };
 
-        let mut response =  Vec::from(format!(
+        if let Err(err) = result {
+            let upgrade_headers = format!("Upgrade: {}\r\nConnection: Upgrade\r\n", protocol);
+            self.response(426).headers(&upgrade_headers).text("Upgrade Required").close().send();
+            return Err(err);
+        }
+
+        let response = format!(
             "HTTP/1.1 101 Switching Protocols\r\n\
-            Upgrade: websocket\r\n\
+            Upgrade: {}\r\n\
             Connection: Upgrade\r\n\
-            Sec-WebSocket-Accept: {}\r\n\
             {}\
             Date: {}\r\n\
             \r\n",
-            &accept,
-            &protocol,
+            protocol,
+            headers,
             self.rfc7231_date_string(),
-        ));
+        );
 
-        for (opcode, payload) in extra_frames {
-            response.extend_from_slice(&frame(*opcode, payload));
-        }
-
-        self.tcp_session.send(&response);
-
-        Ok(Websocket::new(self.tcp_session.clone()))
+        self.tcp_session.send(response.as_bytes());
+        self.tcp_session.clone().into_raw_mode(callback);
+        Ok(self.tcp_session.clone())
     }
 
     /// Raw buffer of request.
@@ -162,11 +468,41 @@ impl Request {
         self.request_data.raw()
     }
 
+    /// Request line as received, see `RequestData::raw_request_line`.
+    pub fn raw_request_line(&self) -> &[u8] {
+        self.request_data.raw_request_line()
+    }
+
+    /// Header lines as received, see `RequestData::raw_headers`.
+    pub fn raw_headers(&self) -> &[u8] {
+        self.request_data.raw_headers()
+    }
+
     /// Path as raw bytes in request buffer.
     pub fn raw_path(&self) -> &[u8] {
         self.request_data.raw_path()
     }
 
+    /// Not empty segments of path between '/' as raw bytes in request buffer.
+    pub fn raw_path_segments(&self) -> impl Iterator<Item = &[u8]> {
+        self.request_data.raw_path_segments()
+    }
+
+    /// Not empty decoded segments of path. Encoded slash "%2F" doesn't split segment.
+    pub fn path_segments(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.request_data.path_segments()
+    }
+
+    /// Returns true if decoded segments of path start with the prefix segments, like `&["api", "v1"]`. Empty and duplicate separators are ignored.
+    pub fn starts_with_segments(&self, prefix: &[&str]) -> bool {
+        self.request_data.starts_with_segments(prefix)
+    }
+
+    /// Decoded segments of path after the prefix segments. None if path doesn't start with the prefix.
+    pub fn tail_after(&self, prefix: &[&str]) -> Option<Vec<Cow<'_, str>>> {
+        self.request_data.tail_after(prefix)
+    }
+
     /// Method as raw bytes in request buffer.
     pub fn raw_method(&self) -> &[u8] {
         self.request_data.raw_method()
@@ -177,6 +513,60 @@ impl Request {
         self.request_data.raw_query()
     }
 
+    /// Scheme of the request. It's scheme of the connection or, if `Settings::trust_forwarded_proto` is set,
+    /// the scheme from "Forwarded" or "X-Forwarded-Proto" header of reverse proxy.
+    pub fn scheme(&self) -> Scheme {
+        match self.request_data.forwarded_proto() {
+            Some(scheme) if self.trust_forwarded_proto => scheme,
+            _ => if self.is_tls() { Scheme::Https } else { Scheme::Http },
+        }
+    }
+
+    /// The request was received on TLS connection. Forwarding headers are not considered, see `scheme`.
+    pub fn is_tls(&self) -> bool {
+        self.tcp_session.is_tls()
+    }
+
+    /// Absolute URL "scheme://host/path?query" from `scheme`, "Host" header and target received from the client
+    /// (original target if the request was forwarded by `forward_to`), for example for redirects or links in emails.
+    /// None if "Host" header is missing or invalid, or target has non-printable characters.
+    pub fn url(&self) -> Option<String> {
+        let host = self.header_value("Host")?.trim();
+        let host_name = host_without_port(host)?;
+        let port = &host[host_name.len()..];
+        if !port.is_empty() && !port.strip_prefix(':').is_some_and(|port| !port.is_empty() && port.bytes().all(|ch| ch.is_ascii_digit())) {
+            return None;
+        }
+
+        let target = match self.original_target() {
+            Some(original_target) => original_target.to_string(),
+            None => path_and_query(self)?,
+        };
+        if !target.bytes().all(|ch| ch.is_ascii_graphic()) {
+            return None;
+        }
+
+        Some(format!("{}://{}{}", self.scheme().as_str(), host, target))
+    }
+
+    /// Runs `f` with the request in `Settings::blocking_pool` for long or blocking work, in the worker if the pool isn't set.
+    /// Next pipelined requests of the connection are parsed after `f` returns, so responses keep order of requests if `f` responds.
+    /// Error of `f` closes the connection as error of the http callback, panic of `f` is answered with "500 Internal Server Error".
+    /// Content must be read before, for example in the callback of `read_content`.
+    /// # Example
+    /// `request.spawn_blocking(|request| { let report = build_report(); request.response(200).text(&report).send(); Ok(()) })`
+    pub fn spawn_blocking(self, f: impl FnOnce(Request) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) {
+        match self.blocking_pool.clone() {
+            Some(blocking_pool) => blocking_pool.spawn(self, f),
+            None => {
+                let tcp_session = self.tcp_session.clone();
+                if let Err(err) = f(self) {
+                    tcp_session.close_by_handler_error(err.as_ref());
+                }
+            }
+        }
+    }
+
     pub fn tcp_session(&self) -> &TcpSession {
         &self.tcp_session
     }
@@ -186,13 +576,140 @@ impl Request {
         if let Ok(http_date_string) = self.tcp_session.inner.http_date_string.read() {
             http_date_string.clone()
         } else {
-            // this code must be unreachable
+            log(Level::Error, format_args!("lock of http date string is poisoned"));
             String::new()
         }
     }
 
-    pub(crate) fn new(request_data: RequestData, tcp_session: TcpSession,) -> Self {
-        Self { request_data, tcp_session }
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(request_data: RequestData, tcp_session: TcpSession, id: u64, default_response_headers: Arc<Vec<(String, String)>>, decompression_limit: Option<usize>, verify_content_digest: bool, times: RequestTimes, on_timing: Option<TimingCallback>, response_transforms: Arc<Vec<TransformFactory>>, trust_forwarded_proto: bool, blocking_pool: Option<BlockingPool>) -> Self {
+        let _in_flight = InFlight::new(tcp_session.clone());
+        Self { request_data, tcp_session, concurrency_permits: Vec::new(), id, default_response_headers, decompression_limit, verify_content_digest, times, on_timing, response_transforms, trust_forwarded_proto, blocking_pool, _in_flight, forwarded: None, continue_sent: false, received_content: None }
+    }
+
+    /// Re-targets the request to other path and query for dispatching in-process, like internal redirect of nginx.
+    /// Method, headers and not yet read content are kept, original path and query are available by `original_target`.
+    /// # Errors
+    /// "500 Internal Server Error" if target doesn't start with '/' or has whitespace or control characters,
+    /// "508 Loop Detected" if the request was forwarded more than 10 times.
+    /// # Example
+    /// `router.dispatch(request.forward_to("/protected/report.pdf")?)`
+    pub fn forward_to(mut self, target: &str) -> Result<Request, HandlerError> {
+        if !target.starts_with('/') || target.bytes().any(|ch| ch.is_ascii_whitespace() || ch.is_ascii_control()) {
+            return Err(HandlerError::new(500, http_status_code_with_name(500)));
+        }
+
+        let forwards = self.forwarded.as_ref().map_or(0, |(_, forwards)| *forwards);
+        if forwards >= MAX_FORWARDS {
+            return Err(HandlerError::new(508, http_status_code_with_name(508)));
+        }
+
+        let original_target = match self.forwarded.take() {
+            Some((original_target, _)) => original_target,
+            None => String::from_utf8_lossy(self.raw_target()).to_string(),
+        };
+
+        self.request_data.set_target(target);
+        self.forwarded = Some((original_target, forwards + 1));
+        Ok(self)
+    }
+
+    /// Path and query received from the client if the request was forwarded by `forward_to`.
+    pub fn original_target(&self) -> Option<&str> {
+        self.forwarded.as_ref().map(|(original_target, _)| original_target.as_str())
+    }
+
+    /// Path with query as raw bytes in request buffer.
+    fn raw_target(&self) -> &[u8] {
+        let data = &self.request_data;
+        let end = if data.raw_query_indices.1 > 0 { data.raw_query_indices.1 } else { data.path_indices.1 };
+        data.raw.get(data.path_indices.0..end).unwrap_or(&[])
+    }
+
+    /// Wall clock time of receiving of the first byte of request.
+    pub fn received_at(&self) -> SystemTime {
+        self.times.received_at
+    }
+
+    /// Timing of request with response that starts sending right now, `send` is zero.
+    pub(crate) fn timing(&self, code: u16, content_len: usize) -> Timing {
+        Timing {
+            session_id: self.tcp_session.id(),
+            request_id: self.id,
+            connection_age: self.connection_age(),
+            method: self.method().to_string(),
+            path: self.path().to_string(),
+            code,
+            content_len,
+            received_at: self.times.received_at,
+            time_to_first_byte: self.times.first_byte.saturating_duration_since(self.times.idle_since),
+            header_parse: self.times.head_parsed.saturating_duration_since(self.times.first_byte),
+            handler: self.times.head_parsed.elapsed(),
+            send: Duration::ZERO,
+        }
+    }
+
+    /// Sequence number of request in tcp session, starting from 1.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Number of requests received on the connection including this one, same as `id`.
+    /// For example the handler can respond with "Connection: close" after some number of requests.
+    pub fn requests_on_connection(&self) -> u64 {
+        self.id
+    }
+
+    /// Connection is kept alive after previous request and reused for this one.
+    pub fn is_reused_connection(&self) -> bool {
+        self.id > 1
+    }
+
+    /// Time since accepting of the connection.
+    pub fn connection_age(&self) -> Duration {
+        self.tcp_session.age()
+    }
+
+    /// See `Settings::content_decompression_limit`.
+    pub(crate) fn decompression_limit(&self) -> Option<usize> {
+        self.decompression_limit
+    }
+
+    /// Headers that are added to responses unless overridden, see `Settings::default_response_headers`.
+    pub(crate) fn default_response_headers(&self) -> &[(String, String)] {
+        &self.default_response_headers
+    }
+
+    /// Adds header to default response headers of this request, for example CORS headers set by router.
+    pub(crate) fn add_default_response_header(&mut self, name: &str, value: &str) {
+        Arc::make_mut(&mut self.default_response_headers).push((name.to_string(), value.to_string()));
+    }
+}
+
+/// Limit of number of internal forwards of request, see `Request::forward_to`.
+const MAX_FORWARDS: usize = 10;
+
+/// Counts request in `requests_in_flight` of tcp session while it lives, connection isn't idle until then.
+struct InFlight(TcpSession);
+
+impl InFlight {
+    fn new(tcp_session: TcpSession) -> Self {
+        tcp_session.inner.requests_in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(tcp_session)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut last_activity) = self.0.inner.last_activity.lock() {
+            *last_activity = Instant::now();
+        }
+        let in_flight = self.0.inner.requests_in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        // `close_when_sent` waits for the last request
+        if in_flight == 1 && self.0.inner.need_close_after_sending.load(Ordering::SeqCst) {
+            self.0.wake();
+        }
     }
 }
 
@@ -223,6 +740,25 @@ pub enum ConnectionType {
     Close,
 }
 
+/// Scheme of connection on which request was received.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Scheme {
+    /// Plain HTTP.
+    Http,
+    /// HTTP over TLS.
+    Https,
+}
+
+impl Scheme {
+    /// "http" or "https".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 /// Supported http protocol versions.
 pub enum HttpVersion {
@@ -240,6 +776,7 @@ pub enum RequestError {
     MethodLenLimit,
     PathLenLimit,
     QueryLenLimit,
+    UriLenLimit,
     WrongVersion,
     UnsupportedProtocol,
     WrongHeader,
@@ -251,8 +788,107 @@ pub enum RequestError {
     PipeliningRequestsLimit,
     ContentLengthLimit,
     ContentLengthParseError,
+    /// Request has both "Transfer-Encoding" and "Content-Length" headers.
+    ContentLengthWithTransferEncoding,
+    /// Transfer coding other than "chunked".
+    UnsupportedTransferEncoding,
+    /// Invalid chunk of content with "Transfer-Encoding: chunked".
+    WrongChunk,
+    TrailersLenLimit,
+    /// All header lines are longer than `ParseHttpRequestSettings::headers_len_limit`.
+    HeadersLenLimit,
+    /// HTTP/1.1 request without "Host" header, see `ParseHttpRequestSettings::reject_invalid_host`.
+    MissingHost,
+    /// Request with several "Host" headers, see `ParseHttpRequestSettings::reject_invalid_host`.
+    DuplicateHost,
+}
+
+impl RequestError {
+    /// HTTP status code of response to the client for the error. None if the connection is closed without response.
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            RequestError::Partial | RequestError::PipeliningRequestsLimit => None,
+            RequestError::PathLenLimit | RequestError::QueryLenLimit | RequestError::UriLenLimit => Some(414),
+            RequestError::HeadersCountLimit
+            | RequestError::HeaderNameLenLimit
+            | RequestError::HeaderValueLenLimit
+            | RequestError::HeadersLenLimit
+            | RequestError::TrailersLenLimit => Some(431),
+            RequestError::MethodLenLimit | RequestError::UnsupportedTransferEncoding => Some(501),
+            RequestError::UnsupportedProtocol => Some(505),
+            RequestError::ContentLengthLimit => Some(413),
+            RequestError::RequestLine
+            | RequestError::WrongVersion
+            | RequestError::VersionLenLimit
+            | RequestError::WrongHeader
+            | RequestError::EmptyHeaderName
+            | RequestError::ContentLengthParseError
+            | RequestError::ContentLengthWithTransferEncoding
+            | RequestError::WrongChunk
+            | RequestError::MissingHost
+            | RequestError::DuplicateHost => Some(400),
+        }
+    }
+
+    /// The error is in one line of request head and doesn't make the end of request unknown, so the connection can be kept
+    /// after skipping of the rest of the head, see `web_session::Settings::recover_from_parse_errors`.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self,
+            RequestError::MethodLenLimit
+            | RequestError::PathLenLimit
+            | RequestError::QueryLenLimit
+            | RequestError::UriLenLimit
+            | RequestError::HeadersCountLimit
+            | RequestError::HeaderNameLenLimit
+            | RequestError::HeaderValueLenLimit
+            | RequestError::HeadersLenLimit
+            | RequestError::WrongHeader
+            | RequestError::EmptyHeaderName
+        )
+    }
+}
+
+/// Counters of request parser of connection, see `Request::parser_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParserStats {
+    /// Bytes of heads of parsed requests, content is not counted.
+    pub bytes_consumed: u64,
+    /// Headers of parsed requests.
+    pub headers_parsed: u64,
+    /// Parsed requests.
+    pub requests_parsed: u64,
+}
+
+/// Number of "Host" headers of request, see `RequestData::host_validation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostValidation {
+    /// One "Host" header.
+    Single,
+    /// No "Host" header, valid only for HTTP/1.0.
+    Missing,
+    /// Several "Host" headers, invalid for any version.
+    Duplicate,
+}
+
+/// Error of `Request::upgrade`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpgradeError {
+    /// Request has no "Upgrade" header.
+    NoUpgradeHeader,
+    /// "Upgrade" header has no required protocol.
+    UnsupportedProtocol,
+    /// "Connection" header has no "upgrade".
+    NoConnectionUpgrade,
+}
+
+impl std::fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
+impl std::error::Error for UpgradeError {}
+
 /// HTTP request like "GET /?abc=123 HTTP/1.1\r\nConnection: keep-alive\r\n\r\n".
 /// after parse.
 #[derive(Clone)]
@@ -273,11 +909,27 @@ pub struct RequestData {
 
     /// Value of header "Connection: keep-alive/close", if no header then None
     pub(crate) connection_type: Option<ConnectionType>,
+    /// Lowercase tokens of all "Connection" headers like "keep-alive", "upgrade".
+    pub(crate) connection_options: Vec<String>,
     /// Value of header "Content-length", if no header then None.
     pub(crate) content_len: Option<usize>,
+    /// Content is sent with "Transfer-Encoding: chunked".
+    pub(crate) chunked: bool,
+    /// Trailer fields received after chunked content.
+    pub(crate) trailers: Vec<Header>,
 
     /// Need for return $str from path() function
     pub(crate) decoded_path: String,
+    /// Decoded names and values of query, parsed on first access.
+    decoded_query: OnceLock<Vec<(String, String)>>,
+    /// Counters of parser of the connection after parsing of this request.
+    pub(crate) parser_stats: ParserStats,
+}
+
+impl Default for RequestData {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RequestData {
@@ -291,17 +943,52 @@ impl RequestData {
             headers: Vec::with_capacity(16),
             raw: Vec::with_capacity(64),
             connection_type: None,
+            connection_options: Vec::new(),
             content_len: None,
+            chunked: false,
+            trailers: Vec::new(),
             decoded_path: String::new(),
+            decoded_query: OnceLock::new(),
+            parser_stats: ParserStats::default(),
         }
     }
 }
 
 impl RequestData {
+    /// Replaces path and query in request buffer by `target` like "/path?query". Encoded slashes in path stay encoded.
+    pub(crate) fn set_target(&mut self, target: &str) {
+        let old_end = if self.raw_query_indices.1 > 0 { self.raw_query_indices.1 } else { self.path_indices.1 };
+        if self.path_indices.0 > old_end || old_end > self.raw.len() {
+            log(Level::Error, format_args!("invalid indices in request buffer"));
+            return;
+        }
+
+        let mut raw = Vec::with_capacity(self.raw.len() + target.len());
+        raw.extend_from_slice(&self.raw[..self.path_indices.0]);
+        raw.extend_from_slice(target.as_bytes());
+        raw.extend_from_slice(&self.raw[old_end..]);
+
+        let path_start = self.path_indices.0;
+        match target.find('?') {
+            Some(query_pos) => {
+                self.path_indices = (path_start, path_start + query_pos);
+                self.raw_query_indices = (path_start + query_pos + 1, path_start + target.len());
+            }
+            None => {
+                self.path_indices = (path_start, path_start + target.len());
+                self.raw_query_indices = (0, 0);
+            }
+        }
+
+        self.raw = raw;
+        self.decoded_path = decode_path(self.raw_path(), false).unwrap_or_default();
+        self.decoded_query = OnceLock::new();
+    }
+
     /// The method slice in request buffer converted to utf8 string. Empty if invalid utf8 string.
     pub fn method(&self) -> &str {
         if self.method_end_index > self.raw.len() {
-            // this code must be unreachable
+            log(Level::Error, format_args!("invalid indices in request buffer"));
             return "";
         }
 
@@ -310,12 +997,29 @@ impl RequestData {
 
     /// Path. Decoded. Empty if no valid utf-8 or decoding error.
     pub fn path(&self) -> &str {
-        return &self.decoded_path;
+        &self.decoded_path
     }
 
     /// The parsed query to names and values array.
-    pub fn query(&self) -> Query {
-        parse_query(&self.raw_query())
+    pub fn query(&self) -> Query<'_, '_> {
+        parse_query(self.raw_query())
+    }
+
+    /// Decoded names and values of query in order of them in request. Parts with invalid utf-8 are skipped.
+    /// Query is parsed and decoded on first call, next calls return cached result.
+    pub fn query_params(&self) -> &[(String, String)] {
+        self.decoded_query.get_or_init(|| {
+            self.query().iter()
+                .filter_map(|part| Some((decode_query_component(part.name).ok()?, decode_query_component(part.value).ok()?)))
+                .collect()
+        })
+    }
+
+    /// First decoded value of query parameter by name. Uses cached query (see `query_params`).
+    pub fn query_value(&self, name: &str) -> Option<&str> {
+        self.query_params().iter()
+            .find(|(param_name, _)| param_name == name)
+            .map(|(_, value)| value.as_str())
     }
 
     /// Header value by name.
@@ -329,6 +1033,37 @@ impl RequestData {
     pub fn version(&self) -> &HttpVersion {
         &self.version
     }
+
+    /// The client sent "Expect: 100-continue" and waits for interim response before sending of content. Always false for HTTP/1.0.
+    pub fn expects_continue(&self) -> bool {
+        self.version == HttpVersion::Http1_1
+            && self.has_content()
+            && self.header_value("Expect").is_some_and(|expect| expect.trim().eq_ignore_ascii_case("100-continue"))
+    }
+
+    /// Number of "Host" headers (RFC 7230, 5.4). Proxying code can rely on `HostValidation::Single` to forward the host.
+    /// See `ParseHttpRequestSettings::reject_invalid_host`.
+    pub fn host_validation(&self) -> HostValidation {
+        match self.headers.iter().filter(|header| header.name.eq_ignore_ascii_case("Host")).count() {
+            0 => HostValidation::Missing,
+            1 => HostValidation::Single,
+            _ => HostValidation::Duplicate,
+        }
+    }
+
+    /// Counters of request parser of the connection including this request, for diagnostics of pathological clients.
+    pub fn parser_stats(&self) -> ParserStats {
+        self.parser_stats
+    }
+
+    /// "Host" headers are valid for version of request: exactly one for HTTP/1.1, at most one for HTTP/1.0.
+    pub fn has_valid_host(&self) -> bool {
+        match self.host_validation() {
+            HostValidation::Single => true,
+            HostValidation::Missing => self.version == HttpVersion::Http1_0,
+            HostValidation::Duplicate => false,
+        }
+    }
     /// Headers.
     pub fn headers(&self) -> &Vec<Header> {
         &self.headers
@@ -338,15 +1073,85 @@ impl RequestData {
     pub fn connection_type(&self) -> &Option<ConnectionType> {
         &self.connection_type
     }
+    /// Lowercase tokens of all "Connection" headers, for example ["keep-alive", "upgrade"] for "Connection: Keep-Alive, Upgrade".
+    pub fn connection_options(&self) -> &[String] {
+        &self.connection_options
+    }
+    /// "Connection" header has the option, case-insensitive.
+    pub fn has_connection_option(&self, option: &str) -> bool {
+        self.connection_options.iter().any(|token| token.eq_ignore_ascii_case(option))
+    }
     /// Value of header "Content-length", if no header then None.
     pub fn content_len(&self) -> usize {
         self.content_len.unwrap_or(0)
     }
 
+    /// Content is sent with "Transfer-Encoding: chunked", its length is unknown until it's received.
+    pub fn is_chunked(&self) -> bool {
+        self.chunked
+    }
+
+    /// Request has content, chunked or with "Content-Length" more than 0.
+    pub fn has_content(&self) -> bool {
+        self.chunked || self.content_len() > 0
+    }
+
+    /// Names of trailer fields announced by "Trailer" headers, they are sent after chunked content.
+    pub fn declared_trailers(&self) -> Vec<&str> {
+        self.headers.iter()
+            .filter(|header| header.name.eq_ignore_ascii_case("Trailer"))
+            .flat_map(|header| header.value.split(','))
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    /// The client accepts trailers in chunked response, "TE" header has "trailers".
+    pub fn accepts_trailers(&self) -> bool {
+        self.headers.iter()
+            .filter(|header| header.name.eq_ignore_ascii_case("TE"))
+            .flat_map(|header| header.value.split(','))
+            .any(|coding| coding.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("trailers"))
+    }
+
+    /// Scheme from "proto" parameter of first element of "Forwarded" header (RFC 7239)
+    /// or, without it, from first value of "X-Forwarded-Proto" header. None if there are no such headers or unknown scheme.
+    pub fn forwarded_proto(&self) -> Option<Scheme> {
+        let header = |name: &str| self.headers.iter().find(|header| header.name.eq_ignore_ascii_case(name)).map(|header| header.value.as_str());
+        let proto = match header("Forwarded") {
+            Some(forwarded) => forwarded.split(',').next()?
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("proto"))?
+                .1.trim().trim_matches('"'),
+            None => header("X-Forwarded-Proto")?.split(',').next()?.trim(),
+        };
+
+        if proto.eq_ignore_ascii_case("https") {
+            Some(Scheme::Https)
+        } else if proto.eq_ignore_ascii_case("http") {
+            Some(Scheme::Http)
+        } else {
+            None
+        }
+    }
+
+    /// Trailer fields received after chunked content. Empty until the content is completely read.
+    pub fn trailers(&self) -> &[Header] {
+        &self.trailers
+    }
+
+    /// Trailer field value by name, see `trailers`.
+    pub fn trailer_value(&self, name: &str) -> Option<&str> {
+        self.trailers.iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| &header.value[..])
+    }
+
     /// Cookies FROM FIRST HEADER "Cookie". RFC 6265, 5.4. "The Cookie Header: When the user agent generates an HTTP request, the user agent MUST NOT attach more than one Cookie header field".
-    pub fn cookies(&self) -> Vec<CookieOfRequst> {
+    pub fn cookies(&self) -> Vec<CookieOfRequst<'_>> {
         if let Some(cookie_header) = self.header_value("Cookie") {
-            return parse_cookie(&cookie_header);
+            return parse_cookie(cookie_header);
         }
 
         Vec::new()
@@ -355,7 +1160,7 @@ impl RequestData {
     /// Check existence header Content-Len, Content-Type and type application/x-www-form-urlencoded.
     /// No check that method is necessarily "POST", "PUT" or "PATCH".
     pub fn has_post_form(&self) -> bool {
-        if self.content_len.is_some() {
+        if self.content_len.is_some() || self.chunked {
             if let Some(value) = self.header_value("Content-Type") {
                 if value == "application/x-www-form-urlencoded" {
                     return true;
@@ -371,20 +1176,64 @@ impl RequestData {
         &self.raw
     }
 
+    /// Request line as received, like "GET /path?query HTTP/1.1", without "\r\n".
+    /// After `Request::forward_to` it has the new target.
+    pub fn raw_request_line(&self) -> &[u8] {
+        let end = self.raw.windows(2).position(|window| window == b"\r\n").unwrap_or(self.raw.len());
+        &self.raw[..end]
+    }
+
+    /// Header lines as received, between the request line and the empty line that ends the head.
+    /// Each line ends with "\r\n", empty if there are no headers. For forwarding or signing of exact original bytes.
+    pub fn raw_headers(&self) -> &[u8] {
+        let start = (self.raw_request_line().len() + 2).min(self.raw.len());
+        let end = if self.raw.ends_with(b"\r\n\r\n") { self.raw.len() - 2 } else { self.raw.len() };
+        &self.raw[start..end.max(start)]
+    }
+
     /// Method as raw bytes in request buffer.
     pub fn raw_method(&self) -> &[u8] {
         if self.method_end_index > self.raw.len() {
-            // this code must be unreachable
+            log(Level::Error, format_args!("invalid indices in request buffer"));
             return &[];
         }
 
         &self.raw[0..self.method_end_index]
     }
 
+    /// Not empty segments of path between '/' as raw bytes in request buffer.
+    pub fn raw_path_segments(&self) -> impl Iterator<Item = &[u8]> {
+        self.raw_path().split(|ch| *ch == b'/').filter(|segment| !segment.is_empty())
+    }
+
+    /// Not empty segments of path between '/', each percent-decoded. "%2F" in segment is decoded to '/' without splitting the segment.
+    /// Invalid utf-8 is replaced by U+FFFD.
+    pub fn path_segments(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.raw_path_segments().map(|segment| percent_decode(segment).decode_utf8_lossy())
+    }
+
+    /// Returns true if decoded segments of path start with the prefix segments. Empty and duplicate separators are ignored,
+    /// so "/api/v1", "/api/v1/" and "//api//v1/users" all start with `&["api", "v1"]`.
+    pub fn starts_with_segments(&self, prefix: &[&str]) -> bool {
+        self.tail_after(prefix).is_some()
+    }
+
+    /// Decoded segments of path after the prefix segments. None if path doesn't start with the prefix.
+    pub fn tail_after(&self, prefix: &[&str]) -> Option<Vec<Cow<'_, str>>> {
+        let mut segments = self.path_segments();
+        for expected in prefix {
+            if segments.next()? != *expected {
+                return None;
+            }
+        }
+
+        Some(segments.collect())
+    }
+
     /// Path as raw bytes in request buffer.
     pub fn raw_path(&self) -> &[u8] {
         if self.path_indices.0 > self.path_indices.1 || self.path_indices.1 > self.raw.len() {
-            // this code must be unreachable
+            log(Level::Error, format_args!("invalid indices in request buffer"));
             return &[];
         }
 
@@ -394,7 +1243,7 @@ impl RequestData {
     /// Query slice in request buffer. Empty if no query.
     pub fn raw_query(&self) -> &[u8] {
         if self.raw_query_indices.0 > self.raw_query_indices.1 || self.raw_query_indices.1 > self.raw.len() {
-            // this code must be unreachable
+            log(Level::Error, format_args!("invalid indices in request buffer"));
             return &[];
         }
 
@@ -1,4 +1,5 @@
-use crate::request::{ConnectionType, Header, HttpVersion, RequestError, RequestData};
+use crate::request::{ConnectionType, Header, HostValidation, HttpVersion, ParserStats, RequestError, RequestData};
+use crate::log::{log, Level};
 use std::str::from_utf8;
 use percent_encoding::percent_decode;
 
@@ -8,6 +9,12 @@ pub struct Parser {
     request: RequestData,
     /// What parse now. Internal state between parsing iterations.
     parse_state: ParseState,
+    /// Length of data searched for the end of failed head by `skip_failed_head`.
+    skip_searched_len: usize,
+    /// Index of first header line in request buffer.
+    headers_start: usize,
+    /// Counters of parsed requests of the connection.
+    stats: ParserStats,
 }
 
 /// What parse now. Internal state between parsing iterations.
@@ -33,24 +40,89 @@ pub struct ParseHttpRequestSettings {
     pub path_len_limit: u16,
     /// Maximum of bytes in query without '?' in request line.
     pub query_len_limit: u16,
+    /// Maximum of bytes in request target (path with '?' and query). In request line.
+    pub uri_len_limit: u16,
     /// Maximum number of headers.
     pub headers_count_limit: u16,
     /// Maximum of bytes in header name.
     pub header_name_len_limit: u16,
     /// Maximum of bytes in header value. Including optional ' '.
     pub header_value_len_limit: u16,
+    /// Maximum of bytes of all header lines of request head, with "\r\n" of each line.
+    pub headers_len_limit: u32,
     /// Maximum of requests count in one socket read operation. Several requests in can come from the client only if he is in pipelining mode. The number of possible requests is still limited by the size of the read buffer. Between read operations, the request counter is reset to zero.
     pub pipelining_requests_limit: u16,
+    /// Decode "%2F" to '/' in path. If false, encoded slashes stay encoded in `Request::path` so they can't be confused with segments separators.
+    pub decode_slash_in_path: bool,
+    /// Reject HTTP/1.1 request without "Host" header and request with several "Host" headers (RFC 7230, 5.4)
+    /// with `RequestError::MissingHost` or `RequestError::DuplicateHost`, the client gets "400 Bad Request".
+    /// If false, such requests are passed to the http callback, see `RequestData::host_validation`. Default false.
+    pub reject_invalid_host: bool,
 }
 
 const VERSION_LEN: usize = 8;
 
+/// Why the head of failed request can't be skipped, see `Parser::skip_failed_head`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SkipHeadError {
+    /// The head is too long.
+    HeadLenLimit,
+    /// The request has content.
+    Content,
+}
+
 impl Parser {
     pub fn new() -> Self {
         Parser {
             parse_state: ParseState::Method,
             request: RequestData::new(),
+            skip_searched_len: 0,
+            headers_start: 0,
+            stats: ParserStats::default(),
+        }
+    }
+
+    /// After error of `push`, skips the rest of head of the failed request up to the empty line, so next requests
+    /// of the connection can be parsed. Returns data after the head when it's found, None if more data is needed.
+    /// Fails if the skipped head is longer than `limit` or has "Content-Length" or "Transfer-Encoding" header,
+    /// because the end of its content is unknown and the content could be parsed as next request.
+    pub(crate) fn skip_failed_head(&mut self, data: &[u8], limit: usize) -> Result<Option<Vec<u8>>, SkipHeadError> {
+        // the empty line may start at the end of searched data
+        let search_from = self.skip_searched_len.saturating_sub(3);
+        self.request.raw.extend_from_slice(data);
+        self.skip_searched_len = self.request.raw.len();
+
+        let head_end = match self.request.raw[search_from..].windows(4).position(|window| window == b"\r\n\r\n") {
+            Some(position) => search_from + position + 4,
+            None if self.request.raw.len() > limit => return Err(SkipHeadError::HeadLenLimit),
+            None => return Ok(None),
+        };
+
+        if head_end > limit {
+            return Err(SkipHeadError::HeadLenLimit);
+        }
+
+        // the first line is request line
+        let has_content = self.request.raw[..head_end].split(|ch| *ch == b'\n').skip(1).any(|line| {
+            match String::from_utf8_lossy(line).split_once(':') {
+                Some((name, value)) => name.trim().eq_ignore_ascii_case("Transfer-Encoding")
+                    || name.trim().eq_ignore_ascii_case("Content-Length") && value.trim() != "0",
+                None => false,
+            }
+        });
+        if has_content {
+            return Err(SkipHeadError::Content);
         }
+
+        let surplus = self.request.raw[head_end..].to_vec();
+        *self = Parser { stats: self.stats, ..Parser::new() };
+        Ok(Some(surplus))
+    }
+
+    /// Length of data of not complete request kept until next `push`.
+    #[cfg(any(test, feature = "fuzz"))]
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.request.raw.len()
     }
 
     /// Push data for parsing. At the moment, in case of an error, the parser becomes invalid and needs to be recreated.
@@ -81,8 +153,8 @@ impl Parser {
                     b' ' => {
                         self.request.path_indices = (path_index, i);
                         self.parse_state = ParseState::Version(i + 1);
-                        if let Ok(decoded) = percent_decode(&self.request.raw[self.request.path_indices.0..self.request.path_indices.1]).decode_utf8() {
-                            self.request.decoded_path = decoded.to_string();
+                        if let Some(decoded) = decode_path(self.request.raw_path(), parse_settings.decode_slash_in_path) {
+                            self.request.decoded_path = decoded;
                         }
                     }
                     b'\n' => {
@@ -91,14 +163,17 @@ impl Parser {
                     b'?' => {
                         self.request.path_indices = (path_index, i);
                         self.parse_state = ParseState::Query(i + 1);
-                        if let Ok(decoded) = percent_decode(&self.request.raw[self.request.path_indices.0..self.request.path_indices.1]).decode_utf8() {
-                            self.request.decoded_path = decoded.to_string();
+                        if let Some(decoded) = decode_path(self.request.raw_path(), parse_settings.decode_slash_in_path) {
+                            self.request.decoded_path = decoded;
                         }
                     }
                     _ => {
                         if i - path_index >= parse_settings.path_len_limit as usize {
                             return Err(RequestError::PathLenLimit);
                         }
+                        if i - path_index >= parse_settings.uri_len_limit as usize {
+                            return Err(RequestError::UriLenLimit);
+                        }
                     }
                 },
                 ParseState::Query(query_index) => match *ch {
@@ -113,13 +188,19 @@ impl Parser {
                         if i - query_index >= parse_settings.query_len_limit as usize {
                             return Err(RequestError::QueryLenLimit);
                         }
+                        if i - self.request.path_indices.0 >= parse_settings.uri_len_limit as usize {
+                            return Err(RequestError::UriLenLimit);
+                        }
                     }
                 },
                 ParseState::Version(version_index) => match *ch {
+                    // no version and no "\r" before "\n"
+                    b'\n' if i == version_index => return Err(RequestError::WrongVersion),
                     b'\n' => match version_from_data(&raw_buf[version_index..i - 1]) {
                         Ok(ver) => {
                             self.request.version = ver;
                             self.parse_state = ParseState::Header(i + 1, 0);
+                            self.headers_start = i + 1;
                         }
                         Err(ver_err) => match ver_err {
                             VersionError::UnsupportedProtocol => return Err(RequestError::UnsupportedProtocol),
@@ -139,6 +220,11 @@ impl Parser {
                         break;
                     }
 
+                    // "\r" of the empty line can be at the limit
+                    if i - self.headers_start > parse_settings.headers_len_limit as usize {
+                        return Err(RequestError::HeadersLenLimit);
+                    }
+
                     // name limit check
                     if header_separator_index == 0 {
                         if i as i32 - header_index as i32 > parse_settings.header_name_len_limit as i32 {
@@ -196,9 +282,9 @@ impl Parser {
                             value: header_value.to_string(),
                         };
 
-                        // check "Contention" header
-                        if self.request.connection_type.is_none() {
-                            self.request.connection_type = self.header_is_connection_type(&header);
+                        // check "Connection" header
+                        if header.name.eq_ignore_ascii_case("Connection") {
+                            push_connection_options(&header.value, &mut self.request.connection_type, &mut self.request.connection_options);
                         }
 
                         // check "Content-Length"  header
@@ -206,6 +292,11 @@ impl Parser {
                             self.request.content_len = self.header_is_content_length(&header)?;
                         }
 
+                        // check "Transfer-Encoding" header
+                        if header.name.eq_ignore_ascii_case("Transfer-Encoding") {
+                            self.request.chunked = self.header_is_chunked(&header)?;
+                        }
+
                         self.request.headers.push(header);
                         self.parse_state = ParseState::Header(i + 1, 0);
                     }
@@ -215,8 +306,25 @@ impl Parser {
 
         // if request end found
         if let Some(request_len) = request_len {
+            // RFC 7230, 3.3.3. Request with both headers is a request smuggling attempt
+            if self.request.chunked && self.request.headers.iter().any(|header| header.name.eq_ignore_ascii_case("Content-Length")) {
+                return Err(RequestError::ContentLengthWithTransferEncoding);
+            }
+
+            if parse_settings.reject_invalid_host && !self.request.has_valid_host() {
+                return Err(match self.request.host_validation() {
+                    HostValidation::Duplicate => RequestError::DuplicateHost,
+                    _ => RequestError::MissingHost,
+                });
+            }
+
             self.parse_state = ParseState::Method;
 
+            self.stats.requests_parsed += 1;
+            self.stats.bytes_consumed += request_len as u64;
+            self.stats.headers_parsed += self.request.headers.len() as u64;
+            self.request.parser_stats = self.stats;
+
             let surplus = self.request.raw[request_len..].to_vec();
             self.request.raw.truncate(request_len);
 
@@ -229,21 +337,9 @@ impl Parser {
         Err(RequestError::Partial)
     }
 
-    fn header_is_connection_type(&self, header: &Header) -> Option<ConnectionType> {
-        if header.name == "Connection" {
-            if header.value == "keep-alive" {
-                return Some(ConnectionType::KeepAlive);
-            } else if header.value == "close" {
-                return Some(ConnectionType::Close);
-            }
-        }
-
-        None
-    }
-
     fn header_is_content_length(&self, header: &Header) -> Result<Option<usize>, RequestError> {
         if header.name == "Content-Length" {
-            if !header.value.chars().nth(0).ok_or(RequestError::ContentLengthParseError)?.is_digit(10) {
+            if !header.value.chars().nth(0).ok_or(RequestError::ContentLengthParseError)?.is_ascii_digit() {
                 return Err(RequestError::ContentLengthParseError);
             }
 
@@ -256,6 +352,165 @@ impl Parser {
 
         Ok(None)
     }
+
+    /// Only "chunked" transfer coding applied once is supported.
+    fn header_is_chunked(&self, header: &Header) -> Result<bool, RequestError> {
+        let mut chunked = self.request.chunked;
+        for coding in header.value.split(',').map(|coding| coding.trim()).filter(|coding| !coding.is_empty()) {
+            if !coding.eq_ignore_ascii_case("chunked") {
+                return Err(RequestError::UnsupportedTransferEncoding);
+            }
+            if chunked {
+                return Err(RequestError::WrongHeader);
+            }
+            chunked = true;
+        }
+
+        Ok(chunked)
+    }
+}
+
+/// Decoder of content sent with "Transfer-Encoding: chunked", RFC 7230, 4.1.
+pub(crate) struct ChunkedDecoder {
+    state: ChunkState,
+    /// Not finished chunk size line or trailer line.
+    line: Vec<u8>,
+    /// Received trailer fields.
+    trailers: Vec<Header>,
+    /// Number of bytes in trailer lines.
+    trailers_len: usize,
+    /// Number of bytes of decoded content.
+    decoded_len: usize,
+}
+
+/// What is decoded now.
+enum ChunkState {
+    Size,
+    /// Data of chunk with number of remaining bytes.
+    Data(usize),
+    /// CRLF after data of chunk.
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+/// Maximum of bytes in chunk size line with extensions.
+const CHUNK_LINE_LEN_LIMIT: usize = 1024;
+/// Maximum of bytes in all trailer lines.
+const TRAILERS_LEN_LIMIT: usize = 8192;
+
+impl ChunkedDecoder {
+    pub(crate) fn new() -> Self {
+        ChunkedDecoder { state: ChunkState::Size, line: Vec::new(), trailers: Vec::new(), trailers_len: 0, decoded_len: 0 }
+    }
+
+    /// Appends data of chunks to `decoded`. Returns number of bytes of `data` that belong to content
+    /// if the last chunk and trailers are received, the rest is next request.
+    pub(crate) fn push(&mut self, data: &[u8], decoded: &mut Vec<u8>) -> Result<Option<usize>, RequestError> {
+        let mut i = 0;
+        while i < data.len() {
+            match self.state {
+                ChunkState::Data(remaining) => {
+                    let len = remaining.min(data.len() - i);
+                    decoded.extend_from_slice(&data[i..i + len]);
+                    self.decoded_len += len;
+                    i += len;
+                    self.state = if len == remaining { ChunkState::DataEnd } else { ChunkState::Data(remaining - len) };
+                }
+                ChunkState::Done => return Ok(Some(i)),
+                _ => {
+                    let rest = &data[i..];
+                    let line_end = rest.iter().position(|ch| *ch == b'\n');
+                    self.line.extend_from_slice(&rest[..line_end.unwrap_or(rest.len())]);
+                    if self.line.len() > CHUNK_LINE_LEN_LIMIT {
+                        return Err(self.line_limit_error());
+                    }
+
+                    match line_end {
+                        Some(line_end) => {
+                            i += line_end + 1;
+                            if self.line.pop() != Some(b'\r') {
+                                return Err(RequestError::WrongChunk);
+                            }
+                            let line = std::mem::take(&mut self.line);
+                            self.on_line(&line)?;
+                        }
+                        None => i = data.len(),
+                    }
+                }
+            }
+        }
+
+        match self.state {
+            ChunkState::Done => Ok(Some(i)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Takes received trailer fields.
+    pub(crate) fn take_trailers(&mut self) -> Vec<Header> {
+        std::mem::take(&mut self.trailers)
+    }
+
+    /// Number of bytes of decoded content.
+    pub(crate) fn decoded_len(&self) -> usize {
+        self.decoded_len
+    }
+
+    fn on_line(&mut self, line: &[u8]) -> Result<(), RequestError> {
+        match self.state {
+            ChunkState::Size => {
+                let size_end = line.iter().position(|ch| *ch == b';').unwrap_or(line.len());
+                let size = from_utf8(&line[..size_end]).map_err(|_| RequestError::WrongChunk)?.trim_end_matches([' ', '\t']);
+                if size.is_empty() || !size.bytes().all(|ch| ch.is_ascii_hexdigit()) {
+                    return Err(RequestError::WrongChunk);
+                }
+
+                match usize::from_str_radix(size, 16) {
+                    Ok(0) => self.state = ChunkState::Trailers,
+                    Ok(size) => self.state = ChunkState::Data(size),
+                    Err(_) => return Err(RequestError::WrongChunk),
+                }
+            }
+            ChunkState::DataEnd => {
+                if !line.is_empty() {
+                    return Err(RequestError::WrongChunk);
+                }
+                self.state = ChunkState::Size;
+            }
+            ChunkState::Trailers => {
+                if line.is_empty() {
+                    self.state = ChunkState::Done;
+                    return Ok(());
+                }
+
+                self.trailers_len += line.len();
+                if self.trailers_len > TRAILERS_LEN_LIMIT {
+                    return Err(RequestError::TrailersLenLimit);
+                }
+
+                let line = from_utf8(line).map_err(|_| RequestError::WrongHeader)?;
+                let (name, value) = line.split_once(':').ok_or(RequestError::WrongHeader)?;
+                if name.is_empty() || name.contains([' ', '\t']) {
+                    return Err(RequestError::WrongHeader);
+                }
+
+                self.trailers.push(Header { name: name.to_string(), value: value.trim().to_string() });
+            }
+            ChunkState::Data(_) | ChunkState::Done => {
+                log(Level::Error, format_args!("unexpected line in chunked content"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn line_limit_error(&self) -> RequestError {
+        match self.state {
+            ChunkState::Trailers => RequestError::TrailersLenLimit,
+            _ => RequestError::WrongChunk,
+        }
+    }
 }
 
 enum VersionError {
@@ -264,6 +519,23 @@ enum VersionError {
     UnsupportedProtocol,
 }
 
+/// Adds tokens of "Connection" header value like "Keep-Alive, Upgrade" to connection options of request,
+/// "close" takes precedence over "keep-alive" in any of "Connection" headers.
+fn push_connection_options(value: &str, connection_type: &mut Option<ConnectionType>, options: &mut Vec<String>) {
+    for token in value.split(',').map(str::trim).filter(|token| !token.is_empty()) {
+        let token = token.to_ascii_lowercase();
+        match token.as_str() {
+            "close" => *connection_type = Some(ConnectionType::Close),
+            "keep-alive" if connection_type.is_none() => *connection_type = Some(ConnectionType::KeepAlive),
+            _ => {}
+        }
+
+        if !options.contains(&token) {
+            options.push(token);
+        }
+    }
+}
+
 fn version_from_data(data: &[u8]) -> Result<HttpVersion, VersionError> {
     if data.len() != VERSION_LEN {
         return Err(VersionError::WrongLen);
@@ -283,17 +555,54 @@ fn version_from_data(data: &[u8]) -> Result<HttpVersion, VersionError> {
     Err(VersionError::UnsupportedProtocol)
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+/// Percent-decodes path. '+' is not decoded in path. If not decode_slash, "%2F" stays encoded.
+/// Returns None if invalid utf-8.
+pub(crate) fn decode_path(raw_path: &[u8], decode_slash: bool) -> Option<String> {
+    if decode_slash {
+        return percent_decode(raw_path).decode_utf8().ok().map(|decoded| decoded.to_string());
+    }
+
+    let mut result = String::new();
+    let mut part_index = 0;
+    let mut i = 0;
+    while i + 2 < raw_path.len() {
+        if raw_path[i] == b'%' && raw_path[i + 1] == b'2' && (raw_path[i + 2] == b'F' || raw_path[i + 2] == b'f') {
+            result.push_str(&percent_decode(&raw_path[part_index..i]).decode_utf8().ok()?);
+            result.push_str("%2F");
+            i += 3;
+            part_index = i;
+        } else {
+            i += 1;
+        }
+    }
+    result.push_str(&percent_decode(&raw_path[part_index..]).decode_utf8().ok()?);
+
+    Some(result)
+}
+
 impl Default for ParseHttpRequestSettings {
     fn default() -> Self {
         ParseHttpRequestSettings {
-            method_len_limit: 7,
+            // "PROPPATCH" of WebDAV is the longest common method
+            method_len_limit: 9,
             path_len_limit: 512,
             query_len_limit: 512,
+            uri_len_limit: 1024,
             // I googled that default limits for headers on other servers: Apache 8K, Nginx 4K-8K, IIS 8K-16K, Tomcat 8K – 48K. I don’t know yet why so many.
             headers_count_limit: 64,
-            header_name_len_limit: 32,
+            // "Access-Control-Request-Private-Network" of CORS preflight is 38 bytes
+            header_name_len_limit: 64,
             header_value_len_limit: 512,
+            headers_len_limit: 32768,
             pipelining_requests_limit: 64,
+            decode_slash_in_path: false,
+            reject_invalid_host: false,
         }
     }
 }
@@ -1,4 +1,16 @@
+use crate::content::ContentDecoder;
+use crate::cookie::cookie_date;
+use crate::handler_error::HandlerError;
+use crate::log::{log, Level};
 use crate::request::{ConnectionType, HttpVersion, Request, RequestData};
+use crate::tcp_session::{SendStatus, TcpSession};
+use crate::transform::{Pipeline, ResponseTransform};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use chrono::{DateTime, Utc};
+use std::time::{Duration, Instant, SystemTime};
 
 /// For build and send HTTP response.
 pub struct Response<'a, 'b, 'c, 'd, 'e> {
@@ -8,6 +20,10 @@ pub struct Response<'a, 'b, 'c, 'd, 'e> {
     content_type: &'a str,
     /// Data of HTTP response content.
     content: &'b[u8],
+    /// Shared content set by `content_arc`, sent without copying.
+    shared_content: Option<Arc<Vec<u8>>>,
+    /// Value of "Content-Encoding" of content set by `precompressed`.
+    precompressed: Option<&'a str>,
     /// If Some - Connection header will be set from value.
     /// If None - Connection header will be set by request Connection header and HTTP version.
     keep_alive_connection: Option<bool>,
@@ -17,6 +33,10 @@ pub struct Response<'a, 'b, 'c, 'd, 'e> {
     cookies: Option<&'d str>,
     /// Location header.
     location: Option<&'e str>,
+    /// Header lines set by typed setters like `retry_after`.
+    typed_headers: String,
+    /// Transforms of content set by `transform`, taken when the response is sent.
+    transforms: RefCell<Pipeline>,
 
     /// Request. Using for build and send response.
     request: Request,
@@ -24,51 +44,221 @@ pub struct Response<'a, 'b, 'c, 'd, 'e> {
 
 impl<'a, 'b, 'c, 'd, 'e> Response<'a, 'b, 'c, 'd, 'e> {
     /// Builds response and send it to the client.
-    pub fn send(&self) {
-        self.try_send(|_| {});
+    /// Returns `SendStatus::Closed` if the connection is already closed, in this case the response is not built.
+    pub fn send(&self) -> SendStatus {
+        self.try_send(|_| {})
     }
 
     /// Builds response and send it to the client.
+    /// If the connection is already closed, `res_callback` is called with error immediately and `SendStatus::Closed` is returned.
     /// # Arguments
     /// * `res_callback` - function that will be called when the write is finished or socket writing error.
-    pub fn try_send(&self, res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) {
-        let mut response = Vec::from(format!(
-            "{} {}\r\n\
-         Date: {}\r\n\
-         {}\
-         Content-Length: {}\r\n\
-         {}\
-         {}\
-         {}\
-         {}{}{}\
-         \r\n",
-            self.request.version().to_string_for_response(),
-            http_status_code_with_name(self.code),
-            self.request.rfc7231_date_string(),
-            self.connection_str(&self.request.request_data()),
-            self.content.len(),
-            self.content_type,
-            if let Some(headers) = self.headers { headers } else { "" },
-            if let Some(cookies) = self.cookies { cookies } else { "" },
-            if self.location.is_some() { "Location: " } else { "" },
-            if let Some(location) = self.location { location } else { "" },
-            if self.location.is_some() { "\r\n" } else { "" },
-        ));
+    pub fn try_send(&self, mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) -> SendStatus {
+        if self.request.tcp_session().is_closed() {
+            res_callback(Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "connection is closed")));
+            return SendStatus::Closed;
+        }
 
-        response.extend_from_slice(self.content);
+        if let Err(err) = self.check_headers() {
+            self.respond_wrong_headers(&err);
+            res_callback(Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, err)));
+            return SendStatus::Queued;
+        }
+
+        // keep-alive is allowed only if the client can find the end of response
+        let framing_allows_keep_alive = framing_allows_keep_alive(self.request.version(), self.headers.unwrap_or_default());
 
-        let need_close_after_response =
+        let need_close_after_response = !framing_allows_keep_alive ||
             if let Some(keep_alive_connection) = self.keep_alive_connection {
                 !keep_alive_connection
             } else {
-                need_close_by_request(&self.request.request_data())
+                need_close_by_request(self.request.request_data())
             };
 
+        let connection_str = if framing_allows_keep_alive {
+            self.connection_str(self.request.request_data())
+        } else {
+            "Connection: close\r\n"
+        };
+
+        let decompressed;
+        let (precompressed_content, encoding_headers) = match self.precompressed {
+            Some(encoding) if accepts_encoding(self.request.header_value("Accept-Encoding").unwrap_or_default(), encoding) => {
+                (Some(self.content), format!("Content-Encoding: {}\r\nVary: Accept-Encoding\r\n", encoding))
+            }
+            Some(encoding) => match decompress(encoding, self.content, self.request.decompression_limit()) {
+                Ok(content) => {
+                    decompressed = content;
+                    (Some(&decompressed[..]), "Vary: Accept-Encoding\r\n".to_string())
+                }
+                Err(err) => {
+                    log(Level::Error, format_args!("precompressed content of response {} is not sent: {}", self.code, err));
+                    res_callback(Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())));
+                    self.request.tcp_session().close_by_handler_error(&HandlerError::new(500, http_status_code_with_name(500)));
+                    return SendStatus::Closed;
+                }
+            },
+            None => (None, String::new()),
+        };
+
+        // precompressed content isn't compressed again
+        let mut pipeline = if precompressed_content.is_some() { Pipeline::default() } else { self.pipeline() };
+        let shared_content = self.shared_content.as_ref().filter(|_| pipeline.is_empty());
+        let transformed;
+        let content = if let Some(precompressed_content) = precompressed_content {
+            precompressed_content
+        } else if let Some(shared_content) = shared_content {
+            &shared_content[..]
+        } else if pipeline.is_empty() {
+            self.content
+        } else {
+            match pipeline.apply(self.shared_content.as_deref().map_or(self.content, |content| &content[..])) {
+                Ok(content) => {
+                    transformed = content;
+                    &transformed[..]
+                }
+                Err(err) => {
+                    res_callback(Err(err));
+                    self.request.tcp_session().close();
+                    return SendStatus::Closed;
+                }
+            }
+        };
+
+        // the end of content is the close of connection, so "Connection" of handler can't be sent
+        let dropped_headers: &[&str] = if framing_allows_keep_alive { &[] } else { &["Connection"] };
+        let mut response = Vec::from(self.head(connection_str, &format!("Content-Length: {}\r\n{}{}", content.len(), pipeline.headers(), encoding_headers), dropped_headers));
+        if shared_content.is_some() {
+            // the connection must not be closed after writing of head
+            self.request.tcp_session().send(&response);
+            response.clear();
+        } else {
+            response.extend_from_slice(content);
+        }
+
         if need_close_after_response {
             self.request.tcp_session().close_after_send();
         }
 
-        self.request.tcp_session().try_send(&response, res_callback);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(session_id = self.request.tcp_session().id(), request_id = self.request.id, code = self.code, content_len = content.len(), close = need_close_after_response, "sending response");
+
+        let handler = self.request.times.head_parsed.elapsed();
+        let mut timing = self.request.on_timing.clone().map(|on_timing| (on_timing, self.request.timing(self.code, content.len())));
+        let mut counted = false;
+        let send_started = Instant::now();
+        let worker_counters = self.request.tcp_session().inner.worker_counters.clone();
+        #[cfg(feature = "tracing")]
+        let (session_id, request_id, code) = (self.request.tcp_session().id(), self.request.id, self.code);
+        let on_written = move |res: Result<(), std::io::Error>| {
+            if !counted {
+                counted = true;
+                let send = send_started.elapsed();
+                worker_counters.add_timing(handler, send);
+
+                #[cfg(feature = "tracing")]
+                tracing::debug!(session_id, request_id, code, handler = ?handler, send = ?send, "response written");
+
+                if let Some((on_timing, mut timing)) = timing.take() {
+                    timing.send = send;
+                    on_timing(&timing);
+                }
+            }
+
+            res_callback(res);
+        };
+
+        match shared_content {
+            Some(shared_content) => self.request.tcp_session().try_send_arc(shared_content, on_written),
+            None => self.request.tcp_session().try_send(&response, on_written),
+        }
+    }
+
+    /// Sends status line and headers of response with chunked transfer encoding, content is sent later in parts by returned `ChunkedResponse`.
+    /// Content set by `content`, `text`, etc is ignored except "Content-Type". To send trailers, announce them in "Trailer" header.
+    /// HTTP/1.0 doesn't support chunked encoding, in this case parts are sent as is and the connection is closed at the end.
+    /// "Transfer-Encoding" set by `headers` is replaced by the response framing, as well as "Connection" for HTTP/1.0.
+    pub fn chunked(&self) -> ChunkedResponse {
+        if let Err(err) = self.check_headers() {
+            self.respond_wrong_headers(&err);
+            return ChunkedResponse {
+                tcp_session: self.request.tcp_session().clone(),
+                chunked: false,
+                close_after_end: true,
+                pipeline: Mutex::new(Pipeline::default()),
+                failed: true,
+            };
+        }
+
+        let chunked = *self.request.version() == HttpVersion::Http1_1;
+
+        let close_after_end = !chunked ||
+            if let Some(keep_alive_connection) = self.keep_alive_connection {
+                !keep_alive_connection
+            } else {
+                need_close_by_request(self.request.request_data())
+            };
+
+        let connection_str = if chunked {
+            self.connection_str(self.request.request_data())
+        } else {
+            "Connection: close\r\n"
+        };
+
+        let pipeline = self.pipeline();
+        // framing is set by the response itself, HTTP/1.0 client reads content until the close of connection
+        let dropped_headers: &[&str] = if chunked { &["Transfer-Encoding"] } else { &["Transfer-Encoding", "Connection"] };
+        let head = self.head(connection_str, &format!("{}{}", if chunked { "Transfer-Encoding: chunked\r\n" } else { "" }, pipeline.headers()), dropped_headers);
+        self.request.tcp_session().send(head.as_bytes());
+
+        ChunkedResponse {
+            tcp_session: self.request.tcp_session().clone(),
+            chunked,
+            close_after_end,
+            pipeline: Mutex::new(pipeline),
+            failed: false,
+        }
+    }
+
+    /// Checks headers set by `content`, `headers`, `cookies` and `location` against response splitting by CR or LF
+    /// and against headers set by the response itself (see `DENIED_HEADERS`).
+    /// If the check fails, `send` and `chunked` send "500 Internal Server Error" instead of the response and close the connection.
+    pub fn check_headers(&self) -> Result<(), HeaderError> {
+        check_header_lines(self.content_type)?;
+        check_header_lines(self.headers.unwrap_or_default())?;
+        check_header_lines(self.cookies.unwrap_or_default())?;
+        check_header_lines(&self.typed_headers)?;
+        check_header_value(self.precompressed.unwrap_or_default())?;
+        check_header_value(self.location.unwrap_or_default())
+    }
+
+    fn respond_wrong_headers(&self, err: &HeaderError) {
+        log(Level::Error, format_args!("response {} is replaced by 500, wrong headers: {}", self.code, err));
+        self.request.tcp_session().close_by_handler_error(&HandlerError::new(500, http_status_code_with_name(500)));
+    }
+
+    /// Adds streaming transform of content, for example compression. Transforms are applied in order of adding,
+    /// then transforms of `Settings::response_transforms`. The transform adds its headers, don't set them by `headers`.
+    pub fn transform(&mut self, transform: Box<dyn ResponseTransform>) -> &mut Self {
+        self.transforms.get_mut().add(transform);
+        self
+    }
+
+    /// Transforms set by `transform` and chosen by `Settings::response_transforms`.
+    fn pipeline(&self) -> Pipeline {
+        let mut pipeline = self.transforms.take();
+        if self.request.response_transforms.is_empty() {
+            return pipeline;
+        }
+
+        let header_lines = format!("{}{}{}", self.content_type, self.headers.unwrap_or_default(), self.typed_headers);
+        for factory in self.request.response_transforms.iter() {
+            if let Some(transform) = factory(&self.request, self.code, &header_lines) {
+                pipeline.add(transform);
+            }
+        }
+
+        pipeline
     }
 
     /// Set any type content.
@@ -76,6 +266,30 @@ impl<'a, 'b, 'c, 'd, 'e> Response<'a, 'b, 'c, 'd, 'e> {
     pub fn content(&mut self, content_type: &'a str, content: &'b [u8]) -> &mut Self {
         self.content_type = content_type;
         self.content = content;
+        self.shared_content = None;
+        self.precompressed = None;
+        self
+    }
+
+    /// Set shared content, for example big cached blob. Unlike `content` it's not copied to the response buffer,
+    /// headers and the content are sent separately (content is copied only if transforms are set).
+    pub fn content_arc(&mut self, content_type: &'a str, content: Arc<Vec<u8>>) -> &mut Self {
+        self.content_type = content_type;
+        self.content = &[];
+        self.shared_content = Some(content);
+        self.precompressed = None;
+        self
+    }
+
+    /// Set content already compressed with `encoding` ("gzip" or "deflate"), for example cached response of upstream.
+    /// It's sent as is with "Content-Encoding" if the client accepts the encoding, otherwise it's decompressed
+    /// (up to `Settings::content_decompression_limit` if set). Transforms are not applied to it.
+    /// If the content can't be decompressed, "500 Internal Server Error" is sent instead and the connection is closed.
+    pub fn precompressed(&mut self, content_type: &'a str, encoding: &'a str, content: &'b [u8]) -> &mut Self {
+        self.content_type = content_type;
+        self.content = content;
+        self.shared_content = None;
+        self.precompressed = Some(encoding);
         self
     }
 
@@ -84,6 +298,8 @@ impl<'a, 'b, 'c, 'd, 'e> Response<'a, 'b, 'c, 'd, 'e> {
     pub fn text(&mut self, text: &'b str) -> &mut Self {
         self.content_type = "Content-Type: text/plain; charset=utf-8\r\n";
         self.content = text.as_bytes();
+        self.shared_content = None;
+        self.precompressed = None;
         self
     }
 
@@ -92,6 +308,8 @@ impl<'a, 'b, 'c, 'd, 'e> Response<'a, 'b, 'c, 'd, 'e> {
     pub fn html(&mut self, html: &'b str) -> &mut Self {
         self.content_type = "Content-Type: text/html; charset=utf-8\r\n";
         self.content = html.as_bytes();
+        self.shared_content = None;
+        self.precompressed = None;
         self
     }
 
@@ -100,6 +318,8 @@ impl<'a, 'b, 'c, 'd, 'e> Response<'a, 'b, 'c, 'd, 'e> {
     pub fn wasm(&mut self, wasm_data: &'b [u8]) -> &mut Self {
         self.content_type = "Content-Type: application/wasm\r\n";
         self.content = wasm_data;
+        self.shared_content = None;
+        self.precompressed = None;
         self
     }
 
@@ -144,24 +364,110 @@ impl<'a, 'b, 'c, 'd, 'e> Response<'a, 'b, 'c, 'd, 'e> {
         self
     }
 
+    /// Set "Retry-After" header for 429, 503 or 3xx responses, as delay in seconds or as date.
+    pub fn retry_after(&mut self, retry_after: impl Into<RetryAfter>) -> &mut Self {
+        let value = match retry_after.into() {
+            // rounded up, the client must not retry before the delay
+            RetryAfter::Delay(delay) => (delay.as_secs() + u64::from(delay.subsec_nanos() > 0)).to_string(),
+            RetryAfter::Date(date) => cookie_date(&date),
+        };
+        self.typed_headers.push_str(&format!("Retry-After: {}\r\n", value));
+        self
+    }
+
+    /// Set "Cache-Control" header, for example `cache_control(&CacheControl::new().public().max_age(Duration::from_secs(300)))`.
+    pub fn cache_control(&mut self, cache_control: &CacheControl) -> &mut Self {
+        self.typed_headers.push_str(&format!("Cache-Control: {}\r\n", cache_control));
+        self
+    }
+
+    /// Set "Cache-Control: no-store", the response must not be stored by any cache.
+    pub fn no_store(&mut self) -> &mut Self {
+        self.cache_control(&CacheControl::new().no_store())
+    }
+
+    /// Set "Expires" header, date after which the response is stale. "max-age" of "Cache-Control" takes precedence.
+    pub fn expires(&mut self, date: impl Into<DateTime<Utc>>) -> &mut Self {
+        self.typed_headers.push_str(&format!("Expires: {}\r\n", cookie_date(&date.into())));
+        self
+    }
+
+    /// Set "Allow" header with allowed methods for 405 responses, for example `&["GET", "HEAD"]`.
+    pub fn allow(&mut self, methods: &[&str]) -> &mut Self {
+        self.typed_headers.push_str(&format!("Allow: {}\r\n", methods.join(", ")));
+        self
+    }
+
+    /// Set "Content-Location" header.
+    pub fn content_location(&mut self, location: &str) -> &mut Self {
+        self.typed_headers.push_str(&format!("Content-Location: {}\r\n", location));
+        self
+    }
+
+    /// Add "WWW-Authenticate" header with challenge for 401 responses, for example
+    /// `www_authenticate("Bearer", &[("realm", "api"), ("error", "invalid_token")])`. Values are quoted.
+    pub fn www_authenticate(&mut self, scheme: &str, params: &[(&str, &str)]) -> &mut Self {
+        let params: Vec<String> = params.iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"")))
+            .collect();
+        let separator = if params.is_empty() { "" } else { " " };
+        self.typed_headers.push_str(&format!("WWW-Authenticate: {}{}{}\r\n", scheme, separator, params.join(", ")));
+        self
+    }
+
     /// Returns new response ready to build.
     pub(crate) fn new(code: u16, request: Request) -> Self {
         Response {
             code,
             content: &[],
+            shared_content: None,
+            precompressed: None,
             content_type: "",
             keep_alive_connection: None,
             headers: None,
             cookies: None,
             location: None,
+            typed_headers: String::new(),
+            transforms: RefCell::new(Pipeline::default()),
             request,
         }
     }
 
+    /// Status line and headers of response with empty line at the end.
+    /// Status line and headers, lines of `headers` with names from `dropped_headers` are skipped.
+    fn head(&self, connection_str: &str, content_len_or_transfer_encoding: &str, dropped_headers: &[&str]) -> String {
+        format!(
+            "{} {}\r\n\
+         Date: {}\r\n\
+         {}\
+         {}\
+         {}\
+         {}\
+         {}\
+         {}\
+         {}\
+         {}{}{}\
+         \r\n",
+            self.request.version().to_string_for_response(),
+            http_status_code_with_name(self.code),
+            self.request.rfc7231_date_string(),
+            connection_str,
+            content_len_or_transfer_encoding,
+            self.content_type,
+            without_headers(self.headers.unwrap_or_default(), dropped_headers),
+            self.typed_headers,
+            default_headers_str(&self.request, &[self.headers.unwrap_or_default(), &self.typed_headers, self.cookies.unwrap_or_default()]),
+            self.cookies.unwrap_or_default(),
+            if self.location.is_some() { "Location: " } else { "" },
+            self.location.unwrap_or_default(),
+            if self.location.is_some() { "\r\n" } else { "" },
+        )
+    }
+
     fn connection_str(&self, request: &RequestData) -> &'static str {
         if let Some(keep_alive_connection) = self.keep_alive_connection {
             if keep_alive_connection {
-                "Connection: keep_alive\r\n"
+                "Connection: keep-alive\r\n"
             } else {
                 "Connection: close\r\n"
             }
@@ -171,28 +477,553 @@ impl<'a, 'b, 'c, 'd, 'e> Response<'a, 'b, 'c, 'd, 'e> {
     }
 }
 
+/// Content of response sent in parts. See `Response::chunked`.
+/// Must be ended by `end` or `end_with_trailers`, otherwise the client will wait for the rest of content.
+pub struct ChunkedResponse {
+    tcp_session: TcpSession,
+    /// False for HTTP/1.0, parts are sent as is.
+    chunked: bool,
+    /// Close connection after the last chunk.
+    close_after_end: bool,
+    /// Transforms of content, see `Response::transform`.
+    pipeline: Mutex<Pipeline>,
+    /// Headers are wrong, error response is sent instead, content is not sent.
+    failed: bool,
+}
+
+impl ChunkedResponse {
+    /// Sends part of content. Empty data is not sent because empty chunk means the end of content.
+    /// Returns `SendStatus::Closed` if the connection is closed, so the rest of content is not needed.
+    pub fn send(&self, data: &[u8]) -> SendStatus {
+        self.try_send(data, |_| {})
+    }
+
+    /// Sends part of content. Empty data is not sent because empty chunk means the end of content.
+    /// # Arguments
+    /// * `res_callback` - function that will be called when the write is finished or socket writing error.
+    pub fn try_send(&self, data: &[u8], mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) -> SendStatus {
+        if self.failed {
+            res_callback(Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "response has wrong headers")));
+            return SendStatus::Closed;
+        }
+
+        let transformed = match self.transform(data, false) {
+            Ok(transformed) => transformed,
+            Err(err) => {
+                res_callback(Err(err));
+                return SendStatus::Closed;
+            }
+        };
+        self.send_part(transformed.as_deref().unwrap_or(data), res_callback)
+    }
+
+    /// Sends part of transformed content.
+    fn send_part(&self, data: &[u8], mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) -> SendStatus {
+        if data.is_empty() {
+            if self.tcp_session.is_closed() {
+                res_callback(Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "connection is closed")));
+                return SendStatus::Closed;
+            }
+            res_callback(Ok(()));
+            return SendStatus::Queued;
+        }
+
+        if self.chunked {
+            let mut chunk = Vec::from(format!("{:X}\r\n", data.len()));
+            chunk.extend_from_slice(data);
+            chunk.extend_from_slice(b"\r\n");
+            self.tcp_session.try_send(&chunk, res_callback)
+        } else {
+            self.tcp_session.try_send(data, res_callback)
+        }
+    }
+
+    /// Sends the end of content.
+    pub fn end(self) {
+        self.try_end_with_trailers("", |_| {});
+    }
+
+    /// Sends the end of content with trailer header lines, each ending with "\r\n", for example "Server-Timing: db;dur=53\r\n".
+    /// Trailers are not sent in HTTP/1.0.
+    pub fn end_with_trailers(self, trailers: &str) {
+        self.try_end_with_trailers(trailers, |_| {});
+    }
+
+    /// Same as `end_with_trailers`.
+    /// # Arguments
+    /// * `res_callback` - function that will be called when the write of all content is finished or socket writing error.
+    pub fn try_end_with_trailers(self, trailers: &str, mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) {
+        if self.failed {
+            res_callback(Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "response has wrong headers")));
+            return;
+        }
+        if let Err(err) = check_header_lines(trailers) {
+            log(Level::Error, format_args!("trailers are not sent, wrong headers: {}", err));
+            res_callback(Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, err)));
+            self.tcp_session.close();
+            return;
+        }
+
+        match self.transform(&[], true) {
+            Ok(Some(rest)) => {
+                self.send_part(&rest, |_| {});
+            }
+            Ok(None) => {}
+            Err(err) => {
+                res_callback(Err(err));
+                self.tcp_session.close();
+                return;
+            }
+        }
+
+        if self.close_after_end {
+            self.tcp_session.close_after_send();
+        }
+
+        if self.chunked {
+            self.tcp_session.try_send(format!("0\r\n{}\r\n", trailers).as_bytes(), res_callback);
+        } else {
+            // empty data for closing after sending queued parts
+            self.tcp_session.try_send(&[], res_callback);
+        }
+    }
+
+    /// Passes data through transforms, None if there are no transforms.
+    fn transform(&self, data: &[u8], finish: bool) -> std::io::Result<Option<Vec<u8>>> {
+        let mut pipeline = self.pipeline.lock().map_err(|_| std::io::Error::other("lock of transforms is poisoned"))?;
+        if pipeline.is_empty() {
+            return Ok(None);
+        }
+
+        if finish { pipeline.finish().map(Some) } else { pipeline.push(data).map(Some) }
+    }
+}
+
+/// Value of "Retry-After" header, see `Response::retry_after`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryAfter {
+    /// Delay, sent in whole seconds rounded up.
+    Delay(Duration),
+    /// Date after which the request can be repeated.
+    Date(DateTime<Utc>),
+}
+
+impl From<Duration> for RetryAfter {
+    fn from(delay: Duration) -> Self {
+        RetryAfter::Delay(delay)
+    }
+}
+
+impl From<DateTime<Utc>> for RetryAfter {
+    fn from(date: DateTime<Utc>) -> Self {
+        RetryAfter::Date(date)
+    }
+}
+
+impl From<SystemTime> for RetryAfter {
+    fn from(date: SystemTime) -> Self {
+        RetryAfter::Date(date.into())
+    }
+}
+
+/// Directives of "Cache-Control" header of response (RFC 9111, 5.2.2), see `Response::cache_control`.
+/// Durations are sent in whole seconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    public: bool,
+    private: bool,
+    no_cache: bool,
+    no_store: bool,
+    no_transform: bool,
+    must_revalidate: bool,
+    proxy_revalidate: bool,
+    immutable: bool,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    stale_if_error: Option<Duration>,
+}
+
+impl CacheControl {
+    /// Creates value without directives.
+    pub fn new() -> Self {
+        CacheControl::default()
+    }
+
+    /// "public", the response can be stored by shared caches even if it's normally not cacheable. Clears "private".
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self.private = false;
+        self
+    }
+
+    /// "private", the response can be stored only by cache of the user agent. Clears "public".
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self.public = false;
+        self
+    }
+
+    /// "no-cache", stored response must be validated before use.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// "no-store", the response must not be stored.
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    /// "no-transform", intermediaries must not transform the content.
+    pub fn no_transform(mut self) -> Self {
+        self.no_transform = true;
+        self
+    }
+
+    /// "must-revalidate", stale response must not be used without validation.
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// "proxy-revalidate", same as "must-revalidate" for shared caches only.
+    pub fn proxy_revalidate(mut self) -> Self {
+        self.proxy_revalidate = true;
+        self
+    }
+
+    /// "immutable", the response will not change while it's fresh, for versioned assets.
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    /// "max-age", time during which the response is fresh.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// "s-maxage", "max-age" for shared caches.
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// "stale-while-revalidate" (RFC 5861), time during which stale response can be used while it's revalidated.
+    pub fn stale_while_revalidate(mut self, duration: Duration) -> Self {
+        self.stale_while_revalidate = Some(duration);
+        self
+    }
+
+    /// "stale-if-error" (RFC 5861), time during which stale response can be used if revalidation fails.
+    pub fn stale_if_error(mut self, duration: Duration) -> Self {
+        self.stale_if_error = Some(duration);
+        self
+    }
+}
+
+impl std::fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.proxy_revalidate, "proxy-revalidate"),
+            (self.immutable, "immutable"),
+        ];
+        let durations = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+            (self.stale_if_error, "stale-if-error"),
+        ];
+
+        let directives: Vec<String> = flags.iter().filter(|(set, _)| *set).map(|(_, name)| name.to_string())
+            .chain(durations.iter().filter_map(|(duration, name)| duration.map(|duration| format!("{}={}", name, duration.as_secs()))))
+            .collect();
+        f.write_str(&directives.join(", "))
+    }
+}
+
+/// Error of header lines of response, see `Response::check_headers`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    /// Lines don't end with "\r\n", or have empty line, CR or LF in value, the response could be split.
+    WrongLineEnd,
+    /// Line has no ':' or name has characters not allowed in token.
+    WrongName,
+    /// Value has control characters other than horizontal tab.
+    ControlCharacter,
+    /// Header is set by `Response` itself, see `DENIED_HEADERS`.
+    Denied(String),
+}
+
+impl std::fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/// Headers that are set by `Response` and must not be set by `Response::headers` or `Response::cookies`.
+pub const DENIED_HEADERS: &[&str] = &["Content-Length", "Date"];
+
+/// Checks header lines like "Name: value\r\n". Empty string is valid.
+pub fn check_header_lines(lines: &str) -> Result<(), HeaderError> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let lines = lines.strip_suffix("\r\n").ok_or(HeaderError::WrongLineEnd)?;
+    for line in lines.split("\r\n") {
+        if line.is_empty() {
+            return Err(HeaderError::WrongLineEnd);
+        }
+
+        let (name, value) = line.split_once(':').ok_or(HeaderError::WrongName)?;
+        if name.is_empty() || !name.bytes().all(is_token_char) {
+            return Err(HeaderError::WrongName);
+        }
+
+        check_header_value(value)?;
+
+        if DENIED_HEADERS.iter().any(|denied| denied.eq_ignore_ascii_case(name)) {
+            return Err(HeaderError::Denied(name.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks header value, it must not have CR, LF and other control characters except horizontal tab.
+pub fn check_header_value(value: &str) -> Result<(), HeaderError> {
+    for ch in value.bytes() {
+        if ch == b'\r' || ch == b'\n' {
+            return Err(HeaderError::WrongLineEnd);
+        }
+        if (ch < 0x20 && ch != b'\t') || ch == 0x7F {
+            return Err(HeaderError::ControlCharacter);
+        }
+    }
+
+    Ok(())
+}
+
+/// RFC 7230, 3.2.6. "tchar".
+fn is_token_char(ch: u8) -> bool {
+    ch.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&ch)
+}
+
+/// Response serialized once and sent to many requests by `Request::send_prepared`, for hot constant endpoints like
+/// health checks or small JSON. Only "Date", "Connection" and default headers of server settings are added when sending,
+/// response transforms of settings are not applied.
+/// Can be used in multi-threaded environment after clone, clones share serialized data.
+#[derive(Clone)]
+pub struct PreparedResponse {
+    inner: Arc<PreparedInner>,
+}
+
+struct PreparedInner {
+    /// Code with name, like "200 OK".
+    code_with_name: Cow<'static, str>,
+    /// Header lines of handler, for overriding of default headers.
+    headers: String,
+    /// "Content-Length", headers, empty line and content.
+    identity: Vec<u8>,
+    /// Length of content in the end of `identity`.
+    content_len: usize,
+    /// Same with gzip content, see `gzip`.
+    gzip: Option<Vec<u8>>,
+}
+
+impl PreparedResponse {
+    /// Serializes response with header lines (each ends with "\r\n", including "Content-Type") and content.
+    /// Returns error if header lines are wrong, same as `Response::check_headers`.
+    pub fn new(code: u16, headers: &str, content: &[u8]) -> Result<Self, HeaderError> {
+        check_header_lines(headers)?;
+
+        Ok(PreparedResponse {
+            inner: Arc::new(PreparedInner {
+                code_with_name: http_status_code_with_name(code),
+                headers: headers.to_string(),
+                identity: serialize_prepared(headers, content),
+                content_len: content.len(),
+                gzip: None,
+            }),
+        })
+    }
+
+    /// Adds variant with gzip content, sent to clients with "Accept-Encoding: gzip". Not added if it isn't smaller.
+    pub fn gzip(mut self) -> Self {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            let content = &inner.identity[inner.identity.len() - inner.content_len..];
+            let compressed = deflate::deflate_bytes_gzip(content);
+            if compressed.len() < content.len() {
+                let headers = format!("{}Content-Encoding: gzip\r\nVary: Accept-Encoding\r\n", inner.headers);
+                inner.gzip = Some(serialize_prepared(&headers, &compressed));
+            }
+        }
+
+        self
+    }
+
+    /// Full response for the request.
+    pub(crate) fn bytes_for(&self, request: &Request) -> Vec<u8> {
+        let accepts_gzip = request.header_value("Accept-Encoding").is_some_and(|encoding| accepts_encoding(encoding, "gzip"));
+        let variant = match &self.inner.gzip {
+            Some(gzip) if accepts_gzip => gzip,
+            _ => &self.inner.identity,
+        };
+
+        let version = request.version().to_string_for_response();
+        let date = request.rfc7231_date_string();
+        let connection = connection_str_by_request(request.request_data());
+        let default_headers = default_headers_str(request, &[&self.inner.headers]);
+
+        let mut result = Vec::with_capacity(version.len() + self.inner.code_with_name.len() + date.len() + connection.len() + default_headers.len() + variant.len() + 16);
+        result.extend_from_slice(version.as_bytes());
+        result.push(b' ');
+        result.extend_from_slice(self.inner.code_with_name.as_bytes());
+        result.extend_from_slice(b"\r\nDate: ");
+        result.extend_from_slice(date.as_bytes());
+        result.extend_from_slice(b"\r\n");
+        result.extend_from_slice(connection.as_bytes());
+        result.extend_from_slice(default_headers.as_bytes());
+        result.extend_from_slice(variant);
+        result
+    }
+}
+
+fn serialize_prepared(headers: &str, content: &[u8]) -> Vec<u8> {
+    let mut result = Vec::from(format!("Content-Length: {}\r\n{}\r\n", content.len(), headers));
+    result.extend_from_slice(content);
+    result
+}
+
+/// Returns default response headers of request (see `Settings::default_response_headers`) as string of header lines,
+/// except headers with names present in `headers` strings.
+pub(crate) fn default_headers_str(request: &Request, headers: &[&str]) -> String {
+    let mut result = String::new();
+    for (name, value) in request.default_response_headers() {
+        let overridden = headers.iter()
+            .flat_map(|headers| headers.split("\r\n"))
+            .any(|line| line.split(':').next().is_some_and(|line_name| line_name.trim().eq_ignore_ascii_case(name)));
+
+        if !overridden {
+            result.push_str(name);
+            result.push_str(": ");
+            result.push_str(value);
+            result.push_str("\r\n");
+        }
+    }
+
+    result
+}
+
 pub fn connection_str_by_request(request: &RequestData) -> &'static str {
     if let Some(connection_type) = &request.connection_type() {
         match connection_type {
-            ConnectionType::KeepAlive => "Connection: keep_alive\r\n",
+            ConnectionType::KeepAlive => "Connection: keep-alive\r\n",
             _ => "Connection: close\r\n",
         }
     } else {
         match request.version {
-            HttpVersion::Http1_1 => "Connection: keep_alive\r\n",
+            HttpVersion::Http1_1 => "Connection: keep-alive\r\n",
             _ => "",
         }
     }
 }
 
-/// Return code name by code number.
-pub fn http_status_code_with_name(code: u16) -> &'static str {
+/// Return code name by code number, for example "404 Not Found".
+/// Names registered by `register_status_code` take precedence over standard ones.
+/// Unknown codes get generic name of their class, for example "599 Server Error",
+/// codes out of range 100..=999 are sent as "500 Internal Server Error".
+pub fn http_status_code_with_name(code: u16) -> Cow<'static, str> {
+    if CUSTOM_CODES_SET.load(Ordering::SeqCst) {
+        if let Some(name) = custom_status_code_with_name(code) {
+            return Cow::Owned(name);
+        }
+    }
+
     match HTTP_CODES_WITH_NAME_BY_CODE.binary_search_by(|probe| probe.0.cmp(&code)) {
-        Ok(index) => HTTP_CODES_WITH_NAME_BY_CODE[index].1,
-        Err(_) => "",
+        Ok(index) => Cow::Borrowed(HTTP_CODES_WITH_NAME_BY_CODE[index].1),
+        Err(_) if (100..=999).contains(&code) => generic_status_code_with_name(code),
+        Err(_) => http_status_code_with_name(500),
     }
 }
 
+/// Registers reason phrase of nonstandard status code or replaces reason of standard one,
+/// for example `register_status_code(599, "Network Connect Timeout Error")`.
+pub fn register_status_code(code: u16, reason: &str) -> Result<(), StatusCodeError> {
+    if !(100..=999).contains(&code) {
+        return Err(StatusCodeError::WrongCode);
+    }
+
+    // reason-phrase = *( HTAB / SP / VCHAR / obs-text )
+    if reason.bytes().any(|byte| byte != b'\t' && (byte < 0x20 || byte == 0x7f)) {
+        return Err(StatusCodeError::WrongReason);
+    }
+
+    let name = format!("{} {}", code, reason);
+    let mut custom_codes = CUSTOM_CODES_WITH_NAME.write().unwrap_or_else(|err| err.into_inner());
+    match custom_codes.binary_search_by(|probe| probe.0.cmp(&code)) {
+        Ok(index) => custom_codes[index].1 = name,
+        Err(index) => custom_codes.insert(index, (code, name)),
+    }
+
+    CUSTOM_CODES_SET.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Error of `register_status_code`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatusCodeError {
+    /// Code is out of range 100..=999.
+    WrongCode,
+    /// Reason contains line end or other control character.
+    WrongReason,
+}
+
+impl std::fmt::Display for StatusCodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for StatusCodeError {}
+
+fn custom_status_code_with_name(code: u16) -> Option<String> {
+    let custom_codes = CUSTOM_CODES_WITH_NAME.read().ok()?;
+    let index = custom_codes.binary_search_by(|probe| probe.0.cmp(&code)).ok()?;
+    Some(custom_codes[index].1.clone())
+}
+
+/// Name of unknown code by its class.
+fn generic_status_code_with_name(code: u16) -> Cow<'static, str> {
+    let class = match code / 100 {
+        1 => "Informational",
+        2 => "Success",
+        3 => "Redirection",
+        4 => "Client Error",
+        5 => "Server Error",
+        _ => "Unknown",
+    };
+
+    Cow::Owned(format!("{} {}", code, class))
+}
+
+/// Codes with names registered by `register_status_code`, sorted by code.
+static CUSTOM_CODES_WITH_NAME: RwLock<Vec<(u16, String)>> = RwLock::new(Vec::new());
+/// Any code is registered, allows to skip locking.
+static CUSTOM_CODES_SET: AtomicBool = AtomicBool::new(false);
+
 // from https://en.wikipedia.org/wiki/List_of_HTTP_status_codes
 pub static HTTP_CODES_WITH_NAME_BY_CODE: &[(u16, &str)] = &[
     // The server has received the request headers and the client should proceed to send the request
@@ -430,6 +1261,77 @@ pub static HTTP_CODES_WITH_NAME_BY_CODE: &[(u16, &str)] = &[
 ];
 
 /// Determines whether to close the connection after responding by the content of the request.
+/// Returns true if the end of response with "Content-Length" and extra `headers` can be found by the client,
+/// so the connection can be reused. If "Transfer-Encoding" is set, "Content-Length" is ignored by the client,
+/// and the response must be chunked in HTTP/1.1 (HTTP/1.0 doesn't support chunked encoding).
+pub fn framing_allows_keep_alive(version: &HttpVersion, headers: &str) -> bool {
+    let transfer_encoding = headers.split("\r\n")
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next()?.trim();
+            let value = parts.next()?.trim();
+            if name.eq_ignore_ascii_case("Transfer-Encoding") { Some(value) } else { None }
+        })
+        .last();
+
+    match transfer_encoding {
+        None => true,
+        Some(transfer_encoding) => {
+            let last_coding = transfer_encoding.rsplit(',').next().unwrap_or("").trim();
+            *version == HttpVersion::Http1_1 && last_coding.eq_ignore_ascii_case("chunked")
+        }
+    }
+}
+
+/// Header lines without lines with names from `names`.
+pub(crate) fn without_headers<'h>(headers: &'h str, names: &[&str]) -> Cow<'h, str> {
+    let dropped = |line: &str| names.iter().any(|name| line.split(':').next().is_some_and(|line_name| line_name.trim().eq_ignore_ascii_case(name)));
+    if !headers.split_inclusive("\r\n").any(dropped) {
+        return Cow::Borrowed(headers);
+    }
+
+    Cow::Owned(headers.split_inclusive("\r\n").filter(|line| !dropped(line)).collect())
+}
+
+/// Weak comparison of "If-None-Match" header value with ETag (RFC 7232, 3.2).
+pub(crate) fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    if_none_match.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// Content coding is listed in "Accept-Encoding" header value (RFC 7231, 5.3.4) and not refused with "q=0".
+/// The coding listed by name takes precedence over "*", for example "gzip;q=0, *" refuses gzip.
+pub(crate) fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    let mut named = None;
+    let mut wildcard = None;
+    for coding in accept_encoding.split(',') {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let refused = params.any(|param| {
+            let param = param.trim();
+            param.len() > 2 && param[..2].eq_ignore_ascii_case("q=") && param[2..].trim().parse::<f32>().is_ok_and(|q| q == 0.0)
+        });
+
+        if name.eq_ignore_ascii_case(encoding) {
+            named = Some(named.unwrap_or(false) || !refused);
+        } else if name == "*" {
+            wildcard = Some(wildcard.unwrap_or(false) || !refused);
+        }
+    }
+
+    named.or(wildcard).unwrap_or(false)
+}
+
+/// Decompresses precompressed content for client that doesn't accept its encoding, see `Response::precompressed`.
+fn decompress(encoding: &str, content: &[u8], limit: Option<usize>) -> Result<Vec<u8>, HandlerError> {
+    let mut decoder = ContentDecoder::new(encoding, limit.unwrap_or(usize::MAX))
+        .ok_or_else(|| HandlerError::new(500, format!("unsupported content encoding {}", encoding)))?;
+    let mut result = decoder.push(content)?;
+    result.extend_from_slice(&decoder.finish()?);
+    Ok(result)
+}
+
 pub fn need_close_by_request(request: &RequestData) -> bool {
     if let Some(connection_type) = &request.connection_type() {
         if let ConnectionType::Close = connection_type {
@@ -0,0 +1,411 @@
+use crate::concurrency::ConcurrencyLimit;
+use crate::cors::Cors;
+use crate::extract::{FromRequest, Params};
+use crate::guard::Guard;
+use crate::handler_error::HandlerError;
+use crate::request::Request;
+use std::sync::Arc;
+
+/// Result of request handler.
+pub type HandlerResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Type erased handler with extraction of its inputs.
+type BoxedHandler = Box<dyn Fn(Request, Params, usize/*content limit*/) -> HandlerResult + Send + Sync>;
+
+/// Selects the handler for request by method, path pattern and guards.
+/// Handlers declare their inputs as extractors (see `extract` module), if extraction fails
+/// the client receives response with error code and the handler is not called.
+/// Can be used in multi-threaded environment after clone.
+///
+/// Path patterns consist of segments separated by '/': static segments ("users"), named parameters
+/// (":id") and tail parameter ("*path") that matches all remaining segments. Empty segments are
+/// ignored, so "/users/" and "/users" are the same.
+#[derive(Clone)]
+pub struct Router {
+    inner: Arc<InnerRouter>,
+}
+
+struct InnerRouter {
+    /// Routes in order of adding. First matched route is selected.
+    routes: Vec<(Route, BoxedHandler)>,
+    /// Handler that is called if no route matched.
+    fallback: Option<BoxedHandler>,
+    /// Maximum length of content that extractors can read to the RAM.
+    content_limit: usize,
+    /// Concurrency limits of requests with path prefixes.
+    prefix_limits: Vec<(Pattern, ConcurrencyLimit)>,
+    /// CORS policy of routes without own policy.
+    cors: Option<Cors>,
+}
+
+impl Router {
+    /// Creates router without routes.
+    pub fn new() -> Self {
+        Router {
+            inner: Arc::new(InnerRouter {
+                routes: Vec::new(),
+                fallback: None,
+                content_limit: 1_000_000,
+                prefix_limits: Vec::new(),
+                cors: None,
+            }),
+        }
+    }
+
+    /// Adds route with handler.
+    pub fn route<E: FromRequest + 'static>(mut self, route: Route, handler: impl Fn(Request, E) -> HandlerResult + Send + Sync + 'static) -> Self {
+        self.inner_mut().routes.push((route, boxed_handler(handler)));
+        self
+    }
+
+    /// Adds route for "GET" method.
+    pub fn get<E: FromRequest + 'static>(self, pattern: &str, handler: impl Fn(Request, E) -> HandlerResult + Send + Sync + 'static) -> Self {
+        self.route(Route::new(pattern).method("GET"), handler)
+    }
+
+    /// Adds route for "POST" method.
+    pub fn post<E: FromRequest + 'static>(self, pattern: &str, handler: impl Fn(Request, E) -> HandlerResult + Send + Sync + 'static) -> Self {
+        self.route(Route::new(pattern).method("POST"), handler)
+    }
+
+    /// Adds route for "PUT" method.
+    pub fn put<E: FromRequest + 'static>(self, pattern: &str, handler: impl Fn(Request, E) -> HandlerResult + Send + Sync + 'static) -> Self {
+        self.route(Route::new(pattern).method("PUT"), handler)
+    }
+
+    /// Adds route for "DELETE" method.
+    pub fn delete<E: FromRequest + 'static>(self, pattern: &str, handler: impl Fn(Request, E) -> HandlerResult + Send + Sync + 'static) -> Self {
+        self.route(Route::new(pattern).method("DELETE"), handler)
+    }
+
+    /// Sets handler that is called if no route matched. By default response is "404 Not Found".
+    pub fn fallback<E: FromRequest + 'static>(mut self, handler: impl Fn(Request, E) -> HandlerResult + Send + Sync + 'static) -> Self {
+        self.inner_mut().fallback = Some(boxed_handler(handler));
+        self
+    }
+
+    /// Maximum length of content that extractors (`Form`, `Json`, `Content`) can read to the RAM.
+    /// If "Content-Length" of request is bigger, the client receives "413 Payload Too Large".
+    pub fn content_limit(mut self, limit: usize) -> Self {
+        self.inner_mut().content_limit = limit;
+        self
+    }
+
+    /// Limits simultaneous in-flight requests to all routes.
+    pub fn limit(self, limit: ConcurrencyLimit) -> Self {
+        self.limit_prefix("/", limit)
+    }
+
+    /// Limits simultaneous in-flight requests to all routes with paths starting with `prefix` segments.
+    /// Request must pass prefix limits in order of adding, then limit of route (see `Route::limit`).
+    pub fn limit_prefix(mut self, prefix: &str, limit: ConcurrencyLimit) -> Self {
+        self.inner_mut().prefix_limits.push((Pattern::parse(prefix), limit));
+        self
+    }
+
+    /// CORS policy of all routes, route can have own policy (see `Route::cors`).
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.inner_mut().cors = Some(cors);
+        self
+    }
+
+    /// Finds route for request and calls its handler.
+    /// If path matched but method didn't, the client receives "405 Method Not Allowed".
+    /// CORS preflight request is answered by policy of route for the requested method, see `Router::cors`.
+    pub fn dispatch(&self, mut request: Request) -> HandlerResult {
+        let mut allowed_methods: Vec<&str> = Vec::new();
+        // decoded segments, so encoded slash "%2F" is part of segment and not separator
+        let path_segments = request.path_segments().map(|segment| segment.into_owned()).collect::<Vec<_>>();
+
+        if Cors::is_preflight(&request) {
+            let requested_method = request.header_value("Access-Control-Request-Method").unwrap_or("");
+            let cors = self.inner.routes.iter()
+                .find(|(route, _)| route.method.is_none_or(|method| method == requested_method)
+                    && route.pattern.match_segments(path_segments.iter().map(|segment| segment.as_str())).is_some())
+                .and_then(|(route, _)| route.cors.as_ref().or(self.inner.cors.as_ref()));
+            if let Some(cors) = cors {
+                cors.respond_preflight(request);
+                return Ok(());
+            }
+        }
+
+        for (route_index, (route, _)) in self.inner.routes.iter().enumerate() {
+            let params = match route.pattern.match_segments(path_segments.iter().map(|segment| segment.as_ref())) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            if let Some(method) = route.method {
+                if method != request.method() {
+                    if !allowed_methods.contains(&method) {
+                        allowed_methods.push(method);
+                    }
+                    continue;
+                }
+            }
+
+            if route.guards.iter().all(|guard| guard.check(&request)) {
+                if let Some(cors) = route.cors.as_ref().or(self.inner.cors.as_ref()) {
+                    cors.apply(&mut request);
+                }
+
+                if let Some(policy) = route.expect_continue.as_ref().filter(|_| request.expects_continue()) {
+                    // content is not transmitted yet, rejection closes the connection
+                    policy(&request)?;
+                }
+
+                let mut limits = Vec::new();
+                for (prefix, limit) in &self.inner.prefix_limits {
+                    if prefix.match_prefix(path_segments.iter().map(|segment| segment.as_ref())) {
+                        limits.push(limit.clone());
+                    }
+                }
+                if let Some(limit) = &route.limit {
+                    limits.push(limit.clone());
+                }
+
+                return self.call_route(route_index, request, params, &limits);
+            }
+        }
+
+        if let Some(fallback) = &self.inner.fallback {
+            return fallback(request, Params::default(), self.inner.content_limit);
+        }
+
+        if !allowed_methods.is_empty() {
+            request.response(405).allow(&allowed_methods).text("405 Method Not Allowed").send();
+            return Ok(());
+        }
+
+        request.response(404).text("404 Not Found").send();
+        Ok(())
+    }
+
+    /// Dispatches the request re-targeted to other path and query, see `Request::forward_to`.
+    /// For example, authentication gateway route hands off the request to protected handler in-process.
+    pub fn forward(&self, request: Request, target: &str) -> HandlerResult {
+        self.dispatch(request.forward_to(target)?)
+    }
+
+    /// Calls handler of route after passing of all concurrency limits.
+    fn call_route(&self, route_index: usize, request: Request, params: Params, limits: &[ConcurrencyLimit]) -> HandlerResult {
+        match limits.split_first() {
+            Some((limit, rest_limits)) => {
+                let router = self.clone();
+                let rest_limits = rest_limits.to_vec();
+                limit.run(request, move |request| router.call_route(route_index, request, params, &rest_limits))
+            }
+            None => {
+                let (_, handler) = &self.inner.routes[route_index];
+                handler(request, params, self.inner.content_limit)
+            }
+        }
+    }
+
+    /// Routes can be added only before the router is cloned.
+    fn inner_mut(&mut self) -> &mut InnerRouter {
+        Arc::get_mut(&mut self.inner).expect("routes must be added before router is cloned")
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+/// Route conditions: path pattern, method and guards.
+pub struct Route {
+    pattern: Pattern,
+    method: Option<&'static str>,
+    guards: Vec<Box<dyn Guard>>,
+    limit: Option<ConcurrencyLimit>,
+    expect_continue: Option<ContinuePolicy>,
+    cors: Option<Cors>,
+}
+
+/// Decides whether the client that waits for "100 Continue" may send content, see `Route::expect_continue`.
+pub type ContinuePolicy = Box<dyn Fn(&Request) -> Result<(), HandlerError> + Send + Sync>;
+
+impl Route {
+    /// Creates route with path pattern for any method.
+    pub fn new(pattern: &str) -> Self {
+        Route {
+            pattern: Pattern::parse(pattern),
+            method: None,
+            guards: Vec::new(),
+            limit: None,
+            expect_continue: None,
+            cors: None,
+        }
+    }
+
+    /// Route only for specified method.
+    pub fn method(mut self, method: &'static str) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// Adds guard. All guards must pass for route to be selected.
+    pub fn guard(mut self, guard: impl Guard + 'static) -> Self {
+        self.guards.push(Box::new(guard));
+        self
+    }
+
+    /// Limits simultaneous in-flight requests to this route.
+    pub fn limit(mut self, limit: ConcurrencyLimit) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Checks headers of requests with "Expect: 100-continue" before the content is transmitted, for example
+    /// authorization and "Content-Length" of big uploads. `Ok` lets the client send content ("100 Continue" is sent
+    /// when the handler reads it), error is sent as final response, like "417 Expectation Failed" or
+    /// "413 Payload Too Large", and the connection is closed. Called before concurrency limits and extractors.
+    pub fn expect_continue(mut self, policy: impl Fn(&Request) -> Result<(), HandlerError> + Send + Sync + 'static) -> Self {
+        self.expect_continue = Some(Box::new(policy));
+        self
+    }
+
+    /// CORS policy of this route instead of policy of router (see `Router::cors`).
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+}
+
+/// Parsed path pattern like "/users/:id/*tail".
+pub(crate) struct Pattern {
+    segments: Vec<Segment>,
+}
+
+enum Segment {
+    /// Segment must be equal.
+    Static(String),
+    /// Any segment, value will be stored as parameter with name.
+    Param(String),
+    /// All remaining segments, value will be stored as parameter with name.
+    Tail(String),
+}
+
+impl Pattern {
+    pub(crate) fn parse(pattern: &str) -> Self {
+        let segments = pattern.split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Tail(name.to_string())
+                } else {
+                    Segment::Static(segment.to_string())
+                }
+            })
+            .collect();
+
+        Pattern { segments }
+    }
+
+    /// Returns parameters if path matches the pattern.
+    #[cfg(test)]
+    pub(crate) fn match_path(&self, path: &str) -> Option<Params> {
+        self.match_segments(path.split('/').filter(|segment| !segment.is_empty()))
+    }
+
+    /// Returns parameters if not empty segments of path match the pattern.
+    pub(crate) fn match_segments<'a>(&self, mut path_segments: impl Iterator<Item = &'a str>) -> Option<Params> {
+        let mut params = Params::default();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Static(expected) => {
+                    if path_segments.next()? != expected {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.push(name, path_segments.next()?);
+                }
+                Segment::Tail(name) => {
+                    let tail = path_segments.by_ref().collect::<Vec<&str>>().join("/");
+                    params.push(name, &tail);
+                }
+            }
+        }
+
+        if path_segments.next().is_some() {
+            return None;
+        }
+
+        Some(params)
+    }
+
+    /// Returns true if not empty segments of path start with segments of the pattern. Parameters are not stored.
+    pub(crate) fn match_prefix<'a>(&self, mut path_segments: impl Iterator<Item = &'a str>) -> bool {
+        for segment in &self.segments {
+            match segment {
+                Segment::Static(expected) => {
+                    if path_segments.next() != Some(expected.as_str()) {
+                        return false;
+                    }
+                }
+                Segment::Param(_) => {
+                    if path_segments.next().is_none() {
+                        return false;
+                    }
+                }
+                Segment::Tail(_) => {
+                    return true;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Wraps handler with extraction of its inputs. If inputs need the content, it's read before the handler is called.
+fn boxed_handler<E: FromRequest + 'static>(handler: impl Fn(Request, E) -> HandlerResult + Send + Sync + 'static) -> BoxedHandler {
+    let handler = Arc::new(handler);
+
+    Box::new(move |request, params, content_limit| {
+        if !E::needs_content() {
+            return match E::from_request(&request, &params, &[]) {
+                Ok(extracted) => handler(request, extracted),
+                Err(rejection) => {
+                    rejection.send(request);
+                    Ok(())
+                }
+            };
+        }
+
+        if request.content_len() > content_limit {
+            request.response(413).text("413 Payload Too Large").close().send();
+            return Ok(());
+        }
+
+        let handler = handler.clone();
+        let mut content = Vec::with_capacity(request.content_len());
+        let chunked = request.is_chunked();
+        request.read_content(move |data, complete| {
+            // length of chunked content is known only after receiving
+            if chunked && content.len() + data.len() > content_limit {
+                return Err(HandlerError::new(413, "413 Payload Too Large").into());
+            }
+            content.extend_from_slice(data);
+            if let Some(request) = complete {
+                return match E::from_request(&request, &params, &content) {
+                    Ok(extracted) => handler(request, extracted),
+                    Err(rejection) => {
+                        rejection.send(request);
+                        Ok(())
+                    }
+                };
+            }
+
+            Ok(())
+        });
+
+        Ok(())
+    })
+}
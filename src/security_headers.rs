@@ -0,0 +1,358 @@
+use std::time::Duration;
+
+/// Builder of security related response headers.
+/// Result can be used as default response headers of server (see `Settings::default_response_headers`)
+/// or as extra headers of single response (see `Response::headers`).
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeaders {
+    content_security_policy: Option<ContentSecurityPolicy>,
+    strict_transport_security: Option<StrictTransportSecurity>,
+    referrer_policy: Option<ReferrerPolicy>,
+    permissions_policy: Option<PermissionsPolicy>,
+    frame_options: Option<FrameOptions>,
+    content_type_nosniff: bool,
+}
+
+impl SecurityHeaders {
+    /// Creates builder without headers.
+    pub fn new() -> Self {
+        SecurityHeaders::default()
+    }
+
+    /// Preset with strict values: CSP "default-src 'self'", HSTS for one year with subdomains,
+    /// "no-referrer", frames denied and "X-Content-Type-Options: nosniff".
+    pub fn strict() -> Self {
+        SecurityHeaders::new()
+            .content_security_policy(ContentSecurityPolicy::new().directive("default-src", &["'self'"]))
+            .strict_transport_security(StrictTransportSecurity::new(Duration::from_secs(31_536_000)).include_subdomains())
+            .referrer_policy(ReferrerPolicy::NoReferrer)
+            .frame_options(FrameOptions::Deny)
+            .content_type_nosniff()
+    }
+
+    /// Sets "Content-Security-Policy" header.
+    pub fn content_security_policy(mut self, policy: ContentSecurityPolicy) -> Self {
+        self.content_security_policy = Some(policy);
+        self
+    }
+
+    /// Sets "Strict-Transport-Security" header. Browsers ignore it in responses over plain HTTP.
+    pub fn strict_transport_security(mut self, hsts: StrictTransportSecurity) -> Self {
+        self.strict_transport_security = Some(hsts);
+        self
+    }
+
+    /// Sets "Referrer-Policy" header.
+    pub fn referrer_policy(mut self, policy: ReferrerPolicy) -> Self {
+        self.referrer_policy = Some(policy);
+        self
+    }
+
+    /// Sets "Permissions-Policy" header.
+    pub fn permissions_policy(mut self, policy: PermissionsPolicy) -> Self {
+        self.permissions_policy = Some(policy);
+        self
+    }
+
+    /// Sets "X-Frame-Options" header.
+    pub fn frame_options(mut self, frame_options: FrameOptions) -> Self {
+        self.frame_options = Some(frame_options);
+        self
+    }
+
+    /// Sets "X-Content-Type-Options: nosniff" header.
+    pub fn content_type_nosniff(mut self) -> Self {
+        self.content_type_nosniff = true;
+        self
+    }
+
+    /// Validates and returns headers as (name, value) pairs.
+    pub fn headers(&self) -> Result<Vec<(String, String)>, SecurityHeadersError> {
+        let mut headers = Vec::new();
+
+        if let Some(policy) = &self.content_security_policy {
+            headers.push(("Content-Security-Policy".to_string(), policy.value()?));
+        }
+        if let Some(hsts) = &self.strict_transport_security {
+            headers.push(("Strict-Transport-Security".to_string(), hsts.value()?));
+        }
+        if let Some(policy) = &self.referrer_policy {
+            headers.push(("Referrer-Policy".to_string(), policy.as_str().to_string()));
+        }
+        if let Some(policy) = &self.permissions_policy {
+            headers.push(("Permissions-Policy".to_string(), policy.value()?));
+        }
+        if let Some(frame_options) = &self.frame_options {
+            headers.push(("X-Frame-Options".to_string(), frame_options.as_str().to_string()));
+        }
+        if self.content_type_nosniff {
+            headers.push(("X-Content-Type-Options".to_string(), "nosniff".to_string()));
+        }
+
+        Ok(headers)
+    }
+
+    /// Validates and returns headers as string of header lines for `Response::headers`.
+    pub fn header_lines(&self) -> Result<String, SecurityHeadersError> {
+        let mut result = String::new();
+        for (name, value) in self.headers()? {
+            result.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        Ok(result)
+    }
+}
+
+/// Value of "Content-Security-Policy" header.
+#[derive(Debug, Clone, Default)]
+pub struct ContentSecurityPolicy {
+    /// Directive names with source lists in order of adding.
+    directives: Vec<(String, Vec<String>)>,
+}
+
+impl ContentSecurityPolicy {
+    /// Creates policy without directives.
+    pub fn new() -> Self {
+        ContentSecurityPolicy::default()
+    }
+
+    /// Adds directive, for example `directive("script-src", &["'self'", "https://cdn.example.com"])`.
+    pub fn directive(mut self, name: &str, sources: &[&str]) -> Self {
+        self.directives.push((name.to_string(), sources.iter().map(|source| source.to_string()).collect()));
+        self
+    }
+
+    /// Validates directives and returns header value.
+    pub fn value(&self) -> Result<String, SecurityHeadersError> {
+        if self.directives.is_empty() {
+            return Err(SecurityHeadersError::EmptyPolicy);
+        }
+
+        let mut directives = Vec::with_capacity(self.directives.len());
+        for (name, sources) in &self.directives {
+            if !is_directive_name(name) {
+                return Err(SecurityHeadersError::WrongDirectiveName(name.clone()));
+            }
+            if directives.iter().any(|directive: &String| directive.split(' ').next() == Some(name.as_str())) {
+                return Err(SecurityHeadersError::DuplicateDirective(name.clone()));
+            }
+            for source in sources {
+                if !is_csp_source(source) {
+                    return Err(SecurityHeadersError::WrongSource { directive: name.clone(), source: source.clone() });
+                }
+            }
+
+            let mut directive = name.clone();
+            for source in sources {
+                directive.push(' ');
+                directive.push_str(source);
+            }
+            directives.push(directive);
+        }
+
+        Ok(directives.join("; "))
+    }
+}
+
+/// Value of "Strict-Transport-Security" header.
+#[derive(Debug, Clone)]
+pub struct StrictTransportSecurity {
+    max_age: Duration,
+    include_subdomains: bool,
+    preload: bool,
+}
+
+impl StrictTransportSecurity {
+    /// Creates value with time during which the browser must use only HTTPS.
+    pub fn new(max_age: Duration) -> Self {
+        StrictTransportSecurity { max_age, include_subdomains: false, preload: false }
+    }
+
+    /// Adds "includeSubDomains" directive.
+    pub fn include_subdomains(mut self) -> Self {
+        self.include_subdomains = true;
+        self
+    }
+
+    /// Adds "preload" directive. Requires "includeSubDomains" and max age at least one year.
+    pub fn preload(mut self) -> Self {
+        self.preload = true;
+        self
+    }
+
+    /// Validates directives and returns header value.
+    pub fn value(&self) -> Result<String, SecurityHeadersError> {
+        if self.preload && (!self.include_subdomains || self.max_age.as_secs() < 31_536_000) {
+            return Err(SecurityHeadersError::WrongPreload);
+        }
+
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+
+        Ok(value)
+    }
+}
+
+/// Value of "Referrer-Policy" header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    Origin,
+    OriginWhenCrossOrigin,
+    SameOrigin,
+    StrictOrigin,
+    StrictOriginWhenCrossOrigin,
+    UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+    /// Header value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReferrerPolicy::NoReferrer => "no-referrer",
+            ReferrerPolicy::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+            ReferrerPolicy::Origin => "origin",
+            ReferrerPolicy::OriginWhenCrossOrigin => "origin-when-cross-origin",
+            ReferrerPolicy::SameOrigin => "same-origin",
+            ReferrerPolicy::StrictOrigin => "strict-origin",
+            ReferrerPolicy::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+            ReferrerPolicy::UnsafeUrl => "unsafe-url",
+        }
+    }
+}
+
+/// Value of "X-Frame-Options" header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOptions {
+    Deny,
+    SameOrigin,
+}
+
+impl FrameOptions {
+    /// Header value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrameOptions::Deny => "DENY",
+            FrameOptions::SameOrigin => "SAMEORIGIN",
+        }
+    }
+}
+
+/// Value of "Permissions-Policy" header.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionsPolicy {
+    /// Features with allow lists in order of adding.
+    features: Vec<(String, Vec<String>)>,
+}
+
+impl PermissionsPolicy {
+    /// Creates policy without features.
+    pub fn new() -> Self {
+        PermissionsPolicy::default()
+    }
+
+    /// Adds feature with allow list. Items of allow list are "self", "*" or origins like "https://example.com".
+    /// Empty allow list disables the feature, for example `feature("camera", &[])` is "camera=()".
+    pub fn feature(mut self, name: &str, allowlist: &[&str]) -> Self {
+        self.features.push((name.to_string(), allowlist.iter().map(|item| item.to_string()).collect()));
+        self
+    }
+
+    /// Validates features and returns header value.
+    pub fn value(&self) -> Result<String, SecurityHeadersError> {
+        if self.features.is_empty() {
+            return Err(SecurityHeadersError::EmptyPolicy);
+        }
+
+        let mut features = Vec::with_capacity(self.features.len());
+        for (name, allowlist) in &self.features {
+            if !is_directive_name(name) {
+                return Err(SecurityHeadersError::WrongDirectiveName(name.clone()));
+            }
+
+            let mut items = Vec::with_capacity(allowlist.len());
+            for item in allowlist {
+                match item.as_str() {
+                    "self" | "*" => items.push(item.clone()),
+                    origin if is_origin(origin) => items.push(format!("\"{}\"", origin)),
+                    _ => return Err(SecurityHeadersError::WrongSource { directive: name.clone(), source: item.clone() }),
+                }
+            }
+
+            if allowlist.len() == 1 && allowlist[0] == "*" {
+                features.push(format!("{}=*", name));
+            } else {
+                features.push(format!("{}=({})", name, items.join(" ")));
+            }
+        }
+
+        Ok(features.join(", "))
+    }
+}
+
+/// Error of validation of security headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecurityHeadersError {
+    /// Policy has no directives.
+    EmptyPolicy,
+    /// Directive or feature name must consist of lowercase latin letters and '-'.
+    WrongDirectiveName(String),
+    /// Directive is specified more than once.
+    DuplicateDirective(String),
+    /// Source of directive contains wrong characters or unknown quoted keyword.
+    WrongSource { directive: String, source: String },
+    /// HSTS "preload" requires "includeSubDomains" and max age at least one year.
+    WrongPreload,
+}
+
+impl std::fmt::Display for SecurityHeadersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for SecurityHeadersError {}
+
+fn is_directive_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name.bytes().all(|ch| ch.is_ascii_lowercase() || ch == b'-')
+}
+
+/// Checks CSP source expression. Quoted keywords must be known, other sources must not contain separators.
+fn is_csp_source(source: &str) -> bool {
+    if source.is_empty() || source.bytes().any(|ch| ch == b';' || ch == b',' || ch.is_ascii_whitespace() || ch.is_ascii_control()) {
+        return false;
+    }
+
+    if let Some(keyword) = source.strip_prefix('\'') {
+        let keyword = match keyword.strip_suffix('\'') {
+            Some(keyword) => keyword,
+            None => return false,
+        };
+
+        return matches!(keyword, "self" | "none" | "unsafe-inline" | "unsafe-eval" | "unsafe-hashes" | "strict-dynamic" | "report-sample" | "wasm-unsafe-eval")
+            || keyword.starts_with("nonce-")
+            || keyword.starts_with("sha256-")
+            || keyword.starts_with("sha384-")
+            || keyword.starts_with("sha512-");
+    }
+
+    !source.contains('\'')
+}
+
+/// Checks origin of allow list of permissions policy like "https://example.com".
+fn is_origin(origin: &str) -> bool {
+    let host = match origin.strip_prefix("https://").or_else(|| origin.strip_prefix("http://")) {
+        Some(host) => host,
+        None => return false,
+    };
+
+    !host.is_empty() && host.bytes().all(|ch| ch.is_ascii_alphanumeric() || ch == b'.' || ch == b'-' || ch == b':' || ch == b'*')
+}
@@ -1,12 +1,22 @@
+use crate::acme::ChallengeStore;
+use crate::blocking_pool::BlockingPool;
+use crate::health::HealthCheck;
+use crate::stats::Stats;
 use crate::tcp_session::TcpSession;
-use crate::worker::Worker;
+use crate::throttle::AcceptRateLimit;
+#[cfg(unix)]
+use crate::tls_handshake::TlsHandshakePool;
+use crate::worker::{Waker, Worker};
 use crate::web_session;
+use crate::websocket::WebsocketRegistry;
 
 use mio::net::TcpListener;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 /// Server event.
 pub enum Event {
@@ -16,10 +26,60 @@ pub enum Event {
     Incoming(TcpSession),
     /// TCP connection was closed. This can be caused either by the server’s initiative when the connection cannot be served, or by forced closure at the initiative of the library user.
     Closed(u64 /*id*/),
+    /// Worker became overloaded (true) or load returned below thresholds (false), see `Settings::load_shedding`.
+    Overloaded(bool),
+    /// Number of connections of the server reached (true) or fell below (false) `Settings::connection_limit`.
+    /// Emitted by each worker that notices the change.
+    ConnectionLimitReached(bool),
+    /// TLS handshake with client failed, the connection is closed.
+    TlsHandshakeFailed { addr: SocketAddr, err: rustls::TLSError },
+    /// Connection is closed by timeout of `web_session::Settings`.
+    Timeout { session_id: u64, kind: TimeoutKind },
+    /// Connection is closed because it exceeded quota of `web_session::Settings::read_quota` or `write_quota`
+    /// (or set by `TcpSession::set_read_quota` or `set_write_quota`).
+    QuotaExceeded { session_id: u64, kind: QuotaKind },
+    /// Data waiting for write to the connection exceeded threshold of `web_session::Settings::slow_consumer` for its duration.
+    /// The connection is not closed.
+    SlowConsumer { session_id: u64, queued_bytes: usize },
+    /// Connection is closed because the client speaks other protocol than the listener.
+    /// Not detected for connections in `Settings::tls_handshake_pool`, their failure is `TlsHandshakeFailed`.
+    ProtocolMismatch { session_id: u64, kind: ProtocolMismatch },
+    /// Connection is closed right after accepting because the client exceeded `Settings::accept_rate_limit`.
+    RateLimited { addr: SocketAddr },
+    /// Phase of handoff of the listener to new process, see `Stopper::handoff`. Emitted by one of workers.
+    #[cfg(unix)]
+    Handoff(crate::handoff::HandoffPhase),
     /// Server error.
     Error(Error),
 }
 
+/// Kind of timeout by which connection was closed, see `Event::Timeout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// Request head is not received in `web_session::Settings::request_head_timeout`.
+    RequestHead,
+    /// Connection is idle longer than `web_session::Settings::idle_timeout`.
+    Idle,
+}
+
+/// Kind of quota by which connection was closed, see `Event::QuotaExceeded`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaKind {
+    /// Received data exceeded `web_session::Settings::read_quota`.
+    Read,
+    /// Written data exceeded `web_session::Settings::write_quota`.
+    Write,
+}
+
+/// Protocol of client other than of the listener, see `Event::ProtocolMismatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolMismatch {
+    /// TLS handshake on plaintext listener, the client got response by `web_session::Settings::tls_on_plaintext`.
+    TlsOnPlaintext,
+    /// Plaintext HTTP request on TLS listener, the connection is closed without TLS alert.
+    PlaintextOnTls,
+}
+
 /// HTTP server errors.
 #[derive(Debug)]
 pub enum Error {
@@ -30,6 +90,9 @@ pub enum Error {
     /// If panicked when processing client incoming data or user code in callbacks.
     /// Tcp connection will be closed, all related resources removed.
     Panicked(u64 /*tcp session id*/),
+    /// Lock of session data is poisoned by panic in user code in other thread.
+    /// Tcp connection will be closed, all related resources removed.
+    PoisonedLock(u64 /*tcp session id*/),
     /// When worker was not created (create mio poll or register listener error).
     WorkerNotCreated(std::io::Error),
     /// Worker panicked with cause of panic.
@@ -38,7 +101,7 @@ pub enum Error {
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
@@ -49,8 +112,48 @@ impl std::error::Error for Error {}
 pub struct Settings {
     /// Configuration of TLS (rustls).
     pub tls_config: Option<Arc<rustls::ServerConfig>>,
+    /// Threads for TLS handshakes instead of workers. Default None.
+    #[cfg(unix)]
+    pub tls_handshake_pool: Option<TlsHandshakePool>,
     // Settings of HTTP parser, websocket settings and other web things.
     pub web_settings: web_session::Settings,
+    /// How workers share accepting of new connections.
+    pub accept_policy: AcceptPolicy,
+    /// Limit of number of connections of all workers together. Default None.
+    pub connection_limit: Option<ConnectionLimit>,
+    /// Limit of rate of new connections from one IP address. Default None.
+    pub accept_rate_limit: Option<Arc<AcceptRateLimit>>,
+}
+
+/// Limit of number of connections of the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionLimit {
+    /// Maximum of connections of all workers together.
+    pub max_connections: usize,
+    /// What to do with new connections while the limit is reached.
+    pub action: ConnectionLimitAction,
+}
+
+/// Behavior of the server while number of connections is at `ConnectionLimit::max_connections`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionLimitAction {
+    /// Accept and close new connections immediately.
+    Close,
+    /// Accept new connections, write "503 Service Unavailable" and close. TLS connections are closed without response.
+    Respond503,
+    /// Stop accepting, new connections wait in the listen backlog until number of connections falls below the limit.
+    PauseAccepting,
+}
+
+/// How workers share accepting of new connections from the listener.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AcceptPolicy {
+    /// Any worker that is woken up first accepts. Default.
+    #[default]
+    Shared,
+    /// Worker accepts only if it has no more active sessions than the least loaded worker plus `slack`,
+    /// so long-lived connections (websockets, etc.) are evenly distributed between threads.
+    LeastConnections { slack: usize },
 }
 
 /// Multithreaded TCP server designed for use as an HTTP server.
@@ -66,29 +169,64 @@ pub struct Server {
 
     /// For stop the server.
     stopper: Stopper,
+
+    /// Statistics of workers.
+    stats: Stats,
 }
 
 impl Server {
     /// Constructs new HTTP server with default settings. Create new MIO listener. The created server is not running, to start, you need to call 'run' method.
     pub fn new(addr: &SocketAddr) -> Result<Server, std::io::Error> {
-        let tcp_listener = TcpListener::bind(&addr)?;
+        let tcp_listener = TcpListener::bind(addr)?;
         Ok(Self::new_from_listener(tcp_listener))
     }
 
     /// Constructs new HTTP server with default settings from existing MIO tcp listener. The created server is not running, to start, you need to call 'run' method.
     pub fn new_from_listener(tcp_listener: TcpListener) -> Self {
+        let stats = Stats::default();
         Server {
             workers: vec![],
             tcp_listener,
             num_threads: num_cpus::get(),
             settings: Settings {
                 tls_config: None,
+                #[cfg(unix)]
+                tls_handshake_pool: None,
                 web_settings: web_session::Settings::default(),
+                accept_policy: AcceptPolicy::default(),
+                connection_limit: None,
+                accept_rate_limit: None,
             },
-            stopper: Stopper { need_stop: Arc::new(AtomicBool::new(false)) },
+            stopper: Stopper::new(stats.clone()),
+            stats,
         }
     }
 
+    /// Constructs new HTTP server with default settings from the first listener passed by systemd socket activation,
+    /// so systemd can start the server on the first connection and keep the socket open between restarts.
+    /// Other passed listeners are closed, use `systemd::listeners` to serve them by several servers.
+    #[cfg(unix)]
+    pub fn from_systemd() -> Result<Server, std::io::Error> {
+        let listener = crate::systemd::listeners()?.into_iter().next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no listening socket is passed by systemd"))?;
+        Ok(Self::new_from_listener(TcpListener::from_std(listener)?))
+    }
+
+    /// Constructs new HTTP server with default settings from listener passed by previous process by `Stopper::handoff`,
+    /// so the binary can be upgraded without closing of the listening port.
+    #[cfg(unix)]
+    pub fn from_handoff() -> Result<Server, std::io::Error> {
+        let listener = crate::handoff::inherited_listener()?
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no listening socket is passed by previous process"))?;
+        Ok(Self::new_from_listener(TcpListener::from_std(listener)?))
+    }
+
+    /// Sets `threads` threads for `Request::spawn_blocking`, see `web_session::Settings::blocking_pool`.
+    pub fn with_blocking_pool(mut self, threads: usize) -> Self {
+        self.settings.web_settings.blocking_pool = Some(BlockingPool::new(threads));
+        self
+    }
+
     /// Starts the server entering an infinite loop.
     ///
     /// # Arguments
@@ -98,6 +236,8 @@ impl Server {
         self.workers = Vec::with_capacity(self.num_threads);
 
         let connections_counter = Arc::new(AtomicU64::new(0));
+        #[cfg(unix)]
+        self.stopper.set_listener(Some(self.tcp_listener.try_clone()?));
 
         for _ in 0..self.num_threads {
             let cloned_tcp_listener = self.tcp_listener.try_clone()?;
@@ -105,12 +245,16 @@ impl Server {
             let event_callback = event_callback.clone();
 
             let settings = self.settings.clone();
+            let stats = self.stats.clone();
 
             match Worker::new_from_listener(cloned_tcp_listener, self.stopper.clone()) {
                 Ok(mut worker) => {
+                     self.stopper.add_waker(worker.waker.clone());
+                     self.stats.add_worker(worker.counters.clone());
                      self.workers.push(std::thread::spawn(move || {
                          worker.connections_counter = connections_counter;
                          worker.settings = settings;
+                         worker.stats = stats;
                          worker.run(&mut |event| event_callback(event));
                      }));
                 }
@@ -128,32 +272,173 @@ impl Server {
             });
         }
 
+        // the port must be closed when the server is stopped
+        #[cfg(unix)]
+        self.stopper.set_listener(None);
+
         Ok(())
     }
 
+    /// Address the server is listening on, for example to find out the port when bound to port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
+        self.tcp_listener.local_addr()
+    }
+
     pub fn stopper(&self) -> Stopper {
         self.stopper.clone()
     }
+
+    /// Returns handle for reading statistics (active sessions, queued write bytes, etc.) of running server from other threads.
+    pub fn stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    /// Enables built-in responder of "/healthz" and "/readyz" requests if it isn't enabled yet.
+    /// Returns handle for change of readiness state (see `HealthCheck::set_ready`).
+    /// For other paths set `settings.web_settings.health_check` before start.
+    pub fn health_check(&mut self) -> HealthCheck {
+        self.settings.web_settings.health_check.get_or_insert_with(HealthCheck::new).clone()
+    }
+
+    /// Enables built-in responder of ACME HTTP-01 challenges if it isn't enabled yet.
+    /// Returns store for tokens of challenges, see `acme::ChallengeStore`.
+    pub fn acme_challenges(&mut self) -> ChallengeStore {
+        self.settings.web_settings.acme_challenges.get_or_insert_with(ChallengeStore::new).clone()
+    }
+
+    /// Returns registry of named groups of websockets for broadcasting from any thread, see `Websocket::join`.
+    pub fn websocket_registry(&self) -> WebsocketRegistry {
+        self.settings.web_settings.websocket_registry.clone()
+    }
 }
 
 /// For stop the server.
 #[derive(Clone)]
 pub struct Stopper {
     need_stop: Arc<AtomicBool>,
+    /// New connections are not accepted, see `drain`.
+    draining: Arc<AtomicBool>,
+    /// Wakers of workers, for applying of changes without waiting for events.
+    wakers: Arc<RwLock<Vec<Arc<Waker>>>>,
+    stats: Stats,
+    /// Clone of listener of running server for `handoff`.
+    #[cfg(unix)]
+    listener: Arc<Mutex<Option<TcpListener>>>,
+    /// Phases of handoff waiting for delivery to `Event::Handoff`.
+    #[cfg(unix)]
+    handoff_phases: Arc<Mutex<VecDeque<crate::handoff::HandoffPhase>>>,
 }
 
 impl Stopper {
     /// Stop the server. Server will stopped in new poll iteration.
     pub fn stop(&self) {
         self.need_stop.store(true, Ordering::SeqCst);
+        self.wake_workers();
+    }
+
+    /// Stops accepting of new connections and waits until data sent to all connections is written and all received
+    /// requests are dropped (responded), but no longer than `timeout`. Returns false if the timeout is expired.
+    /// Call `stop` after it for graceful shutdown or restart without truncated responses.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        self.draining.store(true, Ordering::SeqCst);
+        self.stats.reset_drain_checks();
+        self.wake_workers();
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.stats.drained() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            std::thread::sleep(DRAIN_CHECK_INTERVAL);
+        }
+    }
+
+    /// Starts new process by `command` with the listener of the server (see `Server::from_handoff`), then drains
+    /// the server like `drain`, so new connections are accepted by new process and the port isn't closed.
+    /// Phases are reported by `Event::Handoff`. Call `stop` after it to finish the old process.
+    #[cfg(unix)]
+    pub fn handoff(&self, command: &mut std::process::Command, drain_timeout: Duration) -> Result<std::process::Child, std::io::Error> {
+        use crate::handoff::HandoffPhase;
+
+        let child = match self.listener.lock() {
+            Ok(listener) => match &*listener {
+                Some(listener) => crate::handoff::spawn_with_listener(command, listener)?,
+                None => return Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "server isn't running")),
+            },
+            Err(_) => return Err(std::io::Error::other("listener lock is poisoned")),
+        };
+
+        self.push_handoff_phase(HandoffPhase::Spawned(child.id()));
+        self.push_handoff_phase(HandoffPhase::Draining);
+        let drained = self.drain(drain_timeout);
+        self.push_handoff_phase(HandoffPhase::Drained(drained));
+        Ok(child)
+    }
+
+    /// Takes next phase of handoff for `Event::Handoff`.
+    #[cfg(unix)]
+    pub(crate) fn take_handoff_phase(&self) -> Option<crate::handoff::HandoffPhase> {
+        self.handoff_phases.lock().ok()?.pop_front()
+    }
+
+    #[cfg(unix)]
+    fn push_handoff_phase(&self, phase: crate::handoff::HandoffPhase) {
+        if let Ok(mut handoff_phases) = self.handoff_phases.lock() {
+            handoff_phases.push_back(phase);
+        }
+        self.wake_workers();
+    }
+
+    /// Sets listener for `handoff` while the server is running.
+    #[cfg(unix)]
+    fn set_listener(&self, listener: Option<TcpListener>) {
+        if let Ok(mut current) = self.listener.lock() {
+            *current = listener;
+        }
     }
 
     /// Returns true if it is necessary to stop the server.
     pub(crate) fn need_stop(&self) -> bool {
         self.need_stop.load(Ordering::SeqCst)
     }
+
+    /// Returns true if new connections must not be accepted, see `drain`.
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
     /// Create new stopper.
-    pub(crate) fn new() -> Self {
-        Self { need_stop: Arc::new(AtomicBool::new(false)) }
+    pub(crate) fn new(stats: Stats) -> Self {
+        Self {
+            need_stop: Arc::new(AtomicBool::new(false)),
+            draining: Arc::new(AtomicBool::new(false)),
+            wakers: Arc::new(RwLock::new(Vec::new())),
+            stats,
+            #[cfg(unix)]
+            listener: Arc::new(Mutex::new(None)),
+            #[cfg(unix)]
+            handoff_phases: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub(crate) fn add_waker(&self, waker: Arc<Waker>) {
+        if let Ok(mut wakers) = self.wakers.write() {
+            wakers.push(waker);
+        }
+    }
+
+    fn wake_workers(&self) {
+        if let Ok(wakers) = self.wakers.read() {
+            for waker in wakers.iter() {
+                waker.wake_worker();
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Interval of checking of draining of workers, see `Stopper::drain`.
+pub(crate) const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
\ No newline at end of file
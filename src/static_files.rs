@@ -7,10 +7,11 @@ use std::io;
 use std::io::ErrorKind;
 use std::io::Read;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex};
+use arc_swap::ArcSwap;
 use std::thread::{sleep, spawn};
 use std::time::{Duration, SystemTime};
-use crate::response::need_close_by_request;
+use crate::response::{default_headers_str, etag_matches, need_close_by_request};
 
 /// Dynamic cache in the RAM of files on disk.
 /// It stores the files of the specified directory loaded in the RAM, monitors difference of
@@ -19,14 +20,16 @@ use crate::response::need_close_by_request;
 /// Directory monitoring is done in its own background thread (by default) or manually.
 /// Also it's manages browser-side cache.
 /// Can be used in multi-threaded environment after clone.
-/// Can't long time blocking operations when update cache.
+/// Requests read immutable snapshot of cache without locks, updating builds new snapshot and swaps it.
 /// For manually settings some parameters see 'Builder'.
 #[derive(Clone)]
 pub struct StaticFilesCache {
     /// Path to directory that will be cached in the RAM.
     dir_path: String,
-    /// Cached files data in the RAM and related information.
-    cached_files: Arc<RwLock<BTreeMap<String, StaticFileCache>>>,
+    /// Snapshot of cached files data in the RAM and related information.
+    cached_files: Arc<ArcSwap<CachedFiles>>,
+    /// Serializes changes of snapshot by updating, `add_bytes` and `add_file`, so they are not lost.
+    update_lock: Arc<Mutex<()>>,
 
     /// Need cache data as deflate compressed.
     deflate_encoding: bool,
@@ -36,9 +39,13 @@ pub struct StaticFilesCache {
     use_last_modified: bool,
     /// Need sending of "ETag" header and changes checking for browser cache.
     use_etag: bool,
+    /// How "ETag" is computed.
+    etag_algorithm: EtagAlgorithm,
 
     /// To try send small data in one write operation if data len less then this parameter.
     united_response_limit: usize,
+    /// Send "index.html" for unknown paths without extension, see `Builder::spa_fallback`.
+    spa_fallback: bool,
 }
 
 /// Cached file data and related information in the the RAM.
@@ -58,8 +65,12 @@ pub struct StaticFileCache {
     last_modified: SystemTime,
     /// Prepared string for value of http response header "Last-Modified".
     last_modified_rfc7231: String,
-    /// Prepared string for value of "ETag" header. md5 of all raw file data.
+    /// Prepared string for value of "ETag" header with quotes, see `EtagAlgorithm`.
     etag: String,
+    /// Value of "Cache-Control" header.
+    cache_control: Option<String>,
+    /// Registered by `add_bytes` or `add_file`, not removed and not replaced when updating by directory.
+    registered: bool,
 }
 
 impl StaticFilesCache {
@@ -70,16 +81,17 @@ impl StaticFilesCache {
 
     /// Creates new `Self` with parameters specified in builder.
     pub fn from_builder(path: &str, builder: &Builder) -> Self {
-        let cached_files = Arc::new(RwLock::new(BTreeMap::new()));
-
         let static_files = StaticFilesCache {
             dir_path: path.to_string(),
-            cached_files,
+            cached_files: Arc::new(ArcSwap::from_pointee(BTreeMap::new())),
+            update_lock: Arc::new(Mutex::new(())),
             deflate_encoding: builder.deflate_encoding,
             gzip_encoding: builder.gzip_encoding,
             use_last_modified: builder.use_last_modified,
             use_etag: builder.use_etag,
+            etag_algorithm: builder.etag_algorithm,
             united_response_limit: builder.united_response_limit,
+            spa_fallback: builder.spa_fallback,
         };
 
         let result = static_files.clone();
@@ -102,17 +114,39 @@ impl StaticFilesCache {
 
     /// Send response with file content to the client.
     pub fn send_response(&self, path: &str, request: &Request) -> io::Result<()> {
+        let path = if self.spa_fallback && !self.contains(path) && is_spa_route(path, request) {
+            "index.html"
+        } else {
+            path
+        };
+
         let mut result = Ok(());
 
-        let need_close_by_request = need_close_by_request(&request.request_data());
+        let need_close_by_request = need_close_by_request(request.request_data());
 
         self.get(path, |static_file| {
             match static_file {
                 Some(static_file) => {
+                    // compressed variants are different representations, each has its own ETag
+                    let accept_encoding = request.header_value("Accept-Encoding").unwrap_or_default();
+                    // ranges are served from the identity content
+                    let range = requested_range(request, static_file);
+                    let (content, encoding) = match (&static_file.deflate_data, &static_file.gzip_data) {
+                        _ if range.is_some() => (&static_file.raw_data, None),
+                        (Some(deflate_data), _) if accept_encoding.contains("deflate") => (deflate_data, Some("deflate")),
+                        (_, Some(gzip_data)) if accept_encoding.contains("gzip") => (gzip_data, Some("gzip")),
+                        _ => (&static_file.raw_data, None),
+                    };
+                    let etag = match encoding {
+                        Some(encoding) => variant_etag(&static_file.etag, encoding),
+                        None => static_file.etag.clone(),
+                    };
+                    let vary = if static_file.deflate_data.is_some() || static_file.gzip_data.is_some() { "Vary: Accept-Encoding\r\n" } else { "" };
+
                     let mut apply_browser_cache = false;
-                    if !static_file.etag.is_empty() {
+                    if !etag.is_empty() {
                         if let Some(if_none_match) = request.header_value("If-None-Match") {
-                            if static_file.etag == if_none_match {
+                            if etag_matches(if_none_match, &etag) {
                                 apply_browser_cache = true;
                             }
                         }
@@ -132,12 +166,18 @@ impl StaticFilesCache {
                              {}\
                              {}\
                              {}\
+                             {}\
+                             {}\
+                             {}\
                              \r\n",
                             request.version().to_string_for_response(),
                             request.rfc7231_date_string(),
                             crate::response::connection_str_by_request(request.request_data()),
+                            default_headers_str(request, &[]),
                             if static_file.last_modified_rfc7231.is_empty() { "".to_string() } else { format!("Last-Modified: {}\r\n", static_file.last_modified_rfc7231) },
-                            if static_file.etag.is_empty() { "".to_string() } else { format!("ETag: {}\r\n", static_file.etag) }
+                            if etag.is_empty() { "".to_string() } else { format!("ETag: {}\r\n", etag) },
+                            vary,
+                            cache_control_str(&static_file.cache_control),
                         ));
 
                         if need_close_by_request {
@@ -149,22 +189,53 @@ impl StaticFilesCache {
                         return;
                     }
 
-                    let mut content = &static_file.raw_data;
-                    let mut content_header = "";
-                    if let Some(encoding) = request.header_value("Accept-Encoding") {
-                        if let Some(deflate_data) = &static_file.deflate_data {
-                            if encoding.contains("deflate") {
-                                content = &deflate_data;
-                                content_header = "Content-Encoding: deflate\r\n";
-                            }
-                        } else if let Some(gzip_data) = &static_file.gzip_data {
-                            if encoding.contains("gzip") {
-                                content = &gzip_data;
-                                content_header = "Content-Encoding: gzip\r\n";
-                            }
+                    if let Some(range) = range {
+                        let (code, content_range, part) = match range {
+                            ByteRange::Satisfiable(start, end) => ("206 Partial Content", format!("bytes {}-{}/{}", start, end, content.len()), &content[start..=end]),
+                            ByteRange::Unsatisfiable => ("416 Range Not Satisfiable", format!("bytes */{}", content.len()), &content[..0]),
+                        };
+
+                        let mut response = Vec::from(format!(
+                            "{} {}\r\n\
+                             Date: {}\r\n\
+                             {}\
+                             {}\
+                             {}\
+                             {}\
+                             Content-Range: {}\r\n\
+                             Content-Length: {}\r\n\
+                             Content-Type: {}\r\n\
+                             {}\
+                             {}\
+                             \r\n",
+                            request.version().to_string_for_response(),
+                            code,
+                            request.rfc7231_date_string(),
+                            crate::response::connection_str_by_request(request.request_data()),
+                            vary,
+                            if static_file.last_modified_rfc7231.is_empty() { "".to_string() } else { format!("Last-Modified: {}\r\n", static_file.last_modified_rfc7231) },
+                            if etag.is_empty() { "".to_string() } else { format!("ETag: {}\r\n", etag) },
+                            content_range,
+                            part.len(),
+                            static_file.content_type,
+                            cache_control_str(&static_file.cache_control),
+                            default_headers_str(request, &[])
+                        ));
+                        response.extend_from_slice(part);
+
+                        if need_close_by_request {
+                            request.tcp_session().close_after_send();
                         }
+                        request.tcp_session().send(&response);
+
+                        return;
                     }
 
+                    let content_header = match encoding {
+                        Some(encoding) => format!("Content-Encoding: {}\r\n", encoding),
+                        None => String::new(),
+                    };
+
                     let mut response = Vec::from(format!(
                         "{} 200 OK\r\n\
                          Date: {}\r\n\
@@ -172,17 +243,24 @@ impl StaticFilesCache {
                          {}\
                          {}\
                          {}\
+                         {}\
+                         Accept-Ranges: bytes\r\n\
                          Content-Length: {}\r\n\
                          Content-Type: {}\r\n\
+                         {}\
+                         {}\
                          \r\n",
                         request.version().to_string_for_response(),
                         request.rfc7231_date_string(),
                         crate::response::connection_str_by_request(request.request_data()),
                         content_header,
+                        vary,
                         if static_file.last_modified_rfc7231.is_empty() { "".to_string() } else { format!("Last-Modified: {}\r\n", static_file.last_modified_rfc7231) },
-                        if static_file.etag.is_empty() { "".to_string() } else { format!("ETag: {}\r\n", static_file.etag) },
+                        if etag.is_empty() { "".to_string() } else { format!("ETag: {}\r\n", etag) },
                         content.len(),
-                        static_file.content_type
+                        static_file.content_type,
+                        cache_control_str(&static_file.cache_control),
+                        default_headers_str(request, &[])
                     ));
 
                     if content.len() < self.united_response_limit {
@@ -208,175 +286,425 @@ impl StaticFilesCache {
         result
     }
 
+    /// Returns true if there is file with the path.
+    pub fn contains(&self, path: &str) -> bool {
+        let mut contains = false;
+        self.get(path, |static_file| contains = static_file.is_some());
+        contains
+    }
+
     /// Return current cached files paths.
     pub fn files(&self) -> Vec<String> {
-        let mut result = vec![];
-        if let Ok(cached_files) = self.cached_files.read() {
-            for cached_file in cached_files.keys() {
-                result.push(cached_file.clone());
-            }
-        }
+        self.cached_files.load().keys().cloned().collect()
+    }
 
-        result
+    /// Registers data under path, for example embedded by `include_bytes!`.
+    /// Compression and browser cache headers are prepared as for files of directory.
+    /// # Arguments
+    /// * `content_type` - value of "Content-Type" header.
+    /// * `cache_control` - value of "Cache-Control" header, for example "public, max-age=31536000, immutable".
+    pub fn add_bytes(&self, path: &str, content_type: &str, data: impl Into<Vec<u8>>, cache_control: Option<&str>) {
+        let data = data.into();
+        let modified = SystemTime::now();
+        let etag = self.etag_hasher().update(&data).finish(data.len(), &modified);
+        let mut static_file = self.prepare(data, content_type.to_string(), &modified, etag);
+        static_file.cache_control = cache_control.map(str::to_string);
+        static_file.registered = true;
+
+        self.change(|cached_files| {
+            cached_files.insert(path.trim_start_matches('/').to_string(), static_file);
+            true
+        });
+    }
+
+    /// Registers file from disk under path. The file is loaded once, it's not updated.
+    /// # Arguments
+    /// * `content_type` - value of "Content-Type" header, by extension of file if None.
+    /// * `cache_control` - value of "Cache-Control" header.
+    pub fn add_file(&self, path: &str, file_path: impl AsRef<Path>, content_type: Option<&str>, cache_control: Option<&str>) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        let data = std::fs::read(file_path)?;
+        let content_type = content_type.map_or_else(|| content_type_by_path(file_path), str::to_string);
+        let modified = std::fs::metadata(file_path)?.modified().unwrap_or_else(|_| SystemTime::now());
+
+        let etag = self.etag_hasher().update(&data).finish(data.len(), &modified);
+        let mut static_file = self.prepare(data, content_type, &modified, etag);
+        static_file.cache_control = cache_control.map(str::to_string);
+        static_file.registered = true;
+
+        self.change(|cached_files| {
+            cached_files.insert(path.trim_start_matches('/').to_string(), static_file);
+            true
+        });
+
+        Ok(())
     }
 
     /// Updating the RAM cache in accordance with directory on the disk. It's execute in call thread.
+    /// Requests are served from previous snapshot until the new one is ready. Does nothing for cache without directory.
     pub fn update(&self) {
-        self.remove_nonexistent();
-        self.update_dir("");
+        if self.dir_path.is_empty() {
+            return;
+        }
+
+        self.change(|cached_files| {
+            let removed = self.remove_nonexistent(cached_files);
+            let updated = self.update_dir("", cached_files);
+            removed || updated
+        });
+    }
+
+    /// Applies `change` to copy of current snapshot and swaps snapshot if `change` returns true.
+    fn change(&self, change: impl FnOnce(&mut CachedFiles) -> bool) {
+        let _lock = self.update_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut cached_files = CachedFiles::clone(&self.cached_files.load());
+        if change(&mut cached_files) {
+            self.cached_files.store(Arc::new(cached_files));
+        }
     }
 
-    /// Recursive update the RAM cache in accordance with directory on the disk.
-    fn update_dir(&self, subdir_path: &str) {
+    /// Recursive update the RAM cache in accordance with directory on the disk. Returns true if the cache is changed.
+    fn update_dir(&self, subdir_path: &str, cached_files: &mut CachedFiles) -> bool {
         let mut cur_dir_path = self.dir_path.clone();
         if !subdir_path.is_empty() {
             cur_dir_path.push('/');
-            cur_dir_path += &subdir_path;
+            cur_dir_path += subdir_path;
         }
 
+        let mut changed = false;
         match read_dir(&cur_dir_path) {
             Ok(paths) => {
-                for path in paths {
-                    if let Ok(path) = path {
-                        if let Ok(metadata) = path.metadata() {
-                            if let Some(name) = path.file_name().to_str() {
-                                let mut path_with_subdirs = subdir_path.to_owned();
-                                if !path_with_subdirs.is_empty() {
-                                    path_with_subdirs.push('/');
-                                }
-                                path_with_subdirs += name;
-
-                                if metadata.is_file() {
-                                    self.check_file_and_cache_if_need(&path_with_subdirs, &metadata);
-                                } else if metadata.is_dir() {
-                                    // recurse subdirectory
-                                    self.update_dir(&path_with_subdirs);
-                                }
+                for path in paths.flatten() {
+                    if let Ok(metadata) = path.metadata() {
+                        if let Some(name) = path.file_name().to_str() {
+                            let mut path_with_subdirs = subdir_path.to_owned();
+                            if !path_with_subdirs.is_empty() {
+                                path_with_subdirs.push('/');
+                            }
+                            path_with_subdirs += name;
+
+                            if metadata.is_file() {
+                                changed |= self.check_file_and_cache_if_need(&path_with_subdirs, &metadata, cached_files);
+                            } else if metadata.is_dir() {
+                                // recurse subdirectory
+                                changed |= self.update_dir(&path_with_subdirs, cached_files);
                             }
                         }
                     }
                 }
             }
             Err(_) => {
-                self.clear();
+                changed |= clear(cached_files);
             }
         }
+
+        changed
     }
 
-    /// Get static file data from cache by path. Callback under read blocking of RwLock of files container.
+    /// Get static file data from cache by path. Callback with file of current snapshot, without locks.
     fn get(&self, file_path: &str, mut result_callback: impl FnMut(Option<&StaticFileCache>)) {
-        let file_name = if file_path.starts_with('/') { &file_path[1..] } else { file_path };
+        let file_name = file_path.strip_prefix('/').unwrap_or(file_path);
+        let cached_files = self.cached_files.load();
+        result_callback(cached_files.get(file_name));
+    }
 
-        if let Ok(cached_files) = self.cached_files.read() {
-            if let Some(static_file) = cached_files.get(file_name) {
-                result_callback(Some(static_file));
-                return;
-            }
+    /// Remove from cache nonexistent files in directory on disk. Returns true if some files are removed.
+    fn remove_nonexistent(&self, cached_files: &mut CachedFiles) -> bool {
+        let len = cached_files.len();
+        cached_files.retain(|file_name, cached_file| cached_file.registered || Path::new(&(self.dir_path.clone() + "/" + file_name)).exists());
+        cached_files.len() != len
+    }
+
+    /// Checks of difference of file on the disk and in the RAM and update cache if need. Returns true if file is cached.
+    fn check_file_and_cache_if_need(&self, file_path: &str, metadata: &Metadata, cached_files: &mut CachedFiles) -> bool {
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            Err(_) => return false,
+        };
+
+        let need_cache = match cached_files.get(file_path) {
+            Some(cached_file) => !cached_file.registered && modified > cached_file.last_modified,
+            // cache it if not cached yet
+            None => true,
+        };
+        if !need_cache {
+            return false;
         }
 
-        result_callback(None);
+        match self.load(file_path, &modified) {
+            Some(cached_file) => {
+                cached_files.insert(file_path.to_string(), cached_file);
+                true
+            }
+            None => false,
+        }
     }
 
-    /// Remove from cache nonexistent files in directory on disk.
-    fn remove_nonexistent(&self) {
-        let mut nonexistent = vec![];
-        if let Ok(cached_files) = self.cached_files.read() {
-            for file_name in cached_files.keys() {
-                if !Path::new(&(self.dir_path.clone() + "/" + file_name)).exists() {
-                    nonexistent.push(file_name.clone());
+    /// Loading and preparing file data.
+    fn load(&self, file_path: &str, modified: &SystemTime) -> Option<StaticFileCache> {
+        let mut file = File::open(self.dir_path.clone() + "/" + file_path).ok()?;
+        // ETag is computed while reading
+        let mut etag_hasher = self.etag_hasher();
+        let mut raw_data = vec![];
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(cnt) => {
+                    etag_hasher = etag_hasher.update(&buf[..cnt]);
+                    raw_data.extend_from_slice(&buf[..cnt]);
                 }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return None,
             }
         }
 
-        if nonexistent.is_empty() {
-            return;
+        let content_type = content_type_by_path(Path::new(file_path));
+        let etag = etag_hasher.finish(raw_data.len(), modified);
+        Some(self.prepare(raw_data, content_type, modified, etag))
+    }
+
+    /// Compresses data and prepares browser cache headers by settings.
+    fn prepare(&self, raw_data: Vec<u8>, content_type: String, modified: &SystemTime, etag: String) -> StaticFileCache {
+        let deflate_data = if self.deflate_encoding { Some(Arc::new(deflate_bytes(&raw_data))) } else { None };
+
+        let gzip_data = if self.gzip_encoding { Some(Arc::new(deflate_bytes_gzip(&raw_data))) } else { None };
+
+        let last_modified_rfc7231 = if self.use_last_modified { chrono::DateTime::<chrono::Utc>::from(*modified).to_rfc2822().replace("+0000", "GMT") } else { "".to_string() };
+
+        StaticFileCache {
+            raw_data: Arc::new(raw_data),
+            deflate_data,
+            gzip_data,
+            content_type,
+            last_modified: *modified,
+            last_modified_rfc7231,
+            etag,
+            cache_control: None,
+            registered: false,
         }
+    }
 
-        if let Ok(mut cached_files) =  self.cached_files.write() {
-            for file_name in nonexistent {
-                cached_files.remove(&file_name);
-            }
+    fn etag_hasher(&self) -> EtagHasher {
+        if !self.use_etag {
+            return EtagHasher::Disabled;
+        }
+
+        match self.etag_algorithm {
+            EtagAlgorithm::Md5 => EtagHasher::Md5(md5::Context::new()),
+            EtagAlgorithm::Fnv1a => EtagHasher::Fnv1a(FNV_OFFSET_BASIS),
+            EtagAlgorithm::SizeAndModified => EtagHasher::SizeAndModified,
         }
     }
+}
 
-    /// Checks of difference of file on the disk and in the RAM and update cache if need.
-    fn check_file_and_cache_if_need(&self, file_path: &str, metadata: &Metadata) {
-        if let Ok(modified) = metadata.modified() {
-            let mut last_modified = None;
+/// Cached files by path relative to directory.
+type CachedFiles = BTreeMap<String, StaticFileCache>;
+
+/// Clear cache except registered files. It's calling when updating cache and no directory on the disk.
+/// Returns true if some files are removed.
+fn clear(cached_files: &mut CachedFiles) -> bool {
+    let len = cached_files.len();
+    cached_files.retain(|_, cached_file| cached_file.registered);
+    cached_files.len() != len
+}
 
-            if let Ok(cached_files) = self.cached_files.read() {
-                if let Some(cached_file) = cached_files.get(file_path) {
-                    last_modified = Some(cached_file.last_modified);
+/// How "ETag" header of static files is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EtagAlgorithm {
+    /// Strong ETag from md5 of content.
+    #[default]
+    Md5,
+    /// Strong ETag from 64-bit FNV-1a hash of content, faster than md5.
+    Fnv1a,
+    /// Weak ETag from size and modification time, content is not hashed.
+    SizeAndModified,
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// ETag computing by parts of content.
+enum EtagHasher {
+    Disabled,
+    Md5(md5::Context),
+    Fnv1a(u64),
+    SizeAndModified,
+}
+
+impl EtagHasher {
+    fn update(mut self, data: &[u8]) -> Self {
+        match &mut self {
+            EtagHasher::Md5(context) => context.consume(data),
+            EtagHasher::Fnv1a(hash) => {
+                for byte in data {
+                    *hash = (*hash ^ *byte as u64).wrapping_mul(FNV_PRIME);
                 }
             }
+            EtagHasher::Disabled | EtagHasher::SizeAndModified => {}
+        }
 
-            match last_modified {
-                Some(last_modified) => {
-                    if modified > last_modified {
-                        // update cached data
-                        self.cache(file_path, &modified);
-                    }
-                }
-                None => {
-                    // cache it if not cached yet
-                    self.cache(file_path, &modified);
-                }
+        self
+    }
+
+    /// Value of "ETag" header, empty if disabled.
+    fn finish(self, len: usize, modified: &SystemTime) -> String {
+        match self {
+            EtagHasher::Disabled => String::new(),
+            EtagHasher::Md5(context) => format!("\"{:x}\"", context.compute()),
+            EtagHasher::Fnv1a(hash) => format!("\"{:016x}\"", hash),
+            EtagHasher::SizeAndModified => {
+                let modified = modified.duration_since(std::time::UNIX_EPOCH).map(|modified| modified.as_secs()).unwrap_or_default();
+                format!("W/\"{:x}-{:x}\"", len, modified)
             }
         }
     }
+}
+
+/// ETag of compressed variant, the encoding is appended to opaque tag, for example "\"0cc1\"" to "\"0cc1-gzip\"".
+/// Empty if ETag is disabled.
+fn variant_etag(etag: &str, encoding: &str) -> String {
+    match etag.strip_suffix('"') {
+        Some(opaque) => format!("{}-{}\"", opaque, encoding),
+        None => etag.to_string(),
+    }
+}
 
-    /// Loading and preparing file data and write to the RAM cache.
-    fn cache(&self, file_path: &str, modified: &SystemTime) {
-        // cache it if not cached yet
-        if let Ok(mut file) = File::open(self.dir_path.clone() + "/" + file_path) {
-            let mut raw_data = vec![];
-            if file.read_to_end(&mut raw_data).is_ok() {
-                let file_name = file_path.to_string();
+/// Byte range of content requested by "Range" header, `end` is inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ByteRange {
+    Satisfiable(usize, usize),
+    /// Answered with "416 Range Not Satisfiable".
+    Unsatisfiable,
+}
 
-                let mut extension = String::new();
-                if let Some(e) = Path::new(file_path).extension() {
-                    if let Some(e) = e.to_str() {
-                        extension = e.to_string();
-                    }
-                }
+/// Range of GET request to serve with "206 Partial Content" (RFC 7233). None if the whole file must be sent:
+/// no "Range" header, several or wrong ranges, "If-Range" doesn't match current file.
+fn requested_range(request: &Request, static_file: &StaticFileCache) -> Option<ByteRange> {
+    if request.method() != "GET" {
+        return None;
+    }
+
+    let range = request.header_value("Range")?;
+    if let Some(if_range) = request.header_value("If-Range") {
+        let if_range = if_range.trim();
+        // weak validators don't match (RFC 7233, 3.2)
+        let matches = if if_range.starts_with('"') {
+            if_range == static_file.etag
+        } else {
+            !static_file.last_modified_rfc7231.is_empty() && if_range == static_file.last_modified_rfc7231
+        };
+        if !matches {
+            return None;
+        }
+    }
 
-                let content_type = mime_type_by_extension(&extension).to_string();
+    parse_byte_range(range, static_file.raw_data.len())
+}
 
-                let deflate_data = if self.deflate_encoding { Some(Arc::new(deflate_bytes(&raw_data))) } else { None };
+/// Parses single range of "Range" header value, for example "bytes=0-99", "bytes=100-" or "bytes=-100".
+pub(crate) fn parse_byte_range(range: &str, len: usize) -> Option<ByteRange> {
+    let (unit, spec) = range.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+        return None;
+    }
 
-                let gzip_data = if self.gzip_encoding { Some(Arc::new(deflate_bytes_gzip(&raw_data))) } else { None };
+    let (first, last) = spec.trim().split_once('-')?;
+    let parse = |value: &str| value.trim().parse::<usize>().ok();
+    match (first.trim().is_empty(), last.trim().is_empty()) {
+        // suffix of content
+        (true, false) => match parse(last)? {
+            0 => Some(ByteRange::Unsatisfiable),
+            _ if len == 0 => Some(ByteRange::Unsatisfiable),
+            suffix => Some(ByteRange::Satisfiable(len.saturating_sub(suffix), len - 1)),
+        },
+        (false, true) => match parse(first)? {
+            start if start < len => Some(ByteRange::Satisfiable(start, len - 1)),
+            _ => Some(ByteRange::Unsatisfiable),
+        },
+        (false, false) => {
+            let (start, end) = (parse(first)?, parse(last)?);
+            if end < start {
+                return None;
+            }
+            if start >= len {
+                return Some(ByteRange::Unsatisfiable);
+            }
+            Some(ByteRange::Satisfiable(start, end.min(len - 1)))
+        }
+        (true, true) => None,
+    }
+}
 
-                let last_modified_rfc7231 = if self.use_last_modified { chrono::DateTime::<chrono::Utc>::from(*modified).to_rfc2822().replace("+0000", "GMT") } else { "".to_string() };
+/// GET or HEAD request of path whose last segment has no extension, for example "/users/42".
+fn is_spa_route(path: &str, request: &Request) -> bool {
+    let last_segment = path.rsplit('/').next().unwrap_or_default();
+    matches!(request.method(), "GET" | "HEAD") && !last_segment.contains('.')
+}
 
-                let etag = if self.use_etag { format!("{:x}", md5::compute(&raw_data)) } else { "".to_string() };
+/// Content type by extension of file.
+fn content_type_by_path(path: &Path) -> String {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    mime_type_by_extension(extension).to_string()
+}
 
-                let cached_file = StaticFileCache {
-                    raw_data: Arc::new(raw_data),
-                    deflate_data,
-                    gzip_data,
-                    content_type,
-                    last_modified: *modified,
-                    last_modified_rfc7231,
-                    etag,
-                };
+fn cache_control_str(cache_control: &Option<String>) -> String {
+    match cache_control {
+        Some(cache_control) => format!("Cache-Control: {}\r\n", cache_control),
+        None => String::new(),
+    }
+}
 
-                // short blocking
-                if let Ok(mut cached_files) = self.cached_files.write() {
-                    cached_files.insert(file_name, cached_file);
-                }
+/// Several `StaticFilesCache` mounted on URL prefixes like "/assets" and "/docs", each with its own settings.
+/// The longest prefix matching whole path segments is chosen, the prefix is stripped before lookup of file.
+/// # Example
+/// `StaticMounts::new().mount("/assets", StaticFilesCache::new("web/assets")).mount("/docs", Builder::new().gzip_encoding(false).build("docs"))`
+#[derive(Clone, Default)]
+pub struct StaticMounts {
+    /// Prefixes without trailing slash ("" for root) with caches, longest prefixes first.
+    mounts: Vec<(String, StaticFilesCache)>,
+}
+
+impl StaticMounts {
+    /// Creates empty mounts.
+    pub fn new() -> Self {
+        StaticMounts::default()
+    }
+
+    /// Mounts cache of files on URL prefix. Mount with the same prefix is replaced.
+    pub fn mount(mut self, prefix: &str, static_files: StaticFilesCache) -> Self {
+        let prefix = format!("/{}", prefix.trim_matches('/'));
+        let prefix = prefix.trim_end_matches('/').to_string();
+
+        self.mounts.retain(|(mounted_prefix, _)| *mounted_prefix != prefix);
+        self.mounts.push((prefix, static_files));
+        self.mounts.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        self
+    }
+
+    /// Cache mounted on prefix of request path and path of file in it.
+    pub fn resolve<'a>(&self, path: &'a str) -> Option<(&StaticFilesCache, &'a str)> {
+        self.mounts.iter().find_map(|(prefix, static_files)| {
+            let rest = path.strip_prefix(prefix.as_str())?;
+            if rest.is_empty() || rest.starts_with('/') {
+                Some((static_files, rest))
+            } else {
+                None
             }
-        }
+        })
     }
 
-    /// Clear cache. It's calling when updating cache and no directory on the disk.
-    fn clear(&self) {
-        if let Ok(mut cached_files) = self.cached_files.write() {
-            cached_files.clear();
+    /// Send response with file content to the client. Error `NotFound` if there is no mount or file for the path.
+    pub fn send_response(&self, path: &str, request: &Request) -> io::Result<()> {
+        match self.resolve(path) {
+            Some((static_files, file_path)) => static_files.send_response(file_path, request),
+            None => Err(io::Error::new(ErrorKind::NotFound, "No such static file")),
         }
     }
 }
 
 /// Builder of `StaticFiles`.
+#[derive(Clone)]
 pub struct Builder {
     /// Interval of scanning directory and cache updating in background thread.
     /// If interval is None, then no background thread is create.
@@ -391,12 +719,16 @@ pub struct Builder {
     pub use_last_modified: bool,
     /// Enable/disable using browser cache with "ETag" header.
     pub use_etag: bool,
+    /// How "ETag" is computed.
+    pub etag_algorithm: EtagAlgorithm,
     /// If false then content will loading to the RAM and prepared in current thread when creating.
     /// If true then content will loading in background thread after `updating_interval` or with
     /// manually call `StaticFile::update()` function.
     pub deferred_load: bool,
     /// To try send small data in one write operation if data len less then this parameter.
     pub united_response_limit: usize,
+    /// Send "index.html" with 200 for unknown paths without file extension (single-page application routing).
+    pub spa_fallback: bool,
 }
 
 impl Default for Builder {
@@ -407,8 +739,10 @@ impl Default for Builder {
             gzip_encoding: true,
             use_last_modified: true,
             use_etag: true,
+            etag_algorithm: EtagAlgorithm::Md5,
             united_response_limit: 200000,
             deferred_load: false,
+            spa_fallback: false,
         }
     }
 }
@@ -421,7 +755,13 @@ impl Builder {
 
     /// Creates `StaticFiles` from builder. `path` - path to directory on disk that will be cached.
     pub fn build(&self, path: &str) -> StaticFilesCache {
-        StaticFilesCache::from_builder(path, &self)
+        StaticFilesCache::from_builder(path, self)
+    }
+
+    /// Creates `StaticFiles` without directory for files registered by `add_bytes` and `add_file`.
+    pub fn build_in_memory(&self) -> StaticFilesCache {
+        let builder = Builder { updating_interval: None, ..self.clone() };
+        StaticFilesCache::from_builder("", &builder)
     }
 
     /// Interval of scanning directory and cache updating in background thread.
@@ -457,6 +797,12 @@ impl Builder {
         self
     }
 
+    /// How "ETag" is computed, md5 of content by default.
+    pub fn etag_algorithm(mut self, algorithm: EtagAlgorithm) -> Self {
+        self.etag_algorithm = algorithm;
+        self
+    }
+
     /// If false then content will loading to the RAM and prepared in current thread when creating.
     /// If true then content will loading in background thread after `updating_interval` or with
     /// manually call update function.
@@ -470,4 +816,11 @@ impl Builder {
         self.united_response_limit = size;
         self
     }
+
+    /// Send "index.html" with 200 for unknown GET paths without file extension, so routes of single-page application
+    /// like "/users/42" are handled in the browser. Paths like "/app.js" are still not found if there is no such file.
+    pub fn spa_fallback(mut self, enabled: bool) -> Self {
+        self.spa_fallback = enabled;
+        self
+    }
 }
@@ -0,0 +1,149 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Handle for reading statistics of running server from any thread. See `Server::stats`.
+#[derive(Clone, Default)]
+pub struct Stats {
+    /// Counters of workers, filled when the server is started.
+    workers: Arc<RwLock<Vec<Arc<WorkerCounters>>>>,
+}
+
+impl Stats {
+    /// Snapshot of statistics of each worker. Empty if the server isn't started.
+    pub fn workers(&self) -> Vec<WorkerStats> {
+        match self.workers.read() {
+            Ok(workers) => workers.iter().map(|counters| counters.snapshot()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Snapshot of statistics summed over all workers.
+    pub fn total(&self) -> WorkerStats {
+        self.workers().iter().fold(WorkerStats::default(), |total, worker| WorkerStats {
+            active_sessions: total.active_sessions + worker.active_sessions,
+            websocket_sessions: total.websocket_sessions + worker.websocket_sessions,
+            queued_write_bytes: total.queued_write_bytes + worker.queued_write_bytes,
+            accepted_total: total.accepted_total + worker.accepted_total,
+            rejected_total: total.rejected_total + worker.rejected_total,
+            overloaded: total.overloaded || worker.overloaded,
+            responses_total: total.responses_total + worker.responses_total,
+            requests_total: total.requests_total + worker.requests_total,
+            reused_connection_requests_total: total.reused_connection_requests_total + worker.reused_connection_requests_total,
+            handler_time_total: total.handler_time_total + worker.handler_time_total,
+            send_time_total: total.send_time_total + worker.send_time_total,
+        })
+    }
+
+    /// Number of active sessions of the least loaded worker. None if the server isn't started.
+    pub(crate) fn min_active_sessions(&self) -> Option<usize> {
+        let workers = self.workers.read().ok()?;
+        workers.iter().map(|counters| counters.active_sessions.load(Ordering::SeqCst)).min()
+    }
+
+    /// Number of active sessions of all workers. None if the server isn't started.
+    pub(crate) fn active_sessions(&self) -> Option<usize> {
+        let workers = self.workers.read().ok()?;
+        if workers.is_empty() {
+            return None;
+        }
+
+        Some(workers.iter().map(|counters| counters.active_sessions.load(Ordering::SeqCst)).sum())
+    }
+
+    /// Forgets results of previous checks of draining of workers.
+    pub(crate) fn reset_drain_checks(&self) {
+        if let Ok(workers) = self.workers.read() {
+            for counters in workers.iter() {
+                counters.drain_checked.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// All workers have checked that their sessions have no pending work.
+    pub(crate) fn drained(&self) -> bool {
+        self.workers.read().is_ok_and(|workers| {
+            workers.iter().all(|counters| counters.drain_checked.load(Ordering::SeqCst) && counters.drain_pending.load(Ordering::SeqCst) == 0)
+        })
+    }
+
+    pub(crate) fn add_worker(&self, counters: Arc<WorkerCounters>) {
+        if let Ok(mut workers) = self.workers.write() {
+            workers.push(counters);
+        }
+    }
+}
+
+/// Statistics of worker at the moment of snapshot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    /// Number of connected clients.
+    pub active_sessions: usize,
+    /// Number of connected clients in websocket mode.
+    pub websocket_sessions: usize,
+    /// Number of bytes waiting for the sockets to be ready for write.
+    pub queued_write_bytes: usize,
+    /// Number of accepted connections since the start.
+    pub accepted_total: u64,
+    /// Number of connections closed right after accepting because of `Settings::connection_limit` or `Settings::accept_rate_limit`.
+    pub rejected_total: u64,
+    /// Worker is overloaded by thresholds of `Settings::load_shedding`. In total, true if any worker is overloaded.
+    pub overloaded: bool,
+    /// Number of written responses built by `Response`.
+    pub responses_total: u64,
+    /// Number of received requests.
+    pub requests_total: u64,
+    /// Number of requests received on kept-alive connections after the first request, ratio to `requests_total` shows reuse of connections.
+    pub reused_connection_requests_total: u64,
+    /// Summary time from the end of parsing of request head to the start of sending of response, see `Timing::handler`.
+    pub handler_time_total: Duration,
+    /// Summary time of writing of responses, see `Timing::send`.
+    pub send_time_total: Duration,
+}
+
+/// Counters of worker, updated by the worker and its tcp sessions.
+#[derive(Default)]
+pub(crate) struct WorkerCounters {
+    pub(crate) active_sessions: AtomicUsize,
+    pub(crate) websocket_sessions: AtomicUsize,
+    pub(crate) queued_write_bytes: AtomicUsize,
+    pub(crate) accepted_total: AtomicU64,
+    pub(crate) rejected_total: AtomicU64,
+    pub(crate) overloaded: AtomicBool,
+    pub(crate) responses_total: AtomicU64,
+    pub(crate) requests_total: AtomicU64,
+    pub(crate) reused_connection_requests_total: AtomicU64,
+    pub(crate) handler_micros_total: AtomicU64,
+    pub(crate) send_micros_total: AtomicU64,
+    /// Worker has counted sessions with pending work after start of draining, see `Stopper::drain`.
+    pub(crate) drain_checked: AtomicBool,
+    /// Number of sessions with not written data or not finished requests while draining.
+    pub(crate) drain_pending: AtomicUsize,
+    /// Number of half-closed sessions in grace period, see `TcpSession::shutdown_write`.
+    pub(crate) lingering_sessions: AtomicUsize,
+}
+
+impl WorkerCounters {
+    fn snapshot(&self) -> WorkerStats {
+        WorkerStats {
+            active_sessions: self.active_sessions.load(Ordering::SeqCst),
+            websocket_sessions: self.websocket_sessions.load(Ordering::SeqCst),
+            queued_write_bytes: self.queued_write_bytes.load(Ordering::SeqCst),
+            accepted_total: self.accepted_total.load(Ordering::SeqCst),
+            rejected_total: self.rejected_total.load(Ordering::SeqCst),
+            overloaded: self.overloaded.load(Ordering::SeqCst),
+            responses_total: self.responses_total.load(Ordering::SeqCst),
+            requests_total: self.requests_total.load(Ordering::SeqCst),
+            reused_connection_requests_total: self.reused_connection_requests_total.load(Ordering::SeqCst),
+            handler_time_total: Duration::from_micros(self.handler_micros_total.load(Ordering::SeqCst)),
+            send_time_total: Duration::from_micros(self.send_micros_total.load(Ordering::SeqCst)),
+        }
+    }
+
+    /// Counts timing of written response.
+    pub(crate) fn add_timing(&self, handler: Duration, send: Duration) {
+        self.responses_total.fetch_add(1, Ordering::SeqCst);
+        self.handler_micros_total.fetch_add(handler.as_micros() as u64, Ordering::SeqCst);
+        self.send_micros_total.fetch_add(send.as_micros() as u64, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,93 @@
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// First file descriptor passed by systemd, see sd_listen_fds(3).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes TCP listeners passed by systemd socket activation ("LISTEN_FDS" and "LISTEN_PID" environment variables)
+/// in order of sockets of the unit. Empty if the process isn't activated by socket.
+/// The listeners are taken once, child processes ignore the inherited variables by "LISTEN_PID".
+/// Error if some of the descriptors isn't TCP listener, then none of them is taken. See `Server::from_systemd`.
+pub fn listeners() -> Result<Vec<TcpListener>, std::io::Error> {
+    let count = listen_fds_count(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+
+    if count == 0 || LISTENERS_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(Vec::new());
+    }
+
+    let fds = (0..count as RawFd).map(|index| LISTEN_FDS_START + index);
+    if !fds.clone().all(is_tcp_listener) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "socket passed by systemd is not TCP listener"));
+    }
+
+    // SAFETY: the descriptors are listening sockets passed to this process, taken once by LISTENERS_TAKEN.
+    #[allow(unsafe_code)]
+    let listeners = fds.map(|fd| unsafe { adopt_fd(fd) }).collect();
+    Ok(listeners)
+}
+
+/// Number of descriptors passed to the process with id `pid` by values of "LISTEN_PID" and "LISTEN_FDS".
+pub(crate) fn listen_fds_count(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    let for_this_process = listen_pid.and_then(|listen_pid| listen_pid.trim().parse::<u32>().ok()) == Some(pid);
+    if !for_this_process {
+        return 0;
+    }
+
+    listen_fds.and_then(|listen_fds| listen_fds.trim().parse().ok()).unwrap_or(0)
+}
+
+/// Returns true if descriptor is listening TCP socket. Checked before taking ownership of passed descriptors,
+/// because stale variable can point at other descriptor of the process, for example stdin or file.
+#[allow(unsafe_code)]
+pub(crate) fn is_tcp_listener(fd: RawFd) -> bool {
+    if fd < 0 {
+        return false;
+    }
+
+    // SAFETY: the functions only write to the passed buffers of the passed sizes and don't change the descriptor,
+    // wrong descriptor results in error.
+    unsafe {
+        let mut stat: libc::stat = std::mem::zeroed();
+        if libc::fstat(fd, &mut stat) == -1 || stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+            return false;
+        }
+
+        let int_option = |name| {
+            let mut value: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let res = libc::getsockopt(fd, libc::SOL_SOCKET, name, &mut value as *mut libc::c_int as *mut libc::c_void, &mut len);
+            if res == -1 { None } else { Some(value) }
+        };
+        if int_option(libc::SO_TYPE) != Some(libc::SOCK_STREAM) || int_option(libc::SO_ACCEPTCONN) != Some(1) {
+            return false;
+        }
+
+        let mut addr: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if libc::getsockname(fd, &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr, &mut len) == -1 {
+            return false;
+        }
+
+        let family = addr.ss_family as libc::c_int;
+        family == libc::AF_INET || family == libc::AF_INET6
+    }
+}
+
+/// Takes ownership of listener descriptor passed to the process.
+///
+/// # Safety
+///
+/// The descriptor must be checked by `is_tcp_listener`, must not be owned by other code of the process
+/// and must be taken once.
+#[allow(unsafe_code)]
+pub(crate) unsafe fn adopt_fd(fd: RawFd) -> TcpListener {
+    TcpListener::from_raw_fd(fd)
+}
+
+/// Listeners are taken by `listeners`.
+static LISTENERS_TAKEN: AtomicBool = AtomicBool::new(false);
@@ -1,12 +1,20 @@
+use crate::handler_error::HandlerError;
 use crate::http_error::HttpError;
-use crate::websocket::{Websocket, WebsocketResult, WebsocketError};
+use crate::websocket::{Membership, Websocket, WebsocketRegistry, WebsocketResult, WebsocketError};
 use rustls::Session;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::io;
-use std::io::{ErrorKind, Read, Write};
+use std::io::{Read, Write};
 use std::net::SocketAddr;
 use crate::request::Request;
+use crate::server::{ProtocolMismatch, QuotaKind};
+use crate::stats::WorkerCounters;
+use crate::throttle::Throttle;
+use crate::worker::Waker;
+use crate::log::{log, Level};
+use crate::tls::is_plaintext_http;
+use std::time::{Duration, Instant};
 
 /// Tcp client connection to the server.
 #[derive(Clone)]
@@ -26,24 +34,170 @@ impl TcpSession {
         &self.inner.addr
     }
 
+    /// Time since accepting of the connection.
+    pub fn age(&self) -> Duration {
+        self.inner.connected_at.elapsed()
+    }
+
+    /// Number of bytes of data received from the client since accepting of the connection, after TLS decryption.
+    pub fn bytes_read(&self) -> u64 {
+        self.inner.bytes_read.load(Ordering::SeqCst)
+    }
+
+    /// Number of bytes of data written to the client since accepting of the connection, before TLS encryption.
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.bytes_written.load(Ordering::SeqCst)
+    }
+
+    /// Number of bytes waiting for the socket to be ready for write, for example because the client reads slowly.
+    /// Data sent from other threads and not yet taken by the worker is not counted. See `Settings::slow_consumer`.
+    pub fn queued_bytes(&self) -> usize {
+        self.inner.surpluses_to_write.lock()
+            .map(|surpluses| surpluses.iter().map(|surplus| surplus.data.len() - surplus.write_yet_cnt).sum())
+            .unwrap_or(0)
+    }
+
+    /// Sets maximum of `bytes_read` of the connection, after exceeding the connection is closed with `Event::QuotaExceeded`.
+    /// None for no limit. Initially `Settings::read_quota`, can be changed at any time, for example for big uploads.
+    pub fn set_read_quota(&self, bytes: Option<u64>) {
+        self.inner.read_quota.store(bytes.unwrap_or(u64::MAX), Ordering::SeqCst);
+    }
+
+    /// Sets maximum of `bytes_written` of the connection, after exceeding the connection is closed with `Event::QuotaExceeded`.
+    /// None for no limit. Initially `Settings::write_quota`.
+    pub fn set_write_quota(&self, bytes: Option<u64>) {
+        self.inner.write_quota.store(bytes.unwrap_or(u64::MAX), Ordering::SeqCst);
+    }
+
+    /// Counts data received from the client, closes the connection if read quota is exceeded.
+    /// Returns false if the quota is exceeded, then the data must not be processed.
+    pub(crate) fn count_read(&self, bytes: usize) -> bool {
+        let bytes_read = self.inner.bytes_read.fetch_add(bytes as u64, Ordering::SeqCst) + bytes as u64;
+        if bytes_read > self.inner.read_quota.load(Ordering::SeqCst) {
+            self.inner.exceed_quota(QuotaKind::Read);
+            return false;
+        }
+
+        true
+    }
+
+    /// Takes kind of quota by which the connection was closed.
+    pub(crate) fn take_exceeded_quota(&self) -> Option<QuotaKind> {
+        self.inner.exceeded_quota.lock().ok()?.take()
+    }
+
+    /// Closes the connection after detection of other protocol, see `Event::ProtocolMismatch`.
+    pub(crate) fn close_by_protocol_mismatch(&self, kind: ProtocolMismatch, response: Option<&[u8]>) {
+        self.inner.set_protocol_mismatch(kind);
+        match response {
+            Some(response) => self.close_with_response(response),
+            None => self.close(),
+        }
+    }
+
+    /// Takes protocol mismatch by which the connection was closed.
+    pub(crate) fn take_protocol_mismatch(&self) -> Option<ProtocolMismatch> {
+        self.inner.protocol_mismatch.lock().ok()?.take()
+    }
+
+    /// Sets registry in which websocket of the connection can join groups.
+    pub(crate) fn set_websocket_registry(&self, registry: &WebsocketRegistry) {
+        if let Ok(mut membership) = self.inner.websocket_membership.lock() {
+            *membership = Membership::new(registry);
+        }
+    }
+
+    /// Removes websocket of the connection from groups of registry when the connection is closed.
+    pub(crate) fn leave_websocket_groups(&self) {
+        if let Ok(mut membership) = self.inner.websocket_membership.lock() {
+            membership.leave_all(self.id());
+        }
+    }
+
+    /// Removes the socket from poll of the worker and shuts it down when the session is removed. The socket isn't
+    /// dropped while the user keeps clones of the session, but the client must see closing and events of the socket
+    /// must not be reported to new session with the same token.
+    pub(crate) fn release_socket(&self) {
+        if let Ok(stream) = self.inner.mio_stream.lock() {
+            let _ = self.inner.mio_poll.deregister(&*stream);
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    /// Drops callback of content of request that isn't received completely when the session is removed,
+    /// the callback and its request keep the session, so resources of the callback (files, etc.) would not be released.
+    pub(crate) fn release_content_callback(&self) {
+        let content_callback = self.inner.content_callback.lock().ok().and_then(|mut content_callback| content_callback.take());
+        drop(content_callback); // outside of lock
+    }
+
+    /// Fails writes that are not finished when the session is removed. Their callbacks may keep the session,
+    /// for example to send next part of content, so it would not be released.
+    pub(crate) fn release_write_callbacks(&self) {
+        let surpluses = match self.inner.surpluses_to_write.lock() {
+            Ok(mut surpluses) => std::mem::take(&mut *surpluses),
+            Err(_) => return,
+        };
+
+        let not_written: usize = surpluses.iter().map(|surplus| surplus.data.len().saturating_sub(surplus.write_yet_cnt)).sum();
+        self.inner.worker_counters.queued_write_bytes.fetch_sub(not_written, Ordering::SeqCst);
+
+        // outside of lock, callbacks may send data; empty data of `flush_pending` is written if all data before it is written
+        let mut lost = self.inner.write_error_copy().is_some();
+        for mut surplus in surpluses {
+            lost = lost || !surplus.data.is_empty();
+            if lost {
+                (surplus.res_callback)(Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "connection closed before data was written")));
+            } else {
+                (surplus.res_callback)(Ok(()));
+            }
+        }
+    }
+
     /// Send data to the client. Data may not be sent immediately, but in parts.
-    pub fn send(&self, data: &[u8]) {
-        self.try_send(data, |_| {});
+    /// Data sent from other threads than the worker thread of the connection is queued without locking and written by the worker.
+    /// Returns `SendStatus::Closed` without sending if the connection is already closed.
+    pub fn send(&self, data: &[u8]) -> SendStatus {
+        self.try_send(data, |_| {})
     }
 
     /// Send data to the client. Data may not be sent immediately, but in parts.
+    /// If the connection is already closed, `res_callback` is called with error immediately and `SendStatus::Closed` is returned.
     /// # Arguments
     /// * `res_callback` - function that will be called when the write is finished or socket writing error.
-    pub fn try_send(&self, data: &[u8], mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) {
+    pub fn try_send(&self, data: &[u8], mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) -> SendStatus {
+        if let Some(err) = self.inner.write_error_copy() {
+            // connection is closing after error of writing
+            res_callback(Err(err));
+            return SendStatus::Closed;
+        }
+
+        if self.need_close() || self.inner.is_write_shut_down() {
+            return self.reject_closed(data.is_empty(), Box::new(res_callback));
+        }
+
+        self.send_while_closing(data, res_callback)
+    }
+
+    /// Same as `try_send`, but data is written in the worker thread even if the connection is already closed,
+    /// for error responses of the server before closing.
+    fn send_while_closing(&self, data: &[u8], mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) -> SendStatus {
+        if !self.inner.is_worker_thread() {
+            return self.send_to_outbox(Arc::new(data.to_vec()), Box::new(res_callback));
+        }
+
+        self.drain_outbox();
+
         if let Ok(mut supluses) = self.inner.surpluses_to_write.lock() {
             // already writing, add to the recording queue
             if !supluses.is_empty() {
+                self.inner.worker_counters.queued_write_bytes.fetch_add(data.len(), Ordering::SeqCst);
                 supluses.push(SurplusForWrite {
                     data: Arc::new(data.to_vec()),
                     write_yet_cnt: 0,
                     res_callback: Box::new(res_callback)
                 });
-                return;
+                return SendStatus::Queued;
             }
         }
 
@@ -60,7 +214,7 @@ impl TcpSession {
                     res_callback(Ok(()));
 
                     if self.inner.need_close_after_sending.load(Ordering::SeqCst) {
-                        self.close();
+                        self.close_after_sent();
                     }
                 }
             }
@@ -68,46 +222,83 @@ impl TcpSession {
                 if err.kind() == std::io::ErrorKind::WouldBlock {
                     self.send_later(SurplusForWrite { data: Arc::new(data.to_vec()), write_yet_cnt: 0, res_callback:  Box::new(res_callback) });
                 } else {
+                    self.fail_write(&err);
                     res_callback(Err(err));
-                    self.close();
+                    return SendStatus::Closed;
                 }
             }
         }
+
+        SendStatus::Queued
+    }
+
+    /// Calls `callback` when all data sent before is written to the socket, or with error if writing failed or
+    /// the connection is closed. For example to be sure that the response is not truncated before shutdown.
+    pub fn flush_pending(&self, callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) {
+        self.try_send(&[], callback);
     }
 
     /// Send shared data to the client. Data may not be sent immediately, but in parts.
-    pub fn send_arc(&self, data: &Arc<Vec<u8>>) {
-        self.try_send_arc(data, |_| {});
+    pub fn send_arc(&self, data: &Arc<Vec<u8>>) -> SendStatus {
+        self.try_send_arc(data, |_| {})
     }
 
     /// Send shared data to the client. Data may not be sent immediately, but in parts.
     /// # Arguments
     /// * `res_callback` - function that will be called when the write is finished or socket writing error.
-    pub fn try_send_arc(&self, data: &Arc<Vec<u8>>, mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) {
+    pub fn try_send_arc(&self, data: &Arc<Vec<u8>>, mut res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) -> SendStatus {
+        if let Some(err) = self.inner.write_error_copy() {
+            // connection is closing after error of writing
+            res_callback(Err(err));
+            return SendStatus::Closed;
+        }
+
+        if self.need_close() || self.inner.is_write_shut_down() {
+            return self.reject_closed(data.is_empty(), Box::new(res_callback));
+        }
+
+        if !self.inner.is_worker_thread() {
+            return self.send_to_outbox(data.clone(), Box::new(res_callback));
+        }
+
+        self.drain_outbox();
+        self.write_or_queue(data, Box::new(res_callback))
+    }
+
+    /// Returns true if the connection is closed or is closing, data sent to it is not written.
+    pub fn is_closed(&self) -> bool {
+        self.need_close() || self.inner.is_write_shut_down() || self.inner.write_error_copy().is_some()
+    }
+
+    /// Writes data or adds it to the recording queue. Called in the worker thread after the outbox is drained.
+    fn write_or_queue(&self, data: &Arc<Vec<u8>>, mut res_callback: WriteResultCallback) -> SendStatus {
         if let Ok(mut supluses) = self.inner.surpluses_to_write.lock() {
             // already writing, add to the recording queue
             if !supluses.is_empty() {
+                self.inner.worker_counters.queued_write_bytes.fetch_add(data.len(), Ordering::SeqCst);
                 supluses.push(SurplusForWrite {
                     data: data.clone(),
                     write_yet_cnt: 0,
-                    res_callback: Box::new(res_callback),
+                    res_callback,
                 });
-                return;
+                return SendStatus::Queued;
             }
         }
 
-        match self.inner.write(&data) {
+        match self.inner.write(data) {
             Ok(cnt) => {
                 if cnt < data.len() {
                     self.send_later(SurplusForWrite {
                         data: Arc::clone(data),
                         write_yet_cnt: cnt,
-                        res_callback: Box::new(res_callback),
+                        res_callback,
                     });
                 } else {
                     // all data is written
+                    res_callback(Ok(()));
+
                     if self.is_http_mode() && self.inner.need_close_after_sending.load(Ordering::SeqCst) {
-                        self.close();
+                        self.close_after_sent();
                     }
                 }
             }
@@ -116,14 +307,109 @@ impl TcpSession {
                     self.send_later(SurplusForWrite {
                         data: Arc::clone(data),
                         write_yet_cnt: 0,
-                        res_callback: Box::new(res_callback),
+                        res_callback,
                     });
                 } else {
+                    self.fail_write(&err);
                     res_callback(Err(err));
-                    self.close();
+                    return SendStatus::Closed;
                 }
             }
         }
+
+        SendStatus::Queued
+    }
+
+    /// Set limit of outbound bandwidth of the connection in bytes per second, None for no limit.
+    /// Can be changed at any time, for example for big downloads.
+    pub fn set_bandwidth_limit(&self, bytes_per_sec: Option<u64>) {
+        if let Ok(mut throttle) = self.inner.throttle.lock() {
+            match (&*throttle, bytes_per_sec) {
+                (Some(current), Some(bytes_per_sec)) => current.set_rate(bytes_per_sec),
+                (_, bytes_per_sec) => *throttle = bytes_per_sec.map(Throttle::new),
+            }
+        }
+    }
+
+    /// Limit of outbound bandwidth of the connection in bytes per second.
+    pub fn bandwidth_limit(&self) -> Option<u64> {
+        self.inner.throttle.lock().ok()?.as_ref().map(|throttle| throttle.rate())
+    }
+
+    /// Queues frame for writing by the worker after processing of current events, see `Settings::websocket_coalescing`.
+    pub(crate) fn send_coalesced(&self, data: Vec<u8>, res_callback: WriteResultCallback) -> SendStatus {
+        if let Some(err) = self.inner.write_error_copy() {
+            let mut res_callback = res_callback;
+            res_callback(Err(err));
+            return SendStatus::Closed;
+        }
+
+        self.send_to_outbox(Arc::new(data), res_callback)
+    }
+
+    /// Queues data sent from other thread and wakes the worker for writing it.
+    fn send_to_outbox(&self, data: Arc<Vec<u8>>, res_callback: WriteResultCallback) -> SendStatus {
+        if self.need_close() || self.inner.is_write_shut_down() {
+            return self.reject_closed(data.is_empty(), res_callback);
+        }
+
+        if let Err(mut err) = self.inner.outbox.send(SurplusForWrite { data, write_yet_cnt: 0, res_callback }) {
+            (err.0.res_callback)(Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "connection is closed")));
+            return SendStatus::Closed;
+        }
+
+        if !self.inner.outbox_scheduled.swap(true, Ordering::SeqCst) {
+            self.inner.waker.wake(self.inner.token);
+        }
+
+        SendStatus::Queued
+    }
+
+    /// Calls `res_callback` of data sent to closed connection.
+    fn reject_closed(&self, is_flush: bool, mut res_callback: WriteResultCallback) -> SendStatus {
+        // empty data of `flush_pending` is written if the connection is closed after writing of all data
+        let all_written = self.inner.write_error_copy().is_none() && !self.has_queued_data() && !self.inner.outbox_scheduled.load(Ordering::SeqCst);
+        if is_flush && all_written {
+            res_callback(Ok(()));
+        } else {
+            res_callback(Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "connection is closed")));
+        }
+
+        SendStatus::Closed
+    }
+
+    /// Wakes the worker of the connection for writing of the outbox and continuing of deferred websocket handshake.
+    pub(crate) fn wake(&self) {
+        self.inner.waker.wake(self.inner.token);
+    }
+
+    /// Writes or queues data sent from other threads. Called in the worker thread.
+    pub(crate) fn drain_outbox(&self) {
+        self.inner.outbox_scheduled.store(false, Ordering::SeqCst);
+
+        let sent: Vec<SurplusForWrite> = match self.inner.outbox_receiver.lock() {
+            Ok(outbox_receiver) => outbox_receiver.try_iter().collect(),
+            Err(_) => return,
+        };
+
+        let coalesced_frame_limit = self.inner.coalesced_frame_limit.load(Ordering::SeqCst);
+        let sent = if coalesced_frame_limit > 0 { coalesce(sent, coalesced_frame_limit) } else { sent };
+        let has_sent = !sent.is_empty();
+
+        for surplus in sent {
+            if let Some(err) = self.inner.write_error_copy() {
+                let mut res_callback = surplus.res_callback;
+                res_callback(Err(err));
+                continue;
+            }
+
+            self.write_or_queue(&surplus.data, surplus.res_callback);
+        }
+
+        // `close_when_sent` waited for the outbox
+        if has_sent && !self.is_http_mode() && self.inner.need_close_after_sending.load(Ordering::SeqCst) && !self.has_queued_data() {
+            self.close_after_sent();
+        }
     }
 
     /// To close client socket after all data sent.
@@ -137,12 +423,117 @@ impl TcpSession {
         self.inner.close();
     }
 
+    /// Like `close_after_send`, but after writing of all data the connection is half-closed: the client gets end of stream
+    /// (after TLS "close_notify"), data of the client is read and discarded until the client closes its side or `grace` elapses,
+    /// then the connection is closed. Some clients lose the end of response if the connection is closed while they are
+    /// still sending. Call it before sending of last response, response with "Connection: close" is closed this way too.
+    pub fn shutdown_write(&self, grace: Duration) {
+        if let Ok(mut linger_grace) = self.inner.linger_grace.lock() {
+            *linger_grace = Some(grace);
+        }
+
+        self.close_after_send();
+    }
+
+    /// Closes or half-closes the connection when all data is written after `close_after_send` or `shutdown_write`.
+    fn close_after_sent(&self) {
+        match self.inner.linger_grace.lock().ok().and_then(|linger_grace| *linger_grace) {
+            Some(grace) => self.inner.shutdown_write(grace),
+            None => self.close(),
+        }
+    }
+
+    /// End of grace period after `shutdown_write`, None if the connection isn't half-closed.
+    pub(crate) fn linger_deadline(&self) -> Option<Instant> {
+        *self.inner.linger_deadline.lock().ok()?
+    }
+
+    /// Closes the connection after writing of queued data or immediately if there is nothing to write.
+    /// While some request is not dropped, its response may be not sent yet, then the connection is closed after it.
+    pub(crate) fn close_when_sent(&self) {
+        self.close_after_send();
+        if self.inner.requests_in_flight.load(Ordering::SeqCst) > 0 {
+            return;
+        }
+
+        if let Ok(supluses) = self.inner.surpluses_to_write.lock() {
+            if !supluses.is_empty() {
+                return;
+            }
+        }
+
+        // frames in the outbox are written first, see `Settings::websocket_coalescing`
+        if self.inner.outbox_scheduled.load(Ordering::SeqCst) {
+            return;
+        }
+
+        self.close();
+    }
+
+    /// Closes the connection after `close_after_send` if all data is written and all requests are dropped.
+    pub(crate) fn close_if_sent(&self) {
+        if self.inner.need_close_after_sending.load(Ordering::SeqCst) && !self.has_pending_work() {
+            self.close_after_sent();
+        }
+    }
+
+    /// Called when the client closed its side of connection (read returned 0).
+    /// If there is queued data, the connection will be closed after writing it, otherwise immediately.
+    pub(crate) fn on_read_closed(&self) {
+        self.inner.read_closed.store(true, Ordering::SeqCst);
+
+        if let Ok(supluses) = self.inner.surpluses_to_write.lock() {
+            if !supluses.is_empty() {
+                if let Ok(stream) = self.inner.mio_stream.lock() {
+                    // readable readiness would be reported endlessly after end of stream
+                    if self.inner.mio_poll.reregister(&*stream, self.inner.token, mio::Ready::writable(), mio::PollOpt::level()).is_ok() {
+                        self.close_after_send();
+                        return;
+                    }
+                }
+            }
+        }
+
+        self.close();
+    }
+
+    /// Remembers error of writing to the socket for delivery to the http or websocket callback and closes the connection.
+    /// The callback is called later by the worker because writing may happen inside of the callback.
+    fn fail_write(&self, err: &std::io::Error) {
+        if let Ok(mut write_error) = self.inner.write_error.lock() {
+            if write_error.is_none() {
+                *write_error = Some((err.kind(), err.to_string()));
+            }
+        }
+
+        self.close();
+    }
+
+    /// Calls http or websocket callback with error of writing if it was. Called by the worker before removing of the session.
+    pub(crate) fn report_write_error(&self) {
+        let err = match self.inner.write_error.lock() {
+            Ok(mut write_error) => write_error.take(),
+            Err(_) => None,
+        };
+
+        if let Some((kind, message)) = err {
+            let err = std::io::Error::new(kind, message);
+            if self.inner.websocket_callback.lock().is_ok_and(|callback| callback.is_some()) {
+                self.call_websocket_callback(Err(WebsocketError::WriteError(err)));
+            } else if self.is_http_mode() {
+                self.call_http_callback(Err(HttpError::WriteError(err)));
+            }
+        }
+    }
+
     /// If the data was not sent immediately, it switches to the sending mode in parts.
     fn send_later(&self, mut surplus: SurplusForWrite) {
         if let Ok(mut supluses) = self.inner.surpluses_to_write.lock() {
             if let Ok(stream) = self.inner.mio_stream.lock() {
-                match self.inner.mio_poll.reregister(&*stream, mio::Token(self.inner.slab_key), mio::Ready::writable(), mio::PollOpt::level()) {
+                // writable readiness wakes the worker even if writing is stopped by bandwidth limit
+                match self.inner.mio_poll.reregister(&*stream, self.inner.token, self.inner.interest_while_writing() | mio::Ready::writable(), mio::PollOpt::level()) {
                     Ok(()) => {
+                        self.inner.worker_counters.queued_write_bytes.fetch_add(surplus.data.len() - surplus.write_yet_cnt, Ordering::SeqCst);
                         supluses.push(surplus);
                         return;
                     }
@@ -175,11 +566,43 @@ impl TcpSession {
         }
     }
 
+    /// Switch to raw mode. HTTP and websocket processing of the connection stops, all next received data
+    /// (including data received after the last request) is passed to the callback as is, data is sent by `send`.
+    /// For custom protocols after HTTP upgrade, tunnels, etc. If the callback returns error, the connection is closed.
+    pub fn into_raw_mode(self, callback: impl FnMut(&[u8], &TcpSession) -> Result<(), Box<dyn std::error::Error>> + Send + 'static) {
+        if let Ok(mut raw_callback) = self.inner.raw_callback.lock() {
+            *raw_callback = Some(Box::new(callback));
+        }
+    }
+
     /// Need close of client socket.
     pub(crate) fn need_close(&self) -> bool {
         self.inner.need_close.load(Ordering::SeqCst)
     }
 
+    /// Takes error of failed TLS handshake.
+    pub(crate) fn take_tls_handshake_error(&self) -> Option<rustls::TLSError> {
+        self.inner.tls_handshake_error.lock().ok()?.take()
+    }
+
+    /// Returns true if some data is waiting for the socket to be ready for write.
+    pub(crate) fn has_queued_data(&self) -> bool {
+        self.inner.surpluses_to_write.lock().is_ok_and(|surpluses| !surpluses.is_empty())
+    }
+
+    /// Returns true if some data is not written yet, including data sent from other threads, or some request is not
+    /// dropped yet, so its response may be not sent yet.
+    pub(crate) fn has_pending_work(&self) -> bool {
+        self.has_queued_data()
+            || self.inner.outbox_scheduled.load(Ordering::SeqCst)
+            || self.inner.requests_in_flight.load(Ordering::SeqCst) > 0
+    }
+
+    /// Return true if connection uses TLS.
+    pub fn is_tls(&self) -> bool {
+        self.inner.tls_session.is_some()
+    }
+
     /// Return true if client connection is using for receiving http requests and send responses.
     pub(crate) fn is_http_mode(&self) -> bool {
         self.inner.is_http_mode()
@@ -196,23 +619,75 @@ impl TcpSession {
         }
     }
 
+    /// Helps call callback.
+    pub(crate) fn call_raw_callback(&self, data: &[u8]) {
+        if let Ok(mut callback) = self.inner.raw_callback.lock() {
+            if let Some(callback) = &mut *callback {
+                if callback(data, self).is_err() {
+                    self.close();
+                }
+            }
+        }
+    }
+
     /// Helps call callback.
     pub(crate) fn call_http_callback(&self, request: Result<Request, HttpError>) {
         if let Ok(mut callback) = self.inner.http_request_callback.lock() {
             if let Some(callback) = &mut *callback {
-                if callback(request).is_err() {
-                    self.close();
+                let result = callback(request);
+
+                #[cfg(feature = "tracing")]
+                if let Err(err) = &result {
+                    tracing::debug!(session_id = self.id(), error = %err, "http callback failed, closing connection");
+                }
+
+                if let Err(err) = result {
+                    self.close_by_handler_error(err.as_ref());
                 }
             }
         }
     }
 
+    /// Closes connection after error of http handler. If error is `HandlerError`, sends error response before closing.
+    pub(crate) fn close_by_handler_error(&self, err: &(dyn std::error::Error + 'static)) {
+        match err.downcast_ref::<HandlerError>() {
+            Some(handler_error) => {
+                self.close_with_response(&handler_error.response(self.http_version(), &self.rfc7231_date()));
+            }
+            None => {
+                self.close();
+            }
+        }
+    }
+
+    /// Sends raw response and closes the connection after it.
+    pub(crate) fn close_with_response(&self, response: &[u8]) {
+        self.close_after_send();
+        self.send_while_closing(response, |_| {});
+    }
+
+    /// Current date for "Date" header.
+    pub(crate) fn rfc7231_date(&self) -> String {
+        self.inner.http_date_string.read().map(|date| date.clone()).unwrap_or_default()
+    }
+
+    /// Version for responses of the server itself, of the last received request, "HTTP/1.1" before the first one.
+    pub(crate) fn http_version(&self) -> &'static str {
+        if self.inner.http_1_0.load(Ordering::SeqCst) {
+            "HTTP/1.0"
+        } else {
+            "HTTP/1.1"
+        }
+    }
+
     /// Called when new TCP connection.
-    pub(crate) fn new(id: u64, slab_key: usize, stream: mio::net::TcpStream, addr: SocketAddr, tls_session: Option<Mutex<rustls::ServerSession>>, mio_poll: Arc<mio::Poll>, http_date_string: Arc<RwLock<String>>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(id: u64, token: mio::Token, stream: mio::net::TcpStream, addr: SocketAddr, tls_session: Option<Mutex<rustls::ServerSession>>, mio_poll: Arc<mio::Poll>, http_date_string: Arc<RwLock<String>>, worker_counters: Arc<WorkerCounters>, global_throttle: Option<Arc<Throttle>>, waker: Arc<Waker>) -> Self {
+        let (outbox, outbox_receiver) = mpsc::channel();
         TcpSession {
             inner: Arc::new(InnerTcpSession {
                 id,
-                slab_key,
+                token,
                 mio_stream: Mutex::new(stream),
                 addr,
                 tls_session,
@@ -220,26 +695,67 @@ impl TcpSession {
                 http_request_callback: Mutex::new(None),
                 is_http_mode: Arc::new(AtomicBool::new(false)),
                 websocket_callback: Mutex::new(None),
+                raw_callback: Mutex::new(None),
                 content_callback: Mutex::new(None),
                 need_close: AtomicBool::new(false),
                 surpluses_to_write: Mutex::new(Vec::new()),
+                slow_consumer: Mutex::new(SlowConsumerState::Normal),
                 mio_poll,
                 http_date_string,
                 need_close_after_sending: Arc::new(AtomicBool::new(false)),
+                worker_counters,
+                write_error: Mutex::new(None),
+                read_closed: AtomicBool::new(false),
+                throttle: Mutex::new(None),
+                global_throttle,
+                throttled: AtomicBool::new(false),
+                worker_thread: std::thread::current().id(),
+                outbox,
+                outbox_receiver: Mutex::new(outbox_receiver),
+                outbox_scheduled: AtomicBool::new(false),
+                waker,
+                deferred_handshake: AtomicBool::new(false),
+                handshake_pending: AtomicBool::new(false),
+                tls_handshake_error: Mutex::new(None),
+                requests_in_flight: AtomicUsize::new(0),
+                http_1_0: AtomicBool::new(false),
+                blocking_jobs: AtomicUsize::new(0),
+                coalesced_frame_limit: AtomicUsize::new(0),
+                last_activity: Mutex::new(Instant::now()),
+                connected_at: Instant::now(),
+                bytes_read: AtomicU64::new(0),
+                bytes_written: AtomicU64::new(0),
+                read_quota: AtomicU64::new(u64::MAX),
+                write_quota: AtomicU64::new(u64::MAX),
+                exceeded_quota: Mutex::new(None),
+                protocol_mismatch: Mutex::new(None),
+                tls_data_received: AtomicBool::new(false),
+                linger_grace: Mutex::new(None),
+                linger_deadline: Mutex::new(None),
+                websocket_membership: Mutex::new(Membership::default()),
             }),
         }
     }
 
     /// Writes data that was not written in a previous write attempt. Called when the socket is ready to write again.
     pub(crate) fn send_yet(&self) {
+        // completion callbacks are called after unlocking of the queue because they may send more data
+        for (mut res_callback, result) in self.write_surpluses() {
+            res_callback(result);
+        }
+    }
+
+    /// Writes queued data, returns completion callbacks of finished writes with results.
+    fn write_surpluses(&self) -> Vec<(WriteResultCallback, Result<(), std::io::Error>)> {
+        let mut finished = Vec::new();
+
         if let Ok(mut surpluses_for_write) = self.inner.surpluses_to_write.lock() {
-            // ???
             if surpluses_for_write.is_empty() {
-                // unreachable code
+                log(Level::Warn, format_args!("session {} is writable without queued data", self.id()));
                 if let Ok(stream) = self.inner.mio_stream.lock() {
-                    match self.inner.mio_poll.reregister(&*stream, mio::Token(self.inner.slab_key), mio::Ready::readable(), mio::PollOpt::level()) {
+                    match self.inner.mio_poll.reregister(&*stream, self.inner.token, mio::Ready::readable(), mio::PollOpt::level()) {
                         Ok(()) => {
-                            return;
+                            return finished;
                         }
                         Err(err) => {
                             if self.is_http_mode() {
@@ -252,28 +768,30 @@ impl TcpSession {
                 }
 
                 self.close();
-                return;
+                return finished;
             }
 
             for surplus in surpluses_for_write.iter_mut() {
-                // ???
                 if surplus.write_yet_cnt >= surplus.data.len() {
-                    // unreachable code, data will removed latter from vec below
+                    // data will removed latter from vec below
+                    log(Level::Error, format_args!("session {} has already written data in write queue", self.id()));
                     continue;
                 }
 
                 match self.inner.write(&surplus.data[surplus.write_yet_cnt..]) {
                     Ok(cnt) => {
                         surplus.write_yet_cnt += cnt;
+                        self.inner.worker_counters.queued_write_bytes.fetch_sub(cnt, Ordering::SeqCst);
                         if surplus.write_yet_cnt < surplus.data.len() {
                             // will write latter when writeable
                             break;
                         }
+
+                        finished.push((std::mem::replace(&mut surplus.res_callback, Box::new(|_| {})), Ok(())));
                     }
                     Err(err) => {
                         if err.kind() != std::io::ErrorKind::WouldBlock {
-                            (surplus.res_callback)(Err(err));
-                            self.close();
+                            self.fail_write(&err);
                         }
 
                         // if WouldBlock data will write latter when writeable
@@ -282,11 +800,32 @@ impl TcpSession {
                 }
             }
 
+            if self.inner.write_failed() {
+                // data will not be written anymore, inform about it all waiting for the write
+                let not_written: usize = surpluses_for_write.iter().map(|surplus| surplus.data.len().saturating_sub(surplus.write_yet_cnt)).sum();
+                self.inner.worker_counters.queued_write_bytes.fetch_sub(not_written, Ordering::SeqCst);
+                for surplus in surpluses_for_write.drain(..) {
+                    if surplus.write_yet_cnt < surplus.data.len() {
+                        if let Some(err) = self.inner.write_error_copy() {
+                            finished.push((surplus.res_callback, Err(err)));
+                        }
+                    }
+                }
+                return finished;
+            }
+
             surpluses_for_write.retain(|surplus| surplus.write_yet_cnt < surplus.data.len());
 
+            if !surpluses_for_write.is_empty() && self.inner.is_throttling() {
+                // socket is writable but bandwidth limit is reached, don't wait for writable readiness
+                if let Ok(stream) = self.inner.mio_stream.lock() {
+                    let _ = self.inner.mio_poll.reregister(&*stream, self.inner.token, self.inner.interest_while_writing(), mio::PollOpt::level());
+                }
+            }
+
             if surpluses_for_write.is_empty() {
                 if let Ok(stream) = self.inner.mio_stream.lock() {
-                    if let Err(err) = self.inner.mio_poll.reregister(&*stream, mio::Token(self.inner.slab_key), mio::Ready::readable(), mio::PollOpt::level()) {
+                    if let Err(err) = self.inner.mio_poll.reregister(&*stream, self.inner.token, mio::Ready::readable(), mio::PollOpt::level()) {
                         if self.is_http_mode() {
                             self.call_http_callback(Err(HttpError::PollRegisterError(err)));
                         } else {
@@ -297,10 +836,12 @@ impl TcpSession {
 
                 // all data sent, switch to read mode
                 if self.inner.need_close_after_sending.load(Ordering::SeqCst) {
-                    self.close();
+                    self.close_after_sent();
                 }
             }
         }
+
+        finished
     }
 }
 
@@ -320,15 +861,37 @@ impl Write for TcpSession {
     }
 }
 
+/// Result of sending data to connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendStatus {
+    /// Data is written or queued for writing. Writing can still fail, see result callback of `try_send`.
+    Queued,
+    /// The connection is closed, data is dropped. The client is gone and further work for it is useless.
+    Closed,
+}
+
 /// It's use in load content callback for inform about finish of reading.
 pub type ContentIsComplite = Option<Request>;
 
+/// Callback function that is called when a data read from tcp socket.
+pub(crate) type DataReceivedCallback = Box<dyn FnMut(&[u8]) + Send>;
+/// Callback function that is called when a new HTTP request is received or error receiving it.
+pub(crate) type HttpRequestCallback = Box<dyn FnMut(Result<Request, HttpError>) -> Result<(), Box<dyn std::error::Error>> + Send>;
+/// Callback function that is called when content of HTTP request is received by parts.
+pub(crate) type ContentCallback = Box<dyn FnMut(&[u8]/*data part*/, ContentIsComplite) -> Result<(), Box<dyn std::error::Error>> + Send>;
+/// Callback function that is called when a write is finished or socket writing error.
+type WriteResultCallback = Box<dyn FnMut(Result<(), std::io::Error>) + Send + 'static>;
+/// Callback function that is called with received data in raw mode.
+pub(crate) type RawCallback = Box<dyn FnMut(&[u8], &TcpSession) -> Result<(), Box<dyn std::error::Error>> + Send>;
+/// Callback function that is called when a new websocket frame is received or error receiving it.
+pub(crate) type WebsocketCallback = Box<dyn FnMut(WebsocketResult, Websocket) -> Result<(), WebsocketError> + Send>;
+
 /// Private data of tcp session.
 pub(crate) struct InnerTcpSession {
     /// Tcp client connection id on the server in connection order.
     id: u64,
-    /// Slab key of tcp client connection on the server.
-    slab_key: usize,
+    /// Token of connection in mio poll, see `worker::session_token`.
+    pub(crate) token: mio::Token,
     /// An internet socket address, either IPv4 or IPv6.
     pub(crate) addr: SocketAddr,
     /// Stream which received from MIO event.
@@ -337,15 +900,17 @@ pub(crate) struct InnerTcpSession {
     tls_session: Option<Mutex<rustls::ServerSession>>,
 
     /// Callback function that is called when a data read from tcp socket.
-    pub(crate) on_data_received_callback: Mutex<Option<Box<dyn FnMut(&[u8]) + Send>>>,
+    pub(crate) on_data_received_callback: Mutex<Option<DataReceivedCallback>>,
     /// Sets true when callback is set.
     pub(crate) is_http_mode: Arc<AtomicBool>,
     /// Callback function that is called when a new HTTP request is received or error receiving it.
-    pub(crate) http_request_callback: Mutex<Option<Box<dyn FnMut(Result<Request, HttpError>) -> Result<(), Box<dyn std::error::Error>> + Send>>>,
+    pub(crate) http_request_callback: Mutex<Option<HttpRequestCallback>>,
     /// Callback function that is called when content of HTTP request is fully received or error receiving it.
-    pub(crate) content_callback: Mutex<Option<(Box<dyn FnMut(&[u8]/*data part*/, ContentIsComplite) -> Result<(), Box<dyn std::error::Error>> + Send>, Option<Request>)>>,
+    pub(crate) content_callback: Mutex<Option<(ContentCallback, Option<Request>)>>,
     /// Callback function that is called when a new websocket frame is received or error receiving it.
-    pub(crate) websocket_callback: Mutex<Option<Box<dyn FnMut(WebsocketResult, Websocket) -> Result<(), WebsocketError> + Send>>>,
+    pub(crate) websocket_callback: Mutex<Option<WebsocketCallback>>,
+    /// Callback function that is called with received data in raw mode.
+    pub(crate) raw_callback: Mutex<Option<RawCallback>>,
 
     /// Data that was not written in one write operation and is waiting for the socket to be ready.
     surpluses_to_write: Mutex<Vec<SurplusForWrite>>,
@@ -360,14 +925,164 @@ pub(crate) struct InnerTcpSession {
     pub(crate) http_date_string: Arc<RwLock<String>>,
 
     /// For close the connection after the http response.
-    need_close_after_sending: Arc<AtomicBool>,
+    pub(crate) need_close_after_sending: Arc<AtomicBool>,
+
+    /// Statistics counters of worker that serves the connection.
+    pub(crate) worker_counters: Arc<WorkerCounters>,
+
+    /// Kind and text of error of writing to the socket, waiting for delivery to the callback.
+    write_error: Mutex<Option<(std::io::ErrorKind, String)>>,
+
+    /// The client closed its side of connection, nothing more to read.
+    read_closed: AtomicBool,
+
+    /// Limit of outbound bandwidth of this connection.
+    throttle: Mutex<Option<Throttle>>,
+    /// Limit of outbound bandwidth of all connections of the server.
+    global_throttle: Option<Arc<Throttle>>,
+    /// Writing is stopped by bandwidth limit, the worker resumes it later.
+    pub(crate) throttled: AtomicBool,
+
+    /// Thread of worker that serves the connection.
+    worker_thread: std::thread::ThreadId,
+    /// Data sent from other threads, written by the worker. Senders don't block each other.
+    outbox: mpsc::Sender<SurplusForWrite>,
+    /// Receiver of data sent from other threads, locked only by the worker thread.
+    outbox_receiver: Mutex<mpsc::Receiver<SurplusForWrite>>,
+    /// The worker is already woken for writing of the outbox.
+    outbox_scheduled: AtomicBool,
+    /// Wakes the worker when data is sent from other threads.
+    waker: Arc<Waker>,
+    /// Websocket handshake was deferred, see `Request::accept_websocket_deferred`.
+    pub(crate) deferred_handshake: AtomicBool,
+    /// Deferred websocket handshake is waiting for approval.
+    pub(crate) handshake_pending: AtomicBool,
+    /// Error of TLS handshake, waiting for delivery to `Event::TlsHandshakeFailed`.
+    tls_handshake_error: Mutex<Option<rustls::TLSError>>,
+    /// Number of received requests that are not dropped yet.
+    pub(crate) requests_in_flight: AtomicUsize,
+    /// Last received request is HTTP/1.0, see `TcpSession::http_version`.
+    pub(crate) http_1_0: AtomicBool,
+    /// Number of running jobs of `Request::spawn_blocking` and queued requests of `ConcurrencyLimit`, next pipelined requests are not parsed until it's zero.
+    pub(crate) blocking_jobs: AtomicUsize,
+    /// See `web_session::Settings::websocket_coalescing`, 0 if frames are not coalesced.
+    pub(crate) coalesced_frame_limit: AtomicUsize,
+    /// Moment of last read from socket or of drop of last request, for `Settings::idle_timeout`.
+    pub(crate) last_activity: Mutex<Instant>,
+    /// Moment of accepting of the connection.
+    connected_at: Instant,
+    /// See `TcpSession::bytes_read`.
+    bytes_read: AtomicU64,
+    /// See `TcpSession::bytes_written`.
+    bytes_written: AtomicU64,
+    /// Maximum of `bytes_read`, u64::MAX if there is no limit.
+    read_quota: AtomicU64,
+    /// Maximum of `bytes_written`, u64::MAX if there is no limit.
+    write_quota: AtomicU64,
+    /// Quota by which the connection was closed, waiting for delivery to `Event::QuotaExceeded`.
+    exceeded_quota: Mutex<Option<QuotaKind>>,
+    /// Other protocol by which the connection was closed, waiting for delivery to `Event::ProtocolMismatch`.
+    protocol_mismatch: Mutex<Option<ProtocolMismatch>>,
+    /// First data of TLS connection is received, it's checked for plaintext HTTP.
+    tls_data_received: AtomicBool,
+    /// Grace period of reading after half-close, see `TcpSession::shutdown_write`.
+    linger_grace: Mutex<Option<Duration>>,
+    /// End of grace period, set when write side of the connection is shut down.
+    linger_deadline: Mutex<Option<Instant>>,
+    /// Groups of websocket registry joined by the connection, see `Websocket::join`.
+    pub(crate) websocket_membership: Mutex<Membership>,
+    /// State of detection of slow consumer, see `Settings::slow_consumer`.
+    pub(crate) slow_consumer: Mutex<SlowConsumerState>,
+}
+
+/// Queue of data waiting for write relative to threshold of `Settings::slow_consumer`.
+pub(crate) enum SlowConsumerState {
+    /// Queue is below the threshold.
+    Normal,
+    /// Queue is above the threshold since the time.
+    Backlogged(Instant),
+    /// `Event::SlowConsumer` is emitted, waiting for the queue to fall below the threshold.
+    Reported,
+}
+
+impl Drop for InnerTcpSession {
+    fn drop(&mut self) {
+        // empty data of `flush_pending` is written if all data before it is written
+        let mut lost = self.write_error_copy().is_some();
+
+        // data that will not be written anymore
+        if let Ok(mut surpluses) = self.surpluses_to_write.lock() {
+            let not_written: usize = surpluses.iter().map(|surplus| surplus.data.len().saturating_sub(surplus.write_yet_cnt)).sum();
+            self.worker_counters.queued_write_bytes.fetch_sub(not_written, Ordering::SeqCst);
+
+            for mut surplus in surpluses.drain(..) {
+                lost = lost || !surplus.data.is_empty();
+                if lost {
+                    (surplus.res_callback)(Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "connection closed before data was written")));
+                } else {
+                    (surplus.res_callback)(Ok(()));
+                }
+            }
+        }
+
+        // data sent from other threads after the connection was removed
+        if let Ok(outbox_receiver) = self.outbox_receiver.lock() {
+            for mut surplus in outbox_receiver.try_iter() {
+                lost = lost || !surplus.data.is_empty();
+                if lost {
+                    (surplus.res_callback)(Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "connection closed before data was written")));
+                } else {
+                    (surplus.res_callback)(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+/// Joins consecutive data not longer than `limit` into one write, see `Settings::websocket_coalescing`.
+fn coalesce(sent: Vec<SurplusForWrite>, limit: usize) -> Vec<SurplusForWrite> {
+    let mut result = Vec::with_capacity(sent.len());
+    let mut batch: Vec<SurplusForWrite> = Vec::new();
+    for surplus in sent {
+        if surplus.data.len() > limit {
+            join_batch(&mut batch, &mut result);
+            result.push(surplus);
+        } else {
+            batch.push(surplus);
+        }
+    }
+
+    join_batch(&mut batch, &mut result);
+    result
+}
+
+/// Moves data of `batch` to `result` as one write with callback that calls callbacks of all joined data.
+fn join_batch(batch: &mut Vec<SurplusForWrite>, result: &mut Vec<SurplusForWrite>) {
+    if batch.len() < 2 {
+        result.append(batch);
+        return;
+    }
+
+    let mut data = Vec::with_capacity(batch.iter().map(|surplus| surplus.data.len()).sum());
+    let mut callbacks = Vec::with_capacity(batch.len());
+    for surplus in batch.drain(..) {
+        data.extend_from_slice(&surplus.data);
+        callbacks.push(surplus.res_callback);
+    }
+
+    let res_callback: WriteResultCallback = Box::new(move |res: Result<(), std::io::Error>| {
+        for callback in callbacks.iter_mut() {
+            callback(res.as_ref().map(|_| ()).map_err(|err| std::io::Error::new(err.kind(), err.to_string())));
+        }
+    });
+    result.push(SurplusForWrite { data: Arc::new(data), write_yet_cnt: 0, res_callback });
 }
 
 /// Data that was not written in one write operation and is waiting for the socket to be ready.
 struct SurplusForWrite {
     data: Arc<Vec<u8>>,
     write_yet_cnt: usize,
-    res_callback: Box<dyn FnMut(Result<(), std::io::Error>) + Send + 'static>,
+    res_callback: WriteResultCallback,
 }
 
 /// Private tcp session data.
@@ -381,7 +1096,56 @@ impl InnerTcpSession {
         self.is_http_mode.load(Ordering::SeqCst)
     }
 
+    /// Current thread is the worker thread of the connection.
+    fn is_worker_thread(&self) -> bool {
+        std::thread::current().id() == self.worker_thread
+    }
+
+    /// Poll interest while there is queued data. Reading continues during writing so that incoming data
+    /// (for example websocket frames) is not blocked by long output, unless the client closed its side of connection.
+    /// Writable readiness is not needed while writing is stopped by bandwidth limit.
+    fn interest_while_writing(&self) -> mio::Ready {
+        let mut interest = mio::Ready::empty();
+        if !self.read_closed.load(Ordering::SeqCst) {
+            interest |= mio::Ready::readable();
+        }
+        if !self.throttled.load(Ordering::SeqCst) {
+            interest |= mio::Ready::writable();
+        }
+
+        interest
+    }
+
+    /// Bandwidth of connection is limited.
+    fn is_throttling(&self) -> bool {
+        self.global_throttle.is_some() || self.throttle.lock().is_ok_and(|throttle| throttle.is_some())
+    }
+
+    /// Writing to the socket failed, the connection is closing.
+    fn write_failed(&self) -> bool {
+        self.write_error.lock().is_ok_and(|write_error| write_error.is_some())
+    }
+
+    /// New error same as error of writing.
+    fn write_error_copy(&self) -> Option<std::io::Error> {
+        match self.write_error.lock() {
+            Ok(write_error) => write_error.as_ref().map(|(kind, message)| std::io::Error::new(*kind, message.clone())),
+            Err(_) => None,
+        }
+    }
+
     pub fn read_stream(&self, buf: &mut [u8]) -> io::Result<usize> {
+        // plaintext of TLS record that didn't fit to the buffer is returned before reading of the socket
+        if self.has_tls_plaintext() {
+            if let Some(Ok(mut tls_session)) = self.tls_session.as_ref().map(|tls_session| tls_session.lock()) {
+                let cnt = tls_session.read(buf)?;
+                if cnt > 0 {
+                    self.on_data_received(&buf[..cnt]);
+                    return Ok(cnt);
+                }
+            }
+        }
+
         let read_cnt = {
             match self.mio_stream.lock() {
                 Ok(mut stream) => {
@@ -390,7 +1154,7 @@ impl InnerTcpSession {
                     //~=~=~=~=~=~=~=~=
                 }
                 Err(err) => {
-                    return Err(io::Error::new(ErrorKind::Other, format!("{}", err)));
+                    return Err(io::Error::other(format!("{}", err)));
                 }
             }
         };
@@ -399,30 +1163,61 @@ impl InnerTcpSession {
             return Ok(0);
         }
 
-        let call_on_data_received_callback = |data: &[u8]| {
-            if let Ok(mut on_data_received_callback) = self.on_data_received_callback.lock() {
-                if let Some(on_data_received_callback) = &mut *on_data_received_callback {
-                    on_data_received_callback(data);
-                }
-            }
-        };
+        if let Ok(mut last_activity) = self.last_activity.lock() {
+            *last_activity = Instant::now();
+        }
 
         match &self.tls_session {
             None => {
-                call_on_data_received_callback(&buf[..read_cnt]);
+                self.on_data_received(&buf[..read_cnt]);
                 Ok(read_cnt)
             },
             Some(tls_session) => {
-                let read_buf: &mut dyn std::io::Read = &mut &buf[..read_cnt];
                 match tls_session.lock() {
                     Ok(mut tls_session) => {
-                        tls_session.read_tls(read_buf)?;
+                        let first_data = !self.tls_data_received.swap(true, Ordering::SeqCst);
+                        if first_data && tls_session.is_handshaking() && is_plaintext_http(&buf[..read_cnt]) {
+                            self.set_protocol_mismatch(ProtocolMismatch::PlaintextOnTls);
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, "plaintext HTTP request on TLS connection"));
+                        }
+
+                        let was_handshaking = tls_session.is_handshaking();
+
+                        // the session takes limited amount of data at once, the read can contain several records
+                        let mut received = &buf[..read_cnt];
+                        while !received.is_empty() {
+                            tls_session.read_tls(&mut received)?;
+
+                            if let Err(err) = tls_session.process_new_packets() {
+                                #[cfg(feature = "tracing")]
+                                tracing::debug!(session_id = self.id, error = %err, "tls error");
+
+                                if was_handshaking {
+                                    if let Ok(mut tls_handshake_error) = self.tls_handshake_error.lock() {
+                                        *tls_handshake_error = Some(err.clone());
+                                    }
+                                }
+
+                                return Err(io::Error::other(err));
+                            }
+                        }
 
-                        if let Err(err) = tls_session.process_new_packets() {
-                            return Err(io::Error::new(ErrorKind::Other, err));
+                        #[cfg(feature = "tracing")]
+                        if was_handshaking && !tls_session.is_handshaking() {
+                            tracing::debug!(session_id = self.id, "tls handshake completed");
                         }
 
-                        let tls_readed_cnt = tls_session.read(&mut buf[..])?;
+                        // plaintext of each record is read separately, the rest that doesn't fit is read by next call
+                        let mut tls_readed_cnt = 0;
+                        while tls_readed_cnt < buf.len() {
+                            match tls_session.read(&mut buf[tls_readed_cnt..]) {
+                                Ok(0) => break,
+                                Ok(cnt) => tls_readed_cnt += cnt,
+                                // "close_notify" after data is reported by next read
+                                Err(_) if tls_readed_cnt > 0 => break,
+                                Err(err) => return Err(err),
+                            }
+                        }
                         while tls_session.wants_write() {
                             if let Ok(mut stream) = self.mio_stream.lock() {
                                 //=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=
@@ -435,24 +1230,146 @@ impl InnerTcpSession {
                             return Err(io::Error::new(std::io::ErrorKind::WouldBlock, "operation would block"));
                         }
 
-                        call_on_data_received_callback(&buf[..tls_readed_cnt]);
+                        self.on_data_received(&buf[..tls_readed_cnt]);
 
                         Ok(tls_readed_cnt)
                     }
                     Err(err) => {
-                        Err(io::Error::new(ErrorKind::Other, format!("{}", err)))
+                        Err(io::Error::other(format!("{}", err)))
                     }
                 }
             }
         }
     }
 
+    /// TLS session has decrypted data that is not read yet, see `read_stream`.
+    pub(crate) fn has_tls_plaintext(&self) -> bool {
+        self.tls_session.as_ref().is_some_and(|tls_session| tls_session.lock().is_ok_and(|tls_session| !tls_session.wants_read()))
+    }
+
+    /// Calls callback of `TcpSession::on_data_received`.
+    pub(crate) fn on_data_received(&self, data: &[u8]) {
+        if let Ok(mut on_data_received_callback) = self.on_data_received_callback.lock() {
+            if let Some(on_data_received_callback) = &mut *on_data_received_callback {
+                on_data_received_callback(data);
+            }
+        }
+    }
+
     /// Close of client socket. After clossing will be generated `sever::Event::Disconnected`.
     pub fn close(&self) {
         self.need_close.store(true, Ordering::SeqCst);
     }
 
+    /// Sends TLS "close_notify" and shuts down write side of the socket, see `TcpSession::shutdown_write`.
+    fn shutdown_write(&self, grace: Duration) {
+        let mut linger_deadline = match self.linger_deadline.lock() {
+            Ok(linger_deadline) => linger_deadline,
+            Err(_) => return self.close(),
+        };
+        if linger_deadline.is_some() || self.need_close.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut stream = match self.mio_stream.lock() {
+            Ok(stream) => stream,
+            Err(_) => return self.close(),
+        };
+
+        if let Some(Ok(mut tls_session)) = self.tls_session.as_ref().map(|tls_session| tls_session.lock()) {
+            tls_session.send_close_notify();
+            while tls_session.wants_write() {
+                if tls_session.write_tls(&mut *stream).is_err() {
+                    break;
+                }
+            }
+        }
+
+        match stream.shutdown(std::net::Shutdown::Write) {
+            Ok(()) => {
+                *linger_deadline = Some(Instant::now() + grace);
+                self.worker_counters.lingering_sessions.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(_) => self.close(),
+        }
+    }
+
+    /// Write side of the connection is shut down by `TcpSession::shutdown_write`.
+    fn is_write_shut_down(&self) -> bool {
+        self.linger_deadline.lock().is_ok_and(|linger_deadline| linger_deadline.is_some())
+    }
+
+    /// Writes as much as bandwidth limits allow and counts written data. Returns `WouldBlock` error if nothing can be written now.
     fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let cnt = self.write_throttled(buf)?;
+        let bytes_written = self.bytes_written.fetch_add(cnt.min(buf.len()) as u64, Ordering::SeqCst) + cnt.min(buf.len()) as u64;
+        if bytes_written > self.write_quota.load(Ordering::SeqCst) {
+            self.exceed_quota(QuotaKind::Write);
+        }
+
+        Ok(cnt)
+    }
+
+    /// Keeps the first detected protocol mismatch for `Event::ProtocolMismatch`.
+    fn set_protocol_mismatch(&self, kind: ProtocolMismatch) {
+        if let Ok(mut protocol_mismatch) = self.protocol_mismatch.lock() {
+            protocol_mismatch.get_or_insert(kind);
+        }
+    }
+
+    /// Closes the connection because of exceeded quota.
+    fn exceed_quota(&self, kind: QuotaKind) {
+        if let Ok(mut exceeded_quota) = self.exceeded_quota.lock() {
+            exceeded_quota.get_or_insert(kind);
+        }
+        self.close();
+    }
+
+    /// Writes as much as bandwidth limits allow. Returns `WouldBlock` error if nothing can be written now.
+    fn write_throttled(&self, buf: &[u8]) -> io::Result<usize> {
+        let throttle = match self.throttle.lock() {
+            Ok(throttle) => throttle,
+            Err(err) => return Err(io::Error::other(format!("{}", err))),
+        };
+
+        if throttle.is_none() && self.global_throttle.is_none() {
+            drop(throttle);
+            return self.write_to_stream(buf);
+        }
+
+        let mut allowed = buf.len();
+        if let Some(throttle) = &*throttle {
+            allowed = throttle.take(allowed);
+        }
+        if let Some(global_throttle) = &self.global_throttle {
+            let globally_allowed = global_throttle.take(allowed);
+            if let Some(throttle) = &*throttle {
+                throttle.give_back(allowed - globally_allowed);
+            }
+            allowed = globally_allowed;
+        }
+
+        if allowed == 0 && !buf.is_empty() {
+            if !self.throttled.swap(true, Ordering::SeqCst) {
+                self.waker.throttled(self.token);
+            }
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "bandwidth limit is reached"));
+        }
+
+        let res = self.write_to_stream(&buf[..allowed]);
+
+        let not_written = allowed.saturating_sub(*res.as_ref().unwrap_or(&0));
+        if let Some(throttle) = &*throttle {
+            throttle.give_back(not_written);
+        }
+        if let Some(global_throttle) = &self.global_throttle {
+            global_throttle.give_back(not_written);
+        }
+
+        res
+    }
+
+    fn write_to_stream(&self, buf: &[u8]) -> io::Result<usize> {
         let tls_session = &self.tls_session;
         let stream = &self.mio_stream;
 
@@ -473,12 +1390,12 @@ impl InnerTcpSession {
                                 //~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=
                             }
                             Err(err) => {
-                                Err(io::Error::new(ErrorKind::Other, format!("{}", err)))
+                                Err(io::Error::other(format!("{}", err)))
                             }
                         }
                     }
                     Err(err) => {
-                        Err(io::Error::new(ErrorKind::Other, format!("{}", err)))
+                        Err(io::Error::other(format!("{}", err)))
                     }
                 }
             }
@@ -490,7 +1407,7 @@ impl InnerTcpSession {
                         //~=~=~=~=~=~=~=~=~=~=~=~=
                     }
                     Err(err) => {
-                        Err(io::Error::new(ErrorKind::Other, format!("{}", err)))
+                        Err(io::Error::other(format!("{}", err)))
                     }
                 }
             }
@@ -513,12 +1430,12 @@ impl InnerTcpSession {
                                 //~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=
                             }
                             Err(err) => {
-                                Err(io::Error::new(ErrorKind::Other, format!("{}", err)))
+                                Err(io::Error::other(format!("{}", err)))
                             }
                         }
                     }
                     Err(err) => {
-                        Err(io::Error::new(ErrorKind::Other, format!("{}", err)))
+                        Err(io::Error::other(format!("{}", err)))
                     }
                 }
             }
@@ -530,7 +1447,7 @@ impl InnerTcpSession {
                         //~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=~=
                     }
                     Err(err) => {
-                        Err(io::Error::new(ErrorKind::Other, format!("{}", err)))
+                        Err(io::Error::other(format!("{}", err)))
                     }
                 }
             }
@@ -0,0 +1,281 @@
+use crate::http_error::HttpError;
+use crate::request::Request;
+use crate::server::{Event, Server, Stopper};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Server for tests of request handlers, runs on ephemeral port of localhost in other thread.
+/// Stopped when dropped.
+pub struct TestServer {
+    addr: SocketAddr,
+    stopper: Stopper,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Starts the server, `on_request` is callback of each connection as in `TcpSession::to_http`.
+    pub fn start(on_request: impl FnMut(Result<Request, HttpError>) -> Result<(), Box<dyn std::error::Error>> + Send + Clone + 'static) -> Result<Self, std::io::Error> {
+        TestServer::start_with(|_| {}, on_request)
+    }
+
+    /// Same as `start`, `prepare` is called with the server before start (for settings, etc.).
+    pub fn start_with(
+        prepare: impl FnOnce(&mut Server),
+        on_request: impl FnMut(Result<Request, HttpError>) -> Result<(), Box<dyn std::error::Error>> + Send + Clone + 'static,
+    ) -> Result<Self, std::io::Error> {
+        let mut server = Server::new(&([127, 0, 0, 1], 0).into())?;
+        prepare(&mut server);
+        let addr = server.local_addr()?;
+        let stopper = server.stopper();
+
+        let thread = std::thread::spawn(move || {
+            let _ = server.run(move |server_event| {
+                if let Event::Incoming(tcp_session) = server_event {
+                    tcp_session.to_http(on_request.clone());
+                }
+            });
+        });
+
+        Ok(TestServer { addr, stopper, thread: Some(thread) })
+    }
+
+    /// Address of the server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Client for requests to this server.
+    pub fn client(&self) -> TestClient {
+        TestClient { addr: self.addr, timeout: Duration::from_secs(3) }
+    }
+
+    /// Stopper of the server, for example for `Stopper::drain`.
+    pub fn stopper(&self) -> Stopper {
+        self.stopper.clone()
+    }
+
+    /// Stops the server and waits for stop of its workers.
+    pub fn stop(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stopper.stop();
+            // workers check stop flag in new poll iteration, wake them until listener is closed
+            while TcpStream::connect(self.addr).is_ok() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// Blocking HTTP client for tests. Each request is sent in new connection which is read until closed by the server.
+#[derive(Clone)]
+pub struct TestClient {
+    addr: SocketAddr,
+    /// Limit of time of receiving response.
+    timeout: Duration,
+}
+
+impl TestClient {
+    /// Client of server on address.
+    pub fn new(addr: SocketAddr) -> Self {
+        TestClient { addr, timeout: Duration::from_secs(3) }
+    }
+
+    /// Set limit of time of receiving response. 3 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Builder of GET request.
+    pub fn get(&self, path: &str) -> TestRequest {
+        self.request("GET", path)
+    }
+
+    /// Builder of POST request.
+    pub fn post(&self, path: &str) -> TestRequest {
+        self.request("POST", path)
+    }
+
+    /// Builder of request with any method.
+    pub fn request(&self, method: &str, path: &str) -> TestRequest {
+        TestRequest {
+            client: self.clone(),
+            method: method.to_string(),
+            path: path.to_string(),
+            version: "HTTP/1.1",
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Sends raw request and reads response until the connection is closed by the server.
+    pub fn send_raw(&self, raw_request: &[u8]) -> Result<TestResponse, std::io::Error> {
+        let mut tcp_stream = TcpStream::connect(self.addr)?;
+        tcp_stream.set_write_timeout(Some(self.timeout))?;
+        tcp_stream.write_all(raw_request)?;
+
+        tcp_stream.set_read_timeout(Some(Duration::from_millis(64)))?;
+        let begin_read = Instant::now();
+        let mut raw = Vec::new();
+        loop {
+            match tcp_stream.read_to_end(&mut raw) {
+                Ok(_) => break,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock || err.kind() == std::io::ErrorKind::TimedOut => {
+                    if begin_read.elapsed() > self.timeout {
+                        return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the server didn't close connection after response"));
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(TestResponse::new(raw))
+    }
+}
+
+/// Builder of request of `TestClient`.
+pub struct TestRequest {
+    client: TestClient,
+    method: String,
+    path: String,
+    version: &'static str,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl TestRequest {
+    /// Adds header.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set content, "Content-Length" header is added when sending.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Send request as HTTP/1.0.
+    pub fn http_1_0(mut self) -> Self {
+        self.version = "HTTP/1.0";
+        self
+    }
+
+    /// Raw request. "Connection: close" is added if there is no "Connection" header.
+    pub fn raw(&self) -> Vec<u8> {
+        let mut raw = format!("{} {} {}\r\n", self.method, self.path, self.version);
+        for (name, value) in &self.headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        if !self.has_header("Connection") {
+            raw.push_str("Connection: close\r\n");
+        }
+
+        if !self.body.is_empty() && !self.has_header("Content-Length") {
+            raw.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+
+        raw.push_str("\r\n");
+
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(&self.body);
+        raw
+    }
+
+    /// Sends request and reads response.
+    pub fn send(&self) -> Result<TestResponse, std::io::Error> {
+        self.client.send_raw(&self.raw())
+    }
+
+    fn has_header(&self, name: &str) -> bool {
+        self.headers.iter().any(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Response received by `TestClient`. Assertion methods panic with the response text.
+pub struct TestResponse {
+    raw: Vec<u8>,
+    /// Index of content in raw response.
+    content_index: usize,
+}
+
+impl TestResponse {
+    fn new(raw: Vec<u8>) -> Self {
+        let content_index = raw.windows(4).position(|window| window == b"\r\n\r\n").map_or(raw.len(), |index| index + 4);
+        TestResponse { raw, content_index }
+    }
+
+    /// All received data.
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Status code. 0 if no valid status line.
+    pub fn code(&self) -> u16 {
+        self.head_lines().next()
+            .and_then(|status_line| status_line.split(' ').nth(1))
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// First value of header by name, case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.head_lines().skip(1)
+            .filter_map(|line| {
+                let mut name_value = line.splitn(2, ':');
+                Some((name_value.next()?, name_value.next()?))
+            })
+            .find(|(header_name, _)| header_name.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+
+    /// Content after headers as is (not dechunked).
+    pub fn content(&self) -> &[u8] {
+        &self.raw[self.content_index..]
+    }
+
+    /// Content as utf-8 string, empty if invalid utf-8.
+    pub fn text(&self) -> &str {
+        std::str::from_utf8(self.content()).unwrap_or("")
+    }
+
+    /// Panics if status code is not equal to `code`.
+    pub fn assert_code(&self, code: u16) -> &Self {
+        assert_eq!(self.code(), code, "{}", String::from_utf8_lossy(&self.raw));
+        self
+    }
+
+    /// Panics if there is no header with the value.
+    pub fn assert_header(&self, name: &str, value: &str) -> &Self {
+        assert_eq!(self.header(name), Some(value), "{}", String::from_utf8_lossy(&self.raw));
+        self
+    }
+
+    /// Panics if content is not equal to `text`.
+    pub fn assert_text(&self, text: &str) -> &Self {
+        assert_eq!(self.text(), text, "{}", String::from_utf8_lossy(&self.raw));
+        self
+    }
+
+    fn head_lines(&self) -> impl Iterator<Item = &str> {
+        std::str::from_utf8(&self.raw[..self.content_index]).unwrap_or("")
+            .split("\r\n")
+            .filter(|line| !line.is_empty())
+    }
+}
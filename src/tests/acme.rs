@@ -0,0 +1,45 @@
+use crate::acme::ChallengeStore;
+use crate::testing::TestServer;
+use crate::tls::ReloadableCert;
+
+#[test]
+fn challenges() {
+    let store = ChallengeStore::new();
+    let in_server = store.clone();
+    let server = TestServer::start_with(
+        move |server| server.settings.web_settings.acme_challenges = Some(in_server),
+        |request| {
+            request?.response(200).text("app").send();
+            Ok(())
+        },
+    ).unwrap();
+    let client = server.client();
+
+    store.add("tok_EN-1", "tok_EN-1.thumbprint");
+    client.get("/.well-known/acme-challenge/tok_EN-1").send().unwrap()
+        .assert_code(200)
+        .assert_header("Content-Type", "application/octet-stream")
+        .assert_text("tok_EN-1.thumbprint");
+    client.get("/.well-known/acme-challenge/unknown").send().unwrap().assert_code(404);
+    client.get("/other").send().unwrap().assert_code(200).assert_text("app");
+
+    store.remove("tok_EN-1");
+    client.get("/.well-known/acme-challenge/tok_EN-1").send().unwrap().assert_code(404);
+}
+
+#[test]
+fn reloadable_cert() {
+    let cert = ReloadableCert::new();
+    assert!(!cert.is_set());
+
+    let certs_pem = std::fs::read("examples/keys/cert.pem").unwrap();
+    let key_pem = std::fs::read("examples/keys/key.pem").unwrap();
+    assert!(cert.set_pem(&certs_pem, b"no key").is_err());
+    assert!(cert.set_pem(&key_pem, &key_pem).is_err());
+    cert.set_pem(&certs_pem, &key_pem).unwrap();
+    assert!(cert.is_set());
+
+    // renewal
+    cert.load("examples/keys/cert.pem", "examples/keys/key.pem").unwrap();
+    assert!(cert.is_set());
+}
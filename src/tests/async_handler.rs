@@ -0,0 +1,101 @@
+use crate::async_handler::{AsyncRequest, Executor, SendAsync};
+use crate::handler_error::HandlerError;
+use crate::router::HandlerResult;
+use crate::tests::request::test_request;
+use std::sync::mpsc;
+
+async fn echo(mut request: AsyncRequest) -> HandlerResult {
+    let body = request.body().await;
+    let path = request.path().to_string();
+    let text = format!("{} {}", path, String::from_utf8_lossy(&body));
+    request.into_request().response(200).close().text(&text).send_async().await?;
+    Ok(())
+}
+
+#[test]
+fn body() {
+    let executor = Executor::new(2);
+    let handler = executor.handler(100, echo);
+    test_request(
+        b"POST /echo HTTP/1.1\r\n\
+        Content-Length: 5\r\n\
+        \r\n\
+        hello",
+        move |request| {
+            assert!(handler(request).is_ok());
+        },
+        |response| {
+            assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+            assert!(response.ends_with(b"\r\n\r\n/echo hello"));
+        },
+    );
+}
+
+#[test]
+fn body_limit() {
+    let executor = Executor::new(1);
+    let handler = executor.handler(4, echo);
+    test_request(
+        b"POST /echo HTTP/1.1\r\n\
+        Content-Length: 5\r\n\
+        \r\n\
+        hello",
+        move |request| {
+            assert!(handler(request).is_ok());
+        },
+        |response| {
+            assert!(response.starts_with(b"HTTP/1.1 413 Payload Too Large\r\n"));
+        },
+    );
+}
+
+#[test]
+fn handler_error() {
+    let executor = Executor::new(1);
+    let handler = executor.handler(100, |_request| async { Err(HandlerError::new(403, "Forbidden").into()) });
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        move |request| {
+            assert!(handler(request).is_ok());
+        },
+        |response| {
+            assert!(response.starts_with(b"HTTP/1.1 403 Forbidden\r\n"));
+        },
+    );
+}
+
+#[test]
+fn spawn() {
+    let executor = Executor::new(1);
+    let (sender, receiver) = mpsc::channel();
+    executor.spawn(async move {
+        let _ = sender.send(std::future::ready(42).await);
+    });
+    assert_eq!(receiver.recv().ok(), Some(42));
+}
+
+#[test]
+fn panic_is_500() {
+    async fn panicking(_request: AsyncRequest) -> HandlerResult {
+        panic!("test panic in async handler");
+    }
+
+    let executor = Executor::new(1);
+    let handler = executor.handler(100, panicking);
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        move |request| {
+            assert!(handler(request).is_ok());
+        },
+        |response| {
+            assert!(response.starts_with(b"HTTP/1.1 500 Internal Server Error\r\n"));
+        },
+    );
+
+    // the thread keeps working after panic
+    let (sender, receiver) = mpsc::channel();
+    executor.spawn(async move {
+        let _ = sender.send(42);
+    });
+    assert_eq!(receiver.recv().ok(), Some(42));
+}
@@ -0,0 +1,43 @@
+use crate::auth::{bearer, bearer_token, constant_time_eq, Signer};
+use crate::router::{Route, Router};
+use crate::testing::TestServer;
+
+#[test]
+fn compare() {
+    assert!(constant_time_eq(b"", b""));
+    assert!(constant_time_eq(b"secret", b"secret"));
+    assert!(!constant_time_eq(b"secret", b"secreT"));
+    assert!(!constant_time_eq(b"secret", b"secret1"));
+
+    assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+    assert_eq!(bearer_token("bearer  abc "), Some("abc"));
+    assert_eq!(bearer_token("Basic abc"), None);
+    assert_eq!(bearer_token("Bearer "), None);
+}
+
+#[test]
+fn signer() {
+    let signer = Signer::new(b"0123456789abcdef0123456789abcdef");
+    let signed = signer.sign("user=1.2");
+    assert!(signed.starts_with("user=1.2."));
+    assert_eq!(signer.verify(&signed), Some("user=1.2"));
+
+    assert_eq!(signer.verify(&signed.replace("user=1", "user=2")), None);
+    assert_eq!(signer.verify("user=1.2"), None);
+    assert_eq!(signer.verify("user=1.2.!!"), None);
+    assert_eq!(Signer::new(b"other key").verify(&signed), None);
+}
+
+#[test]
+fn bearer_guard() {
+    let router = Router::new()
+        .route(Route::new("/private").guard(bearer(&["t1", "t2"])), |request, ()| {
+            request.response(200).text("private").send();
+            Ok(())
+        });
+    let server = TestServer::start(move |request| router.dispatch(request?)).unwrap();
+    let client = server.client();
+    client.get("/private").header("Authorization", "Bearer t2").send().unwrap().assert_code(200);
+    client.get("/private").header("Authorization", "Bearer t3").send().unwrap().assert_code(404);
+    client.get("/private").send().unwrap().assert_code(404);
+}
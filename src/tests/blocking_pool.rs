@@ -0,0 +1,50 @@
+use crate::blocking_pool::BlockingPool;
+use crate::testing::TestServer;
+use std::time::Duration;
+
+#[test]
+fn keeps_order() {
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.blocking_pool = Some(BlockingPool::new(4)),
+        |request| {
+            let request = request?;
+            if request.path() == "/slow" {
+                request.spawn_blocking(|request| {
+                    std::thread::sleep(Duration::from_millis(200));
+                    request.response(200).text("slow").send();
+                    Ok(())
+                });
+            } else {
+                let path = request.path().to_string();
+                request.response(200).text(&path).send();
+            }
+            Ok(())
+        }
+    ).unwrap();
+
+    // the fast request after the slow one is answered after it
+    let response = server.client().send_raw(b"GET /slow HTTP/1.1\r\n\r\nGET /fast HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert_eq!(raw.matches("HTTP/1.1 200 OK").count(), 2);
+    assert!(raw.contains("\r\n\r\nslowHTTP/1.1 200 OK") && raw.ends_with("/fast"), "{}", raw);
+    server.stop();
+}
+
+#[test]
+fn panic_is_500() {
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.blocking_pool = Some(BlockingPool::new(1)),
+        |request| {
+            request?.spawn_blocking(|_request| panic!("test panic in blocking job"));
+            Ok(())
+        }
+    ).unwrap();
+
+    let response = server.client().send_raw(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert!(response.raw().starts_with(b"HTTP/1.1 500 Internal Server Error\r\n"));
+
+    // the pool keeps working after panic
+    let response = server.client().send_raw(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert!(response.raw().starts_with(b"HTTP/1.1 500 Internal Server Error\r\n"));
+    server.stop();
+}
@@ -0,0 +1,237 @@
+use crate::concurrency::ConcurrencyLimit;
+use crate::router::{Route, Router};
+use crate::server::{Event, Server};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Handler that responds in other thread after delay, so the request stays in flight.
+fn slow_handler(request: crate::request::Request, _: ()) -> crate::router::HandlerResult {
+    std::thread::spawn(move || {
+        sleep(Duration::from_millis(100));
+        request.response(200).keep_alive().text("done").send();
+    });
+    Ok(())
+}
+
+/// Handler that reads content and responds with its length.
+fn upload_handler(request: crate::request::Request, _: ()) -> crate::router::HandlerResult {
+    let mut received = 0;
+    request.read_content(move |data, complete| {
+        received += data.len();
+        if let Some(request) = complete {
+            request.response(200).keep_alive().text(&format!("received {} done", received)).send();
+        }
+        Ok(())
+    });
+    Ok(())
+}
+
+/// Sends pipelined requests to the router and returns received responses when `responses_count` responses
+/// are received and the last one ends with "done". Responses are sent from other threads,
+/// so the connection is not closed by the server and it's read without waiting of EOF.
+fn pipelined_responses(port: u16, raw_request: &'static [u8], router: Router, responses_count: usize) -> String {
+    let server = Server::new(&([0, 0, 0, 0], port).into());
+    assert!(server.is_ok());
+    let server = match server {
+        Ok(server) => server,
+        Err(_) => return String::new(),
+    };
+
+    let stopper = server.stopper();
+    let (sender, receiver) = mpsc::channel();
+    let server_run_res = server.run(move |server_event| {
+        match server_event {
+            Event::Incoming(tcp_session) => {
+                let router = router.clone();
+                tcp_session.to_http(move |request| {
+                    router.dispatch(request?)
+                });
+            }
+            Event::Started => {
+                let stopper = stopper.clone();
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    let addr = format!("127.0.0.1:{}", port);
+                    let mut response = Vec::new();
+                    if let Ok(mut tcp_stream) = TcpStream::connect(&addr) {
+                        let _ = tcp_stream.set_read_timeout(Some(Duration::from_millis(10)));
+                        let _ = tcp_stream.write_all(raw_request);
+
+                        let begin_read = Instant::now();
+                        let mut buf = [0; 1024];
+                        while begin_read.elapsed() < Duration::from_secs(3) {
+                            if let Ok(cnt) = tcp_stream.read(&mut buf) {
+                                response.extend_from_slice(&buf[..cnt]);
+                            }
+
+                            let response = String::from_utf8_lossy(&response);
+                            if response.matches("HTTP/1.1 ").count() == responses_count && response.ends_with("done") {
+                                break;
+                            }
+                        }
+                    }
+
+                    let _ = sender.send(String::from_utf8_lossy(&response).to_string());
+
+                    stopper.stop();
+                    while TcpStream::connect(&addr).is_ok() {
+                        sleep(Duration::from_millis(1));
+                    }
+                });
+            }
+            _ => {}
+        }
+    });
+    assert!(server_run_res.is_ok());
+
+    receiver.recv().unwrap_or_default()
+}
+
+/// Waits until requests are released in threads of handlers.
+fn wait_released(limit: &ConcurrencyLimit) -> bool {
+    let begin = Instant::now();
+    while limit.in_flight() != 0 || limit.queued() != 0 {
+        if begin.elapsed() > Duration::from_secs(1) {
+            return false;
+        }
+        sleep(Duration::from_millis(1));
+    }
+
+    true
+}
+
+#[test]
+fn queued() {
+    let limit = ConcurrencyLimit::new(1).queue_limit(1);
+    let router = Router::new()
+        .route(Route::new("/slow").limit(limit.clone()), slow_handler);
+
+    let response = pipelined_responses(9103, b"GET /slow HTTP/1.1\r\n\r\nGET /slow HTTP/1.1\r\n\r\n", router, 2);
+    assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2, "{}", response);
+    assert!(wait_released(&limit));
+}
+
+#[test]
+fn queued_with_content() {
+    let limit = ConcurrencyLimit::new(1).queue_limit(4);
+    let router = Router::new()
+        .route(Route::new("/slow").limit(limit.clone()), slow_handler)
+        .route(Route::new("/upload").method("POST").limit(limit.clone()), upload_handler)
+        .get("/last", |request, _: ()| {
+            request.response(200).keep_alive().text("last done").send();
+            Ok(())
+        });
+
+    let raw_request = format!(
+        "GET /slow HTTP/1.1\r\n\r\nPOST /upload HTTP/1.1\r\nContent-Length: 1000\r\n\r\n{}GET /last HTTP/1.1\r\n\r\n",
+        "a".repeat(1000),
+    );
+    let response = pipelined_responses(9105, raw_request.leak().as_bytes(), router, 3);
+    assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 3, "{}", response);
+    // the queued request gets whole content and the next pipelined request is answered after it
+    let uploaded = response.find("received 1000 done");
+    assert!(uploaded.is_some(), "{}", response);
+    assert!(response.ends_with("last done"), "{}", response);
+    assert!(wait_released(&limit));
+}
+
+#[test]
+fn queued_content_limit() {
+    let limit = ConcurrencyLimit::new(1).queue_limit(4).queued_content_limit(100);
+    let router = Router::new()
+        .route(Route::new("/slow").limit(limit.clone()), slow_handler)
+        .route(Route::new("/upload").method("POST").limit(limit.clone()), upload_handler);
+
+    let raw_request = format!(
+        "GET /slow HTTP/1.1\r\n\r\nPOST /upload HTTP/1.1\r\nContent-Length: 1000\r\n\r\n{}",
+        "a".repeat(1000),
+    );
+    let response = pipelined_responses(9106, raw_request.leak().as_bytes(), router, 2);
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", response);
+    assert!(response.ends_with("\r\n\r\ndone"), "{}", response);
+    assert!(wait_released(&limit));
+}
+
+#[test]
+fn rejected() {
+    let limit = ConcurrencyLimit::new(1).retry_after(Duration::from_secs(5));
+    let router = Router::new()
+        .limit_prefix("/api", limit.clone())
+        .get("/api/slow", slow_handler);
+
+    let response = pipelined_responses(9104, b"GET /api/slow HTTP/1.1\r\n\r\nGET /api/slow HTTP/1.1\r\n\r\n", router, 2);
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", response);
+    assert!(response.contains("Retry-After: 5\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\ndone"), "{}", response);
+    assert!(wait_released(&limit));
+}
+
+#[test]
+fn long_queue() {
+    use crate::testing::TestServer;
+    use std::sync::{Arc, Mutex};
+
+    // each connection queues one request, next pipelined requests of it wait
+    const QUEUED: usize = 5_000;
+    let limit = ConcurrencyLimit::new(1).queue_limit(QUEUED).retry_after(Duration::from_millis(1500));
+    let held = Arc::new(Mutex::new(None));
+    let server_limit = limit.clone();
+    let server_held = held.clone();
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.parse_http_request_settings.pipelining_requests_limit = u16::MAX,
+        move |request| {
+            let held = server_held.clone();
+            server_limit.run(request?, move |request| {
+                if request.path() == "/hold" {
+                    *held.lock().unwrap() = Some(request);
+                } else {
+                    request.response(200).keep_alive().text("done").send();
+                }
+                Ok(())
+            })
+        },
+    ).unwrap();
+
+    let mut hold_stream = TcpStream::connect(server.addr()).unwrap();
+    hold_stream.write_all(b"GET /hold HTTP/1.1\r\n\r\n").unwrap();
+    while held.lock().unwrap().is_none() {
+        sleep(Duration::from_millis(1));
+    }
+
+    let mut streams = Vec::new();
+    for _ in 0..QUEUED {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"GET /fast HTTP/1.1\r\n\r\n").unwrap();
+        streams.push(stream);
+    }
+
+    let begin = Instant::now();
+    while limit.queued() < QUEUED && begin.elapsed() < Duration::from_secs(5) {
+        sleep(Duration::from_millis(1));
+    }
+    assert_eq!(limit.queued(), QUEUED);
+
+    // release of the held request runs all queued handlers in this thread without growth of stack
+    let request = held.lock().unwrap().take().unwrap();
+    request.response(200).keep_alive().text("done").send();
+    assert!(wait_released(&limit));
+
+    for mut stream in streams {
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+        while !response.ends_with(b"done") {
+            let cnt = stream.read(&mut buf).unwrap();
+            assert_ne!(cnt, 0);
+            response.extend_from_slice(&buf[..cnt]);
+        }
+    }
+
+    // rejected requests get rounded up delay
+    let limit = ConcurrencyLimit::new(0).retry_after(Duration::from_millis(1500));
+    let server = TestServer::start(move |request| limit.run(request?, |_| Ok(()))).unwrap();
+    server.client().get("/").send().unwrap().assert_code(503).assert_header("Retry-After", "2");
+}
@@ -1,6 +1,8 @@
-use crate::cookie::{parse_cookie, CookieOfRequst, Cookie};
+use crate::cookie::{parse_cookie, parse_cookie_strict, CookieOfRequst, Cookie, MalformedCookie};
 use crate::tests::request::test_request;
 use crate::request::HttpVersion;
+use chrono::{TimeZone, Utc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 impl<'a> PartialEq for CookieOfRequst<'a> {
     fn eq(&self, other: &Self) -> bool {
@@ -28,8 +30,9 @@ fn parse() {
     assert!(parse_cookie("=x").is_empty());
     assert!(parse_cookie(" =x").is_empty());
     assert_eq!(parse_cookie(" x=; "), vec![CookieOfRequst { name: "x", value: "" }]);
-    assert_eq!(parse_cookie("x  = qq q "), vec![CookieOfRequst { name: "x  ", value: " qq q " }]);
-    assert_eq!(parse_cookie("   x  = qq q "), vec![CookieOfRequst { name: "x  ", value: " qq q " }]);
+    assert_eq!(parse_cookie("x  = qq q "), vec![CookieOfRequst { name: "x", value: "qq q" }]);
+    assert_eq!(parse_cookie("   x  = qq q "), vec![CookieOfRequst { name: "x", value: "qq q" }]);
+    assert_eq!(parse_cookie("\tx\t=\t1\t"), vec![CookieOfRequst { name: "x", value: "1" }]);
     assert_eq!(parse_cookie("ab"), vec![CookieOfRequst { name: "ab", value: "" }]);
     assert_eq!(parse_cookie(" abc"), vec![CookieOfRequst { name: "abc", value: "" }]);
     assert_eq!(parse_cookie(" abc=xyz"), vec![CookieOfRequst { name: "abc", value: "xyz" }]);
@@ -37,12 +40,33 @@ fn parse() {
     assert_eq!(parse_cookie(" abc=xyz; xyz=123"), vec![CookieOfRequst { name: "abc", value: "xyz" }, CookieOfRequst { name: "xyz", value: "123" }]);
 
     assert!(parse_cookie("=x").is_empty());
+
+    // quoted values
+    assert_eq!(parse_cookie("x=\"1 2\"; y=\"\""), vec![CookieOfRequst { name: "x", value: "1 2" }, CookieOfRequst { name: "y", value: "" }]);
+    assert_eq!(parse_cookie("x=\""), vec![CookieOfRequst { name: "x", value: "\"" }]);
+
+    // control characters
+    assert_eq!(parse_cookie("x=1\x002; y=2"), vec![CookieOfRequst { name: "y", value: "2" }]);
+    assert_eq!(parse_cookie("x\x7f=1"), vec![]);
+}
+
+#[test]
+fn parse_strict() {
+    let mut malformed = Vec::new();
+    let cookies = parse_cookie_strict("a=1; b; c d=2; e=x y; =3; f=\"q\"; g=\x01", |pair, reason| malformed.push((pair.to_string(), reason)));
+    assert_eq!(cookies, vec![CookieOfRequst { name: "a", value: "1" }, CookieOfRequst { name: "f", value: "q" }]);
+    assert_eq!(malformed, vec![
+        (" b".to_string(), MalformedCookie::NoValue),
+        (" c d=2".to_string(), MalformedCookie::InvalidName),
+        (" e=x y".to_string(), MalformedCookie::InvalidValue),
+        (" =3".to_string(), MalformedCookie::EmptyName),
+        (" g=\x01".to_string(), MalformedCookie::ControlCharacter),
+    ]);
 }
 
 #[test]
 fn local_host() {
     test_request(
-        9093,
         b"GET / HTTP/1.1\r\n\
         Cookie: ABCD=-W-e-QSDEe-QSDEF3erw---W-e-Q-SDEF3erwqew-weqf-;key=Hello world!\r\n\
         Connection: keep-alive\r\n\
@@ -74,7 +98,7 @@ fn local_host() {
                 path: Some("/"),
                 domain: Some("domain"),
                 http_only: false,
-                expires: Some("Wed"),
+                expires: Some(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap()),
                 max_age: Some(38),
                 secure: true,
             }.to_string();
@@ -95,8 +119,21 @@ fn local_host() {
                 Connection: close\r\n\
                 Content-Length: 0\r\n\
                 Set-Cookie: seasddsf=13241abc; HttpOnly\r\n\
-                Set-Cookie: test2=xyz; Path=\"/\"; Domain=\"domain\"; Expires=\"Wed\"; Max-Age=38; Secure\r\n\r\n"
+                Set-Cookie: test2=xyz; Path=\"/\"; Domain=\"domain\"; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=38; Secure\r\n\r\n"
             );
         }
     );
 }
+
+#[test]
+fn expires() {
+    let cookie = Cookie::remove("x").expires_at(UNIX_EPOCH + Duration::from_secs(1_445_412_480));
+    assert_eq!(cookie.header_value(), "x=; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=0; HttpOnly");
+
+    let before = SystemTime::now();
+    let cookie = Cookie::remove("x").expires_in(Duration::from_secs(3600));
+    assert_eq!(cookie.max_age, Some(3600));
+    let expires: SystemTime = cookie.expires.unwrap().into();
+    let expected = before + Duration::from_secs(3600);
+    assert!(expires + Duration::from_secs(2) > expected && expires < expected + Duration::from_secs(2));
+}
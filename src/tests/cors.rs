@@ -0,0 +1,67 @@
+use crate::cors::Cors;
+use crate::router::{Route, Router};
+use crate::testing::TestServer;
+use std::time::Duration;
+
+#[test]
+fn cors() {
+    let router = Router::new()
+        .cors(Cors::new().allow_origin("https://app.example").allow_methods(&["GET", "PUT"]).allow_headers(&["Content-Type"]).max_age(Duration::from_secs(600)))
+        .get("/items", |request, ()| {
+            request.response(200).text("items").send();
+            Ok(())
+        })
+        .route(Route::new("/items").method("PUT"), |request, ()| {
+            request.response(200).text("put").send();
+            Ok(())
+        })
+        .route(Route::new("/device").method("POST").cors(Cors::new().allow_origin("https://public.example").allow_credentials().allow_private_network().expose_headers(&["X-Id"])), |request, ()| {
+            request.response(200).text("device").send();
+            Ok(())
+        })
+        .route(Route::new("/session").method("POST").cors(Cors::new().allow_credentials()), |request, ()| {
+            request.response(200).text("session").send();
+            Ok(())
+        });
+    let server = TestServer::start(move |request| router.dispatch(request?)).unwrap();
+    let client = server.client();
+    let preflight = |path: &str, origin: &str, method: &str| client.request("OPTIONS", path).header("Origin", origin).header("Access-Control-Request-Method", method);
+
+    let response = preflight("/items", "https://app.example", "PUT").header("Access-Control-Request-Headers", "content-type").send().unwrap();
+    response.assert_code(204)
+        .assert_header("Access-Control-Allow-Origin", "https://app.example")
+        .assert_header("Vary", "Origin")
+        .assert_header("Access-Control-Allow-Methods", "GET, PUT")
+        .assert_header("Access-Control-Allow-Headers", "content-type")
+        .assert_header("Access-Control-Max-Age", "600");
+    preflight("/items", "https://other.example", "PUT").send().unwrap().assert_code(403);
+    preflight("/items", "https://app.example", "PUT").header("Access-Control-Request-Headers", "X-Token").send().unwrap().assert_code(403);
+    preflight("/items", "https://app.example", "PUT").header("Access-Control-Request-Private-Network", "true").send().unwrap().assert_code(403);
+    // no route for the method, preflight isn't answered by policy
+    preflight("/items", "https://app.example", "DELETE").send().unwrap().assert_code(405);
+
+    // policy of route
+    let response = preflight("/device", "https://public.example", "POST").header("Access-Control-Request-Private-Network", "true").send().unwrap();
+    response.assert_code(204)
+        .assert_header("Access-Control-Allow-Origin", "https://public.example")
+        .assert_header("Access-Control-Allow-Credentials", "true")
+        .assert_header("Access-Control-Allow-Private-Network", "true");
+    assert_eq!(response.header("Access-Control-Max-Age"), None);
+    // credentials without allowed origins don't allow any origin
+    preflight("/session", "https://public.example", "POST").send().unwrap().assert_code(403);
+
+    // actual requests
+    client.get("/items").header("Origin", "https://app.example").send().unwrap()
+        .assert_text("items")
+        .assert_header("Access-Control-Allow-Origin", "https://app.example");
+    assert_eq!(client.get("/items").header("Origin", "https://other.example").send().unwrap().header("Access-Control-Allow-Origin"), None);
+    assert_eq!(client.get("/items").send().unwrap().header("Access-Control-Allow-Origin"), None);
+    client.post("/device").header("Origin", "https://public.example").send().unwrap()
+        .assert_text("device")
+        .assert_header("Access-Control-Allow-Credentials", "true")
+        .assert_header("Access-Control-Expose-Headers", "X-Id");
+    let response = client.post("/session").header("Origin", "https://evil.example").send().unwrap();
+    response.assert_text("session");
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+    assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+}
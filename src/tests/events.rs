@@ -0,0 +1,500 @@
+use crate::server::{Event, Server, TimeoutKind};
+use crate::throttle::AcceptRateLimit;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Starts server in other thread, returns its address and receiver of descriptions of operational events.
+fn start_server(prepare: impl FnOnce(&mut Server)) -> (SocketAddr, crate::server::Stopper, Receiver<String>) {
+    let mut server = Server::new(&([127, 0, 0, 1], 0).into()).unwrap();
+    server.num_threads = 1;
+    prepare(&mut server);
+    let addr = server.local_addr().unwrap();
+    let stopper = server.stopper();
+
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let _ = server.run(move |event| {
+            let description = match event {
+                Event::Incoming(tcp_session) => {
+                    tcp_session.to_http(|request| {
+                        let request = request?;
+                        let text = if request.path() == "/big" { "x".repeat(8_000_000) } else { "ok".to_string() };
+                        request.response(200).text(&text).send();
+                        Ok(())
+                    });
+                    return;
+                }
+                Event::TlsHandshakeFailed { .. } => "tls handshake failed".to_string(),
+                Event::Timeout { kind, .. } => format!("timeout {:?}", kind),
+                Event::RateLimited { addr } => format!("rate limited {}", addr.ip()),
+                Event::QuotaExceeded { kind, .. } => format!("quota exceeded {:?}", kind),
+                Event::SlowConsumer { queued_bytes, .. } => format!("slow consumer {}", queued_bytes > 1_000_000),
+                Event::ProtocolMismatch { kind, .. } => format!("protocol mismatch {:?}", kind),
+                #[cfg(unix)]
+                Event::Handoff(phase) => format!("handoff {:?}", phase),
+                _ => return,
+            };
+            let _ = sender.send(description);
+        });
+    });
+
+    (addr, stopper, receiver)
+}
+
+fn read_all(mut stream: TcpStream) -> Vec<u8> {
+    stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    response
+}
+
+#[test]
+fn timeouts() {
+    let (addr, stopper, events) = start_server(|server| {
+        server.settings.web_settings.request_head_timeout = Some(Duration::from_millis(200));
+        server.settings.web_settings.idle_timeout = Some(Duration::from_millis(300));
+    });
+
+    // request head is not finished
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    assert!(read_all(stream).starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), format!("timeout {:?}", TimeoutKind::RequestHead));
+
+    // keep-alive connection after response
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let response = read_all(stream);
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(b"ok"));
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), format!("timeout {:?}", TimeoutKind::Idle));
+
+    stopper.stop();
+    let _ = TcpStream::connect(addr);
+}
+
+#[test]
+fn rate_limited() {
+    let (addr, stopper, events) = start_server(|server| {
+        server.settings.accept_rate_limit = Some(Arc::new(AcceptRateLimit::new(1, Duration::from_secs(60))));
+    });
+
+    let allowed = TcpStream::connect(addr).unwrap();
+    assert!(read_all(TcpStream::connect(addr).unwrap()).is_empty());
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "rate limited 127.0.0.1");
+    drop(allowed);
+
+    stopper.stop();
+    let _ = TcpStream::connect(addr);
+}
+
+#[test]
+fn tls_handshake_failed() {
+    use crate::tls::{load_certs, load_private_key};
+    use rustls::{NoClientAuth, ServerConfig};
+
+    let (addr, stopper, events) = start_server(|server| {
+        let mut tls_config = ServerConfig::new(NoClientAuth::new());
+        let certs = load_certs("examples/keys/cert.pem").unwrap();
+        let private_key = load_private_key("examples/keys/key.pem").unwrap();
+        tls_config.set_single_cert_with_ocsp_and_sct(certs, private_key, vec![], vec![]).unwrap();
+        server.settings.tls_config = Some(Arc::new(tls_config));
+    });
+
+    // broken handshake record
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(&[0x16, 0x03, 0x01, 0x00, 0x04, 0x01, 0x00, 0x00, 0x00]).unwrap();
+    read_all(stream);
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "tls handshake failed");
+
+    // plain http to tls port is closed without handshake
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert!(read_all(stream).is_empty());
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "protocol mismatch PlaintextOnTls");
+
+    stopper.stop();
+    let _ = TcpStream::connect(addr);
+}
+
+#[test]
+fn tls_on_plaintext() {
+    use crate::web_session::TlsOnPlaintext;
+
+    // start of ClientHello
+    let client_hello = [0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01, 0xfc, 0x03, 0x03];
+    let tls_request = |addr| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&client_hello).unwrap();
+        String::from_utf8_lossy(&read_all(stream)).to_string()
+    };
+
+    let (addr, stopper, events) = start_server(|_| {});
+    let response = tls_request(addr);
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+    assert!(response.ends_with("use \"http://\" or HTTPS port"), "{}", response);
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "protocol mismatch TlsOnPlaintext");
+    stopper.stop();
+
+    let (addr, stopper, events) = start_server(|server| {
+        server.settings.web_settings.tls_on_plaintext = TlsOnPlaintext::Redirect("https://localhost:8443/".to_string());
+    });
+    let response = tls_request(addr);
+    assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"), "{}", response);
+    assert!(response.contains("\r\nLocation: https://localhost:8443/\r\n"), "{}", response);
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "protocol mismatch TlsOnPlaintext");
+    stopper.stop();
+
+    // header can't be injected by URL of redirect
+    let (addr, stopper, events) = start_server(|server| {
+        server.settings.web_settings.tls_on_plaintext = TlsOnPlaintext::Redirect("https://localhost/\r\nSet-Cookie: a=1".to_string());
+    });
+    let response = tls_request(addr);
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+    assert!(!response.contains("Set-Cookie"), "{}", response);
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "protocol mismatch TlsOnPlaintext");
+    stopper.stop();
+
+    let (addr, stopper, events) = start_server(|server| server.settings.web_settings.tls_on_plaintext = TlsOnPlaintext::Close);
+    assert!(tls_request(addr).is_empty());
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "protocol mismatch TlsOnPlaintext");
+    stopper.stop();
+}
+
+#[cfg(unix)]
+#[test]
+fn tls_handshake_pool() {
+    use crate::tls::{load_certs, load_private_key};
+    use crate::tls_handshake::TlsHandshakePool;
+    use rustls::{NoClientAuth, ServerConfig};
+
+    struct AnyCertificate;
+
+    impl rustls::ServerCertVerifier for AnyCertificate {
+        fn verify_server_cert(&self, _roots: &rustls::RootCertStore, _presented_certs: &[rustls::Certificate], _dns_name: webpki::DNSNameRef, _ocsp_response: &[u8]) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+            Ok(rustls::ServerCertVerified::assertion())
+        }
+    }
+
+    let (addr, stopper, events) = start_server(|server| {
+        let mut tls_config = ServerConfig::new(NoClientAuth::new());
+        let certs = load_certs("examples/keys/cert.pem").unwrap();
+        let private_key = load_private_key("examples/keys/key.pem").unwrap();
+        tls_config.set_single_cert_with_ocsp_and_sct(certs, private_key, vec![], vec![]).unwrap();
+        server.settings.tls_config = Some(Arc::new(tls_config));
+        server.settings.tls_handshake_pool = Some(TlsHandshakePool::new(2, Duration::from_millis(300)).unwrap());
+    });
+
+    // plain http to tls port
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    read_all(stream);
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "tls handshake failed");
+
+    // handshake is not finished in time
+    let stream = TcpStream::connect(addr).unwrap();
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "tls handshake failed");
+    drop(stream);
+
+    let mut client_config = rustls::ClientConfig::new();
+    client_config.dangerous().set_certificate_verifier(Arc::new(AnyCertificate));
+    for _ in 0..3 {
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let session = rustls::ClientSession::new(&Arc::new(client_config.clone()), dns_name);
+        let mut stream = rustls::StreamOwned::new(session, TcpStream::connect(addr).unwrap());
+        stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("ok"), "{}", response);
+    }
+    assert!(events.try_recv().is_err());
+
+    stopper.stop();
+    let _ = TcpStream::connect(addr);
+}
+
+#[cfg(unix)]
+#[test]
+fn tls_handshake_pool_connection_limit() {
+    use crate::server::{ConnectionLimit, ConnectionLimitAction};
+    use crate::tls::{load_certs, load_private_key};
+    use crate::tls_handshake::TlsHandshakePool;
+    use rustls::{NoClientAuth, ServerConfig};
+    use std::time::Instant;
+
+    let (addr, stopper, _events) = start_server(|server| {
+        let mut tls_config = ServerConfig::new(NoClientAuth::new());
+        let certs = load_certs("examples/keys/cert.pem").unwrap();
+        let private_key = load_private_key("examples/keys/key.pem").unwrap();
+        tls_config.set_single_cert_with_ocsp_and_sct(certs, private_key, vec![], vec![]).unwrap();
+        server.settings.tls_config = Some(Arc::new(tls_config));
+        server.settings.tls_handshake_pool = Some(TlsHandshakePool::new(1, Duration::from_secs(10)).unwrap());
+        server.settings.connection_limit = Some(ConnectionLimit { max_connections: 2, action: ConnectionLimitAction::Close });
+    });
+
+    // connections in handshake occupy places of the limit
+    let slow_handshakes = [TcpStream::connect(addr).unwrap(), TcpStream::connect(addr).unwrap()];
+    std::thread::sleep(Duration::from_millis(100));
+    let begin = Instant::now();
+    assert!(read_all(TcpStream::connect(addr).unwrap()).is_empty());
+    assert!(begin.elapsed() < Duration::from_secs(1));
+
+    drop(slow_handshakes);
+    stopper.stop();
+    let _ = TcpStream::connect(addr);
+}
+
+#[test]
+fn drain() {
+    use crate::testing::TestServer;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    let flushed = Arc::new(Mutex::new(None));
+    let flushed_in_handler = flushed.clone();
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        let flushed = flushed_in_handler.clone();
+        // response is sent later from other thread
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            let tcp_session = request.tcp_session().clone();
+            request.response(200).text("done").send();
+            tcp_session.flush_pending(move |res| *flushed.lock().unwrap() = Some(res.is_ok()));
+        });
+        Ok(())
+    }).unwrap();
+
+    let client = server.client();
+    let response = std::thread::spawn(move || client.get("/").send());
+    std::thread::sleep(Duration::from_millis(50));
+
+    let started = Instant::now();
+    assert!(server.stopper().drain(Duration::from_secs(3)));
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    response.join().unwrap().unwrap().assert_code(200).assert_text("done");
+    assert_eq!(*flushed.lock().unwrap(), Some(true));
+
+    // new connections are not accepted while draining
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    stream.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    assert!(stream.read(&mut [0; 16]).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn handoff() {
+    use std::process::{Command, Stdio};
+
+    let (addr, stopper, events) = start_server(|_| {});
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    assert!(String::from_utf8_lossy(&read_all(stream)).ends_with("ok"));
+
+    // new process gets open listener and its number
+    let mut command = Command::new("sh");
+    command.arg("-c").arg("echo $ANWEB_LISTEN_FD $ANWEB_LISTEN_PARENT_PID && : <&$ANWEB_LISTEN_FD").stdout(Stdio::piped());
+    let child = stopper.handoff(&mut command, Duration::from_secs(3)).unwrap();
+    let pid = child.id();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let output = String::from_utf8_lossy(&output.stdout).to_string();
+    let mut vars = output.split_whitespace();
+    assert!(vars.next().unwrap().parse::<i32>().is_ok());
+    assert_eq!(vars.next().unwrap(), std::process::id().to_string());
+
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), format!("handoff Spawned({})", pid));
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "handoff Draining");
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "handoff Drained(true)");
+
+    stopper.stop();
+}
+
+#[test]
+fn quotas() {
+    use crate::testing::TestServer;
+
+    let server = TestServer::start(|request| {
+        let request = request?;
+        let text = format!("{} {}", request.tcp_session().bytes_read(), request.tcp_session().bytes_written());
+        request.response(200).text(&text).send();
+        Ok(())
+    }).unwrap();
+    let raw_request = b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n";
+    let response = server.client().send_raw(raw_request).unwrap();
+    response.assert_code(200).assert_text(&format!("{} 0", raw_request.len()));
+
+    let (addr, stopper, events) = start_server(|server| server.settings.web_settings.read_quota = Some(64));
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut first_response = Vec::new();
+    while !first_response.ends_with(b"ok") {
+        let mut buf = [0; 256];
+        let cnt = stream.read(&mut buf).unwrap();
+        assert!(cnt > 0);
+        first_response.extend_from_slice(&buf[..cnt]);
+    }
+    stream.write_all(format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "x".repeat(64)).as_bytes()).unwrap();
+    assert!(read_all(stream).is_empty());
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "quota exceeded Read");
+    stopper.stop();
+
+    // request over the quota isn't passed to the handler
+    let handled = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let server_handled = handled.clone();
+    let server = TestServer::start_with(|server| server.settings.web_settings.read_quota = Some(64), move |request| {
+        server_handled.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        request?.response(200).send();
+        Ok(())
+    }).unwrap();
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "x".repeat(64)).as_bytes()).unwrap();
+    assert!(read_all(stream).is_empty());
+    assert_eq!(handled.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+    let (addr, stopper, events) = start_server(|server| server.settings.web_settings.write_quota = Some(16));
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    read_all(stream);
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "quota exceeded Write");
+    stopper.stop();
+}
+
+#[test]
+fn slow_consumer() {
+    use crate::web_session::SlowConsumer;
+
+    let (addr, stopper, events) = start_server(|server| {
+        server.settings.web_settings.slow_consumer = Some(SlowConsumer { queued_bytes: 1_000_000, duration: Duration::from_millis(200) });
+    });
+
+    // the client doesn't read the response
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /big HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(events.recv_timeout(Duration::from_secs(3)).unwrap(), "slow consumer true");
+    // emitted once while the queue stays above the threshold
+    assert!(events.recv_timeout(Duration::from_millis(500)).is_err());
+
+    let mut response = Vec::new();
+    let mut buf = vec![0; 65536];
+    while !response.ends_with(b"x") || response.len() < 8_000_000 {
+        let cnt = stream.read(&mut buf).unwrap();
+        assert!(cnt > 0);
+        response.extend_from_slice(&buf[..cnt]);
+    }
+    assert!(events.try_recv().is_err());
+
+    stopper.stop();
+    let _ = TcpStream::connect(addr);
+}
+
+#[test]
+fn rapid_connect_close() {
+    use std::sync::Mutex;
+
+    let mut server = Server::new(&([127, 0, 0, 1], 0).into()).unwrap();
+    server.num_threads = 1;
+    let addr = server.local_addr().unwrap();
+    let stopper = server.stopper();
+
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        // clones of sessions keep sockets open after closing, their events must not reach new sessions with same token
+        let kept_sessions = Arc::new(Mutex::new(Vec::new()));
+        let _ = server.run(move |event| match event {
+            Event::Incoming(tcp_session) => {
+                kept_sessions.lock().unwrap().push(tcp_session.clone());
+                tcp_session.to_http(|request| {
+                    request?.response(200).close().text("ok").send();
+                    Ok(())
+                });
+            }
+            Event::Closed(session_id) => {
+                let _ = sender.send(session_id);
+            }
+            _ => {}
+        });
+    });
+
+    const CYCLES: usize = 200;
+    for i in 0..CYCLES {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        if i % 2 == 0 {
+            // closed without request
+            drop(stream);
+            continue;
+        }
+
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_all(stream);
+        assert!(response.ends_with(b"ok"), "{}", String::from_utf8_lossy(&response));
+    }
+
+    let mut closed = Vec::new();
+    while closed.len() < CYCLES {
+        closed.push(receiver.recv_timeout(Duration::from_secs(3)).unwrap());
+    }
+    closed.sort();
+    closed.dedup();
+    assert_eq!(closed.len(), CYCLES);
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+    stopper.stop();
+}
+
+#[test]
+fn shutdown_write() {
+    use std::time::Instant;
+
+    let mut server = Server::new(&([127, 0, 0, 1], 0).into()).unwrap();
+    server.num_threads = 1;
+    let addr = server.local_addr().unwrap();
+    let stopper = server.stopper();
+
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let _ = server.run(move |event| match event {
+            Event::Incoming(tcp_session) => {
+                tcp_session.to_http(|request| {
+                    let request = request?;
+                    request.tcp_session().shutdown_write(Duration::from_millis(300));
+                    request.response(200).close().text("ok").send();
+                    Ok(())
+                });
+            }
+            Event::Closed(_) => {
+                let _ = sender.send(Instant::now());
+            }
+            _ => {}
+        });
+    });
+
+    // the client gets end of stream after the response and still can send, the connection is closed when the client closes it
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert!(response.ends_with(b"ok"), "{}", String::from_utf8_lossy(&response));
+    stream.write_all(b"data sent after the response").unwrap();
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+    let closed_by_client = Instant::now();
+    drop(stream);
+    assert!(receiver.recv_timeout(Duration::from_secs(1)).unwrap() - closed_by_client < Duration::from_millis(250));
+
+    // the client doesn't close, the connection is closed after the grace period
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let sent = Instant::now();
+    let response = read_all(stream.try_clone().unwrap());
+    assert!(response.ends_with(b"ok"));
+    assert!(receiver.recv_timeout(Duration::from_secs(2)).unwrap() - sent >= Duration::from_millis(300));
+
+    stopper.stop();
+}
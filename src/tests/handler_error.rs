@@ -0,0 +1,51 @@
+use crate::handler_error::HandlerError;
+use crate::tests::request::test_request;
+
+#[test]
+fn handler_error_response() {
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            request.read_content(|_, _| {
+                Err(HandlerError::bad_request("Wrong id").into())
+            });
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+            assert!(response.contains("\r\nConnection: close\r\n"), "{}", response);
+            assert!(response.ends_with("\r\n\r\nWrong id"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn handler_error_response_http_1_0() {
+    test_request(
+        b"GET / HTTP/1.0\r\n\r\n",
+        |request| {
+            request.read_content(|_, _| {
+                Err(HandlerError::bad_request("Wrong id").into())
+            });
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.0 400 Bad Request\r\n"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn other_error_closes_connection() {
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            request.read_content(|_, _| {
+                Err("some error".into())
+            });
+        },
+        |response| {
+            assert!(response.is_empty());
+        }
+    );
+}
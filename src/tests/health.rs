@@ -0,0 +1,31 @@
+use crate::health::HealthCheck;
+use crate::tests::request::test_request_with_server;
+
+fn test_health_request(health_check: &HealthCheck, raw_request: &'static [u8], expected_start: &'static str, expected_end: &'static str) {
+    let health_check = health_check.clone();
+    test_request_with_server(
+        |server| server.settings.web_settings.health_check = Some(health_check),
+        raw_request,
+        |request| {
+            assert_eq!(request.path(), "/other");
+            request.response(200).text("other").send();
+        },
+        move |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with(expected_start), "{}", response);
+            assert!(response.ends_with(expected_end), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn health_check() {
+    let health_check = HealthCheck::new();
+    test_health_request(&health_check, b"GET /healthz HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 200 OK", "\r\n\r\nok");
+    test_health_request(&health_check, b"GET /readyz HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 200 OK", "\r\n\r\nready");
+    test_health_request(&health_check, b"GET /other HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 200 OK", "\r\n\r\nother");
+
+    health_check.set_ready(false);
+    test_health_request(&health_check, b"GET /readyz HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 503 Service Unavailable", "\r\n\r\nnot ready");
+    test_health_request(&health_check, b"GET /healthz HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 200 OK", "\r\n\r\nok");
+}
@@ -0,0 +1,45 @@
+use crate::handler_error::HandlerError;
+use crate::inspection::Inspector;
+use crate::testing::TestServer;
+
+#[test]
+fn inspector() {
+    let server = TestServer::start_with(
+        |server| {
+            server.settings.web_settings.inspector = Some(Inspector::new(|request, content| {
+                if request.header_value("User-Agent") == Some("badbot") {
+                    return Err(HandlerError::new(403, "Forbidden"));
+                }
+                if content.is_some_and(|content| content.windows(10).any(|window| window == b"DROP TABLE")) {
+                    return Err(HandlerError::bad_request("Rejected"));
+                }
+                if request.path() == "/large" && content.is_some() {
+                    return Err(HandlerError::bad_request("Unexpected content"));
+                }
+                Ok(())
+            }).body_limit(100));
+        },
+        |request| {
+            let request = request?;
+            let mut received = Vec::new();
+            request.read_content(move |data, request| {
+                received.extend_from_slice(data);
+                if let Some(request) = request {
+                    request.response(200).text(&String::from_utf8_lossy(&received)).send();
+                }
+                Ok(())
+            });
+            Ok(())
+        },
+    ).unwrap();
+    let client = server.client();
+
+    client.get("/").send().unwrap().assert_code(200).assert_text("");
+    client.get("/").header("User-Agent", "badbot").send().unwrap().assert_code(403).assert_text("Forbidden");
+    client.post("/").body("name=1").send().unwrap().assert_code(200).assert_text("name=1");
+    client.post("/").body("name=1; DROP TABLE users").send().unwrap().assert_code(400).assert_text("Rejected");
+
+    // content longer than the limit is not buffered
+    let large = "x".repeat(1000);
+    client.post("/large").body(large.clone()).send().unwrap().assert_code(200).assert_text(&large);
+}
@@ -0,0 +1,22 @@
+use crate::log::{log, set_logger, Level};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn logger() {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let messages_in_logger = messages.clone();
+    set_logger(Some(Arc::new(move |level, message: &str| {
+        // other tests may log in parallel
+        if message.starts_with("test message") {
+            if let Ok(mut messages) = messages_in_logger.lock() {
+                messages.push((level, message.to_string()));
+            }
+        }
+    })));
+
+    log(Level::Warn, format_args!("test message {}", 1));
+    set_logger(None);
+    log(Level::Error, format_args!("test message {}", 2));
+
+    assert_eq!(*messages.lock().unwrap(), vec![(Level::Warn, "test message 1".to_string())]);
+}
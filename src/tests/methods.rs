@@ -0,0 +1,63 @@
+use crate::tests::request::{test_request, test_request_with_server};
+
+#[test]
+fn connect_and_trace_rejected() {
+    for raw_request in [&b"CONNECT example.com:443 HTTP/1.1\r\nConnection: close\r\n\r\n"[..], b"TRACE / HTTP/1.1\r\nConnection: close\r\n\r\n"].iter() {
+        test_request(
+            raw_request,
+            |_| panic!("must not be passed to handler"),
+            |response| {
+                let response = std::str::from_utf8(response).unwrap_or("");
+                assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed"), "{}", response);
+                assert!(response.contains("\r\nAllow: GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS\r\n"), "{}", response);
+            }
+        );
+    }
+}
+
+#[test]
+fn trace_rejected_allows_connect() {
+    test_request_with_server(
+        |server| server.settings.web_settings.reject_connect = false,
+        b"TRACE / HTTP/1.1\r\nConnection: close\r\n\r\n",
+        |_| panic!("must not be passed to handler"),
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed"), "{}", response);
+            assert!(response.contains("\r\nAllow: GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS, CONNECT\r\n"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn trace_echo() {
+    test_request_with_server(
+        |server| server.settings.web_settings.trace_echo = true,
+        b"TRACE /path?a=1 HTTP/1.1\r\nConnection: close\r\nCookie: secret=1\r\nX-Some: value\r\n\r\n",
+        |_| panic!("must not be passed to handler"),
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+            assert!(response.contains("Content-Type: message/http\r\n"), "{}", response);
+            assert!(response.ends_with("\r\n\r\nTRACE /path?a=1 HTTP/1.1\r\nConnection: close\r\nX-Some: value\r\n\r\n"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn connect_passed_to_handler() {
+    test_request_with_server(
+        |server| server.settings.web_settings.reject_connect = false,
+        b"CONNECT example.com:443 HTTP/1.1\r\nConnection: close\r\n\r\n",
+        |request| {
+            assert_eq!(request.method(), "CONNECT");
+            request.response(200).text("tunnel").send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+            assert!(!response.contains("message/http"), "{}", response);
+            assert!(response.ends_with("\r\n\r\ntunnel"), "{}", response);
+        }
+    );
+}
@@ -0,0 +1,92 @@
+use crate::micro_cache::{CachedResponse, MicroCache};
+use crate::testing::TestServer;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn micro_cache() {
+    let cache = MicroCache::new(Duration::from_millis(300)).vary("Accept-Language");
+    let renders = Arc::new(AtomicUsize::new(0));
+    let cache_in_server = cache.clone();
+    let renders_in_server = renders.clone();
+    let server = TestServer::start(move |request| {
+        let renders = renders_in_server.clone();
+        cache_in_server.serve(request?, move |request| {
+            let cnt = renders.fetch_add(1, Ordering::SeqCst) + 1;
+            let code = if request.path() == "/missing" { 404 } else { 200 };
+            Ok(CachedResponse::new(code, "text/plain", format!("render {}", cnt)).header("X-Test", "1"))
+        })
+    }).unwrap();
+    let client = server.client();
+
+    let response = client.get("/page").send().unwrap();
+    response.assert_code(200).assert_text("render 1").assert_header("X-Test", "1");
+    assert!(response.header("Age").is_none());
+
+    // hit
+    client.get("/page").send().unwrap().assert_text("render 1").assert_header("Age", "0");
+    // other query and vary header are other keys
+    client.get("/page?a=1").send().unwrap().assert_text("render 2");
+    client.get("/page").header("Accept-Language", "de").send().unwrap().assert_text("render 3");
+    // bypass, fresh result is stored
+    client.get("/page").header("Cache-Control", "no-cache").send().unwrap().assert_text("render 4");
+    client.get("/page").send().unwrap().assert_text("render 4");
+    // not cached
+    client.post("/page").send().unwrap().assert_text("render 5");
+    client.get("/missing").send().unwrap().assert_code(404);
+    client.get("/missing").send().unwrap().assert_text("render 7");
+    assert_eq!(cache.len(), 3);
+
+    // expired
+    std::thread::sleep(Duration::from_millis(400));
+    client.get("/page").send().unwrap().assert_text("render 8");
+
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn micro_cache_limits() {
+    let cache = MicroCache::new(Duration::from_secs(60)).max_entries(2);
+    let server = TestServer::start({
+        let cache = cache.clone();
+        move |request| cache.serve(request?, |request| Ok(CachedResponse::text(request.path().to_string())))
+    }).unwrap();
+    let client = server.client();
+
+    for path in &["/a", "/b", "/c"] {
+        client.get(path).send().unwrap().assert_text(path);
+    }
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn micro_cache_etag() {
+    let renders = Arc::new(AtomicUsize::new(0));
+    let cache = MicroCache::new(Duration::from_millis(200)).etag();
+    let server = TestServer::start({
+        let renders = renders.clone();
+        move |request| {
+            let renders = renders.clone();
+            cache.serve(request?, move |_| {
+                renders.fetch_add(1, Ordering::SeqCst);
+                Ok(CachedResponse::text("same content"))
+            })
+        }
+    }).unwrap();
+    let client = server.client();
+
+    let response = client.get("/").send().unwrap();
+    response.assert_code(200);
+    let etag = response.header("ETag").unwrap().to_string();
+    assert!(etag.starts_with("W/\""));
+
+    client.get("/").header("If-None-Match", &etag).send().unwrap().assert_code(304).assert_header("ETag", &etag);
+    client.get("/").header("If-None-Match", "\"other\", W/\"x\"").send().unwrap().assert_code(200);
+
+    // the page is rendered again after expiration, but the content is the same
+    std::thread::sleep(Duration::from_millis(300));
+    client.get("/").header("If-None-Match", &etag).send().unwrap().assert_code(304).assert_text("");
+    assert_eq!(renders.load(Ordering::SeqCst), 2);
+}
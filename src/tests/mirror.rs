@@ -0,0 +1,141 @@
+use crate::mirror::{Mirror, MirrorEvent, WRITER_QUEUE_LIMIT, WRITER_REQUEST_LEN_LIMIT};
+use crate::testing::TestServer;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Echo of content of request.
+fn echo_server(mirror: Mirror) -> TestServer {
+    TestServer::start_with(
+        move |server| server.settings.web_settings.mirror = Some(mirror.clone()),
+        |request| {
+            let request = request?;
+            let mut received = Vec::new();
+            request.read_content(move |data, request| {
+                received.extend_from_slice(data);
+                if let Some(request) = request {
+                    request.response(200).text(&String::from_utf8_lossy(&received)).send();
+                }
+                Ok(())
+            });
+            Ok(())
+        },
+    ).unwrap()
+}
+
+fn wait_for(condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timeout");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn events() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink_events = events.clone();
+    let mirror = Mirror::new(|request| request.path() != "/private", move |_, event| {
+        let event = match event {
+            MirrorEvent::Head(head) => format!("head {}", String::from_utf8_lossy(head)),
+            MirrorEvent::Content(content) => format!("content {}", String::from_utf8_lossy(content)),
+            event => format!("{:?}", event),
+        };
+        sink_events.lock().unwrap().push(event);
+    });
+    let server = echo_server(mirror);
+    let client = server.client();
+
+    client.get("/private").send().unwrap().assert_code(200);
+    let form = client.post("/form").body("a=1");
+    form.send().unwrap().assert_code(200).assert_text("a=1");
+    client.send_raw(b"POST /chunked HTTP/1.1\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n0\r\n\r\n").unwrap().assert_text("ab");
+    assert_eq!(*events.lock().unwrap(), [
+        format!("head {}", String::from_utf8_lossy(&form.raw()).trim_end_matches("a=1")),
+        "content a=1".to_string(),
+        "End".to_string(),
+        "head POST /chunked HTTP/1.1\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n".to_string(),
+        "content 2\r\nab\r\n0\r\n\r\n".to_string(),
+        "End".to_string(),
+    ]);
+
+    // content isn't received completely
+    events.lock().unwrap().clear();
+    let mut stream = std::net::TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc").unwrap();
+    wait_for(|| events.lock().unwrap().len() == 2);
+    drop(stream);
+    wait_for(|| events.lock().unwrap().len() == 3);
+    assert_eq!(events.lock().unwrap()[2], "Aborted");
+}
+
+/// Writer to shared buffer.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn to_writer() {
+    let buffer = Buffer::default();
+    let server = echo_server(Mirror::to_writer(|request| request.method() == "POST", buffer.clone()));
+    let client = server.client();
+
+    client.get("/").send().unwrap().assert_code(200);
+    let post = client.post("/upload").body("content");
+    post.send().unwrap().assert_text("content");
+
+    wait_for(|| !buffer.0.lock().unwrap().is_empty());
+    assert_eq!(*buffer.0.lock().unwrap(), post.raw());
+}
+
+/// Writer that waits while the gate is locked.
+struct Stalled {
+    gate: Arc<Mutex<()>>,
+    buffer: Buffer,
+}
+
+impl Write for Stalled {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let _gate = self.gate.lock();
+        self.buffer.write(data)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn to_writer_limits() {
+    let buffer = Buffer::default();
+    let gate = Arc::new(Mutex::new(()));
+    let closed_gate = gate.lock().unwrap();
+    let mirror = Mirror::to_writer(|_| true, Stalled { gate: gate.clone(), buffer: buffer.clone() });
+    let server = echo_server(mirror.clone());
+    let client = server.client();
+
+    // requests over queue of stalled writer are dropped
+    let requests = WRITER_QUEUE_LIMIT + 20;
+    for _ in 0..requests {
+        client.get("/").send().unwrap().assert_code(200);
+    }
+    // one request may be taken from the queue by the writer
+    let dropped = mirror.dropped();
+    assert!((19..=20).contains(&dropped), "{}", dropped);
+
+    // too long request is dropped without buffering
+    drop(closed_gate);
+    wait_for(|| buffer.0.lock().unwrap().len() == (requests - dropped as usize) * client.get("/").raw().len());
+    client.post("/").body(vec![b'x'; WRITER_REQUEST_LEN_LIMIT]).send().unwrap().assert_code(200);
+    assert_eq!(mirror.dropped(), dropped + 1);
+}
@@ -4,7 +4,38 @@ mod request;
 mod query;
 mod cookie;
 mod websocket;
+mod websocket_client;
 mod response;
 mod post_form;
 mod read_content;
 mod multipart;
+mod router;
+mod concurrency;
+mod health;
+mod stats;
+mod throttle;
+mod security_headers;
+mod handler_error;
+mod methods;
+mod testing;
+mod raw_mode;
+mod redirect_server;
+mod micro_cache;
+mod static_files;
+mod inspection;
+mod events;
+mod log;
+mod worker;
+#[cfg(unix)]
+mod systemd;
+mod webdav;
+mod acme;
+#[cfg(feature = "urlencoded")]
+mod urlencoded;
+mod auth;
+mod blocking_pool;
+#[cfg(feature = "async")]
+mod async_handler;
+mod parsers;
+mod mirror;
+mod cors;
@@ -45,7 +45,6 @@ fn localhost() {
     let origin_file_data = Arc::new(origin_file_data);
 
     test_request(
-        9097,
         &request,
         move |request| {
             assert_eq!(request.method(), "POST");
@@ -86,14 +85,14 @@ fn localhost() {
                                     current_part = CurrentPart::File(Vec::new());
                                 },
                                 CurrentPart::File(_data) => {
-                                    assert!(false);
+                                    panic!();
                                 },
                             }
                         },
                         MultipartParserEvent::Data { data_part, end } => {
                             match &mut current_part {
                                 CurrentPart::None => {
-                                    assert!(false);
+                                    panic!();
                                 },
                                 CurrentPart::Field1(data) => {
                                     data.extend_from_slice(data_part);
@@ -121,7 +120,7 @@ fn localhost() {
                                 CurrentPart::File(data) => {
                                     assert_eq!(data, &*origin_file_data);
                                 },
-                                _ => assert!(false),
+                                _ => panic!(),
                             }
 
                             fifnished = true;
@@ -153,3 +152,42 @@ fn localhost() {
         }
     );
 }
+
+#[test]
+fn builder() {
+    use crate::multipart::MultipartBuilder;
+
+    let multipart = MultipartBuilder::with_boundary("byteranges", "xyz")
+        .byte_range("text/plain", 0, 1, 10, b"ab")
+        .byte_range("text/plain", 8, 9, 10, b"ij");
+    assert_eq!(multipart.content_type(), "Content-Type: multipart/byteranges; boundary=xyz\r\n");
+    assert_eq!(
+        String::from_utf8(multipart.build()).unwrap(),
+        "--xyz\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\nab\r\n\
+        --xyz\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\nij\r\n\
+        --xyz--\r\n"
+    );
+
+    assert_ne!(MultipartBuilder::new("mixed").boundary(), MultipartBuilder::new("mixed").boundary());
+
+    test_request(
+        b"GET /stream HTTP/1.1\r\nConnection: close\r\n\r\n",
+        |request| {
+            let stream = MultipartBuilder::with_boundary("x-mixed-replace", "frame").start(request, 200);
+            stream.send_part("Content-Type: image/jpeg\r\n", b"1");
+            stream.send_part("Content-Type: image/jpeg\r\n", b"2");
+            stream.end();
+        },
+        |response| {
+            let response = String::from_utf8_lossy(response);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.contains("Content-Type: multipart/x-mixed-replace; boundary=frame\r\n"));
+            assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+            assert!(response.ends_with("\
+                28\r\n--frame\r\nContent-Type: image/jpeg\r\n\r\n1\r\n\r\n\
+                28\r\n--frame\r\nContent-Type: image/jpeg\r\n\r\n2\r\n\r\n\
+                B\r\n--frame--\r\n\r\n\
+                0\r\n\r\n"));
+        },
+    );
+}
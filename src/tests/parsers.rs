@@ -0,0 +1,74 @@
+use crate::fuzz;
+use crate::websocket::{frame, masked_frame};
+use proptest::prelude::*;
+
+/// Request heads with valid and broken parts.
+fn request_head() -> impl Strategy<Value = Vec<u8>> {
+    let method = prop::sample::select(vec!["GET", "POST", "PUT", "G3T", ""]);
+    let path = "/[a-z0-9%?=&/]{0,16}";
+    let version = prop::sample::select(vec!["HTTP/1.1", "HTTP/1.0", "HTTP/2", "HTTX"]);
+    let header = ("[A-Za-z-]{0,8}", "[ -~]{0,8}");
+    let content_len = prop::option::of(0..8usize);
+    (method, path, version, prop::collection::vec(header, 0..4), content_len).prop_map(|(method, path, version, headers, content_len)| {
+        let mut head = format!("{} {} {}\r\n", method, path, version);
+        for (name, value) in headers {
+            head += &format!("{}: {}\r\n", name, value);
+        }
+        if let Some(content_len) = content_len {
+            head += &format!("Content-Length: {}\r\n\r\n{}", content_len, "x".repeat(content_len));
+        } else {
+            head += "\r\n";
+        }
+        head.into_bytes()
+    })
+}
+
+/// Frames with payloads up to and over the limit of `fuzz::websocket_parser`.
+fn frames() -> impl Strategy<Value = Vec<u8>> {
+    let opcode = prop::sample::select(vec![0u8, 1, 2, 8, 9, 10, 3]);
+    let payload = prop_oneof![0..4usize, 120..130usize, 1020..1030usize, 65530..65540usize]
+        .prop_flat_map(|len| prop::collection::vec(any::<u8>(), len));
+    let frame = (opcode, payload, any::<bool>(), any::<[u8; 4]>())
+        .prop_map(|(opcode, payload, masked, key)| if masked { masked_frame(opcode, &payload, key) } else { frame(opcode, &payload) });
+    prop::collection::vec(frame, 1..4).prop_map(|frames| frames.concat())
+}
+
+/// Multipart content with boundary "xyz", parts contain fragments of the boundary.
+fn multipart_content() -> impl Strategy<Value = Vec<u8>> {
+    let data = prop::collection::vec(prop::sample::select(vec!["a", "-", "--", "--xy", "--xyz", "\r\n", "\r\n\r\n"]), 0..8);
+    let part = ("[a-z: ]{0,8}", data).prop_map(|(disposition, data)| format!("--xyz\r\n{}\r\n\r\n{}\r\n", disposition, data.concat()));
+    (prop::collection::vec(part, 0..4), "[a-z-]{0,4}", "[a-z\r\n-]{0,8}")
+        .prop_map(|(parts, preamble, epilogue)| format!("{}{}--xyz--{}", preamble, parts.concat(), epilogue).into_bytes())
+}
+
+proptest! {
+    #[test]
+    fn request_parser_bytes(data in prop::collection::vec(any::<u8>(), 0..256), chunk_len in 1..64usize) {
+        fuzz::request_parser(&data, chunk_len);
+    }
+
+    #[test]
+    fn request_parser_heads(heads in prop::collection::vec(request_head(), 1..4), chunk_len in 1..64usize) {
+        fuzz::request_parser(&heads.concat(), chunk_len);
+    }
+
+    #[test]
+    fn websocket_parser_bytes(data in prop::collection::vec(any::<u8>(), 0..256), chunk_len in 1..64usize) {
+        fuzz::websocket_parser(&data, chunk_len);
+    }
+
+    #[test]
+    fn websocket_parser_frames(data in frames(), chunk_len in 1..64usize) {
+        fuzz::websocket_parser(&data, chunk_len);
+    }
+
+    #[test]
+    fn multipart_parser_bytes(data in prop::collection::vec(any::<u8>(), 0..256), chunk_len in 1..64usize) {
+        fuzz::multipart_parser(&data, chunk_len);
+    }
+
+    #[test]
+    fn multipart_parser_parts(data in multipart_content(), chunk_len in 1..64usize) {
+        fuzz::multipart_parser(&data, chunk_len);
+    }
+}
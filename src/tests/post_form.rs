@@ -4,7 +4,6 @@ use crate::tests::request::test_request;
 #[test]
 fn localhost() {
     test_request(
-        9092,
         b"POST /form HTTP/1.1\r\n\
         Connection: close\r\n\
         Content-Type: application/x-www-form-urlencoded\r\n\
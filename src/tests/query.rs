@@ -1,6 +1,7 @@
 use crate::query::{parse_query, QueryNameValue};
 use crate::tests::request::test_request;
 use crate::request::HttpVersion;
+use crate::request_parser::{ParseHttpRequestSettings, Parser};
 
 impl PartialEq for QueryNameValue<'_, '_> {
     fn eq(&self, other: &Self) -> bool {
@@ -28,10 +29,24 @@ fn parse() {
     );
 }
 
+#[test]
+fn cached_params() {
+    let mut parser = Parser::new();
+    let (request, _) = parser.push(b"GET /?a=1+2&b=%E0%AC%B6&bad=%FF&a=3 HTTP/1.1\r\n\r\n", &ParseHttpRequestSettings::default()).unwrap();
+
+    let params = request.query_params();
+    assert_eq!(params, &[("a".to_string(), "1 2".to_string()), ("b".to_string(), "ଶ".to_string()), ("a".to_string(), "3".to_string())][..]);
+    assert!(std::ptr::eq(params, request.query_params()));
+
+    assert_eq!(request.query_value("a"), Some("1 2"));
+    assert_eq!(request.query_value("b"), Some("ଶ"));
+    assert_eq!(request.query_value("bad"), None);
+    assert_eq!(request.query_value("c"), None);
+}
+
 #[test]
 pub fn local_host() {
     test_request(
-        9091,
         b"GET /query?first=text1&second=utf-8+%E0%AC%B6%E1%A8%87%D8%86 HTTP/1.0\r\n\r\n",
         |request| {
             assert_eq!(request.method(), "GET");
@@ -62,3 +77,33 @@ pub fn local_host() {
         }
     );
 }
+
+#[test]
+fn structured() {
+    use crate::query::{KeyConvention, QueryValue};
+
+    let string = |value: &str| QueryValue::String(value.to_string());
+
+    let flat = parse_query(b"a=1&a=2&b=3&a=4&bad=%FF").structured(KeyConvention::Flat);
+    assert_eq!(flat.get("a").and_then(|a| a.as_array()), Some(&[string("1"), string("2"), string("4")][..]));
+    assert_eq!(flat.get("b").and_then(|b| b.as_str()), Some("3"));
+    assert!(!flat.contains_key("bad"));
+
+    let nested = parse_query(b"items[]=1&items%5B%5D=2&user[name]=x+y&user[address][city]=z&a=1&a=2&broken[=1&[x]=2&deep[a][]=3")
+        .structured(KeyConvention::Brackets);
+    assert_eq!(nested["items"], QueryValue::Array(vec![string("1"), string("2")]));
+    assert_eq!(nested["user"].get("name").and_then(|name| name.as_str()), Some("x y"));
+    assert_eq!(nested["user"].get("address").and_then(|address| address.get("city")), Some(&string("z")));
+    assert_eq!(nested["a"], string("2"));
+    assert_eq!(nested["broken["], string("1"));
+    assert_eq!(nested["[x]"], string("2"));
+    assert_eq!(nested["deep"].get("a"), Some(&QueryValue::Array(vec![string("3")])));
+
+    let users = parse_query(b"users[][name]=a&users[][age]=1&users[][name]=b").structured(KeyConvention::Brackets);
+    let users = users["users"].as_array().unwrap();
+    assert_eq!(users.len(), 2);
+    assert_eq!((users[0].get("name"), users[0].get("age"), users[1].get("name")), (Some(&string("a")), Some(&string("1")), Some(&string("b"))));
+
+    let too_deep = format!("a{}=1", "[x]".repeat(40));
+    assert!(parse_query(too_deep.as_bytes()).structured(KeyConvention::Brackets).is_empty());
+}
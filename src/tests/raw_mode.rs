@@ -0,0 +1,81 @@
+use crate::testing::TestServer;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[test]
+fn upgrade_to_raw_mode() {
+    let server = TestServer::start(|request| {
+        let request = request?;
+        let tcp_session = request.tcp_session().clone();
+        request.response(101).headers("Upgrade: uppercase\r\n").send();
+        tcp_session.into_raw_mode(|data, tcp_session| {
+            tcp_session.send(&data.to_ascii_uppercase());
+            Ok(())
+        });
+        Ok(())
+    }).unwrap();
+
+    let mut tcp_stream = TcpStream::connect(server.addr()).unwrap();
+    tcp_stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+
+    // raw data right after request head, also looks like a request
+    tcp_stream.write_all(b"GET / HTTP/1.1\r\nConnection: upgrade\r\nUpgrade: uppercase\r\n\r\nget / http/1.1\r\n").unwrap();
+
+    let mut received = Vec::new();
+    let mut buf = [0; 1024];
+    while !received.ends_with(b"GET / HTTP/1.1\r\n") {
+        let cnt = tcp_stream.read(&mut buf).unwrap();
+        assert_ne!(cnt, 0);
+        received.extend_from_slice(&buf[..cnt]);
+    }
+    assert!(received.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"), "{}", String::from_utf8_lossy(&received));
+
+    tcp_stream.write_all(b"abc").unwrap();
+    let cnt = tcp_stream.read(&mut buf).unwrap();
+    assert_eq!(&buf[..cnt], b"ABC");
+}
+
+#[test]
+fn upgrade() {
+    use crate::request::UpgradeError;
+    use std::sync::mpsc;
+
+    let (sender, receiver) = mpsc::channel();
+    let sender = std::sync::Arc::new(std::sync::Mutex::new(sender));
+    let server = TestServer::start(move |request| {
+        let result = request?.upgrade("mqtt", "X-Extra: 1\r\n", |data, tcp_session| {
+            tcp_session.send(&data.to_ascii_uppercase());
+            Ok(())
+        });
+        if let Ok(sender) = sender.lock() {
+            let _ = sender.send(result.err());
+        }
+        Ok(())
+    }).unwrap();
+
+    let mut tcp_stream = TcpStream::connect(server.addr()).unwrap();
+    tcp_stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    tcp_stream.write_all(b"GET / HTTP/1.1\r\nConnection: keep-alive, Upgrade\r\nUpgrade: h2c, MQTT/3.1.1\r\n\r\nabc").unwrap();
+
+    let mut received = Vec::new();
+    let mut buf = [0; 1024];
+    while !received.ends_with(b"ABC") {
+        let cnt = tcp_stream.read(&mut buf).unwrap();
+        assert_ne!(cnt, 0);
+        received.extend_from_slice(&buf[..cnt]);
+    }
+    let received = String::from_utf8_lossy(&received);
+    assert!(received.starts_with("HTTP/1.1 101 Switching Protocols\r\nUpgrade: mqtt\r\nConnection: Upgrade\r\nX-Extra: 1\r\n"), "{}", received);
+    assert_eq!(receiver.recv().unwrap(), None);
+
+    let client = server.client();
+    client.get("/").header("Upgrade", "websocket").header("Connection", "Upgrade").send().unwrap()
+        .assert_code(426)
+        .assert_header("Upgrade", "mqtt");
+    assert_eq!(receiver.recv().unwrap(), Some(UpgradeError::UnsupportedProtocol));
+    client.get("/").header("Upgrade", "mqtt").send().unwrap().assert_code(426);
+    assert_eq!(receiver.recv().unwrap(), Some(UpgradeError::NoConnectionUpgrade));
+    client.get("/").send().unwrap().assert_code(426);
+    assert_eq!(receiver.recv().unwrap(), Some(UpgradeError::NoUpgradeHeader));
+}
@@ -6,7 +6,6 @@ use std::sync::Arc;
 fn empty() {
     // with 0 in "Content-Length" header
     test_request(
-        9094,
         b"POST / HTTP/1.1\r\n\
                     Content-Length: 0\r\n\
                     \r\n",
@@ -19,7 +18,7 @@ fn empty() {
             request.read_content(move |data, complete| {
                 content.extend_from_slice(data);
                 if let Some(request) = complete {
-                    assert_eq!(&content, &[]);
+                    assert!(content.is_empty());
                     request.response(200).close().send();
                 }
                 Ok(())
@@ -43,7 +42,6 @@ fn empty() {
 
     // without "Content-Length" header
     test_request(
-        9094,
         b"POST / HTTP/1.1\r\n\r\n",
         |request| {
             assert_eq!(request.method(), "POST");
@@ -54,7 +52,7 @@ fn empty() {
             request.read_content(move |data, complete| {
                 content.extend_from_slice(data);
                 if let Some(request) = complete {
-                    assert_eq!(&content, &[]);
+                    assert!(content.is_empty());
                     request.response(200).close().send();
                 }
                 Ok(())
@@ -80,7 +78,6 @@ fn empty() {
 #[test]
 fn small_content() {
     test_request(
-        9095,
         b"POST / HTTP/1.1\r\n\
                     Content-Type: Content-Type: text/plain; charset=utf-8\r\n\
                     Content-Length: 12\r\n\
@@ -140,7 +137,6 @@ fn big_content() {
     request.extend_from_slice(&origin_content);
 
     test_request(
-        9096,
         &request,
         move |request| {
             assert_eq!(request.method(), "POST");
@@ -152,7 +148,7 @@ fn big_content() {
             request.read_content(move |data, complete| {
                 content.extend_from_slice(data);
                 if let Some(request) = complete {
-                    let received_contant_is_same_original = &content[..] == &origin_content[..];
+                    let received_contant_is_same_original = content[..] == origin_content[..];
                     assert!(received_contant_is_same_original);
                     request.response(200).close().send();
                 }
@@ -176,3 +172,521 @@ fn big_content() {
     );
 }
 
+#[test]
+fn spilled_content() {
+    use crate::content::Content;
+    use crate::testing::TestServer;
+    use std::io::Read;
+
+    let server = TestServer::start(|request| {
+        let request = request?;
+        request.read_content_spilled(1024, |content, request| {
+            let mut received = Vec::new();
+            content.reader()?.read_to_end(&mut received)?;
+            let place = match &content {
+                Content::Memory(_) => "memory".to_string(),
+                Content::File(file) => {
+                    assert_eq!(file.len(), received.len() as u64);
+                    assert!(file.path().exists());
+                    file.path().display().to_string()
+                }
+            };
+
+            // temporary file is removed here, before response
+            drop(content);
+            let place_header = format!("X-Place: {}\r\n", place);
+            request.response(200).headers(&place_header).content("Content-Type: application/octet-stream\r\n", &received).close().send();
+            Ok(())
+        });
+        Ok(())
+    }).unwrap();
+
+    let response = server.client().post("/").body("small").send().unwrap();
+    response.assert_code(200).assert_header("X-Place", "memory").assert_text("small");
+
+    let big: Vec<u8> = (0..100000).map(|i| i as u8).collect();
+    let response = server.client().post("/").body(big.clone()).send().unwrap();
+    assert_eq!(response.content(), &big[..]);
+    let path = response.header("X-Place").unwrap();
+    assert_ne!(path, "memory");
+    // removed after handling
+    assert!(!std::path::Path::new(path).exists());
+}
+
+#[test]
+fn unread_content() {
+    use crate::testing::TestServer;
+
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.discard_unread_content_limit = 100,
+        |request| {
+            let request = request?;
+            let text = format!("{} {}", request.method(), request.path());
+            request.response(200).text(&text).send();
+            Ok(())
+        }
+    ).unwrap();
+
+    // content looking like request is discarded, next pipelined request is processed
+    let response = server.client().send_raw(
+        b"POST /first HTTP/1.1\r\n\
+        Content-Length: 24\r\n\
+        \r\n\
+        GET /smuggled HTTP/1.1\r\n\
+        GET /second HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n"
+    ).unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert!(raw.contains("POST /first"), "{}", raw);
+    assert!(raw.contains("GET /second"), "{}", raw);
+    assert!(!raw.contains("smuggled"), "{}", raw);
+
+    // content longer than limit, the connection is closed after the response
+    let mut raw_request = b"POST /big HTTP/1.1\r\nContent-Length: 200\r\n\r\n".to_vec();
+    raw_request.extend_from_slice(&[b'x'; 200]);
+    raw_request.extend_from_slice(b"GET /second HTTP/1.1\r\n\r\n");
+    let response = server.client().send_raw(&raw_request).unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert!(raw.contains("POST /big"), "{}", raw);
+    assert!(!raw.contains("GET /second"), "{}", raw);
+}
+
+#[test]
+fn unread_content_async_response() {
+    use crate::testing::TestServer;
+
+    // response sent from other thread after the content is rejected isn't lost
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.discard_unread_content_limit = 100,
+        |request| {
+            let request = request?;
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                let text = format!("{} {}", request.method(), request.path());
+                request.response(200).text(&text).send();
+            });
+            Ok(())
+        }
+    ).unwrap();
+
+    let mut raw_request = b"POST /big HTTP/1.1\r\nContent-Length: 200\r\n\r\n".to_vec();
+    raw_request.extend_from_slice(&[b'x'; 200]);
+    raw_request.extend_from_slice(b"GET /second HTTP/1.1\r\n\r\n");
+    let response = server.client().send_raw(&raw_request).unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert!(raw.contains("POST /big"), "{}", raw);
+    assert!(!raw.contains("GET /second"), "{}", raw);
+}
+
+#[test]
+fn decompression() {
+    use crate::testing::TestServer;
+    use deflate::{deflate_bytes_gzip, deflate_bytes_zlib};
+
+    let on_request = |request: Result<crate::request::Request, crate::http_error::HttpError>| {
+        let request = request?;
+        let mut content = vec![];
+        request.read_content(move |data, complete| {
+            content.extend_from_slice(data);
+            if let Some(request) = complete {
+                let encoding = format!("X-Encoding: {}\r\n", request.content_encoding().unwrap_or("none"));
+                request.response(200).headers(&encoding).content("Content-Type: application/octet-stream\r\n", &content).send();
+            }
+            Ok(())
+        });
+        Ok(())
+    };
+
+    let text = "Hello world! ".repeat(1000);
+    let gzip = deflate_bytes_gzip(text.as_bytes());
+    let zlib = deflate_bytes_zlib(text.as_bytes());
+
+    let server = TestServer::start_with(|server| server.settings.web_settings.content_decompression_limit = Some(20000), on_request).unwrap();
+    let client = server.client();
+    client.post("/").header("Content-Encoding", "gzip").body(gzip.clone()).send().unwrap()
+        .assert_code(200).assert_header("X-Encoding", "gzip").assert_text(&text);
+    client.post("/").header("Content-Encoding", "deflate").body(zlib).send().unwrap()
+        .assert_code(200).assert_header("X-Encoding", "deflate").assert_text(&text);
+    client.post("/").body("plain").send().unwrap()
+        .assert_code(200).assert_header("X-Encoding", "none").assert_text("plain");
+    client.post("/").header("Content-Encoding", "gzip").body("not gzip").send().unwrap()
+        .assert_code(400);
+
+    let big = deflate_bytes_gzip("Hello world! ".repeat(10000).as_bytes());
+    client.post("/").header("Content-Encoding", "gzip").body(big).send().unwrap()
+        .assert_code(413);
+
+    // disabled by default
+    let server = TestServer::start(on_request).unwrap();
+    let response = server.client().post("/").header("Content-Encoding", "gzip").body(gzip.clone()).send().unwrap();
+    response.assert_code(200).assert_header("X-Encoding", "gzip");
+    assert_eq!(response.content(), &gzip[..]);
+}
+
+#[test]
+fn content_digest() {
+    use crate::testing::TestServer;
+
+    let on_request = |request: Result<crate::request::Request, crate::http_error::HttpError>| {
+        let mut content = Vec::new();
+        request?.read_content(move |data, complete| {
+            content.extend_from_slice(data);
+            if let Some(request) = complete {
+                request.response(200).content("Content-Type: application/octet-stream\r\n", &content).send();
+            }
+            Ok(())
+        });
+        Ok(())
+    };
+
+    let body = "uploaded ".repeat(100);
+    let md5 = base64::encode(md5::compute(&body).0);
+    let sha256 = base64::encode(ring::digest::digest(&ring::digest::SHA256, body.as_bytes()));
+    let wrong = base64::encode(md5::compute("other").0);
+
+    let server = TestServer::start_with(|server| server.settings.web_settings.verify_content_digest = true, on_request).unwrap();
+    let client = server.client();
+    client.post("/").header("Content-MD5", &md5).body(body.clone()).send().unwrap().assert_code(200).assert_text(&body);
+    client.post("/").header("Digest", &format!("SHA-256={},UNIXsum=30637", sha256)).body(body.clone()).send().unwrap()
+        .assert_code(200).assert_text(&body);
+    client.post("/").header("Content-Digest", &format!("sha-256=:{}:", sha256)).body(body.clone()).send().unwrap()
+        .assert_code(200).assert_text(&body);
+    client.post("/").body(body.clone()).send().unwrap().assert_code(200).assert_text(&body);
+
+    client.post("/").header("Content-MD5", &wrong).body(body.clone()).send().unwrap().assert_code(400);
+    client.post("/").header("Digest", &format!("SHA-256={}, MD5={}", sha256, wrong)).body(body.clone()).send().unwrap()
+        .assert_code(400);
+    client.post("/").header("Content-Digest", "sha-256=:not base64:").body(body.clone()).send().unwrap().assert_code(400);
+
+    // disabled by default
+    let server = TestServer::start(on_request).unwrap();
+    server.client().post("/").header("Content-MD5", &wrong).body(body.clone()).send().unwrap().assert_code(200);
+}
+
+#[test]
+fn chunked_content() {
+    use crate::testing::TestServer;
+
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.discard_unread_content_limit = 10,
+        |request| {
+            let request = request?;
+            if request.path() == "/unread" {
+                request.response(200).text("unread").send();
+                return Ok(());
+            }
+
+            assert!(request.is_chunked() && request.has_content());
+            assert_eq!(request.request_data().declared_trailers(), vec!["Checksum", "Status"]);
+            assert!(request.accepts_trailers());
+            let mut content = vec![];
+            request.read_content(move |data, complete| {
+                content.extend_from_slice(data);
+                if let Some(request) = complete {
+                    let text = format!("{} {} {:?}", request.path(), String::from_utf8_lossy(&content), request.trailer_value("checksum"));
+                    request.response(200).text(&text).send();
+                }
+                Ok(())
+            });
+            Ok(())
+        }
+    ).unwrap();
+
+    // content is decoded, trailers are passed with complete content, next pipelined request is processed
+    let response = server.client().send_raw(
+        b"POST /first HTTP/1.1\r\n\
+        Transfer-Encoding: chunked\r\n\
+        Trailer: Checksum, Status\r\n\
+        TE: trailers\r\n\
+        \r\n\
+        5;ext=1\r\nHello\r\n\
+        7\r\n, world\r\n\
+        0\r\n\
+        Checksum: 123\r\n\
+        \r\n\
+        GET /unread HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n"
+    ).unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert!(raw.contains("/first Hello, world Some(\"123\")"), "{}", raw);
+    assert!(raw.contains("unread"), "{}", raw);
+
+    // unread content looking like request is discarded
+    let response = server.client().send_raw(
+        b"POST /unread HTTP/1.1\r\n\
+        Transfer-Encoding: chunked\r\n\
+        \r\n\
+        8\r\nGET /x\r\n\r\n\
+        0\r\n\r\n\
+        GET /unread HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n"
+    ).unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert_eq!(raw.matches("unread").count(), 2, "{}", raw);
+
+    // unread content longer than limit, the connection is closed after the response
+    let response = server.client().send_raw(
+        b"POST /unread HTTP/1.1\r\n\
+        Transfer-Encoding: chunked\r\n\
+        \r\n\
+        10\r\n0123456789abcdef\r\n\
+        0\r\n\r\n\
+        GET /unread HTTP/1.1\r\n\
+        \r\n"
+    ).unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert_eq!(raw.matches("unread").count(), 1, "{}", raw);
+
+    // smuggling attempt, unsupported coding and invalid chunk
+    for (raw_request, status) in [
+        (&b"POST /unread HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n0\r\n\r\n"[..], "400 Bad Request"),
+        (&b"POST /unread HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n"[..], "501 Not Implemented"),
+        (&b"POST /unread HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n\r\n"[..], "400 Bad Request"),
+    ] {
+        let response = server.client().send_raw(raw_request).unwrap();
+        let raw = String::from_utf8_lossy(response.raw()).to_string();
+        assert!(raw.contains(status), "{}", raw);
+    }
+}
+
+#[test]
+fn read_limits_per_event() {
+    use crate::tests::request::test_request_with_server;
+
+    const LEN: usize = 100_000;
+    let mut request = format!("POST / HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", LEN).into_bytes();
+    request.extend_from_slice(&[b'x'; LEN]);
+
+    // one read per event, the rest is read on next iterations of the worker
+    test_request_with_server(
+        |server| server.settings.web_settings.reads_per_event_limit = 1,
+        &request,
+        |request| {
+            let mut len = 0;
+            request.read_content(move |data, complete| {
+                len += data.len();
+                if let Some(request) = complete {
+                    request.response(200).text(&len.to_string()).send();
+                }
+                Ok(())
+            })
+        },
+        |response| {
+            assert!(response.ends_with(LEN.to_string().as_bytes()));
+        }
+    );
+}
+
+#[test]
+fn read_buffer() {
+    use crate::tests::request::test_request_with_server;
+
+    const LEN: usize = 100_000;
+    let mut request = format!("POST / HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", LEN).into_bytes();
+    request.extend((0..LEN).map(|i| b'a' + (i % 26) as u8));
+
+    // buffer grows from 16 bytes up to 256 bytes while content is received
+    test_request_with_server(
+        |server| {
+            server.settings.web_settings.read_buffer_size = 16;
+            server.settings.web_settings.read_buffer_limit = 256;
+        },
+        &request,
+        |request| {
+            let mut content = Vec::new();
+            request.read_content(move |data, complete| {
+                content.extend_from_slice(data);
+                if let Some(request) = complete {
+                    let valid = content.iter().enumerate().all(|(i, byte)| *byte == b'a' + (i % 26) as u8);
+                    request.response(200).text(&format!("{} {}", content.len(), valid)).send();
+                }
+                Ok(())
+            })
+        },
+        |response| {
+            assert!(response.ends_with(format!("{} true", LEN).as_bytes()), "{}", String::from_utf8_lossy(response));
+        }
+    );
+}
+
+#[test]
+fn read_buffer_tls() {
+    use crate::testing::TestServer;
+    use crate::tls::{load_certs, load_private_key};
+    use rustls::{NoClientAuth, ServerConfig};
+    use std::io::{Read, Write};
+
+    struct AnyCertificate;
+
+    impl rustls::ServerCertVerifier for AnyCertificate {
+        fn verify_server_cert(&self, _roots: &rustls::RootCertStore, _presented_certs: &[rustls::Certificate], _dns_name: webpki::DNSNameRef, _ocsp_response: &[u8]) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+            Ok(rustls::ServerCertVerified::assertion())
+        }
+    }
+
+    let server = TestServer::start_with(
+        |server| {
+            let mut tls_config = ServerConfig::new(NoClientAuth::new());
+            let certs = load_certs("examples/keys/cert.pem").unwrap();
+            let private_key = load_private_key("examples/keys/key.pem").unwrap();
+            tls_config.set_single_cert_with_ocsp_and_sct(certs, private_key, vec![], vec![]).unwrap();
+            server.settings.tls_config = Some(Arc::new(tls_config));
+        },
+        |request| {
+            let mut content = Vec::new();
+            request?.read_content(move |data, complete| {
+                content.extend_from_slice(data);
+                if let Some(request) = complete {
+                    let valid = content.iter().enumerate().all(|(i, byte)| *byte == b'a' + (i % 26) as u8);
+                    request.response(200).text(&format!("{} {}", content.len(), valid)).send();
+                }
+                Ok(())
+            });
+            Ok(())
+        }
+    ).unwrap();
+
+    let mut client_config = rustls::ClientConfig::new();
+    client_config.dangerous().set_certificate_verifier(Arc::new(AnyCertificate));
+    let client_config = Arc::new(client_config);
+
+    // plaintext of one record is bigger than initial buffer, one read of grown buffer gets several records
+    for len in [16_000, 300_000] {
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let session = rustls::ClientSession::new(&client_config, dns_name);
+        let tcp_stream = std::net::TcpStream::connect(server.addr()).unwrap();
+        tcp_stream.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        let mut stream = rustls::StreamOwned::new(session, tcp_stream);
+        let mut request = format!("POST / HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", len).into_bytes();
+        request.extend((0..len).map(|i| b'a' + (i % 26) as u8));
+        stream.write_all(&request).unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(response.ends_with(format!("{} true", len).as_bytes()), "{}", String::from_utf8_lossy(&response));
+    }
+}
+
+#[test]
+fn pipe_content() {
+    use crate::testing::TestServer;
+
+    /// Writer failing on write, like full disk.
+    struct Failing;
+    impl std::io::Write for Failing {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("no space"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let server = TestServer::start(|request| {
+        let request = request?;
+        if request.path() == "/failing" {
+            request.pipe_content_to(Failing, |_, request| {
+                request.response(200).send();
+                Ok(())
+            });
+        } else {
+            request.pipe_content_to(Vec::new(), |content, request| {
+                request.response(200).text(&String::from_utf8_lossy(&content)).send();
+                Ok(())
+            });
+        }
+        Ok(())
+    }).unwrap();
+
+    server.client().post("/").body("piped content").send().unwrap().assert_code(200).assert_text("piped content");
+    server.client().post("/failing").body("piped content").send().unwrap().assert_code(500);
+}
+
+#[test]
+fn content_length_limit() {
+    use crate::request::RequestError;
+    use crate::testing::TestServer;
+    use crate::web_session::RejectedContent;
+    use crate::http_error::HttpError;
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let rejected = Arc::new(AtomicUsize::new(0));
+    let rejected_clone = rejected.clone();
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.content_length_limit = Some(1000),
+        move |request| {
+            let request = match request {
+                Err(HttpError::ParseRequestError(RequestError::ContentLengthLimit)) => {
+                    rejected_clone.fetch_add(1, Ordering::SeqCst);
+                    return Ok(());
+                }
+                request => request?,
+            };
+            request.read_content(|_, complete| {
+                if let Some(request) = complete {
+                    request.response(200).text("accepted").send();
+                }
+                Ok(())
+            });
+            Ok(())
+        }
+    ).unwrap();
+
+    // content is drained, the client uploads it entirely and gets the response
+    let mut raw_request = b"POST / HTTP/1.1\r\nContent-Length: 500000\r\n\r\n".to_vec();
+    raw_request.extend_from_slice(&[b'x'; 500_000]);
+    let response = server.client().send_raw(&raw_request).unwrap();
+    response.assert_code(413).assert_header("Connection", "close");
+    assert_eq!(rejected.load(Ordering::SeqCst), 1);
+
+    server.client().post("/").body(vec![b'x'; 1000]).send().unwrap().assert_code(200).assert_text("accepted");
+
+    // chunked content is limited by decoded length
+    let chunked_request = |chunks: usize| {
+        let mut raw_request = b"POST / HTTP/1.1\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for _ in 0..chunks {
+            raw_request.extend_from_slice(b"1f4\r\n");
+            raw_request.extend_from_slice(&[b'x'; 500]);
+            raw_request.extend_from_slice(b"\r\n");
+        }
+        raw_request.extend_from_slice(b"0\r\n\r\n");
+        server.client().send_raw(&raw_request).unwrap()
+    };
+    chunked_request(2).assert_code(200).assert_text("accepted");
+    chunked_request(3).assert_code(413).assert_header("Connection", "close");
+    assert_eq!(rejected.load(Ordering::SeqCst), 2);
+
+    // content over the drain limit isn't waited for
+    let mut stream = std::net::TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(3))).unwrap();
+    stream.write_all(b"POST / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n").unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 413 Payload Too Large\r\n"), "{}", String::from_utf8_lossy(&response));
+    assert_eq!(rejected.load(Ordering::SeqCst), 3);
+
+    // the client waiting for "100 Continue" doesn't send content, the connection is closed right after the response
+    let response = server.client().send_raw(b"POST / HTTP/1.1\r\nContent-Length: 5000\r\nExpect: 100-continue\r\n\r\n").unwrap();
+    response.assert_code(413);
+    assert_eq!(rejected.load(Ordering::SeqCst), 4);
+    server.stop();
+
+    let server = TestServer::start_with(
+        |server| {
+            server.settings.web_settings.content_length_limit = Some(1000);
+            server.settings.web_settings.rejected_content = RejectedContent::Close;
+        },
+        |request| {
+            request?.response(200).send();
+            Ok(())
+        }
+    ).unwrap();
+
+    // the connection is closed without waiting for content
+    let response = server.client().send_raw(b"POST / HTTP/1.1\r\nContent-Length: 5000\r\n\r\n").unwrap();
+    response.assert_code(413);
+}
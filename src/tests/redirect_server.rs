@@ -0,0 +1,76 @@
+use crate::redirect_server::RedirectServer;
+use crate::server::{Server, Stopper};
+use crate::testing::TestClient;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+fn start(redirect_server: RedirectServer) -> (SocketAddr, Stopper) {
+    let server = Server::new(&([127, 0, 0, 1], 0).into()).unwrap();
+    let addr = server.local_addr().unwrap();
+    (addr, redirect_server.num_threads(1).run_server(server))
+}
+
+fn stop(addr: SocketAddr, stopper: Stopper) {
+    stopper.stop();
+    while TcpStream::connect(addr).is_ok() {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn redirects() {
+    let (addr, stopper) = start(RedirectServer::https(8443).hsts(100).health_check());
+    let client = TestClient::new(addr);
+    client.get("/a/b?c=d").header("Host", "example.com:8080").send().unwrap()
+        .assert_code(301)
+        .assert_header("Location", "https://example.com:8443/a/b?c=d")
+        .assert_header("Strict-Transport-Security", "max-age=100");
+    client.get("/").header("Host", "[::1]:80").send().unwrap()
+        .assert_header("Location", "https://[::1]:8443/");
+    client.get("/").send().unwrap().assert_code(400);
+    client.get("/").header("Host", "evil\rhost").send().unwrap().assert_code(400);
+    client.get("/healthz").header("Host", "example.com").send().unwrap().assert_code(200);
+    stop(addr, stopper);
+
+    let (addr, stopper) = start(RedirectServer::https(443).code(308));
+    TestClient::new(addr).get("/x").header("Host", "example.com").send().unwrap()
+        .assert_code(308)
+        .assert_header("Location", "https://example.com/x");
+    stop(addr, stopper);
+
+    let (addr, stopper) = start(RedirectServer::new("https://example.org:9000/"));
+    TestClient::new(addr).get("/x?y").send().unwrap()
+        .assert_header("Location", "https://example.org:9000/x?y");
+    stop(addr, stopper);
+
+    let (addr, stopper) = start(RedirectServer::fixed("https://example.org/").code(303));
+    TestClient::new(addr).get("/x?y").send().unwrap()
+        .assert_code(303)
+        .assert_header("Location", "https://example.org/");
+    stop(addr, stopper);
+}
+
+#[test]
+fn canonical_host() {
+    use crate::testing::TestServer;
+
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.canonical_host = Some("www.example.com".to_string()),
+        |request| {
+            request?.response(200).text("app").send();
+            Ok(())
+        },
+    ).unwrap();
+    let client = server.client();
+
+    let response = client.send_raw(b"GET /a?b=1 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n").unwrap();
+    response.assert_code(301).assert_header("Location", "http://www.example.com/a?b=1");
+
+    let response = client.send_raw(b"GET / HTTP/1.1\r\nHost: WWW.Example.com:80\r\nConnection: close\r\n\r\n").unwrap();
+    response.assert_code(200).assert_text("app");
+
+    client.send_raw(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap().assert_code(400);
+    client.send_raw(b"GET / HTTP/1.1\r\nHost: www.example.com/x\r\nConnection: close\r\n\r\n").unwrap().assert_code(400);
+    client.send_raw(b"GET / HTTP/1.1\r\nHost: www.example.com:8o\r\nConnection: close\r\n\r\n").unwrap().assert_code(400);
+    client.send_raw(b"GET / HTTP/1.0\r\n\r\n").unwrap().assert_code(200);
+}
@@ -1,12 +1,10 @@
 #[cfg(test)]
 use crate::request::{Header, HttpVersion, RequestError};
 use crate::request_parser::{ParseHttpRequestSettings, Parser};
-use crate::server::{Event, Server};
-use std::thread::sleep;
-use std::net::TcpStream;
-use std::io::{Write, Read};
-use std::time::{Duration, Instant};
+use crate::server::Server;
+use crate::testing::TestServer;
 use crate::request::Request;
+use std::io::{Read, Write};
 
 impl PartialEq for Header {
     fn eq(&self, other: &Self) -> bool {
@@ -20,10 +18,14 @@ fn parse() {
         method_len_limit: 7,
         path_len_limit: 512,
         query_len_limit: 512,
+        uri_len_limit: 1024,
         headers_count_limit: 5,
         header_name_len_limit: 64,
         header_value_len_limit: 512,
+        headers_len_limit: 32768,
         pipelining_requests_limit: 12,
+        decode_slash_in_path: false,
+        reject_invalid_host: false,
     };
 
     let mut parser = Parser::new();
@@ -31,7 +33,7 @@ fn parse() {
     if let Ok((_request, surplus)) = parser.push(request_str.as_bytes(), &parse_settings) {
         assert!(surplus.is_empty());
     } else {
-        assert!(false);
+        panic!();
     }
 
     let mut parser = Parser::new();
@@ -40,7 +42,7 @@ fn parse() {
     if let Ok((_request, surplus)) = parser.push(request_str.as_bytes(), &parse_settings) {
         assert_eq!(surplus.len(), 3);
     } else {
-        assert!(false);
+        panic!();
     }
 
     let mut parser = Parser::new();
@@ -53,7 +55,7 @@ fn parse() {
         assert_eq!(request.version, HttpVersion::Http1_1);
         assert!(request.headers.is_empty());
     } else {
-        assert!(false);
+        panic!();
     }
 
     let mut parser = Parser::new();
@@ -66,7 +68,7 @@ fn parse() {
         assert_eq!(request.version, HttpVersion::Http1_0);
         assert!(!request.headers.is_empty());
     } else {
-        assert!(false);
+        panic!();
     }
 
     let mut parser = Parser::new();
@@ -84,50 +86,50 @@ fn parse() {
             ]
         );
     } else {
-        assert!(false);
+        panic!();
     }
 
     let mut parser = Parser::new();
 
     let request_str = "";
     if parser.push(request_str.as_bytes(), &parse_settings).is_ok() {
-        assert!(false);
+        panic!();
     }
 
     let mut parser = Parser::new();
 
     let request_str = "/index?a=1&b=2;c=3 HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
     if parser.push(request_str.as_bytes(), &parse_settings).is_ok() {
-        assert!(false);
+        panic!();
     }
 
     let request_str = "GET /ws /index?a=1&b=2;c=3 HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
     if Parser::new().push(request_str.as_bytes(), &parse_settings).is_ok() {
-        assert!(false);
+        panic!();
     }
 
     // usupported protocol
     let request_str = "GET / HTTP/1.5\r\n\r\n";
     match Parser::new().push(request_str.as_bytes(), &parse_settings) {
         Ok(_) => {
-            assert!(false);
+            panic!();
         }
         Err(err) => {
             if let RequestError::UnsupportedProtocol = err {
             } else {
-                assert!(false);
+                panic!();
             }
         }
     }
 
     let request_str = "GET / HTTP/1.1 \r\nConnection: keep-alive\r\n";
     if Parser::new().push(request_str.as_bytes(), &parse_settings).is_ok() {
-        assert!(false);
+        panic!();
     }
 
     let request_str = "GET / HTTP/1.1\r\n: sd\r\n\r\n";
     if Parser::new().push(request_str.as_bytes(), &parse_settings).is_ok() {
-        assert!(false);
+        panic!();
     }
 
     let request_str = "GET / HTTP/1.1\r\n : sd\r\n\r\n";
@@ -135,172 +137,241 @@ fn parse() {
 
     let request_str = "GET / HTTP/1.1\r\nSD:\r\n\r\n";
     if Parser::new().push(request_str.as_bytes(), &parse_settings).is_ok() {
-        assert!(false);
+        panic!();
     }
 
     let request_str = "GET / HTTP/1.1\r\nSD: \r\n\r\n";
     if Parser::new().push(request_str.as_bytes(), &parse_settings).is_ok() {
-        assert!(false);
+        panic!();
     }
 }
 
+#[test]
+fn path_decoding() {
+    let mut parse_settings = ParseHttpRequestSettings::default();
+
+    let (request, _) = Parser::new().push(b"GET /a%2Fb/c%2fd+e/%41 HTTP/1.1\r\n\r\n", &parse_settings).unwrap();
+    assert_eq!(request.path(), "/a%2Fb/c%2Fd+e/A");
+    assert_eq!(request.raw_path_segments().collect::<Vec<&[u8]>>(), vec![&b"a%2Fb"[..], b"c%2fd+e", b"%41"]);
+    assert_eq!(request.path_segments().collect::<Vec<_>>(), vec!["a/b", "c/d+e", "A"]);
+
+    parse_settings.decode_slash_in_path = true;
+    let (request, _) = Parser::new().push(b"GET /a%2Fb/c HTTP/1.1\r\n\r\n", &parse_settings).unwrap();
+    assert_eq!(request.path(), "/a/b/c");
+    assert_eq!(request.path_segments().collect::<Vec<_>>(), vec!["a/b", "c"]);
+}
+
+#[test]
+fn path_segments_matching() {
+    let (request, _) = Parser::new().push(b"GET //api/v1//users/%41/ HTTP/1.1\r\n\r\n", &ParseHttpRequestSettings::default()).unwrap();
+    assert!(request.starts_with_segments(&[]));
+    assert!(request.starts_with_segments(&["api"]));
+    assert!(request.starts_with_segments(&["api", "v1"]));
+    assert!(!request.starts_with_segments(&["v1"]));
+    assert!(!request.starts_with_segments(&["api", "v1", "users", "A", "x"]));
+    assert_eq!(request.tail_after(&["api", "v1"]), Some(vec!["users".into(), "A".into()]));
+    assert_eq!(request.tail_after(&["api", "v1", "users", "A"]), Some(vec![]));
+    assert_eq!(request.tail_after(&["api", "v2"]), None);
+}
+
 #[test]
 fn limits() {
     let parse_settings = ParseHttpRequestSettings {
         method_len_limit: 5,
         path_len_limit: 512,
         query_len_limit: 512,
+        uri_len_limit: 1024,
         headers_count_limit: 2,
         header_name_len_limit: 5,
         header_value_len_limit: 8,
+        headers_len_limit: 32768,
         pipelining_requests_limit: 12,
+        decode_slash_in_path: false,
+        reject_invalid_host: false,
     };
 
     // norm
     let request_str = "GET / HTTP/1.1\r\n1234: abc\r\n\r\n";
-    if let Err(_) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        assert!(false);
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_err() {
+        panic!();
     }
 
     let request_str = "GET / HTTP/1.1\r\n12345: abc\r\n\r\n";
-    if let Err(_) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        assert!(false);
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_err() {
+        panic!();
     }
 
     let request_str = "GET / HTTP/1.1\r\n123456: abc\r\n\r\n";
-    if let Ok(_) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        assert!(false);
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_ok() {
+        panic!();
     }
 
     // headers count limit--------------------------------------------
     // less
     let request_str = "GET / HTTP/1.1\r\nabcd: as\r\n\r\n";
-    if let Err(_) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        assert!(false);
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_err() {
+        panic!();
     }
 
     // equal
     let request_str = "GET / HTTP/1.1\r\nabcd: as\r\nAAA: 12\r\n\r\n";
-    if let Err(_) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        assert!(false);
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_err() {
+        panic!();
     }
 
     // more
     let request_str = "GET / HTTP/1.1\r\nabcd: as\r\nAAA: 12\r\nVBWER: ASD2\r\n\r\n";
-    if let Ok(_) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        assert!(false);
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_ok() {
+        panic!();
     }
 
     // header value limit--------------------------------------------
     // less
     let request_str = "GET / HTTP/1.1\r\nabcd: as\r\n\r\n";
-    if let Err(err) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        if let RequestError::HeaderValueLenLimit = err {
-            assert!(false);
-        }
+    if let Err(RequestError::HeaderValueLenLimit) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
+        panic!();
     }
 
     // equal
     let request_str = "GET / HTTP/1.1\r\nxyz: bcafghs\r\n\r\n";
-    if let Err(_) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        assert!(false);
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_err() {
+        panic!();
     }
 
     // more
     let request_str = "GET / HTTP/1.1\r\nxyz: bcaajsxs\r\n\r\n";
-    if let Ok(_) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        assert!(false);
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_ok() {
+        panic!();
     }
 
     // empty header---------------------------------------------------
     let request_str = "GET / HTTP/1.1\r\n: abcasdf\r\n\r\n";
-    if let Err(err) = Parser::new().push(request_str.as_bytes(), &parse_settings) {
-        if let RequestError::EmptyHeaderName = err {
-        } else {
-            assert!(false);
-        }
-    } else {
-        assert!(false);
+    match Parser::new().push(request_str.as_bytes(), &parse_settings) {
+        Err(RequestError::EmptyHeaderName) => {}
+        _ => panic!(),
+    }
+
+    // uri limit------------------------------------------------------
+    let parse_settings = ParseHttpRequestSettings { uri_len_limit: 10, ..parse_settings };
+    let request_str = "GET /abc?defgh HTTP/1.1\r\n\r\n";
+    if Parser::new().push(request_str.as_bytes(), &parse_settings).is_err() {
+        panic!();
+    }
+
+    let request_str = "GET /abc?defghi HTTP/1.1\r\n\r\n";
+    match Parser::new().push(request_str.as_bytes(), &parse_settings) {
+        Err(RequestError::UriLenLimit) => {}
+        _ => panic!(),
     }
 }
 
-/// Starts the server on localhost, opens the client socket,
-/// makes request ('raw_request') to the server,
+#[test]
+fn parse_error_responses() {
+    let server = TestServer::start(|request| {
+        request?.response(200).text("unreachable").send();
+        Ok(())
+    }).unwrap();
+
+    let long_path = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(600));
+    let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "abc: def\r\n".repeat(100));
+    let cases = [
+        (long_path.as_str(), 414),
+        (many_headers.as_str(), 431),
+        ("VERYLONGMETHOD / HTTP/1.1\r\n\r\n", 501),
+        ("GET / HTTP/2.0\r\n\r\n", 505),
+        ("GET / HTTP/1.1\r\n: abc\r\n\r\n", 400),
+    ];
+
+    for (raw_request, code) in cases.iter() {
+        let response = server.client().send_raw(raw_request.as_bytes()).unwrap();
+        response.assert_code(*code).assert_header("Connection", "close");
+    }
+
+    // disabled auto-responses
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.respond_to_parse_errors = false,
+        |_| Ok(()),
+    ).unwrap();
+    let response = server.client().send_raw(long_path.as_bytes()).unwrap();
+    assert!(response.raw().is_empty());
+}
+
+#[test]
+fn host_validation() {
+    use crate::request::HostValidation;
+
+    let parse = |raw: &str, reject_invalid_host: bool| {
+        let settings = ParseHttpRequestSettings { reject_invalid_host, ..ParseHttpRequestSettings::default() };
+        Parser::new().push(raw.as_bytes(), &settings).map(|(request, _)| (request.host_validation(), request.has_valid_host()))
+    };
+
+    assert!(matches!(parse("GET / HTTP/1.1\r\nHost: a\r\n\r\n", true), Ok((HostValidation::Single, true))));
+    assert!(matches!(parse("GET / HTTP/1.0\r\n\r\n", true), Ok((HostValidation::Missing, true))));
+    assert!(matches!(parse("GET / HTTP/1.1\r\n\r\n", true), Err(RequestError::MissingHost)));
+    assert!(matches!(parse("GET / HTTP/1.0\r\nHost: a\r\nhost: b\r\n\r\n", true), Err(RequestError::DuplicateHost)));
+
+    // tolerated and exposed
+    assert!(matches!(parse("GET / HTTP/1.1\r\n\r\n", false), Ok((HostValidation::Missing, false))));
+    assert!(matches!(parse("GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n", false), Ok((HostValidation::Duplicate, false))));
+
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.parse_http_request_settings.reject_invalid_host = true,
+        |request| {
+            request?.response(200).text("ok").send();
+            Ok(())
+        },
+    ).unwrap();
+    server.client().get("/").header("Host", "a").send().unwrap().assert_code(200);
+    server.client().get("/").send().unwrap().assert_code(400).assert_header("Connection", "close");
+    server.client().get("/").header("Host", "a").header("Host", "b").send().unwrap().assert_code(400);
+}
+
+#[test]
+fn parser_stats() {
+    use crate::request::ParserStats;
+
+    let settings = ParseHttpRequestSettings::default();
+    let mut parser = Parser::new();
+    let (first, surplus) = parser.push(b"GET / HTTP/1.1\r\nA: 1\r\n\r\nGET /b HTTP/1.1\r\nB: 2\r\nC: 3\r\n\r\n", &settings).unwrap();
+    let (second, _) = parser.push(&surplus, &settings).unwrap();
+    assert_eq!(first.parser_stats(), ParserStats { bytes_consumed: 24, headers_parsed: 1, requests_parsed: 1 });
+    assert_eq!(second.parser_stats(), ParserStats { bytes_consumed: 55, headers_parsed: 3, requests_parsed: 2 });
+
+    // total length of header lines
+    let settings = ParseHttpRequestSettings { headers_len_limit: 25, ..settings };
+    assert!(Parser::new().push(b"GET / HTTP/1.1\r\nA: 12345678\r\nB: 1234567\r\n\r\n", &settings).is_ok());
+    assert!(matches!(Parser::new().push(b"GET / HTTP/1.1\r\nA: 12345678\r\nB: 12345678\r\n\r\n", &settings), Err(RequestError::HeadersLenLimit)));
+}
+
+/// Starts the server on localhost, makes request ('raw_request') to the server,
 /// calls callback when request is received on server side, reads response,
 /// calls callback when response is received, and stops the server.
-pub fn test_request(port: u16, raw_request: &[u8], on_request: impl FnMut(Request)  + Send + Clone + 'static, on_response: impl FnMut(&[u8]) + Send + Clone + 'static) {
-    let server = Server::new(&([0, 0, 0, 0], port).into());
+pub fn test_request(raw_request: &[u8], on_request: impl FnMut(Request) + Send + Clone + 'static, on_response: impl FnMut(&[u8])) {
+    test_request_with_server(|_| {}, raw_request, on_request, on_response);
+}
+
+/// Same as `test_request`, `prepare` is called with the server before start (for settings, etc.).
+pub fn test_request_with_server(prepare: impl FnOnce(&mut Server), raw_request: &[u8], mut on_request: impl FnMut(Request) + Send + Clone + 'static, mut on_response: impl FnMut(&[u8])) {
+    let server = TestServer::start_with(prepare, move |request| {
+        assert!(request.is_ok());
+        on_request(request?);
+        Ok(())
+    });
     assert!(server.is_ok());
     if let Ok(server) = server {
-        let stopper = server.stopper();
-        let raw_request = raw_request.to_vec();
-        let server_run_res = server.run(move |server_event| {
-            match server_event {
-                Event::Incoming(tcp_session) => {
-                    let mut on_request = on_request.clone();
-                    tcp_session.to_http(move |request| {
-                        assert!(request.is_ok());
-                        on_request(request?);
-                        Ok(())
-                    });
-                }
-                Event::Started => {
-                    let stopper = stopper.clone();
-                    let mut on_response = on_response.clone();
-                    let raw_request = raw_request.to_vec();
-                    std::thread::spawn(move || {
-                        let addr = &format!("127.0.0.1:{}", port.to_string());
-                        let tcp_stream = TcpStream::connect(addr);
-                        assert!(tcp_stream.is_ok());
-                        if let Ok(mut tcp_stream) = tcp_stream {
-                            let res = tcp_stream.set_write_timeout(Some(Duration::from_millis(64)));
-                            assert!(res.is_ok());
-                            let res = tcp_stream.write_all(&raw_request);
-                            assert!(res.is_ok());
-
-                            let mut response: Vec<u8> = Vec::new();
-                            let res = tcp_stream.set_read_timeout(Some(Duration::from_millis(64)));
-                            assert!(res.is_ok());
-                            let begin_read = Instant::now();
-                            loop {
-                                assert!(begin_read.elapsed() < Duration::from_secs(3));
-
-                                let res = tcp_stream.read_to_end(&mut response);
-                                if let Err(err) = &res {
-                                    if err.kind() == std::io::ErrorKind::WouldBlock {
-                                        sleep(Duration::from_millis(1));
-                                        continue;
-                                    }
-                                }
-
-                                assert!(res.is_ok());
-                                break;
-                            }
-
-                            on_response(&response);
-
-                            stopper.stop();
-                            loop {
-                                if TcpStream::connect(addr).is_ok() {
-                                    sleep(Duration::from_millis(1));
-                                } else {
-                                    break;
-                                }
-                            }
-                        }
-                    });
-                }
-                _ => {}
-            }
-        });
-        assert!(server_run_res.is_ok());
+        let response = server.client().send_raw(raw_request);
+        assert!(response.is_ok());
+        if let Ok(response) = response {
+            on_response(response.raw());
+        }
+
+        server.stop();
     }
 }
 
 #[test]
 fn hello_world() {
     test_request(
-        9090,
         b"GET / HTTP/1.1\r\n\r\n",
         |request| {
             assert_eq!(request.method(), "GET");
@@ -325,3 +396,187 @@ fn hello_world() {
         }
     );
 }
+
+#[test]
+fn raw_request_line_and_headers() {
+    let parse_settings = ParseHttpRequestSettings::default();
+
+    let mut parser = Parser::new();
+    let (request, surplus) = parser.push(b"POST /a%20b?x=1 HTTP/1.1\r\nHost: example.com\r\nX-Sign:  a b \r\nContent-Length: 3\r\n\r\nabc", &parse_settings).unwrap();
+    assert_eq!(request.raw_request_line(), b"POST /a%20b?x=1 HTTP/1.1");
+    assert_eq!(request.raw_headers(), b"Host: example.com\r\nX-Sign:  a b \r\nContent-Length: 3\r\n");
+    assert_eq!(surplus, b"abc");
+
+    let mut parser = Parser::new();
+    let (request, _) = parser.push(b"GET / HTTP/1.0\r\n\r\n", &parse_settings).unwrap();
+    assert_eq!(request.raw_request_line(), b"GET / HTTP/1.0");
+    assert!(request.raw_headers().is_empty());
+}
+
+#[test]
+fn connection_options() {
+    use crate::request::ConnectionType;
+
+    let parse_settings = ParseHttpRequestSettings::default();
+
+    let mut parser = Parser::new();
+    let request_str = "GET / HTTP/1.0\r\nconnection: Keep-Alive, Upgrade\r\nUpgrade: h2c\r\n\r\n";
+    let (request, _) = parser.push(request_str.as_bytes(), &parse_settings).unwrap();
+    assert!(matches!(request.connection_type(), Some(ConnectionType::KeepAlive)));
+    assert_eq!(request.connection_options(), ["keep-alive", "upgrade"]);
+    assert!(request.has_connection_option("Upgrade"));
+
+    let mut parser = Parser::new();
+    let request_str = "GET / HTTP/1.1\r\nConnection: keep-alive\r\nConnection: TE, CLOSE\r\n\r\n";
+    let (request, _) = parser.push(request_str.as_bytes(), &parse_settings).unwrap();
+    assert!(matches!(request.connection_type(), Some(ConnectionType::Close)));
+    assert_eq!(request.connection_options(), ["keep-alive", "te", "close"]);
+
+    // legacy HTTP/1.0 client with capitalized token reuses the connection
+    let server = TestServer::start(|request| {
+        request?.response(200).text("ok").send();
+        Ok(())
+    }).unwrap();
+    let mut tcp_stream = std::net::TcpStream::connect(server.addr()).unwrap();
+    for _ in 0..2 {
+        tcp_stream.write_all(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+        let mut buf = [0; 1024];
+        let len = tcp_stream.read(&mut buf).unwrap();
+        let response = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(response.contains("\r\nConnection: keep-alive\r\n"), "{}", response);
+    }
+}
+
+#[test]
+fn recover_from_parse_errors() {
+    let server = TestServer::start_with(
+        |server| {
+            server.settings.web_settings.recover_from_parse_errors = true;
+            server.settings.web_settings.parse_http_request_settings.header_value_len_limit = 32;
+        },
+        |request| {
+            if let Ok(request) = request {
+                let text = request.path().to_string();
+                request.response(200).text(&text).send();
+            }
+            Ok(())
+        }
+    ).unwrap();
+
+    let read_responses = |stream: &mut std::net::TcpStream| {
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(3))).unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        String::from_utf8_lossy(&response).to_string()
+    };
+
+    // the long header is skipped, the next request is served on the same connection
+    let mut stream = std::net::TcpStream::connect(server.addr()).unwrap();
+    let long = "x".repeat(64);
+    stream.write_all(format!("GET /first HTTP/1.1\r\nX-Long: {}\r\nX-Other: 1\r\n\r\nGET /second HTTP/1.1\r\n", long).as_bytes()).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    stream.write_all(b"Connection: close\r\n\r\n").unwrap();
+    let responses = read_responses(&mut stream);
+    assert!(responses.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{}", responses);
+    assert!(responses.contains("\r\nConnection: keep-alive\r\n"), "{}", responses);
+    assert!(responses.contains("HTTP/1.1 200 OK\r\n"), "{}", responses);
+    assert!(responses.ends_with("/second"), "{}", responses);
+
+    // the head is skipped in parts
+    let mut stream = std::net::TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(format!("GET /first HTTP/1.1\r\nX-Long: {}", long).as_bytes()).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    stream.write_all(b"\r\nX-Other: 1\r").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    stream.write_all(b"\n\r\nGET /third HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let responses = read_responses(&mut stream);
+    assert!(responses.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{}", responses);
+    assert!(responses.ends_with("/third"), "{}", responses);
+
+    // end of content of failed request is unknown
+    let mut stream = std::net::TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(format!("POST /first HTTP/1.1\r\nX-Long: {}\r\nContent-Length: 16\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n", long).as_bytes()).unwrap();
+    let responses = read_responses(&mut stream);
+    assert!(responses.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{}", responses);
+    assert!(responses.contains("\r\nConnection: close\r\n"), "{}", responses);
+    assert!(!responses.contains("smuggled"), "{}", responses);
+}
+
+#[test]
+fn url() {
+    use crate::request::Scheme;
+    use crate::testing::TestServer;
+
+    let start = |trust_forwarded_proto: bool| TestServer::start_with(
+        move |server| server.settings.web_settings.trust_forwarded_proto = trust_forwarded_proto,
+        |request| {
+            let request = request?;
+            let text = format!("{} {:?} {}", request.is_tls(), request.scheme(), request.url().unwrap_or_default());
+            request.response(200).text(&text).send();
+            Ok(())
+        }
+    ).unwrap();
+
+    let server = start(false);
+    server.client().get("/path?a=1").header("Host", "example.com:8080").send().unwrap().assert_text("false Http http://example.com:8080/path?a=1");
+    server.client().get("/").header("Host", "example.com").header("X-Forwarded-Proto", "https").send().unwrap().assert_text("false Http http://example.com/");
+    server.client().get("/").header("Host", "example.com:port").send().unwrap().assert_text("false Http ");
+    server.client().get("/").http_1_0().send().unwrap().assert_text("false Http ");
+    server.stop();
+
+    let server = start(true);
+    server.client().get("/").header("Host", "example.com").header("X-Forwarded-Proto", "https, http").send().unwrap().assert_text("false Https https://example.com/");
+    server.client().get("/").header("Host", "example.com").header("Forwarded", "for=192.0.2.60;proto=\"https\", proto=http").send().unwrap().assert_text("false Https https://example.com/");
+    server.client().get("/").header("Host", "example.com").header("X-Forwarded-Proto", "ftp").send().unwrap().assert_text("false Http http://example.com/");
+    server.stop();
+
+    assert_eq!(Scheme::Https.as_str(), "https");
+}
+
+#[test]
+fn deep_pipelining() {
+    use crate::testing::TestServer;
+
+    const REQUESTS: usize = 5000;
+    let server = TestServer::start_with(
+        |server| {
+            let settings = &mut server.settings.web_settings;
+            settings.parse_http_request_settings.pipelining_requests_limit = u16::MAX;
+            settings.read_buffer_size = 1_000_000;
+            settings.read_buffer_limit = 1_000_000;
+            settings.read_bytes_per_event_limit = 1_000_000;
+        },
+        |request| {
+            let request = request?;
+            let path = request.path().to_string();
+            request.response(200).text(&path).send();
+            Ok(())
+        }
+    ).unwrap();
+
+    // all requests in one read are processed in a loop, not recursively
+    let mut raw_request = Vec::new();
+    for i in 0..REQUESTS {
+        raw_request.extend_from_slice(format!("GET /{} HTTP/1.1\r\n\r\n", i).as_bytes());
+    }
+    raw_request.extend_from_slice(b"GET /last HTTP/1.1\r\nConnection: close\r\n\r\n");
+    let response = server.client().send_raw(&raw_request).unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert_eq!(raw.matches("HTTP/1.1 200 OK").count(), REQUESTS + 1);
+    assert!(raw.contains(&format!("\r\n\r\n/{}HTTP", REQUESTS - 1)) && raw.ends_with("/last"), "{}", &raw[raw.len() - 200..]);
+    server.stop();
+
+    // unprocessed surplus over limit closes the connection
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.pending_bytes_limit = 50,
+        |request| {
+            let request = request?;
+            let path = request.path().to_string();
+            request.response(200).text(&path).send();
+            Ok(())
+        }
+    ).unwrap();
+    let response = server.client().send_raw(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n\r\nGET /d HTTP/1.1\r\n\r\n").unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert!(raw.ends_with("/a") && !raw.contains("/b"), "{}", raw);
+}
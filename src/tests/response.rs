@@ -1,34 +1,62 @@
 use crate::request::{RequestData, HttpVersion, ConnectionType};
-use crate::response::{HTTP_CODES_WITH_NAME_BY_CODE, http_status_code_with_name, need_close_by_request};
+use crate::response::{HTTP_CODES_WITH_NAME_BY_CODE, StatusCodeError, framing_allows_keep_alive, http_status_code_with_name, need_close_by_request, register_status_code};
+use crate::tests::request::{test_request, test_request_with_server};
+use crate::http_error::HttpError;
+use crate::testing::TestServer;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
 
 #[test]
 fn close_by_request() {
     let mut request = RequestData::new();
     request.version = HttpVersion::Http1_0;
     request.connection_type = Some(ConnectionType::Close);
-    assert_eq!(need_close_by_request(&request), true);
+    assert!(need_close_by_request(&request));
 
     request.version = HttpVersion::Http1_0;
     request.connection_type = Some(ConnectionType::KeepAlive);
-    assert_eq!(need_close_by_request(&request), false);
+    assert!(!need_close_by_request(&request));
 
     // by default in HTTP/1.0 connection close
     request.version = HttpVersion::Http1_0;
     request.connection_type = None;
-    assert_eq!(need_close_by_request(&request), true);
+    assert!(need_close_by_request(&request));
 
     request.version = HttpVersion::Http1_1;
     request.connection_type = Some(ConnectionType::Close);
-    assert_eq!(need_close_by_request(&request), true);
+    assert!(need_close_by_request(&request));
 
     request.version = HttpVersion::Http1_1;
     request.connection_type = Some(ConnectionType::KeepAlive);
-    assert_eq!(need_close_by_request(&request), false);
+    assert!(!need_close_by_request(&request));
 
     // by default in HTTP/1.1 connection keep-alive
     request.version = HttpVersion::Http1_1;
     request.connection_type = None;
-    assert_eq!(need_close_by_request(&request), false);
+    assert!(!need_close_by_request(&request));
+}
+
+#[test]
+fn keep_alive_framing() {
+    assert!(framing_allows_keep_alive(&HttpVersion::Http1_0, ""));
+    assert!(framing_allows_keep_alive(&HttpVersion::Http1_0, "X-Some: 1\r\n"));
+    assert!(framing_allows_keep_alive(&HttpVersion::Http1_1, "transfer-encoding: gzip, chunked\r\n"));
+    assert!(!framing_allows_keep_alive(&HttpVersion::Http1_1, "Transfer-Encoding: gzip\r\n"));
+    assert!(!framing_allows_keep_alive(&HttpVersion::Http1_0, "Transfer-Encoding: chunked\r\n"));
+
+    test_request(
+        b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        |request| {
+            request.response(200).keep_alive().headers("Transfer-Encoding: chunked\r\n").text("").send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.contains("\r\nConnection: close\r\n"), "{}", response);
+        }
+    );
 }
 
 #[test]
@@ -36,4 +64,524 @@ fn http_code_name_test() {
     for t in HTTP_CODES_WITH_NAME_BY_CODE {
         assert_eq!(http_status_code_with_name(t.0), t.1);
     }
+
+    assert_eq!(http_status_code_with_name(299), "299 Success");
+    assert_eq!(http_status_code_with_name(1000), "500 Internal Server Error");
+    assert_eq!(register_status_code(1000, "Too Big"), Err(StatusCodeError::WrongCode));
+    assert_eq!(register_status_code(599, "Timeout\r\nSet-Cookie: a=b"), Err(StatusCodeError::WrongReason));
+    assert_eq!(register_status_code(599, "Network Connect Timeout Error"), Ok(()));
+    assert_eq!(http_status_code_with_name(599), "599 Network Connect Timeout Error");
+    assert_eq!(register_status_code(598, "First"), Ok(()));
+    assert_eq!(register_status_code(598, "Second"), Ok(()));
+    assert_eq!(http_status_code_with_name(598), "598 Second");
+
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            request.response(599).close().text("timeout").send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.1 599 Network Connect Timeout Error\r\n"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn default_response_headers() {
+    let default_headers = Arc::new(vec![
+        ("Server".to_string(), "anweb".to_string()),
+        ("X-Frame-Options".to_string(), "DENY".to_string()),
+    ]);
+
+    test_request_with_server(
+        move |server| server.settings.web_settings.default_response_headers = default_headers,
+        b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+        |request| {
+            request.response(200).headers("x-frame-options: SAMEORIGIN\r\n").text("ok").send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.contains("\r\nServer: anweb\r\n"), "{}", response);
+            assert!(response.contains("\r\nx-frame-options: SAMEORIGIN\r\n"), "{}", response);
+            assert!(!response.contains("DENY"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn early_hints_and_chunked_with_trailers() {
+    test_request(
+        b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+        |request| {
+            request.early_hints(&["</style.css>; rel=preload; as=style"]);
+            let chunked = request.response(200).headers("Trailer: X-Checksum\r\n").text("").chunked();
+            chunked.send(b"hello");
+            chunked.send(b"");
+            chunked.send(b", world!");
+            chunked.end_with_trailers("X-Checksum: 42\r\n");
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload; as=style\r\n\r\nHTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.contains("\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n"), "{}", response);
+            assert!(!response.contains("Content-Length"), "{}", response);
+            assert!(response.ends_with("\r\n\r\n5\r\nhello\r\n8\r\n, world!\r\n0\r\nX-Checksum: 42\r\n\r\n"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn chunked_http_1_0() {
+    test_request(
+        b"GET / HTTP/1.0\r\n\r\n",
+        |request| {
+            request.early_hints(&["</style.css>; rel=preload"]);
+            let chunked = request.response(200).chunked();
+            chunked.send(b"hello");
+            chunked.send(b", world!");
+            chunked.end_with_trailers("X-Checksum: 42\r\n");
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+            assert!(response.contains("\r\nConnection: close\r\n"), "{}", response);
+            assert!(!response.contains("Transfer-Encoding"), "{}", response);
+            assert!(response.ends_with("\r\n\r\nhello, world!"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn framing_headers_of_handler() {
+    // HTTP/1.0 client reads content of unknown length until the close, "Connection" and "Transfer-Encoding" of handler are dropped
+    test_request(
+        b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        |request| {
+            let chunked = request.response(200).headers("Connection: keep-alive\r\nTransfer-Encoding: chunked\r\nX-A: 1\r\n").chunked();
+            chunked.send(b"hello");
+            chunked.end();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert_eq!(response.matches("Connection:").count(), 1, "{}", response);
+            assert!(response.contains("\r\nConnection: close\r\n"), "{}", response);
+            assert!(!response.contains("Transfer-Encoding"), "{}", response);
+            assert!(response.contains("\r\nX-A: 1\r\n"), "{}", response);
+            assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+        }
+    );
+
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            let chunked = request.response(200).close().headers("Transfer-Encoding: chunked\r\n").chunked();
+            chunked.send(b"hello");
+            chunked.end();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert_eq!(response.matches("Transfer-Encoding").count(), 1, "{}", response);
+            assert!(response.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"), "{}", response);
+        }
+    );
+
+    // "Transfer-Encoding" of handler makes "Content-Length" ignored by HTTP/1.0 client
+    test_request(
+        b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        |request| {
+            request.response(200).headers("Transfer-Encoding: identity\r\nConnection: keep-alive\r\n").text("hello").send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert_eq!(response.matches("Connection:").count(), 1, "{}", response);
+            assert!(response.contains("\r\nConnection: close\r\n"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn write_completion_and_error() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Arc::new(std::sync::Mutex::new(sender));
+    let server = TestServer::start(move |request| {
+        let sender = sender.clone();
+        match request {
+            Ok(request) => {
+                let content = vec![b'x'; 16_000_000];
+                request.response(200).close().content("", &content).try_send(move |result| {
+                    if let Ok(sender) = sender.lock() {
+                        let _ = sender.send(format!("sent {:?}", result.map_err(|err| err.kind())));
+                    }
+                });
+            }
+            Err(err) => {
+                if let Ok(sender) = sender.lock() {
+                    // reset connection can be detected by reading as well as by writing
+                    let _ = sender.send(format!("{}", matches!(err, HttpError::WriteError(_) | HttpError::ReadError(_))));
+                }
+            }
+        }
+        Ok(())
+    });
+    assert!(server.is_ok());
+    if let Ok(server) = server {
+        // completion callback is called when all queued data is written
+        let response = server.client().get("/").send();
+        assert!(response.is_ok());
+        if let Ok(response) = response {
+            assert_eq!(response.content().len(), 16_000_000);
+        }
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).ok(), Some("sent Ok(())".to_string()));
+
+        // client doesn't read response and closes connection
+        let tcp_stream = TcpStream::connect(server.addr());
+        assert!(tcp_stream.is_ok());
+        if let Ok(mut tcp_stream) = tcp_stream {
+            assert!(tcp_stream.write_all(b"GET / HTTP/1.1\r\n\r\n").is_ok());
+            sleep(Duration::from_millis(100));
+        }
+
+        let mut results = Vec::new();
+        while let Ok(result) = receiver.recv_timeout(Duration::from_secs(1)) {
+            results.push(result);
+        }
+        assert!(results.iter().any(|result| result.starts_with("sent Err(")), "{:?}", results);
+        assert!(results.contains(&"true".to_string()), "{:?}", results);
+
+        server.stop();
+    }
+}
+
+#[test]
+fn transforms() {
+    use crate::transform::{GzipTransform, ResponseTransform};
+    use std::io::Read;
+
+    struct Upper;
+    impl ResponseTransform for Upper {
+        fn headers(&self) -> String {
+            "X-Upper: 1\r\n".to_string()
+        }
+
+        fn push(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+            Ok(data.to_ascii_uppercase())
+        }
+
+        fn finish(&mut self) -> std::io::Result<Vec<u8>> {
+            Ok(b"!".to_vec())
+        }
+    }
+
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.response_transforms = Arc::new(vec![GzipTransform::factory(&["text/"])]),
+        |request| {
+            let request = request?;
+            if request.path() == "/chunked" {
+                let chunked = request.response(200).transform(Box::new(Upper)).chunked();
+                chunked.send(b"abc");
+                chunked.send(b"def");
+                chunked.end();
+            } else if request.path() == "/encoded" {
+                request.response(200).headers("Content-Encoding: identity\r\n").text("hello hello hello").send();
+            } else {
+                request.response(200).text("hello hello hello").send();
+            }
+            Ok(())
+        },
+    ).unwrap();
+
+    // compressed by global transform for client accepting gzip
+    let response = server.client().get("/text").header("Accept-Encoding", "gzip, deflate").send().unwrap();
+    response.assert_header("Content-Encoding", "gzip").assert_header("Vary", "Accept-Encoding");
+    assert_eq!(response.header("Content-Length").unwrap(), response.content().len().to_string());
+    let mut text = String::new();
+    flate2::read::GzDecoder::new(response.content()).read_to_string(&mut text).unwrap();
+    assert_eq!(text, "hello hello hello");
+
+    for accept_encoding in ["deflate", "gzip;q=0, deflate"] {
+        let response = server.client().get("/text").header("Accept-Encoding", accept_encoding).send().unwrap();
+        response.assert_text("hello hello hello");
+        assert!(response.header("Content-Encoding").is_none());
+    }
+
+    // already encoded by the handler
+    let response = server.client().get("/encoded").header("Accept-Encoding", "gzip").send().unwrap();
+    response.assert_text("hello hello hello").assert_header("Content-Encoding", "identity");
+
+    // transform of one response, the rest is sent before the last chunk
+    let response = server.client().get("/chunked").header("Accept-Encoding", "gzip").send().unwrap();
+    response.assert_header("X-Upper", "1");
+    assert!(response.header("Content-Encoding").is_none());
+    assert_eq!(response.content(), b"3\r\nABC\r\n3\r\nDEF\r\n1\r\n!\r\n0\r\n\r\n");
+}
+
+#[test]
+fn header_injection() {
+    use crate::response::{check_header_lines, check_header_value, HeaderError};
+
+    assert_eq!(check_header_lines(""), Ok(()));
+    assert_eq!(check_header_lines("X-A: 1\r\nX-B: two\twords\r\n"), Ok(()));
+    assert_eq!(check_header_lines("X-A: 1"), Err(HeaderError::WrongLineEnd));
+    assert_eq!(check_header_lines("X-A: 1\r\n\r\n<html>\r\n"), Err(HeaderError::WrongLineEnd));
+    assert_eq!(check_header_lines("X-A: 1\nSet-Cookie: a=b\r\n"), Err(HeaderError::WrongLineEnd));
+    assert_eq!(check_header_lines("X A: 1\r\n"), Err(HeaderError::WrongName));
+    assert_eq!(check_header_lines("X-A: \x001\r\n"), Err(HeaderError::ControlCharacter));
+    assert_eq!(check_header_lines("content-length: 0\r\n"), Err(HeaderError::Denied("content-length".to_string())));
+    assert_eq!(check_header_value("/path\r\nSet-Cookie: session=stolen"), Err(HeaderError::WrongLineEnd));
+
+    // user input in location, the client gets error instead of injected header
+    test_request(
+        b"GET /?next=%0D%0ASet-Cookie:%20a=b HTTP/1.1\r\n\r\n",
+        |request| {
+            let next = request.query_value("next").unwrap_or("").to_string();
+            request.response(302).location(&next).send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{}", response);
+            assert!(!response.contains("Set-Cookie"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn typed_headers() {
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            request.response(503).close().retry_after(Duration::from_millis(120_500)).text("busy").send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.contains("\r\nRetry-After: 121\r\n"), "{}", response);
+        }
+    );
+
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            request.response(503).close().retry_after(Duration::from_millis(500)).send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.contains("\r\nRetry-After: 1\r\n"), "{}", response);
+        }
+    );
+
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            let date = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+            request.response(429).close().retry_after(date).send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.contains("\r\nRetry-After: Wed, 21 Oct 2015 07:28:00 GMT\r\n"), "{}", response);
+        }
+    );
+
+    test_request(
+        b"DELETE / HTTP/1.1\r\n\r\n",
+        |request| {
+            request.response(405)
+                .close()
+                .allow(&["GET", "HEAD"])
+                .content_location("/index.html")
+                .www_authenticate("Bearer", &[("realm", "api \"v1\""), ("error", "invalid_token")])
+                .send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.contains("\r\nAllow: GET, HEAD\r\n"), "{}", response);
+            assert!(response.contains("\r\nContent-Location: /index.html\r\n"), "{}", response);
+            assert!(response.contains("\r\nWWW-Authenticate: Bearer realm=\"api \\\"v1\\\"\", error=\"invalid_token\"\r\n"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn cache_control() {
+    use crate::response::CacheControl;
+    use chrono::{TimeZone, Utc};
+
+    let value = CacheControl::new().private().public().max_age(Duration::from_secs(300)).stale_if_error(Duration::from_millis(60_900));
+    assert_eq!(value.to_string(), "public, max-age=300, stale-if-error=60");
+    assert_eq!(CacheControl::new().to_string(), "");
+
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            let expires = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+            request.response(200)
+                .close()
+                .cache_control(&CacheControl::new().no_cache().must_revalidate().immutable().s_maxage(Duration::from_secs(10)))
+                .expires(expires)
+                .text("cached")
+                .send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.contains("\r\nCache-Control: no-cache, must-revalidate, immutable, s-maxage=10\r\n"), "{}", response);
+            assert!(response.contains("\r\nExpires: Wed, 21 Oct 2015 07:28:00 GMT\r\n"), "{}", response);
+        }
+    );
+
+    test_request(
+        b"GET / HTTP/1.1\r\n\r\n",
+        |request| {
+            request.response(200).close().no_store().text("secret").send();
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.contains("\r\nCache-Control: no-store\r\n"), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn send_to_closed_connection() {
+    use crate::tcp_session::SendStatus;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+
+    let (sender, receiver) = mpsc::channel();
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        let sender = sender.clone();
+        // client disconnects before the response is ready
+        std::thread::spawn(move || {
+            while !request.tcp_session().is_closed() {
+                sleep(Duration::from_millis(10));
+            }
+
+            let tcp_session = request.tcp_session().clone();
+            let failed = Arc::new(AtomicBool::new(false));
+            let failed_in_callback = failed.clone();
+            let status = request.response(200).text("late").try_send(move |res| failed_in_callback.store(res.is_err(), Ordering::SeqCst));
+            let _ = sender.send((status, failed.load(Ordering::SeqCst), tcp_session.send(b"raw")));
+        });
+        Ok(())
+    }).unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    sleep(Duration::from_millis(50));
+    drop(stream);
+
+    let (status, failed, raw_status) = receiver.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert_eq!(status, SendStatus::Closed);
+    assert!(failed);
+    assert_eq!(raw_status, SendStatus::Closed);
+}
+
+#[test]
+fn prepared_response() {
+    use crate::response::{HeaderError, PreparedResponse};
+
+    // same validation of header lines as for `Response`
+    assert_eq!(PreparedResponse::new(200, "X-A: 1\r\n\r\n<html>\r\n", b"").err(), Some(HeaderError::WrongLineEnd));
+    assert_eq!(PreparedResponse::new(200, "Content-Length: 0\r\n", b"").err(), Some(HeaderError::Denied("Content-Length".to_string())));
+
+    let json = format!("{{\"status\": \"ok\", \"padding\": \"{}\"}}", "x".repeat(200));
+    let prepared = PreparedResponse::new(200, "Content-Type: application/json\r\nX-Frame-Options: SAMEORIGIN\r\n", json.as_bytes()).unwrap().gzip();
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.default_response_headers = Arc::new(vec![
+            ("X-Frame-Options".to_string(), "DENY".to_string()),
+            ("X-Default".to_string(), "1".to_string()),
+        ]),
+        move |request| {
+            request?.send_prepared(&prepared);
+            Ok(())
+        },
+    ).unwrap();
+
+    let client = server.client();
+    for _ in 0..2 {
+        let response = client.get("/").send().unwrap();
+        response.assert_code(200).assert_header("Content-Type", "application/json").assert_header("X-Default", "1")
+            .assert_header("X-Frame-Options", "SAMEORIGIN").assert_text(&json);
+        assert!(response.header("Date").is_some());
+        assert!(response.header("Content-Encoding").is_none());
+    }
+
+    let response = client.get("/").header("Accept-Encoding", "gzip, deflate").send().unwrap();
+    response.assert_code(200).assert_header("Content-Encoding", "gzip").assert_header("Vary", "Accept-Encoding");
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(response.content()), &mut decoded).unwrap();
+    assert_eq!(decoded, json);
+
+    let response = client.get("/").header("Accept-Encoding", "gzip;q=0, deflate").send().unwrap();
+    assert!(response.header("Content-Encoding").is_none());
+
+    let response = client.get("/").http_1_0().send().unwrap();
+    assert!(response.raw().starts_with(b"HTTP/1.0 200 OK\r\n"));
+    response.assert_text(&json);
+}
+
+#[test]
+fn shared_content() {
+    let blob = Arc::new((0..200_000).map(|i| (i % 251) as u8).collect::<Vec<u8>>());
+    let shared = blob.clone();
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        let close = request.path() == "/close";
+        let mut response = request.response(200);
+        response.content_arc("Content-Type: application/octet-stream\r\n", shared.clone());
+        if close {
+            response.close();
+        }
+        response.send();
+        Ok(())
+    }).unwrap();
+
+    let client = server.client();
+    for path in ["/", "/", "/close"] {
+        let response = client.get(path).send().unwrap();
+        response.assert_code(200).assert_header("Content-Length", "200000");
+        assert!(response.content() == &blob[..]);
+    }
+}
+
+#[test]
+fn precompressed() {
+    use crate::transform::GzipTransform;
+
+    let gzip = Arc::new(deflate::deflate_bytes_gzip(b"hello hello hello"));
+    let content = gzip.clone();
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.response_transforms = Arc::new(vec![GzipTransform::factory(&["text/"])]),
+        move |request| {
+            let request = request?;
+            let encoding = if request.path() == "/wrong" { "br" } else { "gzip" };
+            request.response(200).precompressed("Content-Type: text/plain; charset=utf-8\r\n", encoding, &content).send();
+            Ok(())
+        },
+    ).unwrap();
+
+    // sent as is without second compression
+    let client = server.client();
+    let response = client.get("/").header("Accept-Encoding", "deflate, gzip").send().unwrap();
+    response.assert_header("Content-Encoding", "gzip").assert_header("Vary", "Accept-Encoding");
+    assert_eq!(response.content(), &gzip[..]);
+
+    let response = client.get("/").header("Accept-Encoding", "*").send().unwrap();
+    response.assert_header("Content-Encoding", "gzip");
+
+    // decompressed for client that doesn't accept gzip
+    for accept_encoding in [None, Some("gzip;q=0, deflate"), Some("gzip;q=0, *"), Some("*, GZIP; Q=0")] {
+        let mut request = client.get("/");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header("Accept-Encoding", accept_encoding);
+        }
+        let response = request.send().unwrap();
+        response.assert_text("hello hello hello").assert_header("Vary", "Accept-Encoding");
+        assert!(response.header("Content-Encoding").is_none());
+    }
+
+    let response = client.get("/wrong").send().unwrap();
+    response.assert_code(500);
 }
@@ -0,0 +1,235 @@
+use crate::extract::{Form, FromRequest, Params, Path, Query, Rejection};
+use crate::guard;
+use crate::guard::Guard;
+use crate::request::Request;
+use crate::router::{Pattern, Route, Router};
+use crate::tests::request::test_request;
+use std::collections::HashMap;
+
+#[test]
+fn pattern() {
+    let pattern = Pattern::parse("/users/:id/posts/:post");
+    let params = pattern.match_path("/users/12/posts/abc");
+    assert!(params.is_some());
+    if let Some(params) = params {
+        assert_eq!(params.value("id"), Some("12"));
+        assert_eq!(params.value("post"), Some("abc"));
+        assert_eq!(params.get::<u64>("id").ok(), Some(12));
+        assert!(params.get::<u64>("post").is_err());
+    }
+
+    assert!(pattern.match_path("/users/12/posts").is_none());
+    assert!(pattern.match_path("/users/12/posts/abc/def").is_none());
+    assert!(pattern.match_path("/users//12/posts/abc/").is_some());
+
+    let pattern = Pattern::parse("/");
+    assert!(pattern.match_path("/").is_some());
+    assert!(pattern.match_path("").is_some());
+    assert!(pattern.match_path("/a").is_none());
+
+    let pattern = Pattern::parse("/static/*path");
+    assert_eq!(pattern.match_path("/static/css/main.css").and_then(|params| params.value("path").map(|path| path.to_string())), Some("css/main.css".to_string()));
+    assert_eq!(pattern.match_path("/static").and_then(|params| params.value("path").map(|path| path.to_string())), Some("".to_string()));
+    assert!(pattern.match_path("/other/css").is_none());
+}
+
+/// Extractor that requires "Authorization" header.
+struct Token(String);
+
+impl FromRequest for Token {
+    fn from_request(request: &Request, _params: &Params, _content: &[u8]) -> Result<Self, Rejection> {
+        request.header_value("Authorization")
+            .map(|value| Token(value.to_string()))
+            .ok_or(Rejection { code: 401, message: "No token".to_string() })
+    }
+}
+
+fn router() -> Router {
+    Router::new()
+        .get("/users/:id", |request, (Path(id), Query(query)): (Path<u64>, Query<HashMap<String, String>>)| {
+            let body = format!("user {} {:?}", id, query.get("sort"));
+            request.response(200).text(&body).send();
+            Ok(())
+        })
+        .post("/form", |request, Form(form): Form<Vec<(String, String)>>| {
+            let body = format!("{:?}", form);
+            request.response(200).text(&body).send();
+            Ok(())
+        })
+        .route(Route::new("/private").guard(guard::header("Authorization", |value| value.starts_with("Bearer ")).and(guard::method("GET"))), |request, Token(token)| {
+            request.response(200).text(&token).send();
+            Ok(())
+        })
+        .get("/files/:name", |request, Path(name): Path<String>| {
+            request.response(200).text(&name).send();
+            Ok(())
+        })
+        .get("/private", |request, ()| {
+            request.response(403).text("forbidden").send();
+            Ok(())
+        })
+}
+
+fn test_router_request(raw_request: &'static [u8], expected_start: &'static str, expected_end: &'static str) {
+    let router = router();
+    test_request(
+        raw_request,
+        move |request| {
+            assert!(router.dispatch(request).is_ok());
+        },
+        move |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with(expected_start), "{}", response);
+            assert!(response.ends_with(expected_end), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn path_and_query() {
+    test_router_request(b"GET /users/42?sort=name HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 200 OK", "\r\n\r\nuser 42 Some(\"name\")");
+}
+
+#[test]
+fn malformed_path_param() {
+    test_router_request(b"GET /users/abc HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 400 Bad Request", "\r\n\r\nWrong path parameter \"id\": invalid digit found in string");
+}
+
+#[test]
+fn encoded_slash_in_path_param() {
+    test_router_request(b"GET /files/a%2Fb+c HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 200 OK", "\r\n\r\na/b+c");
+}
+
+#[test]
+fn form() {
+    test_router_request(b"POST /form HTTP/1.1\r\nConnection: close\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 11\r\n\r\na=1&b=x+%41", "HTTP/1.1 200 OK", "\r\n\r\n[(\"a\", \"1\"), (\"b\", \"x A\")]");
+    test_router_request(b"POST /form HTTP/1.1\r\nConnection: close\r\nContent-Length: 3\r\n\r\na=1", "HTTP/1.1 415 Unsupported Media Type", "\r\n\r\nExpected application/x-www-form-urlencoded content");
+}
+
+#[test]
+fn guards_and_methods() {
+    test_router_request(b"GET /private HTTP/1.1\r\nConnection: close\r\nAuthorization: Bearer 123\r\n\r\n", "HTTP/1.1 200 OK", "\r\n\r\nBearer 123");
+    test_router_request(b"GET /private HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 403 Forbidden", "\r\n\r\nforbidden");
+    test_router_request(b"DELETE /users/1 HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 405 Method Not Allowed", "\r\n\r\n405 Method Not Allowed");
+    test_router_request(b"GET /unknown HTTP/1.1\r\nConnection: close\r\n\r\n", "HTTP/1.1 404 Not Found", "\r\n\r\n404 Not Found");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json() {
+    use crate::extract::Json;
+
+    let router = Router::new()
+        .post("/json", |request, Json(value): Json<serde_json::Value>| {
+            let body = format!("{}", value["name"]);
+            request.response(200).text(&body).send();
+            Ok(())
+        });
+
+    test_request(
+        b"POST /json HTTP/1.1\r\nConnection: close\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: 15\r\n\r\n{\"name\": \"abc\"}",
+        move |request| {
+            assert!(router.dispatch(request).is_ok());
+        },
+        |response| {
+            let response = std::str::from_utf8(response).unwrap_or("");
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+            assert!(response.ends_with("\r\n\r\n\"abc\""), "{}", response);
+        }
+    );
+}
+
+#[test]
+fn forward() {
+    use crate::testing::TestServer;
+
+    let protected = Router::new()
+        .post("/internal/upload", |request, Form(form): Form<Vec<(String, String)>>| {
+            let body = format!("{:?} {:?} {:?} {:?}", request.original_target(), request.path(), request.query_value("user"), form);
+            request.response(200).text(&body).send();
+            Ok(())
+        });
+
+    let gate = Router::new()
+        .post("/gate", move |request, ()| {
+            if request.header_value("Authorization") != Some("Bearer 123") {
+                request.response(401).text("unauthorized").send();
+                return Ok(());
+            }
+            protected.forward(request, "/internal/upload?user=123")
+        })
+        .get("/loop", |mut request, ()| {
+            loop {
+                request = request.forward_to("/loop")?;
+            }
+        })
+        .get("/bad", |request, ()| {
+            request.forward_to("no-slash")?;
+            Ok(())
+        });
+
+    let server = TestServer::start(move |request| gate.dispatch(request?)).unwrap();
+
+    server.client().post("/gate?x=1").header("Authorization", "Bearer 123").header("Content-Type", "application/x-www-form-urlencoded").body("a=1").send().unwrap()
+        .assert_code(200)
+        .assert_text("Some(\"/gate?x=1\") \"/internal/upload\" Some(\"123\") [(\"a\", \"1\")]");
+    server.client().post("/gate").body("a=1").send().unwrap().assert_code(401);
+    server.client().get("/loop").send().unwrap().assert_code(508);
+    server.client().get("/bad").send().unwrap().assert_code(500);
+}
+
+#[test]
+fn expect_continue() {
+    use crate::extract::Content;
+    use crate::handler_error::HandlerError;
+    use crate::testing::TestServer;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    let router = Router::new()
+        .route(
+            Route::new("/upload").method("PUT").expect_continue(|request| {
+                if request.header_value("Authorization").is_none() {
+                    return Err(HandlerError::new(401, "401 Unauthorized"));
+                }
+                Ok(())
+            }),
+            |request, Content(content): Content| {
+                request.response(200).text(&format!("got {}", content.len())).send();
+                Ok(())
+            },
+        );
+    let server = TestServer::start(move |request| router.dispatch(request?)).unwrap();
+
+    let read_response = |stream: &mut TcpStream, end: &str| {
+        let mut response = Vec::new();
+        while !String::from_utf8_lossy(&response).ends_with(end) {
+            let mut buf = [0; 256];
+            let cnt = stream.read(&mut buf).unwrap();
+            assert!(cnt > 0, "{}", String::from_utf8_lossy(&response));
+            response.extend_from_slice(&buf[..cnt]);
+        }
+        String::from_utf8(response).unwrap()
+    };
+
+    // interim response before content, then final response
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    stream.write_all(b"PUT /upload HTTP/1.1\r\nAuthorization: Bearer 1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut stream, "\r\n\r\n"), "HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(b"hello").unwrap();
+    let response = read_response(&mut stream, "got 5");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    // rejected by policy without content
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    stream.write_all(b"PUT /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 1000000\r\n\r\n").unwrap();
+    let response = read_response(&mut stream, "401 Unauthorized");
+    assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", response);
+
+    // unsupported expectation
+    let response = server.client().request("PUT", "/upload").header("Expect", "something").body("x").send().unwrap();
+    response.assert_code(417);
+}
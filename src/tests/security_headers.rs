@@ -0,0 +1,46 @@
+use crate::security_headers::{ContentSecurityPolicy, FrameOptions, PermissionsPolicy, ReferrerPolicy, SecurityHeaders, SecurityHeadersError, StrictTransportSecurity};
+use std::time::Duration;
+
+#[test]
+fn security_headers() {
+    let headers = SecurityHeaders::new()
+        .content_security_policy(ContentSecurityPolicy::new()
+            .directive("default-src", &["'self'"])
+            .directive("img-src", &["'self'", "data:", "https://cdn.example.com"])
+            .directive("upgrade-insecure-requests", &[]))
+        .strict_transport_security(StrictTransportSecurity::new(Duration::from_secs(31_536_000)).include_subdomains().preload())
+        .referrer_policy(ReferrerPolicy::StrictOriginWhenCrossOrigin)
+        .permissions_policy(PermissionsPolicy::new().feature("camera", &[]).feature("geolocation", &["self", "https://maps.example.com"]))
+        .frame_options(FrameOptions::SameOrigin)
+        .content_type_nosniff()
+        .header_lines();
+
+    assert_eq!(headers, Ok(
+        "Content-Security-Policy: default-src 'self'; img-src 'self' data: https://cdn.example.com; upgrade-insecure-requests\r\n\
+         Strict-Transport-Security: max-age=31536000; includeSubDomains; preload\r\n\
+         Referrer-Policy: strict-origin-when-cross-origin\r\n\
+         Permissions-Policy: camera=(), geolocation=(self \"https://maps.example.com\")\r\n\
+         X-Frame-Options: SAMEORIGIN\r\n\
+         X-Content-Type-Options: nosniff\r\n".to_string()
+    ));
+
+    assert!(SecurityHeaders::strict().headers().is_ok());
+}
+
+#[test]
+fn validation() {
+    let csp = |policy: ContentSecurityPolicy| SecurityHeaders::new().content_security_policy(policy).headers();
+
+    assert_eq!(csp(ContentSecurityPolicy::new()), Err(SecurityHeadersError::EmptyPolicy));
+    assert_eq!(csp(ContentSecurityPolicy::new().directive("Script_Src", &[])), Err(SecurityHeadersError::WrongDirectiveName("Script_Src".to_string())));
+    assert_eq!(csp(ContentSecurityPolicy::new().directive("script-src", &["self"]).directive("script-src", &[])), Err(SecurityHeadersError::DuplicateDirective("script-src".to_string())));
+    assert!(csp(ContentSecurityPolicy::new().directive("script-src", &["'self'; img-src *"])).is_err());
+    assert!(csp(ContentSecurityPolicy::new().directive("script-src", &["'unknown'"])).is_err());
+    assert!(csp(ContentSecurityPolicy::new().directive("script-src", &["'nonce-abc'"])).is_ok());
+
+    let hsts = StrictTransportSecurity::new(Duration::from_secs(60)).include_subdomains().preload();
+    assert_eq!(SecurityHeaders::new().strict_transport_security(hsts).headers(), Err(SecurityHeadersError::WrongPreload));
+
+    let permissions = PermissionsPolicy::new().feature("camera", &["example.com"]);
+    assert!(SecurityHeaders::new().permissions_policy(permissions).headers().is_err());
+}
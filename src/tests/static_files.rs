@@ -0,0 +1,264 @@
+use crate::static_files::{Builder, EtagAlgorithm, StaticMounts};
+use crate::testing::TestServer;
+use std::fs;
+use std::path::PathBuf;
+
+/// Temporary directory with files, removed on drop.
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let dir = std::env::temp_dir().join(format!("anweb-test-{}-{}", name, std::process::id()));
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        TestDir(dir)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn mounts() {
+    let assets = TestDir::new("assets", &[("app.js", "js"), ("css/style.css", "css")]);
+    let site = TestDir::new("site", &[("index.html", "index"), ("assets-list.txt", "list")]);
+
+    let builder = Builder::new().updating_interval(None);
+    let mounts = StaticMounts::new()
+        .mount("/", builder.build(site.path()))
+        .mount("/assets/", Builder::new().updating_interval(None).gzip_encoding(false).build(assets.path()));
+
+    assert!(mounts.resolve("/assets/app.js").is_some_and(|(_, path)| path == "/app.js"));
+    assert!(mounts.resolve("/assets-list.txt").is_some_and(|(_, path)| path == "/assets-list.txt"));
+    assert!(StaticMounts::new().mount("docs", builder.build(site.path())).resolve("/docs2/index.html").is_none());
+
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        if mounts.send_response(request.path(), &request).is_err() {
+            request.response(404).text("Not found").send();
+        }
+        Ok(())
+    }).unwrap();
+    let client = server.client();
+
+    client.get("/assets/app.js").send().unwrap().assert_code(200).assert_text("js");
+    client.get("/assets/css/style.css").send().unwrap().assert_header("Content-Type", "text/css").assert_text("css");
+    client.get("/index.html").send().unwrap().assert_text("index");
+    client.get("/assets-list.txt").send().unwrap().assert_text("list");
+    client.get("/assets/index.html").send().unwrap().assert_code(404);
+}
+
+#[test]
+fn registered_assets() {
+    let dir = TestDir::new("registered", &[("on-disk.txt", "disk"), ("data.bin", "bin")]);
+    let static_files = Builder::new().updating_interval(None).build(dir.path());
+    static_files.add_bytes("/app.js", "application/javascript", &b"console.log(1)"[..], Some("public, max-age=31536000, immutable"));
+    static_files.add_file("/renamed.json", dir.0.join("data.bin"), Some("application/json"), None).unwrap();
+
+    // registered files stay after updating by directory
+    fs::remove_file(dir.0.join("data.bin")).unwrap();
+    static_files.update();
+    assert_eq!(static_files.files(), ["app.js", "on-disk.txt", "renamed.json"]);
+
+    let in_memory = Builder::new().gzip_encoding(false).build_in_memory();
+    in_memory.add_bytes("index.html", "text/html", "<p>embedded</p>", None);
+    in_memory.update();
+
+    let mounts = StaticMounts::new().mount("/", static_files).mount("/embedded", in_memory);
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        mounts.send_response(request.path(), &request)?;
+        Ok(())
+    }).unwrap();
+    let client = server.client();
+
+    let response = client.get("/app.js").send().unwrap();
+    response.assert_code(200)
+        .assert_header("Content-Type", "application/javascript")
+        .assert_header("Cache-Control", "public, max-age=31536000, immutable")
+        .assert_text("console.log(1)");
+    client.get("/app.js").header("If-None-Match", response.header("ETag").unwrap()).send().unwrap()
+        .assert_code(304)
+        .assert_header("Cache-Control", "public, max-age=31536000, immutable");
+    client.get("/renamed.json").send().unwrap().assert_header("Content-Type", "application/json").assert_text("bin");
+    client.get("/embedded/index.html").send().unwrap().assert_text("<p>embedded</p>");
+}
+
+#[test]
+fn spa_fallback() {
+    let app = TestDir::new("spa", &[("index.html", "app"), ("app.js", "js")]);
+    let mounts = StaticMounts::new().mount("/app", Builder::new().updating_interval(None).spa_fallback(true).build(app.path()));
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        if mounts.send_response(request.path(), &request).is_err() {
+            request.response(404).text("Not found").send();
+        }
+        Ok(())
+    }).unwrap();
+    let client = server.client();
+
+    client.get("/app/app.js").send().unwrap().assert_text("js");
+    client.get("/app").send().unwrap().assert_code(200).assert_text("app");
+    client.get("/app/users/42").send().unwrap().assert_code(200).assert_header("Content-Type", "text/html").assert_text("app");
+    client.get("/app/missing.js").send().unwrap().assert_code(404);
+    client.post("/app/users").send().unwrap().assert_code(404);
+    client.get("/other").send().unwrap().assert_code(404);
+}
+
+#[test]
+fn etag_algorithms() {
+    let dir = TestDir::new("etag", &[("a.txt", "a")]);
+    let etag = |algorithm| {
+        let static_files = Builder::new().updating_interval(None).etag_algorithm(algorithm).build(dir.path());
+        let server = TestServer::start(move |request| {
+            let request = request?;
+            static_files.send_response(request.path(), &request)?;
+            Ok(())
+        }).unwrap();
+
+        let etag = server.client().get("/a.txt").send().unwrap().header("ETag").unwrap().to_string();
+        server.client().get("/a.txt").header("If-None-Match", &format!("\"x\", {}", etag)).send().unwrap().assert_code(304);
+        etag
+    };
+
+    assert_eq!(etag(EtagAlgorithm::Md5), "\"0cc175b9c0f1b6a831c399e269772661\"");
+    assert_eq!(etag(EtagAlgorithm::Fnv1a), "\"af63dc4c8601ec8c\"");
+    assert!(etag(EtagAlgorithm::SizeAndModified).starts_with("W/\"1-"));
+}
+
+#[test]
+fn etag_per_encoding() {
+    let dir = TestDir::new("etag-encoding", &[("a.txt", "a")]);
+    let static_files = Builder::new().updating_interval(None).deflate_encoding(false).build(dir.path());
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        static_files.send_response(request.path(), &request)?;
+        Ok(())
+    }).unwrap();
+    let client = server.client();
+
+    let identity = client.get("/a.txt").send().unwrap();
+    identity.assert_header("ETag", "\"0cc175b9c0f1b6a831c399e269772661\"").assert_header("Vary", "Accept-Encoding");
+    let gzip = client.get("/a.txt").header("Accept-Encoding", "gzip").send().unwrap();
+    gzip.assert_header("Content-Encoding", "gzip").assert_header("ETag", "\"0cc175b9c0f1b6a831c399e269772661-gzip\"");
+
+    // ETag of other variant doesn't match
+    client.get("/a.txt").header("Accept-Encoding", "gzip").header("If-None-Match", identity.header("ETag").unwrap()).send().unwrap()
+        .assert_code(200);
+    client.get("/a.txt").header("Accept-Encoding", "gzip").header("If-None-Match", gzip.header("ETag").unwrap()).send().unwrap()
+        .assert_code(304)
+        .assert_header("ETag", gzip.header("ETag").unwrap())
+        .assert_header("Vary", "Accept-Encoding");
+
+    // without compressed variants
+    let dir = TestDir::new("etag-identity", &[("a.txt", "a")]);
+    let static_files = Builder::new().updating_interval(None).deflate_encoding(false).gzip_encoding(false).build(dir.path());
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        static_files.send_response(request.path(), &request)?;
+        Ok(())
+    }).unwrap();
+    let response = server.client().get("/a.txt").header("Accept-Encoding", "gzip").send().unwrap();
+    response.assert_header("ETag", "\"0cc175b9c0f1b6a831c399e269772661\"");
+    assert_eq!(response.header("Vary"), None);
+}
+
+#[test]
+fn snapshot_update() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = TestDir::new("snapshot", &[("a.txt", "a"), ("b.txt", "b")]);
+    let static_files = Builder::new().updating_interval(None).build(dir.path());
+    static_files.add_bytes("embedded.txt", "text/plain", "embedded", None);
+    let mut files = static_files.files();
+    files.sort();
+    assert_eq!(files, ["a.txt", "b.txt", "embedded.txt"]);
+
+    // requests are served from snapshot while other thread updates it
+    let updater = static_files.clone();
+    let updating = std::thread::spawn(move || {
+        for _ in 0..100 {
+            updater.update();
+        }
+    });
+    let server_files = static_files.clone();
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        server_files.send_response(request.path(), &request)?;
+        Ok(())
+    }).unwrap();
+    for _ in 0..20 {
+        server.client().get("/a.txt").send().unwrap().assert_code(200).assert_text("a");
+    }
+    updating.join().unwrap();
+
+    fs::write(dir.0.join("a.txt"), "changed").unwrap();
+    let file = fs::File::options().write(true).open(dir.0.join("a.txt")).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+    fs::remove_file(dir.0.join("b.txt")).unwrap();
+    fs::write(dir.0.join("c.txt"), "c").unwrap();
+    static_files.update();
+
+    let mut files = static_files.files();
+    files.sort();
+    assert_eq!(files, ["a.txt", "c.txt", "embedded.txt"]);
+    server.client().get("/a.txt").send().unwrap().assert_text("changed");
+    server.client().get("/embedded.txt").send().unwrap().assert_text("embedded");
+}
+
+#[test]
+fn ranges() {
+    use crate::static_files::{parse_byte_range, ByteRange};
+
+    assert_eq!(parse_byte_range("bytes=0-3", 10), Some(ByteRange::Satisfiable(0, 3)));
+    assert_eq!(parse_byte_range("bytes=5-", 10), Some(ByteRange::Satisfiable(5, 9)));
+    assert_eq!(parse_byte_range("bytes=-3", 10), Some(ByteRange::Satisfiable(7, 9)));
+    assert_eq!(parse_byte_range("bytes=-30", 10), Some(ByteRange::Satisfiable(0, 9)));
+    assert_eq!(parse_byte_range("bytes=8-100", 10), Some(ByteRange::Satisfiable(8, 9)));
+    assert_eq!(parse_byte_range("bytes=10-", 10), Some(ByteRange::Unsatisfiable));
+    assert_eq!(parse_byte_range("bytes=-0", 10), Some(ByteRange::Unsatisfiable));
+    assert_eq!(parse_byte_range("bytes=0-1,3-4", 10), None);
+    assert_eq!(parse_byte_range("bytes=4-1", 10), None);
+    assert_eq!(parse_byte_range("items=0-1", 10), None);
+
+    let static_files = Builder::new().updating_interval(None).build("");
+    static_files.add_bytes("video.mp4", "video/mp4", "0123456789", None);
+    let server = TestServer::start(move |request| {
+        let request = request?;
+        static_files.send_response(request.path(), &request)?;
+        Ok(())
+    }).unwrap();
+    let client = server.client();
+
+    let full = client.get("/video.mp4").send().unwrap();
+    full.assert_code(200).assert_header("Accept-Ranges", "bytes");
+    let etag = full.header("ETag").unwrap().to_string();
+
+    // identity content is sliced even if the client accepts compression
+    let part = client.get("/video.mp4").header("Range", "bytes=2-4").header("Accept-Encoding", "gzip").send().unwrap();
+    part.assert_code(206).assert_header("Content-Range", "bytes 2-4/10").assert_header("ETag", &etag);
+    assert_eq!(part.content(), b"234");
+    assert_eq!(part.header("Content-Encoding"), None);
+
+    client.get("/video.mp4").header("Range", "bytes=20-").send().unwrap()
+        .assert_code(416)
+        .assert_header("Content-Range", "bytes */10");
+
+    // "If-Range" with current ETag keeps the range, with other one the whole file is sent
+    client.get("/video.mp4").header("Range", "bytes=-2").header("If-Range", &etag).send().unwrap()
+        .assert_code(206);
+    let stale = client.get("/video.mp4").header("Range", "bytes=-2").header("If-Range", "\"stale\"").send().unwrap();
+    stale.assert_code(200);
+    assert_eq!(stale.content(), b"0123456789");
+}
@@ -0,0 +1,275 @@
+use crate::stats::{Stats, WorkerStats};
+use crate::tests::request::test_request_with_server;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn stats() {
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let stats_in_prepare = stats.clone();
+    let stats_in_request = stats.clone();
+
+    test_request_with_server(
+        move |server| {
+            if let Ok(mut stats) = stats_in_prepare.lock() {
+                *stats = server.stats();
+            }
+        },
+        b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+        move |request| {
+            let total = stats_in_request.lock().map(|stats| stats.total()).unwrap_or_default();
+            assert_eq!(total, WorkerStats { active_sessions: 1, websocket_sessions: 0, queued_write_bytes: 0, accepted_total: 1, overloaded: false, requests_total: 1, ..WorkerStats::default() });
+            request.response(200).text("ok").send();
+        },
+        |response| {
+            assert!(response.starts_with(b"HTTP/1.1 200 OK"));
+        }
+    );
+
+    let stats = stats.lock().map(|stats| stats.clone()).unwrap_or_default();
+    assert!(!stats.workers().is_empty());
+    // stopping of test server makes extra connections
+    assert!(stats.total().accepted_total >= 1);
+}
+
+#[test]
+fn load_shedding() {
+    use crate::testing::TestServer;
+    use crate::web_session::{LoadShedding, LoadSheddingMode};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    let start_server = |mode| {
+        TestServer::start_with(
+            move |server| {
+                server.num_threads = 1;
+                server.settings.web_settings.load_shedding = Some(LoadShedding { max_active_sessions: 1, max_queued_write_bytes: 1_000_000, mode });
+            },
+            |request| {
+                request?.response(200).text("ok").send();
+                Ok(())
+            },
+        ).unwrap()
+    };
+
+    // respond 503
+    let server = start_server(LoadSheddingMode::Respond503);
+    let idle = TcpStream::connect(server.addr()).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    server.client().get("/").send().unwrap().assert_code(503);
+
+    drop(idle);
+    std::thread::sleep(Duration::from_millis(100));
+    server.client().get("/").send().unwrap().assert_code(200);
+    drop(server);
+
+    // stop accepting, connection waits in backlog until the load decreases
+    let server = start_server(LoadSheddingMode::StopAccepting);
+    let idle = TcpStream::connect(server.addr()).unwrap();
+    let busy = TcpStream::connect(server.addr()).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    let client = server.client();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(client.get("/").send().map(|response| response.code()));
+    });
+
+    assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+
+    drop(idle);
+    drop(busy);
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap().unwrap(), 200);
+}
+
+#[test]
+fn least_connections_accept_policy() {
+    use crate::server::AcceptPolicy;
+    use crate::testing::TestServer;
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let stats_in_prepare = stats.clone();
+    let server = TestServer::start_with(
+        move |server| {
+            server.num_threads = 4;
+            server.settings.accept_policy = AcceptPolicy::LeastConnections { slack: 0 };
+            if let Ok(mut stats) = stats_in_prepare.lock() {
+                *stats = server.stats();
+            }
+        },
+        |_| Ok(()),
+    ).unwrap();
+
+    let mut connections = Vec::new();
+    for _ in 0..12 {
+        connections.push(TcpStream::connect(server.addr()).unwrap());
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let stats = stats.lock().map(|stats| stats.clone()).unwrap_or_default();
+    let active_sessions: Vec<usize> = stats.workers().iter().map(|worker| worker.active_sessions).collect();
+    assert_eq!(active_sessions.iter().sum::<usize>(), 12);
+    assert!(active_sessions.iter().all(|active_sessions| *active_sessions == 3), "{:?}", active_sessions);
+}
+
+#[test]
+fn timing() {
+    use crate::testing::TestServer;
+    use crate::timing::Timing;
+    use std::time::{Duration, SystemTime};
+
+    let timings: Arc<Mutex<Vec<Timing>>> = Arc::new(Mutex::new(Vec::new()));
+    let timings_in_settings = timings.clone();
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let stats_in_prepare = stats.clone();
+    let server = TestServer::start_with(
+        move |server| {
+            if let Ok(mut stats) = stats_in_prepare.lock() {
+                *stats = server.stats();
+            }
+            server.settings.web_settings.on_timing = Some(Arc::new(move |timing: &Timing| {
+                if let Ok(mut timings) = timings_in_settings.lock() {
+                    timings.push(timing.clone());
+                }
+            }));
+        },
+        |request| {
+            let request = request?;
+            assert!(request.received_at() <= SystemTime::now());
+            std::thread::sleep(Duration::from_millis(20));
+            request.response(200).text("ok").send();
+            Ok(())
+        },
+    ).unwrap();
+
+    let before = SystemTime::now();
+    server.client().get("/timed").send().unwrap().assert_code(200);
+
+    // the callback is called by the worker after writing, wait for it
+    let mut timing = None;
+    for _ in 0..100 {
+        timing = timings.lock().unwrap().first().cloned();
+        if timing.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let timing = timing.unwrap();
+    assert_eq!((timing.method.as_str(), timing.path.as_str(), timing.code, timing.content_len), ("GET", "/timed", 200, 2));
+    assert!(timing.received_at >= before);
+    assert!(timing.handler >= Duration::from_millis(20));
+
+    let total = stats.lock().unwrap().total();
+    assert!(total.responses_total >= 1);
+    assert!(total.handler_time_total >= Duration::from_millis(20));
+}
+
+#[test]
+fn connection_limit() {
+    use crate::server::{ConnectionLimit, ConnectionLimitAction};
+    use crate::testing::TestServer;
+    use std::io::Read;
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    let start_server = |action| {
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let stats_in_prepare = stats.clone();
+        let server = TestServer::start_with(
+            move |server| {
+                server.num_threads = 2;
+                server.settings.connection_limit = Some(ConnectionLimit { max_connections: 2, action });
+                if let Ok(mut stats) = stats_in_prepare.lock() {
+                    *stats = server.stats();
+                }
+            },
+            |request| {
+                request?.response(200).text("ok").send();
+                Ok(())
+            },
+        ).unwrap();
+        let stats = stats.lock().map(|stats| stats.clone()).unwrap_or_default();
+        (server, stats)
+    };
+
+    let read_rejected = |addr| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        response
+    };
+
+    // respond 503
+    let (server, stats) = start_server(ConnectionLimitAction::Respond503);
+    let idle = [TcpStream::connect(server.addr()).unwrap(), TcpStream::connect(server.addr()).unwrap()];
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(read_rejected(server.addr()).starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
+    assert_eq!(stats.total().rejected_total, 1);
+
+    drop(idle);
+    std::thread::sleep(Duration::from_millis(100));
+    server.client().get("/").send().unwrap().assert_code(200);
+    drop(server);
+
+    // close
+    let (server, _) = start_server(ConnectionLimitAction::Close);
+    let idle = [TcpStream::connect(server.addr()).unwrap(), TcpStream::connect(server.addr()).unwrap()];
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(read_rejected(server.addr()).is_empty());
+    drop(idle);
+    drop(server);
+
+    // pause accepting, connection waits in backlog until other connection is closed
+    let (server, _) = start_server(ConnectionLimitAction::PauseAccepting);
+    let idle = TcpStream::connect(server.addr()).unwrap();
+    let busy = TcpStream::connect(server.addr()).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    let client = server.client();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(client.get("/").send().map(|response| response.code()));
+    });
+
+    assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+
+    drop(idle);
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap().unwrap(), 200);
+    drop(busy);
+}
+
+#[test]
+fn connection_reuse() {
+    use crate::testing::TestServer;
+
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let stats_in_prepare = stats.clone();
+    let server = TestServer::start_with(
+        move |server| {
+            if let Ok(mut stats) = stats_in_prepare.lock() {
+                *stats = server.stats();
+            }
+        },
+        |request| {
+            let request = request?;
+            let text = format!("[{} {}]", request.requests_on_connection(), request.is_reused_connection());
+            assert!(request.connection_age() < std::time::Duration::from_secs(3));
+            // close after the second request
+            if request.requests_on_connection() >= 2 {
+                request.response(200).text(&text).close().send();
+            } else {
+                request.response(200).text(&text).send();
+            }
+            Ok(())
+        },
+    ).unwrap();
+
+    let response = server.client().send_raw(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n").unwrap();
+    let raw = String::from_utf8_lossy(response.raw()).to_string();
+    assert!(raw.contains("[1 false]") && raw.contains("[2 true]"), "{}", raw);
+
+    let total = stats.lock().unwrap().total();
+    assert_eq!((total.requests_total, total.reused_connection_requests_total), (2, 1));
+}
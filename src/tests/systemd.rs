@@ -0,0 +1,36 @@
+use crate::handoff::inherited_fd;
+use crate::systemd::{is_tcp_listener, listen_fds_count};
+use std::os::unix::io::AsRawFd;
+
+#[test]
+fn listen_fds() {
+    assert_eq!(listen_fds_count(Some("42"), Some("2"), 42), 2);
+    assert_eq!(listen_fds_count(Some("42"), Some("2"), 43), 0); // inherited by other process
+    assert_eq!(listen_fds_count(None, Some("2"), 42), 0);
+    assert_eq!(listen_fds_count(Some("42"), None, 42), 0);
+    assert_eq!(listen_fds_count(Some("42"), Some("x"), 42), 0);
+
+    // not activated by socket
+    assert!(crate::server::Server::from_systemd().is_err());
+}
+
+#[test]
+fn tcp_listener_check() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    assert!(is_tcp_listener(listener.as_raw_fd()));
+
+    let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    assert!(!is_tcp_listener(stream.as_raw_fd()));
+    let file = std::fs::File::open("Cargo.toml").unwrap();
+    assert!(!is_tcp_listener(file.as_raw_fd()));
+    assert!(!is_tcp_listener(-1));
+}
+
+#[test]
+fn handoff_fd() {
+    assert_eq!(inherited_fd(Some("5"), Some("42"), 42), Some(5));
+    assert_eq!(inherited_fd(Some("5"), Some("42"), 43), None); // inherited by grandchild
+    assert_eq!(inherited_fd(Some("5"), None, 42), None);
+    assert_eq!(inherited_fd(Some("-1"), Some("42"), 42), None);
+    assert_eq!(inherited_fd(None, Some("42"), 42), None);
+}
@@ -0,0 +1,42 @@
+use crate::testing::TestServer;
+
+#[test]
+fn client_requests() {
+    let server = TestServer::start(|request| {
+        let request = request?;
+        let mut content = Vec::new();
+        request.read_content(move |data, complete| {
+            content.extend_from_slice(data);
+            if let Some(request) = complete {
+                let body = format!("{} {} {:?} {}", request.method(), request.path(), request.header_value("X-Test"), String::from_utf8_lossy(&content));
+                request.response(200).headers("X-Reply: yes\r\n").text(&body).send();
+            }
+            Ok(())
+        });
+        Ok(())
+    });
+    assert!(server.is_ok());
+    if let Ok(server) = server {
+        assert_ne!(server.addr().port(), 0);
+        let client = server.client();
+
+        let response = client.get("/a?b=c").header("X-Test", "1").send();
+        assert!(response.is_ok());
+        if let Ok(response) = response {
+            response
+                .assert_code(200)
+                .assert_header("x-reply", "yes")
+                .assert_header("Content-Type", "text/plain; charset=utf-8")
+                .assert_text("GET /a Some(\"1\") ");
+        }
+
+        let response = client.post("/form").body("x=1").http_1_0().send();
+        assert!(response.is_ok());
+        if let Ok(response) = response {
+            assert!(response.raw().starts_with(b"HTTP/1.0 200 OK\r\n"));
+            response.assert_text("POST /form None x=1");
+        }
+
+        server.stop();
+    }
+}
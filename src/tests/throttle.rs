@@ -0,0 +1,57 @@
+use crate::testing::TestServer;
+use crate::throttle::Throttle;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const CONTENT_LEN: usize = 300_000;
+
+fn on_request(request: Result<crate::request::Request, crate::http_error::HttpError>) -> Result<(), Box<dyn std::error::Error>> {
+    let request = request?;
+    if request.path() == "/unlimited" {
+        request.tcp_session().set_bandwidth_limit(None);
+    }
+
+    request.response(200).content("Content-Type: application/octet-stream\r\n", &[b'x'; CONTENT_LEN]).send();
+    Ok(())
+}
+
+#[test]
+fn session_limit() {
+    let server = TestServer::start_with(|server| server.settings.web_settings.session_bandwidth_limit = Some(200_000), on_request).unwrap();
+    let client = server.client().timeout(Duration::from_secs(10));
+
+    let begin = Instant::now();
+    let response = client.get("/").send().unwrap();
+    assert_eq!(response.content().len(), CONTENT_LEN);
+    let elapsed = begin.elapsed();
+    assert!(elapsed > Duration::from_millis(1200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(3), "{:?}", elapsed);
+
+    // limit removed at runtime
+    let begin = Instant::now();
+    let response = client.get("/unlimited").send().unwrap();
+    assert_eq!(response.content().len(), CONTENT_LEN);
+    assert!(begin.elapsed() < Duration::from_millis(500), "{:?}", begin.elapsed());
+}
+
+#[test]
+fn global_limit() {
+    let throttle = Arc::new(Throttle::new(400_000));
+    let server = TestServer::start_with(move |server| server.settings.web_settings.global_bandwidth_limit = Some(throttle), on_request).unwrap();
+    let client = server.client().timeout(Duration::from_secs(10));
+
+    // two clients together are limited
+    let begin = Instant::now();
+    let threads: Vec<_> = (0..2).map(|_| {
+        let client = client.clone();
+        std::thread::spawn(move || client.get("/").send().unwrap().content().len())
+    }).collect();
+
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), CONTENT_LEN);
+    }
+
+    let elapsed = begin.elapsed();
+    assert!(elapsed > Duration::from_millis(1200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(3), "{:?}", elapsed);
+}
@@ -0,0 +1,99 @@
+use crate::query::parse_query;
+use crate::router::Router;
+use crate::testing::TestServer;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Search {
+    q: String,
+    page: u32,
+    #[serde(default)]
+    tag: Vec<String>,
+    exact: Option<bool>,
+    limit: Option<u16>,
+    order: Order,
+}
+
+#[test]
+fn deserialize_query() {
+    let search: Search = parse_query(b"q=rust+web%21&page=2&tag=a&tag=b&exact=on&limit=&order=desc").deserialize().unwrap();
+    assert_eq!(search, Search {
+        q: "rust web!".to_string(),
+        page: 2,
+        tag: vec!["a".to_string(), "b".to_string()],
+        exact: Some(true),
+        limit: None,
+        order: Order::Desc,
+    });
+
+    // scalar field gets the first value, missing sequence is default
+    let search: Search = parse_query(b"q=a&q=b&page=1&order=asc").deserialize().unwrap();
+    assert_eq!((search.q.as_str(), search.tag.len(), search.exact), ("a", 0, None));
+
+    let err = parse_query(b"q=a&page=x&order=asc").deserialize::<Search>().unwrap_err();
+    assert_eq!(err.to_string(), "\"page\": wrong value \"x\": invalid digit found in string");
+    let err = parse_query(b"page=1&order=asc").deserialize::<Search>().unwrap_err();
+    assert_eq!(err.to_string(), "missing field `q`");
+    let err = parse_query(b"q=a&page=1&order=up").deserialize::<Search>().unwrap_err();
+    assert!(err.to_string().starts_with("\"order\": unknown variant `up`"), "{}", err);
+}
+
+#[test]
+fn many_distinct_names() {
+    let mut query: String = (0..100_000).map(|i| format!("p{}=1&", i)).collect();
+    query.push_str("q=x&tag=a&page=3&tag=b&order=asc");
+    let search: Search = parse_query(query.as_bytes()).deserialize().unwrap();
+    assert_eq!((search.q.as_str(), search.page, search.tag), ("x", 3, vec!["a".to_string(), "b".to_string()]));
+}
+
+fn post_form(client: &crate::testing::TestClient, path: &str, body: &str) -> crate::testing::TestResponse {
+    client.post(path).header("Content-Type", "application/x-www-form-urlencoded").body(body).send().unwrap()
+}
+
+#[test]
+fn form_into_and_extractors() {
+    use crate::extract::{Form, Query, Urlencoded};
+
+    #[derive(Deserialize)]
+    struct SignUp {
+        name: String,
+        age: u8,
+    }
+
+    let router = Router::new()
+        .get("/search", |request, Query(Urlencoded(search)): Query<Urlencoded<Search>>| {
+            request.response(200).text(&format!("{} {:?}", search.page, search.tag)).send();
+            Ok(())
+        })
+        .post("/signup", |request, Form(Urlencoded(sign_up)): Form<Urlencoded<SignUp>>| {
+            request.response(200).text(&format!("{} {}", sign_up.name, sign_up.age)).send();
+            Ok(())
+        })
+        .post("/callback", |request, ()| {
+            request.form_into(|sign_up: Result<SignUp, _>, request| {
+                match sign_up {
+                    Ok(sign_up) => request.response(200).text(&sign_up.name).send(),
+                    Err(err) => request.response(400).text(&err.to_string()).send(),
+                };
+                Ok(())
+            });
+            Ok(())
+        });
+
+    let server = TestServer::start(move |request| router.dispatch(request?)).unwrap();
+    let client = server.client();
+    client.get("/search?q=x&page=3&tag=a&tag=b&order=asc").send().unwrap().assert_code(200).assert_text("3 [\"a\", \"b\"]");
+    client.get("/search?q=x&order=asc").send().unwrap().assert_code(400).assert_text("Wrong query: missing field `page`");
+    post_form(&client, "/signup", "name=Ann&age=30").assert_code(200).assert_text("Ann 30");
+    post_form(&client, "/signup", "name=Ann&age=300")
+        .assert_code(400).assert_text("Wrong form: \"age\": wrong value \"300\": number too large to fit in target type");
+    post_form(&client, "/callback", "name=Bob&age=7").assert_code(200).assert_text("Bob");
+    post_form(&client, "/callback", "name=Bob").assert_code(400).assert_text("missing field `age`");
+}
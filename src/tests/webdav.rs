@@ -0,0 +1,148 @@
+use crate::testing::TestServer;
+use crate::webdav::WebDav;
+use std::fs;
+
+#[test]
+fn share() {
+    let root = std::env::temp_dir().join(format!("anweb-test-webdav-{}", std::process::id()));
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("docs/a b.txt"), "hello").unwrap();
+
+    let dav = WebDav::new(&root).prefix("/dav");
+    let server = TestServer::start(move |request| dav.handle(request?)).unwrap();
+    let client = server.client();
+
+    let response = client.request("OPTIONS", "/dav/").send().unwrap();
+    response.assert_code(200).assert_header("DAV", "1");
+    assert!(response.header("Allow").unwrap_or_default().contains("PROPFIND"));
+
+    let response = client.request("PROPFIND", "/dav/docs").header("Depth", "1").send().unwrap();
+    response.assert_code(207);
+    assert!(response.text().contains("<D:href>/dav/docs/</D:href>"), "{}", response.text());
+    assert!(response.text().contains("<D:collection/>"), "{}", response.text());
+    assert!(response.text().contains("<D:href>/dav/docs/a%20b.txt</D:href>"), "{}", response.text());
+    assert!(response.text().contains("<D:getcontentlength>5</D:getcontentlength>"), "{}", response.text());
+    client.request("PROPFIND", "/dav/").header("Depth", "infinity").send().unwrap().assert_code(403);
+
+    client.request("MKCOL", "/dav/new").send().unwrap().assert_code(201);
+    client.request("MKCOL", "/dav/new").send().unwrap().assert_code(405);
+    client.request("MKCOL", "/dav/missing/new").send().unwrap().assert_code(409);
+
+    client.request("PUT", "/dav/new/file.txt").body("content").send().unwrap().assert_code(201);
+    client.request("PUT", "/dav/new/file.txt").body("changed").send().unwrap().assert_code(204);
+    client.get("/dav/new/file.txt").send().unwrap().assert_code(200).assert_text("changed");
+
+    client.request("COPY", "/dav/new").header("Destination", "http://localhost/dav/copy").send().unwrap().assert_code(201);
+    assert_eq!(fs::read_to_string(root.join("copy/file.txt")).unwrap(), "changed");
+    client.request("MOVE", "/dav/copy/file.txt").header("Destination", "/dav/docs/a%20b.txt").header("Overwrite", "F").send().unwrap().assert_code(412);
+    client.request("MOVE", "/dav/copy/file.txt").header("Destination", "/dav/docs/moved.txt").send().unwrap().assert_code(201);
+    assert!(root.join("docs/moved.txt").exists() && !root.join("copy/file.txt").exists());
+    client.request("COPY", "/dav/new").header("Destination", "http://other/elsewhere").send().unwrap().assert_code(502);
+    client.request("COPY", "/dav/new").send().unwrap().assert_code(400);
+
+    client.request("DELETE", "/dav/new").send().unwrap().assert_code(204);
+    assert!(!root.join("new").exists());
+    client.request("DELETE", "/dav/new").send().unwrap().assert_code(404);
+
+    // no way out of the root
+    client.get("/dav/docs/..%2F..%2Fetc/passwd").send().unwrap().assert_code(400);
+    client.get("/other").send().unwrap().assert_code(404);
+
+    let read_only = WebDav::new(&root).read_only(true);
+    let server = TestServer::start(move |request| read_only.handle(request?)).unwrap();
+    server.client().request("DELETE", "/docs").send().unwrap().assert_code(405);
+    assert!(root.join("docs").exists());
+
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn big_file() {
+    let root = std::env::temp_dir().join(format!("anweb-test-webdav-big-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(root.join("big.bin"), &data).unwrap();
+
+    let dav = WebDav::new(&root);
+    let server = TestServer::start(move |request| dav.handle(request?)).unwrap();
+    let response = server.client().get("/big.bin").send().unwrap();
+    response.assert_code(200).assert_header("Transfer-Encoding", "chunked");
+
+    // file is sent by parts, all of them are received in order
+    let mut content = response.content();
+    let mut received = Vec::new();
+    loop {
+        let size_end = content.windows(2).position(|window| window == b"\r\n").unwrap();
+        let size = usize::from_str_radix(std::str::from_utf8(&content[..size_end]).unwrap(), 16).unwrap();
+        if size == 0 {
+            break;
+        }
+        received.extend_from_slice(&content[size_end + 2..size_end + 2 + size]);
+        content = &content[size_end + 2 + size + 2..];
+    }
+    assert!(received == data);
+
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn upload() {
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    let root = std::env::temp_dir().join(format!("anweb-test-webdav-upload-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("file.txt"), "original").unwrap();
+
+    let dav = WebDav::new(&root).max_file_len(10);
+    let server = TestServer::start(move |request| dav.handle(request?)).unwrap();
+    let client = server.client();
+    let files = || {
+        let mut files: Vec<String> = fs::read_dir(&root).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        files.sort();
+        files
+    };
+
+    // aborted upload doesn't change existing file, the temporary file is removed
+    let mut stream = std::net::TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"PUT /file.txt HTTP/1.1\r\nContent-Length: 10\r\n\r\nnew").unwrap();
+    let begin = Instant::now();
+    while files().len() < 2 && begin.elapsed() < Duration::from_secs(3) {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(fs::read_to_string(root.join("file.txt")).unwrap(), "original");
+    drop(stream);
+    while files().len() > 1 && begin.elapsed() < Duration::from_secs(3) {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(files(), ["file.txt"]);
+
+    // limit of length
+    client.request("PUT", "/file.txt").body("0123456789!").send().unwrap().assert_code(413);
+    let response = client.send_raw(b"PUT /file.txt HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n6\r\n012345\r\n6\r\n6789!!\r\n0\r\n\r\n").unwrap();
+    response.assert_code(413);
+    assert_eq!(fs::read_to_string(root.join("file.txt")).unwrap(), "original");
+    assert_eq!(files(), ["file.txt"]);
+
+    client.request("PUT", "/file.txt").body("0123456789").send().unwrap().assert_code(204);
+    assert_eq!(fs::read_to_string(root.join("file.txt")).unwrap(), "0123456789");
+    assert_eq!(files(), ["file.txt"]);
+
+    // symbolic links aren't followed by copy
+    #[cfg(unix)]
+    {
+        let outside = root.with_extension("outside");
+        fs::write(&outside, "secret").unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("dir/link")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+        client.request("COPY", "/dir").header("Destination", "/copy").send().unwrap().assert_code(201);
+        assert!(root.join("copy").is_dir() && fs::symlink_metadata(root.join("copy/link")).is_err());
+        client.request("COPY", "/link").header("Destination", "/copied_link").send().unwrap().assert_code(403);
+        assert!(!root.join("copied_link").exists());
+        let _ = fs::remove_file(&outside);
+    }
+
+    let _ = fs::remove_dir_all(&root);
+}
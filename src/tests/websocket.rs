@@ -1,5 +1,5 @@
 #[cfg(test)]
-use crate::websocket::{Parser, frame, TEXT_OPCODE, BINARY_OPCODE};
+use crate::websocket::{Parser, frame, masked_frame, TEXT_OPCODE, BINARY_OPCODE};
 
 #[test]
 fn parse_one_good_frame() {
@@ -7,7 +7,7 @@ fn parse_one_good_frame() {
     let mut parser = Parser::new();
     if let Ok(result) = parser.parse_yet(&incoming_data, 12) {
         if let Some((frame, surplus)) = result {
-            assert_eq!(frame.fin(), true);
+            assert!(frame.fin());
             assert_eq!(frame.opcode(), 1);
             assert_eq!(frame.raw(), [129, 140, 211, 25, 248, 86, 72, 101, 108, 108, 111, 32, 119, 111, 114, 108, 100, 33]);
             let expected_mask: &[u8] = &[211, 25, 248, 86];
@@ -17,10 +17,10 @@ fn parse_one_good_frame() {
             assert!(surplus.is_empty());
         } else {
             // because data contains full frame
-            assert!(false);
+            panic!();
         }
     } else {
-        assert!(false);
+        panic!();
     }
 }
 
@@ -30,7 +30,7 @@ fn parse_two_good_frame_and_surplus() {
     let mut parser = Parser::new();
     if let Ok(result) = parser.parse_yet(&incoming_data, 100) {
         if let Some((frame, surplus)) = result {
-            assert_eq!(frame.fin(), true);
+            assert!(frame.fin());
             assert_eq!(frame.opcode(), 1);
             assert_eq!(frame.raw(), [129, 131, 216, 213, 165, 109, 49, 50, 51]);
             let expected_mask: &[u8] = &[216, 213, 165, 109];
@@ -41,7 +41,7 @@ fn parse_two_good_frame_and_surplus() {
             let incoming_data = [129, 134, 6, 145, 169, 18, 103, 243, 202, 118, 99, 247, 129, 137];
             if let Ok(result) = parser.parse_yet(&incoming_data, 100) {
                 if let Some((frame, surplus)) = result {
-                    assert_eq!(frame.fin(), true);
+                    assert!(frame.fin());
                     assert_eq!(frame.opcode(), 1);
                     assert_eq!(frame.raw(), [129, 134, 6, 145, 169, 18, 97, 98, 99, 100, 101, 102]);
                     let expected_mask: &[u8] = &[6, 145, 169, 18];
@@ -50,17 +50,17 @@ fn parse_two_good_frame_and_surplus() {
                     assert_eq!(surplus, [129, 137]);
                 } else {
                     // because data contains full frame
-                    assert!(false);
+                    panic!();
                 }
             } else {
-                assert!(false);
+                panic!();
             }
         } else {
             // because data contains full frame
-            assert!(false);
+            panic!();
         }
     } else {
-        assert!(false);
+        panic!();
     }
 }
 
@@ -70,7 +70,7 @@ fn parse_two_good_frame_together_and_surplus() {
     let mut parser = Parser::new();
     if let Ok(result) = parser.parse_yet(&incoming_data, 100) {
         if let Some((frame, surplus)) = result {
-            assert_eq!(frame.fin(), true);
+            assert!(frame.fin());
             assert_eq!(frame.opcode(), 1);
             assert_eq!(frame.raw(), [129, 131, 216, 213, 165, 109, 49, 50, 51]);
             let expected_mask: &[u8] = &[216, 213, 165, 109];
@@ -80,7 +80,7 @@ fn parse_two_good_frame_together_and_surplus() {
 
             if let Ok(result) = parser.parse_yet(&surplus, 100) {
                 if let Some((frame, surplus)) = result {
-                    assert_eq!(frame.fin(), true);
+                    assert!(frame.fin());
                     assert_eq!(frame.opcode(), 1);
                     assert_eq!(frame.raw(), [129, 134, 6, 145, 169, 18, 97, 98, 99, 100, 101, 102]);
                     let expected_mask: &[u8] = &[6, 145, 169, 18];
@@ -89,17 +89,17 @@ fn parse_two_good_frame_together_and_surplus() {
                     assert_eq!(surplus, [129, 133]);
                 } else {
                     // because data contains full frame
-                    assert!(false);
+                    panic!();
                 }
             } else {
-                assert!(false);
+                panic!();
             }
         } else {
             // because data contains full frame
-            assert!(false);
+            panic!();
         }
     } else {
-        assert!(false);
+        panic!();
     }
 }
 
@@ -110,7 +110,7 @@ fn parse_empty() {
     if let Ok(result) = parser.parse_yet(&incoming_data, 100) {
         assert!(result.is_none());
     } else {
-        assert!(false);
+        panic!();
     }
 }
 
@@ -121,7 +121,7 @@ fn parse_part_of_frame() {
     if let Ok(result) = parser.parse_yet(&incoming_data, 100) {
         assert!(result.is_none());
     } else {
-        assert!(false);
+        panic!();
     }
 }
 
@@ -131,16 +131,16 @@ fn parse_close_frame() {
     let mut parser = Parser::new();
     if let Ok(result) = parser.parse_yet(&incoming_data, 100) {
         if let Some((frame, surplus)) = result {
-            assert_eq!(frame.fin(), true);
+            assert!(frame.fin());
             assert_eq!(frame.opcode(), 8);
             assert!(frame.is_close());
             assert!(surplus.is_empty());
         } else {
             // because data contains full frame
-            assert!(false);
+            panic!();
         }
     } else {
-        assert!(false);
+        panic!();
     }
 }
 
@@ -158,7 +158,50 @@ fn make_no_masked_frame_for_send() {
 fn payload_len_limit() {
     let incoming_data = [129, 140, 211, 25, 248, 86, 155, 124, 148, 58, 188, 57, 143, 57, 161, 117, 156, 119];
     let mut parser = Parser::new();
-    if let Err(_) = parser.parse_yet(&incoming_data, 11) {
-        assert!(true);
-    }
+    assert!(parser.parse_yet(&incoming_data, 11).is_err());
+}
+
+#[test]
+fn make_masked_frame() {
+    assert_eq!(masked_frame(TEXT_OPCODE, b"", [1, 2, 3, 4]), [129, 128, 1, 2, 3, 4]);
+    assert_eq!(
+        masked_frame(TEXT_OPCODE, b"Hello world!", [211, 25, 248, 86]),
+        [129, 140, 211, 25, 248, 86, 155, 124, 148, 58, 188, 57, 143, 57, 161, 117, 156, 119]
+    );
+
+    let payload = vec![7; 300];
+    let data = masked_frame(BINARY_OPCODE, &payload, [5, 6, 7, 8]);
+    assert_eq!(&data[..4], [130, 254, 1, 44]);
+    let mut parser = Parser::new();
+    let (frame, surplus) = parser.parse_yet(&data, 300).unwrap().unwrap();
+    assert_eq!(frame.payload(), &payload[..]);
+    assert!(surplus.is_empty());
+}
+
+#[test]
+fn client_mode_parse() {
+    let mut data = frame(TEXT_OPCODE, b"Hello");
+    let payload = vec![9; 300];
+    data.extend_from_slice(&frame(BINARY_OPCODE, &payload));
+
+    let mut parser = Parser::client();
+    assert!(parser.parse_yet(&data[..3], 300).unwrap().is_none());
+
+    let (first, surplus) = parser.parse_yet(&data[3..], 300).unwrap().unwrap();
+    assert!(first.is_text());
+    assert!(first.mask().is_none());
+    assert_eq!(first.payload(), b"Hello");
+
+    let (second, surplus) = parser.parse_yet(&surplus, 300).unwrap().unwrap();
+    assert!(second.is_binary());
+    assert_eq!(second.payload(), &payload[..]);
+    assert!(surplus.is_empty());
+
+    // server must not send masked frames
+    let mut parser = Parser::client();
+    assert!(parser.parse_yet(&masked_frame(TEXT_OPCODE, b"Hello", [1, 2, 3, 4]), 300).is_err());
+
+    // and server parser still requires mask
+    let mut parser = Parser::new();
+    assert!(parser.parse_yet(&frame(TEXT_OPCODE, b"Hello"), 300).is_err());
 }
@@ -0,0 +1,591 @@
+use crate::server::{Event, Server};
+use crate::websocket::{BINARY_OPCODE, TEXT_OPCODE};
+use crate::websocket_client::{WebsocketClient, WebsocketClientError};
+use crate::testing::TestServer;
+use std::net::TcpStream;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+#[test]
+fn wrong_url() {
+    assert!(matches!(WebsocketClient::connect("http://127.0.0.1/ws"), Err(WebsocketClientError::WrongUrl)));
+    assert!(matches!(WebsocketClient::connect("ws://:80/ws"), Err(WebsocketClientError::WrongUrl)));
+    assert!(matches!(WebsocketClient::connect("ws://127.0.0.1:port/ws"), Err(WebsocketClientError::WrongUrl)));
+    assert!(matches!(WebsocketClient::connect("wss://127.0.0.1/ws"), Err(WebsocketClientError::NoTlsConfig)));
+}
+
+#[test]
+fn echo() {
+    let port = 9116;
+    let server = Server::new(&([0, 0, 0, 0], port).into());
+    assert!(server.is_ok());
+    if let Ok(server) = server {
+        let stopper = server.stopper();
+        let server_run_res = server.run(move |server_event| {
+            match server_event {
+                Event::Incoming(tcp_session) => {
+                    tcp_session.to_http(|request| {
+                        let request = request?;
+                        assert_eq!(request.path(), "/ws");
+                        assert_eq!(request.header_value("Host"), Some("127.0.0.1:9116"));
+                        request.accept_websocket()?.on_frame(|frame, websocket| {
+                            let frame = frame?;
+                            websocket.send(frame.opcode(), frame.payload());
+                            Ok(())
+                        });
+                        Ok(())
+                    });
+                }
+                Event::Started => {
+                    let stopper = stopper.clone();
+                    std::thread::spawn(move || {
+                        let client = WebsocketClient::connect(&format!("ws://127.0.0.1:{}/ws", port));
+                        assert!(client.is_ok());
+                        if let Ok(client) = client {
+                            let (sender, receiver) = mpsc::channel();
+                            client.on_frame(move |frame, _client| {
+                                let frame = frame?;
+                                let _ = sender.send((frame.opcode(), frame.payload().to_vec()));
+                                Ok(())
+                            });
+
+                            let big_payload = vec![7; 70000];
+                            client.send(TEXT_OPCODE, b"hello");
+                            client.send(BINARY_OPCODE, &big_payload);
+
+                            assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).ok(), Some((TEXT_OPCODE, b"hello".to_vec())));
+                            assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).ok(), Some((BINARY_OPCODE, big_payload)));
+
+                            client.close();
+                            assert!(client.is_closed());
+                        }
+
+                        stopper.stop();
+                        let addr = &format!("127.0.0.1:{}", port);
+                        while TcpStream::connect(addr).is_ok() {
+                            sleep(Duration::from_millis(1));
+                        }
+                    });
+                }
+                _ => {}
+            }
+        });
+
+        assert!(server_run_res.is_ok());
+    }
+}
+
+#[test]
+fn read_while_writing() {
+    let (sender, receiver) = mpsc::channel();
+    let sender = Arc::new(Mutex::new(sender));
+    let server = TestServer::start(move |request| {
+        let sender = sender.clone();
+        request?.accept_websocket()?.on_frame(move |frame, websocket| {
+            let frame = frame?;
+            if frame.payload() == b"big" {
+                // much more than socket buffers, will be queued until the client reads
+                websocket.send(BINARY_OPCODE, &vec![0; 32_000_000]);
+            } else if let Ok(sender) = sender.lock() {
+                let _ = sender.send((frame.payload().to_vec(), websocket.tcp_session().inner.worker_counters.queued_write_bytes.load(Ordering::SeqCst)));
+            }
+            Ok(())
+        });
+        Ok(())
+    });
+    assert!(server.is_ok());
+    if let Ok(server) = server {
+        let client = WebsocketClient::connect(&format!("ws://{}/ws", server.addr()));
+        assert!(client.is_ok());
+        if let Ok(client) = client {
+            // the client doesn't read, but the server still receives frames
+            client.send(TEXT_OPCODE, b"big");
+            sleep(Duration::from_millis(100));
+            client.send(TEXT_OPCODE, b"ping");
+
+            let received = receiver.recv_timeout(Duration::from_secs(3));
+            assert!(received.is_ok());
+            if let Ok((payload, queued_write_bytes)) = received {
+                assert_eq!(payload, b"ping");
+                assert!(queued_write_bytes > 0);
+            }
+
+            client.close();
+        }
+
+        server.stop();
+    }
+}
+
+#[test]
+fn broadcast_from_threads() {
+    const THREADS: usize = 4;
+    const FRAMES: usize = 500;
+
+    let server = TestServer::start(|request| {
+        request?.accept_websocket()?.on_frame(|frame, websocket| {
+            if frame?.payload() == b"start" {
+                for thread in 0..THREADS {
+                    let websocket = websocket.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..FRAMES {
+                            websocket.send(TEXT_OPCODE, format!("{} {}", thread, i).as_bytes());
+                        }
+                    });
+                }
+            }
+            Ok(())
+        });
+        Ok(())
+    }).unwrap();
+
+    let client = WebsocketClient::connect(&format!("ws://{}/ws", server.addr())).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    client.on_frame(move |frame, _client| {
+        let _ = sender.send(String::from_utf8_lossy(frame?.payload()).to_string());
+        Ok(())
+    });
+    client.send(TEXT_OPCODE, b"start");
+
+    // frames of each thread are received in order of sending
+    let mut next = [0; THREADS];
+    for _ in 0..THREADS * FRAMES {
+        let text = receiver.recv_timeout(Duration::from_secs(3)).unwrap();
+        let mut thread_and_index = text.split(' ').map(|number| number.parse::<usize>().unwrap());
+        let (thread, index) = (thread_and_index.next().unwrap(), thread_and_index.next().unwrap());
+        assert_eq!(index, next[thread]);
+        next[thread] += 1;
+    }
+
+    client.close();
+}
+
+#[test]
+fn send_while_reading() {
+    use std::time::Instant;
+
+    let (sender, receiver) = mpsc::channel();
+    let sender = Arc::new(Mutex::new(sender));
+    let server = TestServer::start(move |request| {
+        let sender = sender.clone();
+        request?.accept_websocket()?.on_frame(move |frame, _websocket| {
+            if let Ok(sender) = sender.lock() {
+                let _ = sender.send(frame?.payload().to_vec());
+            }
+            Ok(())
+        });
+        Ok(())
+    }).unwrap();
+
+    let client = WebsocketClient::connect(&format!("ws://{}/ws", server.addr())).unwrap();
+    client.on_frame(|_frame, _client| Ok(()));
+    sleep(Duration::from_millis(50));
+
+    // the server sends nothing, reading thread waits for data but doesn't block sending
+    let mut latencies: Vec<Duration> = (0..20).map(|_| {
+        sleep(Duration::from_millis(3));
+        let started = Instant::now();
+        client.try_send(TEXT_OPCODE, b"ping").unwrap();
+        started.elapsed()
+    }).collect();
+    latencies.sort();
+    assert!(latencies[latencies.len() / 2] < Duration::from_millis(5), "{:?}", latencies);
+
+    for _ in 0..20 {
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), b"ping");
+    }
+
+    client.close();
+}
+
+#[test]
+fn tls_echo() {
+    use crate::tls::{load_certs, load_private_key};
+    use rustls::{NoClientAuth, ServerConfig};
+
+    struct AnyCertificate;
+
+    impl rustls::ServerCertVerifier for AnyCertificate {
+        fn verify_server_cert(&self, _roots: &rustls::RootCertStore, _presented_certs: &[rustls::Certificate], _dns_name: webpki::DNSNameRef, _ocsp_response: &[u8]) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+            Ok(rustls::ServerCertVerified::assertion())
+        }
+    }
+
+    let server = TestServer::start_with(
+        |server| {
+            let mut tls_config = ServerConfig::new(NoClientAuth::new());
+            let certs = load_certs("examples/keys/cert.pem").unwrap();
+            let private_key = load_private_key("examples/keys/key.pem").unwrap();
+            tls_config.set_single_cert_with_ocsp_and_sct(certs, private_key, vec![], vec![]).unwrap();
+            server.settings.tls_config = Some(Arc::new(tls_config));
+        },
+        |request| {
+            request?.accept_websocket()?.on_frame(|frame, websocket| {
+                let frame = frame?;
+                websocket.send(frame.opcode(), frame.payload());
+                Ok(())
+            });
+            Ok(())
+        }
+    ).unwrap();
+
+    let mut client_config = rustls::ClientConfig::new();
+    client_config.dangerous().set_certificate_verifier(Arc::new(AnyCertificate));
+    let url = format!("wss://localhost:{}/ws", server.addr().port());
+    let client = WebsocketClient::connect_tls(&url, Arc::new(client_config)).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    client.on_frame(move |frame, _client| {
+        let frame = frame?;
+        let _ = sender.send((frame.opcode(), frame.payload().to_vec()));
+        Ok(())
+    });
+
+    let big_payload = vec![7; 70000];
+    client.send(TEXT_OPCODE, b"hello");
+    client.send(BINARY_OPCODE, &big_payload);
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), (TEXT_OPCODE, b"hello".to_vec()));
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), (BINARY_OPCODE, big_payload));
+
+    client.close();
+    assert!(client.is_closed());
+}
+
+#[test]
+fn deferred_handshake() {
+    use crate::websocket::{frame, masked_frame};
+    use std::io::{Read, Write};
+
+    let server = TestServer::start(|request| {
+        let request = request?;
+        let authorized = request.query_value("token") == Some("good");
+        let pending = request.accept_websocket_deferred()?;
+        assert_eq!(pending.protocols(), ["chat", "json"]);
+        std::thread::spawn(move || {
+            // checking of token
+            sleep(Duration::from_millis(50));
+            if authorized {
+                pending.approve(Some("chat")).on_frame(|frame, websocket| {
+                    websocket.send(TEXT_OPCODE, frame?.payload());
+                    Ok(())
+                });
+            } else {
+                pending.reject(401);
+            }
+        });
+        Ok(())
+    }).unwrap();
+
+    let connect = |token: &str| {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+        let mut data = format!("GET /ws?token={} HTTP/1.1\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Protocol: chat, json\r\n\r\n", token).into_bytes();
+        // the frame is sent before the handshake response
+        data.extend_from_slice(&masked_frame(TEXT_OPCODE, b"early", [1, 2, 3, 4]));
+        stream.write_all(&data).unwrap();
+        stream
+    };
+
+    let mut stream = connect("good");
+    let mut received = Vec::new();
+    let mut buf = [0; 1024];
+    while !received.ends_with(&frame(TEXT_OPCODE, b"early")) {
+        let cnt = stream.read(&mut buf).unwrap();
+        assert!(cnt > 0);
+        received.extend_from_slice(&buf[..cnt]);
+    }
+    let received = String::from_utf8_lossy(&received);
+    assert!(received.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(received.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert!(received.contains("Sec-WebSocket-Protocol: chat\r\n"));
+
+    let mut stream = connect("bad");
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert!(received.starts_with(b"HTTP/1.1 401 Unauthorized\r\n"));
+}
+
+#[test]
+fn groups() {
+    let mut registry = None;
+    let kept = Arc::new(Mutex::new(None));
+    let server_kept = kept.clone();
+    let server = TestServer::start_with(|server| registry = Some(server.websocket_registry()), move |request| {
+        let kept = server_kept.clone();
+        request?.accept_websocket()?.on_frame(move |frame, websocket| {
+            let frame = frame?;
+            let text = String::from_utf8_lossy(frame.payload());
+            if text == "keep" {
+                *kept.lock().unwrap() = Some(websocket.clone());
+            }
+            if let Some(group) = text.strip_prefix("join ") {
+                websocket.join(group);
+            } else if let Some(group) = text.strip_prefix("leave ") {
+                websocket.leave(group);
+            }
+            websocket.send(TEXT_OPCODE, format!("{:?}", websocket.groups()).as_bytes());
+            Ok(())
+        });
+        Ok(())
+    }).unwrap();
+    let registry = registry.unwrap();
+
+    let connect = |commands: &[&str]| {
+        let client = WebsocketClient::connect(&format!("ws://{}/ws", server.addr())).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        client.on_frame(move |frame, _client| {
+            let _ = sender.send(String::from_utf8_lossy(frame?.payload()).to_string());
+            Ok(())
+        });
+        for command in commands {
+            client.send(TEXT_OPCODE, command.as_bytes());
+            receiver.recv_timeout(Duration::from_secs(3)).unwrap();
+        }
+        (client, receiver)
+    };
+
+    let (first, first_frames) = connect(&["join room:1", "join room:2", "keep"]);
+    let (second, second_frames) = connect(&["join room:1", "join room:2", "leave room:2"]);
+    second.send(TEXT_OPCODE, b"groups");
+    assert_eq!(second_frames.recv_timeout(Duration::from_secs(3)).unwrap(), "[\"room:1\"]");
+
+    assert_eq!(registry.broadcast_to("room:1", TEXT_OPCODE, b"to room 1"), 2);
+    assert_eq!(first_frames.recv_timeout(Duration::from_secs(3)).unwrap(), "to room 1");
+    assert_eq!(second_frames.recv_timeout(Duration::from_secs(3)).unwrap(), "to room 1");
+    assert_eq!(registry.broadcast_to("room:2", TEXT_OPCODE, b"to room 2"), 1);
+    assert_eq!(first_frames.recv_timeout(Duration::from_secs(3)).unwrap(), "to room 2");
+    assert_eq!(registry.broadcast_to("room:3", TEXT_OPCODE, b"nobody"), 0);
+
+    // membership is removed when the connection is closed
+    first.close();
+    for _ in 0..100 {
+        if registry.members("room:1").len() == 1 && registry.groups() == ["room:1"] {
+            break;
+        }
+        sleep(Duration::from_millis(20));
+    }
+    assert_eq!(registry.members("room:1").len(), 1);
+    assert_eq!(registry.groups(), ["room:1"]);
+    assert!(second_frames.try_recv().is_err());
+
+    // closed websocket doesn't join
+    let closed = kept.lock().unwrap().take().unwrap();
+    closed.join("room:3");
+    assert_eq!(registry.groups(), ["room:1"]);
+    assert!(closed.groups().is_empty());
+    second.close();
+}
+
+#[test]
+fn text_messages() {
+    use crate::websocket::MessageError;
+
+    let server = TestServer::start(|request| {
+        request?.accept_websocket()?.on_text(16, |text, websocket| {
+            match text {
+                Ok(text) => websocket.send_text(&text.to_uppercase()),
+                Err(MessageError::NotText) => websocket.send_text("not text"),
+                Err(MessageError::TooLarge) => websocket.send_text("too large"),
+                Err(err) => websocket.send_text(&err.to_string()),
+            };
+            Ok(())
+        });
+        Ok(())
+    }).unwrap();
+
+    let client = WebsocketClient::connect(&format!("ws://{}/ws", server.addr())).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    client.on_frame(move |frame, _client| {
+        let frame = frame?;
+        let _ = sender.send((frame.opcode(), frame.payload().to_vec()));
+        Ok(())
+    });
+
+    let recv = || receiver.recv_timeout(Duration::from_secs(3)).unwrap();
+    client.send(TEXT_OPCODE, b"hello");
+    assert_eq!(recv(), (TEXT_OPCODE, b"HELLO".to_vec()));
+    client.send(0x9, b"ping");
+    assert_eq!(recv(), (0xA, b"ping".to_vec()));
+    client.send(BINARY_OPCODE, b"\x01\x02");
+    assert_eq!(recv(), (TEXT_OPCODE, b"not text".to_vec()));
+
+    // the connection is closed after too large message
+    client.send(TEXT_OPCODE, &[b'a'; 17]);
+    assert_eq!(recv(), (TEXT_OPCODE, b"too large".to_vec()));
+    assert_eq!(recv(), (0x8, 1009u16.to_be_bytes().to_vec()));
+    for _ in 0..100 {
+        if client.is_closed() {
+            break;
+        }
+        sleep(Duration::from_millis(20));
+    }
+    assert!(client.is_closed());
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_messages() {
+    #[derive(serde::Deserialize, serde::Serialize)]
+    struct Move {
+        x: i32,
+        y: i32,
+    }
+
+    let server = TestServer::start(|request| {
+        request?.accept_websocket()?.on_json(1024, |value: Result<Move, _>, websocket| {
+            match value {
+                Ok(value) => { websocket.send_json(&Move { x: value.x + 1, y: value.y + 1 }).unwrap(); }
+                Err(_) => { websocket.send_text("wrong move"); }
+            }
+            Ok(())
+        });
+        Ok(())
+    }).unwrap();
+
+    let client = WebsocketClient::connect(&format!("ws://{}/ws", server.addr())).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    client.on_frame(move |frame, _client| {
+        let _ = sender.send(String::from_utf8_lossy(frame?.payload()).to_string());
+        Ok(())
+    });
+
+    client.send(TEXT_OPCODE, br#"{"x":1,"y":2}"#);
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), r#"{"x":2,"y":3}"#);
+    client.send(TEXT_OPCODE, br#"{"x":"1"}"#);
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), "wrong move");
+}
+
+#[test]
+fn frames_per_read_limit() {
+    use crate::websocket::masked_frame;
+    use std::io::Write;
+
+    const FRAMES: usize = 1000;
+
+    let (sender, receiver) = mpsc::channel();
+    let sender = Arc::new(Mutex::new(sender));
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.websocket_frames_per_read_limit = 4,
+        move |request| {
+            let sender = sender.clone();
+            request?.accept_websocket()?.on_frame(move |frame, _websocket| {
+                if let Ok(sender) = sender.lock() {
+                    let _ = sender.send(String::from_utf8_lossy(frame?.payload()).to_string());
+                }
+                Ok(())
+            });
+            Ok(())
+        }
+    ).unwrap();
+
+    // all frames in one write, the rest after the limit is processed on next iterations of the worker
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    let mut data = b"GET /ws HTTP/1.1\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n".to_vec();
+    for i in 0..FRAMES {
+        data.extend_from_slice(&masked_frame(TEXT_OPCODE, i.to_string().as_bytes(), [1, 2, 3, 4]));
+    }
+    stream.write_all(&data).unwrap();
+
+    for i in 0..FRAMES {
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), i.to_string());
+    }
+}
+
+#[test]
+fn frames_per_read_limit_backpressure() {
+    use crate::websocket::masked_frame;
+    use std::io::Write;
+
+    const FRAMES: usize = 5000;
+
+    let (sender, receiver) = mpsc::channel();
+    let sender = Arc::new(Mutex::new(sender));
+    let server = TestServer::start_with(
+        |server| {
+            server.settings.web_settings.websocket_frames_per_read_limit = 1;
+            server.settings.web_settings.read_buffer_limit = 1024;
+            server.settings.web_settings.pending_bytes_limit = 1200;
+        },
+        move |request| {
+            let sender = sender.clone();
+            request?.accept_websocket()?.on_frame(move |frame, _websocket| {
+                if let Ok(sender) = sender.lock() {
+                    let _ = sender.send(String::from_utf8_lossy(frame?.payload()).to_string());
+                }
+                Ok(())
+            });
+            Ok(())
+        }
+    ).unwrap();
+
+    // frames are much more than pending limit, the server doesn't read while it has unprocessed frames
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    let mut data = b"GET /ws HTTP/1.1\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n".to_vec();
+    for i in 0..FRAMES {
+        data.extend_from_slice(&masked_frame(TEXT_OPCODE, i.to_string().as_bytes(), [1, 2, 3, 4]));
+    }
+    let writer = std::thread::spawn(move || {
+        stream.write_all(&data).unwrap();
+        stream
+    });
+
+    for i in 0..FRAMES {
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), i.to_string());
+    }
+    drop(writer.join());
+}
+
+#[test]
+fn coalescing() {
+    const FRAMES: usize = 300;
+
+    let server = TestServer::start_with(
+        |server| server.settings.web_settings.websocket_coalescing = Some(1024),
+        |request| {
+            request?.accept_websocket()?.on_text(16, |text, websocket| {
+                if text.is_ok() {
+                    for i in 0..FRAMES {
+                        websocket.send_text(&i.to_string());
+                    }
+                    // bigger than limit of coalescing, sent after small frames
+                    websocket.send(BINARY_OPCODE, &vec![7; 5000]);
+                }
+                Ok(())
+            });
+            Ok(())
+        }
+    ).unwrap();
+
+    let client = WebsocketClient::connect(&format!("ws://{}/ws", server.addr())).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    client.on_frame(move |frame, _client| {
+        let frame = frame?;
+        let _ = sender.send((frame.opcode(), frame.payload().to_vec()));
+        Ok(())
+    });
+    client.send(TEXT_OPCODE, b"start");
+
+    for i in 0..FRAMES {
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), (TEXT_OPCODE, i.to_string().into_bytes()));
+    }
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(3)).unwrap(), (BINARY_OPCODE, vec![7; 5000]));
+
+    // close frame is written before closing of the connection
+    client.send(TEXT_OPCODE, b"message longer than limit");
+    let (opcode, payload) = receiver.recv_timeout(Duration::from_secs(3)).unwrap();
+    assert_eq!(opcode, crate::websocket::CLOSE_OPCODE);
+    assert_eq!(payload, 1009u16.to_be_bytes());
+
+    client.close();
+}
@@ -0,0 +1,16 @@
+use crate::worker::{session_token, token_slab_key};
+
+#[test]
+fn versioned_tokens() {
+    // sessions reusing slab key get different tokens
+    let first = session_token(5, 100);
+    let second = session_token(5, 101);
+    assert_ne!(first, second);
+    assert_eq!(token_slab_key(first), 5);
+    assert_eq!(token_slab_key(second), 5);
+
+    // generation is wrapped, slab key is kept
+    let token = session_token(7, u64::MAX);
+    assert_eq!(token_slab_key(token), 7);
+    assert_ne!(token, session_token(7, u64::MAX - 1));
+}
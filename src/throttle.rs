@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limit of outbound bandwidth (token bucket). Used for each tcp session (`TcpSession::set_bandwidth_limit`)
+/// and for all sessions of the server together (`Settings::global_bandwidth_limit`).
+pub struct Throttle {
+    bucket: Mutex<Bucket>,
+}
+
+impl Throttle {
+    /// Limit in bytes per second. Short bursts up to a tenth of the rate (but at least 1 KB) are allowed.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        Throttle {
+            bucket: Mutex::new(Bucket {
+                rate: bytes_per_sec,
+                tokens: burst(bytes_per_sec),
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Limit in bytes per second.
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().map_or(0, |bucket| bucket.rate)
+    }
+
+    /// Change limit in bytes per second, takes effect immediately.
+    pub fn set_rate(&self, bytes_per_sec: u64) {
+        if let Ok(mut bucket) = self.bucket.lock() {
+            bucket.refill();
+            bucket.rate = bytes_per_sec.max(1);
+            bucket.tokens = bucket.tokens.min(burst(bucket.rate));
+        }
+    }
+
+    /// Takes permission to write up to `want` bytes. Returns number of allowed bytes, 0 if the limit is reached.
+    pub(crate) fn take(&self, want: usize) -> usize {
+        match self.bucket.lock() {
+            Ok(mut bucket) => {
+                bucket.refill();
+                let allowed = (bucket.tokens as usize).min(want);
+                bucket.tokens -= allowed as f64;
+                allowed
+            }
+            Err(_) => want,
+        }
+    }
+
+    /// Returns permission for bytes that were taken but not written.
+    pub(crate) fn give_back(&self, cnt: usize) {
+        if cnt == 0 {
+            return;
+        }
+
+        if let Ok(mut bucket) = self.bucket.lock() {
+            bucket.tokens = (bucket.tokens + cnt as f64).min(burst(bucket.rate));
+        }
+    }
+}
+
+struct Bucket {
+    /// Bytes per second.
+    rate: u64,
+    /// Number of bytes that can be written now.
+    tokens: f64,
+    /// Time of last refill.
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(burst(self.rate));
+        self.updated = now;
+    }
+}
+
+/// Size of bucket.
+fn burst(rate: u64) -> f64 {
+    (rate / 10).max(1024) as f64
+}
+
+/// Limit of number of new connections from one IP address in a period, see `Settings::accept_rate_limit`.
+pub struct AcceptRateLimit {
+    max_connections: u32,
+    period: Duration,
+    /// Start of current period and number of connections in it by address.
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl AcceptRateLimit {
+    /// Allows `max_connections` from one address in each `period`.
+    pub fn new(max_connections: u32, period: Duration) -> Self {
+        AcceptRateLimit { max_connections, period, windows: Mutex::new(HashMap::new()) }
+    }
+
+    /// Counts new connection from address, returns false if the limit is exceeded.
+    pub(crate) fn allow(&self, addr: IpAddr) -> bool {
+        let mut windows = match self.windows.lock() {
+            Ok(windows) => windows,
+            Err(_) => return true,
+        };
+
+        let now = Instant::now();
+        if windows.len() >= WINDOWS_CLEANUP_LEN {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < self.period);
+        }
+
+        let (start, count) = windows.entry(addr).or_insert((now, 0));
+        if now.duration_since(*start) >= self.period {
+            *start = now;
+            *count = 0;
+        }
+
+        *count += 1;
+        *count <= self.max_connections
+    }
+}
+
+/// Number of addresses after which expired periods are removed.
+const WINDOWS_CLEANUP_LEN: usize = 10000;
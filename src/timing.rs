@@ -0,0 +1,47 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Timing breakdown of request and its response, passed to `Settings::on_timing` when the response is written.
+#[derive(Debug, Clone)]
+pub struct Timing {
+    /// Id of tcp session.
+    pub session_id: u64,
+    /// Number of request in tcp session, more than 1 if the connection is reused.
+    pub request_id: u64,
+    /// Time from accepting of connection to the start of sending of response.
+    pub connection_age: Duration,
+    /// Request method.
+    pub method: String,
+    /// Decoded request path.
+    pub path: String,
+    /// Status code of response.
+    pub code: u16,
+    /// Length of response content.
+    pub content_len: usize,
+    /// Wall clock time of receiving of the first byte of request.
+    pub received_at: SystemTime,
+    /// From accepting of connection or parsing of previous request to the first byte of request.
+    pub time_to_first_byte: Duration,
+    /// From the first byte of request to the end of parsing of request head.
+    pub header_parse: Duration,
+    /// From the end of parsing of request head to the start of sending of response.
+    pub handler: Duration,
+    /// From the start of sending of response to the end of its writing to socket.
+    pub send: Duration,
+}
+
+/// Callback of `Settings::on_timing`.
+pub type TimingCallback = Arc<dyn Fn(&Timing) + Send + Sync>;
+
+/// Moments of receiving of request.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RequestTimes {
+    /// Moment of accepting of connection or parsing of previous request.
+    pub(crate) idle_since: Instant,
+    /// Moment of the first byte of request.
+    pub(crate) first_byte: Instant,
+    /// Wall clock time of the first byte of request.
+    pub(crate) received_at: SystemTime,
+    /// Moment of the end of parsing of request head.
+    pub(crate) head_parsed: Instant,
+}
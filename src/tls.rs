@@ -1,5 +1,18 @@
+use rustls::sign::CertifiedKey;
 use std::fs;
 use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+
+/// Data starts with TLS handshake record, for example ClientHello of client that speaks TLS to plaintext listener.
+pub(crate) fn is_tls_handshake(data: &[u8]) -> bool {
+    data.starts_with(&[0x16, 0x03])
+}
+
+/// Data starts with method of HTTP request like "GET ", for example of client that speaks plaintext to TLS listener.
+pub(crate) fn is_plaintext_http(data: &[u8]) -> bool {
+    let method_len = data.iter().take_while(|ch| ch.is_ascii_uppercase()).count();
+    (3..=9).contains(&method_len) && data.get(method_len) == Some(&b' ')
+}
 
 pub fn load_certs(filename: &str) -> Result<Vec<rustls::Certificate>, LoadCertificateError> {
     let cert_file = fs::File::open(filename)?;
@@ -32,6 +45,70 @@ pub fn load_private_key(filename: &str) -> Result<rustls::PrivateKey, LoadPrivat
     }
 }
 
+/// Certificate of server that can be replaced while the server is running, for example after renewal by ACME
+/// (see `acme::ChallengeStore`). New TLS connections get the current certificate, established ones keep previous.
+/// Can be used in multi-threaded environment after clone, all clones share the certificate.
+#[derive(Clone, Default)]
+pub struct ReloadableCert {
+    current: Arc<RwLock<Option<CertifiedKey>>>,
+}
+
+impl ReloadableCert {
+    /// Creates empty certificate, handshakes fail until it's set.
+    pub fn new() -> Self {
+        ReloadableCert::default()
+    }
+
+    /// Sets certificate chain and its private key.
+    pub fn set(&self, certs: Vec<rustls::Certificate>, key: &rustls::PrivateKey) -> Result<(), LoadPrivateKeyError> {
+        let signing_key = rustls::sign::any_supported_type(key).map_err(|_| LoadPrivateKeyError::UnsupportedKeyType)?;
+        if let Ok(mut current) = self.current.write() {
+            *current = Some(CertifiedKey::new(certs, Arc::new(signing_key)));
+        }
+
+        Ok(())
+    }
+
+    /// Sets certificate chain and private key from PEM data, as ACME clients usually get them.
+    pub fn set_pem(&self, certs_pem: &[u8], key_pem: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let certs = rustls::internal::pemfile::certs(&mut &certs_pem[..]).map_err(|_| LoadCertificateError::CannotExtractSertificates)?;
+        if certs.is_empty() {
+            return Err(LoadCertificateError::CannotExtractSertificates.into());
+        }
+
+        let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut &key_pem[..]).map_err(|_| LoadPrivateKeyError::RsaPrivateKeys)?;
+        if keys.is_empty() {
+            keys = rustls::internal::pemfile::rsa_private_keys(&mut &key_pem[..]).map_err(|_| LoadPrivateKeyError::RsaPrivateKeys)?;
+        }
+        let key = keys.first().ok_or(LoadPrivateKeyError::RsaKeyIsEmpty)?;
+
+        Ok(self.set(certs, key)?)
+    }
+
+    /// Sets certificate chain and private key from files.
+    pub fn load(&self, certs_filename: &str, key_filename: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.set(load_certs(certs_filename)?, &load_private_key(key_filename)?)?)
+    }
+
+    /// Returns true if certificate is set.
+    pub fn is_set(&self) -> bool {
+        self.current.read().is_ok_and(|current| current.is_some())
+    }
+
+    /// TLS config of server without client authentication that uses the certificate, for `Settings::tls_config`.
+    pub fn server_config(&self) -> rustls::ServerConfig {
+        let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        config.cert_resolver = Arc::new(self.clone());
+        config
+    }
+}
+
+impl rustls::ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: rustls::ClientHello) -> Option<CertifiedKey> {
+        self.current.read().ok()?.clone()
+    }
+}
+
 #[derive(Debug)]
 pub enum LoadCertificateError {
     CannotOpenFile(std::io::Error),
@@ -52,7 +129,7 @@ impl From<()> for LoadCertificateError {
 
 impl std::fmt::Display for LoadCertificateError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
@@ -63,6 +140,8 @@ pub enum LoadPrivateKeyError {
     CannotOpenFile(std::io::Error),
     RsaPrivateKeys,
     RsaKeyIsEmpty,
+    /// Key type isn't supported by TLS implementation.
+    UnsupportedKeyType,
 }
 
 impl From<std::io::Error> for LoadPrivateKeyError {
@@ -79,7 +158,7 @@ impl From<()> for LoadPrivateKeyError {
 
 impl std::fmt::Display for LoadPrivateKeyError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
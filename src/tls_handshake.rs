@@ -0,0 +1,201 @@
+use crate::worker::Waker;
+use mio::unix::EventedFd;
+use rustls::Session;
+use slab::Slab;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+/// Threads for TLS handshakes of new connections, so bursts of handshakes don't delay other sessions of workers.
+/// Connection joins the worker with `server::Event::Incoming` after its handshake is completed, failed handshake is reported
+/// with `server::Event::TlsHandshakeFailed` only. Connections in handshake are counted in active sessions of the worker
+/// that accepted them, so they are limited by `server::Settings::connection_limit` and are visible in `Stats`.
+/// Set to `server::Settings::tls_handshake_pool`. Can be used in multi-threaded environment after clone, all clones share threads,
+/// the threads are stopped when all clones are dropped. Unix only.
+#[derive(Clone)]
+pub struct TlsHandshakePool {
+    inner: Arc<PoolInner>,
+}
+
+impl TlsHandshakePool {
+    /// Starts `threads` threads (at least one). Handshake not completed in `timeout` fails.
+    pub fn new(threads: usize, timeout: Duration) -> Result<Self, std::io::Error> {
+        let mut pool_threads = Vec::new();
+        for _ in 0..threads.max(1) {
+            let poll = mio::Poll::new()?;
+            let (registration, set_readiness) = mio::Registration::new2();
+            poll.register(&registration, WAKE_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+            let (jobs, jobs_receiver) = mpsc::channel();
+
+            let thread_set_readiness = set_readiness.clone();
+            std::thread::spawn(move || {
+                let _registration = registration;
+                run(poll, thread_set_readiness, jobs_receiver, timeout);
+            });
+
+            pool_threads.push(PoolThread { jobs, set_readiness });
+        }
+
+        Ok(TlsHandshakePool { inner: Arc::new(PoolInner { threads: pool_threads, next: AtomicUsize::new(0) }) })
+    }
+
+    /// Number of threads.
+    pub fn threads(&self) -> usize {
+        self.inner.threads.len()
+    }
+
+    /// Passes the connection to one of threads, the result is sent to `done` and the worker is woken by `waker`.
+    pub(crate) fn handshake(&self, stream: mio::net::TcpStream, addr: SocketAddr, tls_session: rustls::ServerSession, done: mpsc::Sender<Handshaken>, waker: Arc<Waker>) {
+        let index = self.inner.next.fetch_add(1, Ordering::Relaxed) % self.inner.threads.len();
+        let thread = &self.inner.threads[index];
+        let job = Job { stream, addr, tls_session, started: Instant::now(), done, waker };
+        if thread.jobs.send(job).is_ok() {
+            let _ = thread.set_readiness.set_readiness(mio::Ready::readable());
+        }
+    }
+}
+
+/// Result of handshake for the worker.
+pub(crate) struct Handshaken {
+    pub stream: mio::net::TcpStream,
+    pub addr: SocketAddr,
+    /// Established session and application data received with the last packet of handshake.
+    pub result: Result<(rustls::ServerSession, Vec<u8>), rustls::TLSError>,
+}
+
+struct PoolInner {
+    threads: Vec<PoolThread>,
+    /// Thread for the next handshake, round-robin.
+    next: AtomicUsize,
+}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        for thread in &mut self.threads {
+            // the thread finds that channel of jobs is disconnected and stops
+            thread.jobs = mpsc::channel().0;
+            let _ = thread.set_readiness.set_readiness(mio::Ready::readable());
+        }
+    }
+}
+
+struct PoolThread {
+    jobs: mpsc::Sender<Job>,
+    set_readiness: mio::SetReadiness,
+}
+
+/// Connection in handshake.
+struct Job {
+    stream: mio::net::TcpStream,
+    addr: SocketAddr,
+    tls_session: rustls::ServerSession,
+    started: Instant,
+    done: mpsc::Sender<Handshaken>,
+    waker: Arc<Waker>,
+}
+
+impl Job {
+    /// Processes received packets of handshake. Returns None while the handshake is not completed.
+    fn advance(&mut self) -> Option<Result<Vec<u8>, rustls::TLSError>> {
+        while self.tls_session.is_handshaking() {
+            match self.tls_session.read_tls(&mut self.stream) {
+                Ok(0) => return Some(Err(rustls::TLSError::General("connection closed during handshake".to_string()))),
+                Ok(_) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(err) => return Some(Err(rustls::TLSError::General(err.to_string()))),
+            }
+
+            if let Err(err) = self.tls_session.process_new_packets() {
+                // alert for the client
+                let _ = self.tls_session.write_tls(&mut self.stream);
+                return Some(Err(err));
+            }
+        }
+
+        while self.tls_session.wants_write() {
+            match self.tls_session.write_tls(&mut self.stream) {
+                Ok(_) => {}
+                // the rest is written by the worker
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(err) => return Some(Err(rustls::TLSError::General(err.to_string()))),
+            }
+        }
+
+        if self.tls_session.is_handshaking() {
+            return None;
+        }
+
+        let mut early_data = Vec::new();
+        match self.tls_session.read_to_end(&mut early_data) {
+            Ok(_) => Some(Ok(early_data)),
+            Err(err) => Some(Err(rustls::TLSError::General(err.to_string()))),
+        }
+    }
+
+    fn finish(self, result: Result<Vec<u8>, rustls::TLSError>) {
+        let Job { stream, addr, tls_session, done, waker, .. } = self;
+        let handshaken = Handshaken { stream, addr, result: result.map(|early_data| (tls_session, early_data)) };
+        if done.send(handshaken).is_ok() {
+            waker.wake_worker();
+        }
+    }
+}
+
+/// Loop of thread of the pool.
+fn run(poll: mio::Poll, set_readiness: mio::SetReadiness, jobs: mpsc::Receiver<Job>, timeout: Duration) {
+    let mut handshakes: Slab<Job> = Slab::new();
+    let mut events = mio::Events::with_capacity(1024);
+
+    loop {
+        if poll.poll(&mut events, Some(TIMEOUT_CHECK_INTERVAL)).is_err() {
+            return;
+        }
+
+        for event in events.iter() {
+            let key = event.token().0;
+            if event.token() == WAKE_TOKEN || !handshakes.contains(key) {
+                continue;
+            }
+
+            if let Some(result) = handshakes[key].advance() {
+                let job = handshakes.remove(key);
+                let _ = poll.deregister(&EventedFd(&job.stream.as_raw_fd()));
+                job.finish(result);
+            }
+        }
+
+        let _ = set_readiness.set_readiness(mio::Ready::empty());
+        loop {
+            match jobs.try_recv() {
+                Ok(job) => {
+                    let entry = handshakes.vacant_entry();
+                    // by descriptor, mio stream can be registered only in one poll, and it's registered later in poll of the worker
+                    match poll.register(&EventedFd(&job.stream.as_raw_fd()), mio::Token(entry.key()), mio::Ready::readable(), mio::PollOpt::level()) {
+                        Ok(()) => {
+                            entry.insert(job);
+                        }
+                        Err(err) => job.finish(Err(rustls::TLSError::General(err.to_string()))),
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return,
+            }
+        }
+
+        let expired: Vec<usize> = handshakes.iter().filter(|(_, job)| job.started.elapsed() > timeout).map(|(key, _)| key).collect();
+        for key in expired {
+            let job = handshakes.remove(key);
+            let _ = poll.deregister(&EventedFd(&job.stream.as_raw_fd()));
+            job.finish(Err(rustls::TLSError::General("handshake timeout".to_string())));
+        }
+    }
+}
+
+/// MIO key of waker of thread of the pool.
+const WAKE_TOKEN: mio::Token = mio::Token(usize::MAX - 1);
+
+/// Interval of checking of handshake timeouts.
+const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
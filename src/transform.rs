@@ -0,0 +1,126 @@
+use crate::request::Request;
+use crate::response::accepts_encoding;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Streaming transform of response content, for example compression or signing of chunks.
+/// Set for one response by `Response::transform` or for all responses by `Settings::response_transforms`.
+pub trait ResponseTransform: Send {
+    /// Header lines added to response, each ending with "\r\n", for example "Content-Encoding: gzip\r\n".
+    fn headers(&self) -> String;
+
+    /// Transforms next part of content. Result can be empty if the transform accumulates data.
+    fn push(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>>;
+
+    /// Returns the rest of transformed content after the last part.
+    fn finish(&mut self) -> std::io::Result<Vec<u8>>;
+}
+
+/// Chooses transform of response by request, status code and header lines of response ("Content-Type" line,
+/// then lines set by `Response::headers` and typed headers). Returns None if the response is sent as is.
+/// See `Settings::response_transforms`.
+pub type TransformFactory = Arc<dyn Fn(&Request, u16, &str) -> Option<Box<dyn ResponseTransform>> + Send + Sync>;
+
+/// Transforms applied in order, output of each is input of next.
+#[derive(Default)]
+pub(crate) struct Pipeline {
+    transforms: Vec<Box<dyn ResponseTransform>>,
+}
+
+impl Pipeline {
+    pub(crate) fn add(&mut self, transform: Box<dyn ResponseTransform>) {
+        self.transforms.push(transform);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Header lines of all transforms.
+    pub(crate) fn headers(&self) -> String {
+        self.transforms.iter().map(|transform| transform.headers()).collect()
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut data = data.to_vec();
+        for transform in &mut self.transforms {
+            data = transform.push(&data)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Finishes transforms in order, the rest of each is passed through next ones before they are finished.
+    pub(crate) fn finish(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for transform in &mut self.transforms {
+            data = transform.push(&data)?;
+            data.extend_from_slice(&transform.finish()?);
+        }
+
+        Ok(data)
+    }
+
+    /// Transforms whole content.
+    pub(crate) fn apply(&mut self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut data = self.push(content)?;
+        data.extend_from_slice(&self.finish()?);
+        Ok(data)
+    }
+}
+
+/// Gzip compression of response content.
+pub struct GzipTransform {
+    encoder: GzEncoder<Vec<u8>>,
+}
+
+impl GzipTransform {
+    /// Creates compression with level from 0 (none) to 9 (best).
+    pub fn new(level: u32) -> Self {
+        GzipTransform { encoder: GzEncoder::new(Vec::new(), Compression::new(level.min(9))) }
+    }
+
+    /// Factory for `Settings::response_transforms` that compresses responses with default level for clients with
+    /// "Accept-Encoding: gzip" if "Content-Type" starts with one of `content_types`, for example "text/".
+    /// Responses with "Content-Encoding" set by the handler are not compressed again.
+    pub fn factory(content_types: &[&str]) -> TransformFactory {
+        let content_types: Vec<String> = content_types.iter().map(|content_type| content_type.to_string()).collect();
+        Arc::new(move |request, code, header_lines| {
+            let accepts_gzip = request.header_value("Accept-Encoding").is_some_and(|encoding| accepts_encoding(encoding, "gzip"));
+            let content_type = header_line_value(header_lines, "Content-Type").unwrap_or_default();
+            let compressible = content_types.iter().any(|prefix| content_type.starts_with(prefix.as_str()));
+            let encoded = header_line_value(header_lines, "Content-Encoding").is_some();
+            if !accepts_gzip || !compressible || encoded || code < 200 || code == 204 || code == 304 {
+                return None;
+            }
+
+            Some(Box::new(GzipTransform::new(Compression::default().level())))
+        })
+    }
+}
+
+/// Value of header line with the name, case insensitive.
+fn header_line_value<'a>(header_lines: &'a str, name: &str) -> Option<&'a str> {
+    header_lines.split("\r\n").find_map(|line| {
+        let (line_name, value) = line.split_once(':')?;
+        line_name.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+impl ResponseTransform for GzipTransform {
+    fn headers(&self) -> String {
+        "Content-Encoding: gzip\r\nVary: Accept-Encoding\r\n".to_string()
+    }
+
+    fn push(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.encoder.write_all(data)?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+
+    fn finish(&mut self) -> std::io::Result<Vec<u8>> {
+        self.encoder.try_finish()?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+}
@@ -0,0 +1,239 @@
+use crate::query::{decode_query_component, Query};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use std::collections::hash_map::{Entry, HashMap};
+
+/// Maps parsed query or url-encoded form into user type with serde. Requires "urlencoded" feature.
+/// Fields of structure are names of parameters. Repeated parameters like "tag=a&tag=b" go to sequence fields (`Vec<String>`),
+/// scalar field gets the first value. Empty value of `Option` field is `None`. Booleans are "true"/"on"/"1" and "false"/"off"/"0",
+/// enums are unit variants by name. Nested structures are not supported.
+pub fn from_query<T: DeserializeOwned>(query: &Query) -> Result<T, UrlencodedError> {
+    let mut fields: Vec<(String, Vec<String>)> = Vec::new();
+    // index of field by name, a form can have many distinct names
+    let mut indexes: HashMap<String, usize> = HashMap::new();
+    for part in query.iter() {
+        let name = decode_query_component(part.name)
+            .map_err(|_| UrlencodedError(format!("name of parameter \"{}\" is not UTF-8", String::from_utf8_lossy(part.name))))?;
+        let value = decode_query_component(part.value)
+            .map_err(|_| UrlencodedError(format!("value of \"{}\" is not UTF-8", name)))?;
+
+        match indexes.entry(name) {
+            Entry::Occupied(entry) => fields[*entry.get()].1.push(value),
+            Entry::Vacant(entry) => {
+                fields.push((entry.key().clone(), vec![value]));
+                entry.insert(fields.len() - 1);
+            }
+        }
+    }
+
+    T::deserialize(FieldsDeserializer { fields: fields.into_iter(), current: None })
+}
+
+/// Error of mapping query or form into user type, description for the client.
+#[derive(Debug, Clone)]
+pub struct UrlencodedError(pub String);
+
+impl std::fmt::Display for UrlencodedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UrlencodedError {}
+
+impl de::Error for UrlencodedError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        UrlencodedError(msg.to_string())
+    }
+}
+
+/// Parameters grouped by name in order of first occurrence.
+struct FieldsDeserializer {
+    fields: std::vec::IntoIter<(String, Vec<String>)>,
+    /// Name and values of the field whose key is given to the visitor.
+    current: Option<(String, Vec<String>)>,
+}
+
+impl<'de> de::Deserializer<'de> for FieldsDeserializer {
+    type Error = UrlencodedError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+        newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for FieldsDeserializer {
+    type Error = UrlencodedError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        match self.fields.next() {
+            Some((name, values)) => {
+                self.current = Some((name.clone(), values));
+                seed.deserialize(name.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        let (name, values) = self.current.take().ok_or_else(|| UrlencodedError("value without name".to_string()))?;
+        seed.deserialize(ValuesDeserializer(values))
+            .map_err(|err| UrlencodedError(format!("\"{}\": {}", name, err)))
+    }
+}
+
+/// All values of one parameter.
+struct ValuesDeserializer(Vec<String>);
+
+impl ValuesDeserializer {
+    fn first(mut self) -> ValueDeserializer {
+        ValueDeserializer(if self.0.is_empty() { String::new() } else { self.0.swap_remove(0) })
+    }
+}
+
+macro_rules! forward_to_first_value {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            de::Deserializer::$method(self.first(), visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for ValuesDeserializer {
+    type Error = UrlencodedError;
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(ValuesAccess(self.0.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.0.iter().all(|value| value.is_empty()) {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        self.first().deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        self.first().deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        self.first().deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_first_value! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128 deserialize_f32 deserialize_f64
+        deserialize_char deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf deserialize_unit
+        deserialize_map deserialize_identifier deserialize_ignored_any
+    }
+}
+
+struct ValuesAccess(std::vec::IntoIter<String>);
+
+impl<'de> SeqAccess<'de> for ValuesAccess {
+    type Error = UrlencodedError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        match self.0.next() {
+            Some(value) => seed.deserialize(ValueDeserializer(value)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+/// One decoded value.
+struct ValueDeserializer(String);
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            match self.0.trim().parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(err) => Err(UrlencodedError(format!("wrong value \"{}\": {}", self.0, err))),
+            }
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = UrlencodedError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0.as_str() {
+            "true" | "on" | "1" => visitor.visit_bool(true),
+            "false" | "off" | "0" => visitor.visit_bool(false),
+            _ => Err(UrlencodedError(format!("wrong value \"{}\": expected boolean", self.0))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
@@ -1,77 +1,375 @@
+use crate::acme::ChallengeStore;
+use crate::blocking_pool::BlockingPool;
+use crate::handler_error::HandlerError;
+use crate::health::HealthCheck;
+use crate::inspection::Inspector;
 use crate::http_error::HttpError;
-use crate::request::{RequestError, RequestData, Request};
-use crate::request_parser::{ParseHttpRequestSettings, Parser};
-use crate::tcp_session::TcpSession;
+use crate::mirror::{Mirror, MirrorEvent};
+use crate::redirect_server::{host_without_port, path_and_query};
+use crate::request::{HttpVersion, RequestError, RequestData, Request};
+use crate::request_parser::{ChunkedDecoder, ParseHttpRequestSettings, Parser, SkipHeadError};
+use crate::response::{check_header_value, http_status_code_with_name};
+use crate::server::{ProtocolMismatch, TimeoutKind};
+use crate::tcp_session::{SlowConsumerState, TcpSession};
+use crate::throttle::Throttle;
+use crate::timing::{RequestTimes, TimingCallback};
+use crate::tls::is_tls_handshake;
+use crate::transform::TransformFactory;
 use crate::websocket;
 use std::sync::atomic::Ordering;
-use crate::websocket::WebsocketError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use crate::websocket::{WebsocketError, WebsocketRegistry};
 
 /// Read, accumulate and process incoming data from clients. Parse http, websockets, tls and etc.
 pub(crate) struct WebSession {
     /// The framework user is using this.
     pub(crate) tcp_session: TcpSession,
     state: State,
+    /// Sets true if lock of session data is poisoned by panic in other thread. Session is closed in this case.
+    poisoned_lock: bool,
+    /// Received data left after processed request or websocket frame, for example pipelined requests.
+    /// Surpluses are coalesced here and processed by `process_data` in a loop instead of recursion.
+    pending: Vec<u8>,
+    /// Buffer for read from socket, allocated on first read, see `Settings::read_buffer_size`.
+    read_buf: Vec<u8>,
+    /// Websocket frames passed to the callback since last readiness event, see `Settings::websocket_frames_per_read_limit`.
+    frames_in_read: usize,
 }
 
 impl WebSession {
     pub fn new(tcp_session: TcpSession) -> Self {
+        tcp_session.inner.worker_counters.active_sessions.fetch_add(1, Ordering::SeqCst);
+
         WebSession {
             tcp_session,
-            state: State::Http(HttpState {
+            state: State::Http(Box::new(HttpState {
                 request_parser: Parser::new(),
                 content_len: 0,
                 already_read_content_len: 0,
+                chunked: None,
+                discard_limit: None,
                 pipelining_http_requests_count: 0,
-            })
+                requests_count: 0,
+                inspected: None,
+                idle_since: Instant::now(),
+                first_byte: None,
+                skipping_failed_head: false,
+                close_after_content: false,
+                mirroring: None,
+            })),
+            poisoned_lock: false,
+            pending: Vec::new(),
+            read_buf: Vec::new(),
+            frames_in_read: 0,
         }
     }
 
-    pub fn read_stream(&mut self, settings: &Settings, read_buf: &mut [u8]) {
-        if let State::Http(http) = &mut self.state {
-            http.pipelining_http_requests_count = 0;
-        }
+    /// Checks timeouts of settings, closes the connection if one is exceeded and returns its kind.
+    pub fn close_if_timed_out(&mut self, settings: &Settings) -> Option<TimeoutKind> {
+        let http = match &self.state {
+            State::Http(http) => http,
+            _ => return None,
+        };
 
-        match self.tcp_session.inner.read_stream(read_buf) {
-            Ok(read_cnt) => {
-                if read_cnt == 0 {
+        if let (Some(timeout), Some((first_byte, _))) = (settings.request_head_timeout, http.first_byte) {
+            if first_byte.elapsed() > timeout {
+                if settings.respond_to_parse_errors {
+                    self.tcp_session.close_by_handler_error(&HandlerError::new(408, http_status_code_with_name(408)));
+                } else {
                     self.tcp_session.close();
-                    return;
                 }
+                return Some(TimeoutKind::RequestHead);
+            }
+        }
+
+        if let Some(timeout) = settings.idle_timeout {
+            let inner = &self.tcp_session.inner;
+            let idle = http.first_byte.is_none()
+                && inner.requests_in_flight.load(Ordering::SeqCst) == 0
+                && inner.content_callback.lock().is_ok_and(|content_callback| content_callback.is_none())
+                && !self.tcp_session.has_queued_data()
+                && inner.last_activity.lock().is_ok_and(|last_activity| last_activity.elapsed() > timeout);
+
+            if idle {
+                self.tcp_session.close();
+                return Some(TimeoutKind::Idle);
+            }
+        }
+
+        None
+    }
 
-                self.process_data(&read_buf[..read_cnt], settings);
+    /// Checks queue of data waiting for write, returns number of queued bytes when the client becomes slow consumer.
+    pub fn check_slow_consumer(&mut self, slow_consumer: &SlowConsumer) -> Option<usize> {
+        let queued_bytes = self.tcp_session.queued_bytes();
+        let mut state = self.tcp_session.inner.slow_consumer.lock().ok()?;
+        if queued_bytes <= slow_consumer.queued_bytes {
+            *state = SlowConsumerState::Normal;
+            return None;
+        }
+
+        match *state {
+            SlowConsumerState::Normal => *state = SlowConsumerState::Backlogged(Instant::now()),
+            SlowConsumerState::Backlogged(since) if since.elapsed() >= slow_consumer.duration => {
+                *state = SlowConsumerState::Reported;
+                return Some(queued_bytes);
             }
-            Err(err) => {
-                if err.kind() != std::io::ErrorKind::WouldBlock {
-                    if self.tcp_session.is_http_mode() {
-                        self.tcp_session.call_http_callback(Err(HttpError::ReadError(err)));
-                    } else {
-                        self.tcp_session.call_websocket_callback(Err(WebsocketError::ReadError(err)));
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Returns true if lock of session data was found poisoned by panic in other thread.
+    pub fn is_poisoned_lock(&self) -> bool {
+        self.poisoned_lock
+    }
+
+    /// Reads from socket until it has no data or limits of reading per readiness event are reached,
+    /// the rest is read on next iteration of the worker because socket is registered level-triggered.
+    pub fn read_stream(&mut self, settings: &Settings) {
+        self.frames_in_read = 0;
+
+        if matches!(self.state, State::Websocket(_)) && !self.pending.is_empty() {
+            // frames left after the limit of previous event are processed before reading, so a busy client
+            // is slowed down by TCP flow control instead of growth of pending data
+            self.process_pending(settings);
+            if !self.pending.is_empty() {
+                return;
+            }
+        }
+
+        // taken because received data is processed by methods of the session
+        let mut read_buf = std::mem::take(&mut self.read_buf);
+        let initial_size = settings.read_buffer_size.max(1);
+        if read_buf.len() < initial_size {
+            read_buf.resize(initial_size, 0);
+        }
+
+        self.read_into(settings, &mut read_buf);
+
+        self.read_buf = read_buf;
+    }
+
+    /// Reads with the buffer, see `read_stream`. The buffer grows twice up to `Settings::read_buffer_limit` when a read fills it
+    /// and shrinks back to `Settings::read_buffer_size` when the socket has no more data.
+    fn read_into(&mut self, settings: &Settings, read_buf: &mut Vec<u8>) {
+        let mut read_total = 0;
+        let mut reads = 0;
+        loop {
+            if let State::Http(http) = &mut self.state {
+                http.pipelining_http_requests_count = 0;
+            }
+
+            match self.tcp_session.inner.read_stream(read_buf) {
+                Ok(read_cnt) => {
+                    if read_cnt == 0 {
+                        self.tcp_session.on_read_closed();
+                        return;
                     }
 
-                    self.tcp_session.close();
+                    let first_data = self.tcp_session.bytes_read() == 0;
+                    if !self.tcp_session.count_read(read_cnt) {
+                        return;
+                    }
+
+                    if first_data && self.is_tls_on_plaintext(&read_buf[..read_cnt]) {
+                        self.respond_to_tls_on_plaintext(settings);
+                        return;
+                    }
+
+                    self.process_data(&read_buf[..read_cnt], settings);
+
+                    reads += 1;
+                    read_total += read_cnt;
+                    if self.tcp_session.need_close() || self.poisoned_lock {
+                        return;
+                    }
+
+                    if read_cnt == read_buf.len() && read_buf.len() < settings.read_buffer_limit {
+                        read_buf.resize((read_buf.len() * 2).min(settings.read_buffer_limit), 0);
+                    }
+
+                    // decrypted data left in TLS session is read regardless of limits, the socket may have no more data
+                    // for the next event
+                    let limit_reached = reads >= settings.reads_per_event_limit.max(1)
+                        || read_total >= settings.read_bytes_per_event_limit
+                        || self.frames_budget_exhausted(settings);
+                    if limit_reached && !self.tcp_session.inner.has_tls_plaintext() {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    if err.kind() == std::io::ErrorKind::WouldBlock {
+                        let initial_size = settings.read_buffer_size.max(1);
+                        if read_buf.len() > initial_size {
+                            read_buf.truncate(initial_size);
+                            read_buf.shrink_to_fit();
+                        }
+                    } else {
+                        if self.tcp_session.is_http_mode() {
+                            self.tcp_session.call_http_callback(Err(HttpError::ReadError(err)));
+                        } else {
+                            self.tcp_session.call_websocket_callback(Err(WebsocketError::ReadError(err)));
+                        }
+
+                        self.tcp_session.close();
+                    }
+                    return;
                 }
             }
         }
     }
 
+    /// First received data of plaintext HTTP connection is TLS handshake.
+    fn is_tls_on_plaintext(&self, data: &[u8]) -> bool {
+        matches!(self.state, State::Http(_))
+            && !self.tcp_session.is_tls()
+            && self.tcp_session.inner.raw_callback.lock().is_ok_and(|callback| callback.is_none())
+            && is_tls_handshake(data)
+    }
+
+    /// Closes connection of client that speaks TLS, with response by `Settings::tls_on_plaintext`.
+    fn respond_to_tls_on_plaintext(&self, settings: &Settings) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(session_id = self.tcp_session.id(), "tls handshake on plaintext connection");
+
+        let response = match &settings.tls_on_plaintext {
+            TlsOnPlaintext::Close => None,
+            TlsOnPlaintext::Redirect(location) if check_header_value(location).is_ok() => Some(format!(
+                "HTTP/1.1 301 Moved Permanently\r\nDate: {}\r\nConnection: close\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                self.tcp_session.rfc7231_date(),
+                location,
+            ).into_bytes()),
+            // also wrong URL of redirect
+            _ => Some(HandlerError::bad_request(TLS_ON_PLAINTEXT_MESSAGE).response(self.tcp_session.http_version(), &self.tcp_session.rfc7231_date())),
+        };
+
+        self.tcp_session.close_by_protocol_mismatch(ProtocolMismatch::TlsOnPlaintext, response.as_deref());
+    }
+
+    /// Processes data received before the session is added to the worker, for example with the last packet of TLS handshake.
+    pub fn process_received(&mut self, data: &[u8], settings: &Settings) {
+        self.tcp_session.inner.on_data_received(data);
+        if self.tcp_session.count_read(data.len()) {
+            self.process_data(data, settings);
+        }
+    }
+
+    /// Processes received data, then surpluses left after processing of requests and frames.
     fn process_data(&mut self, data: &[u8], settings: &Settings) {
-        if self.tcp_session.need_close() {
+        // data received earlier is processed first
+        if !self.pending.is_empty() || self.waits_blocking_job() || self.frames_budget_exhausted(settings) {
+            self.defer(data, settings);
+        } else {
+            self.process_chunk(data, settings);
+        }
+
+        self.process_pending(settings);
+    }
+
+    /// Processes surpluses left after processing of requests and frames.
+    fn process_pending(&mut self, settings: &Settings) {
+        while !self.pending.is_empty() && !self.tcp_session.need_close() && !self.poisoned_lock && !self.waits_blocking_job() && !self.frames_budget_exhausted(settings) {
+            let mut chunk = std::mem::take(&mut self.pending);
+            self.process_chunk(&chunk, settings);
+            if self.pending.is_empty() {
+                // allocation is reused for next surpluses
+                chunk.clear();
+                self.pending = chunk;
+            }
+        }
+
+        if !self.pending.is_empty() && self.frames_budget_exhausted(settings) {
+            // the socket may have no more data, the rest is processed on next iteration of the worker
+            self.tcp_session.wake();
+        }
+    }
+
+    /// Limit of websocket frames per readiness event is reached, see `Settings::websocket_frames_per_read_limit`.
+    fn frames_budget_exhausted(&self, settings: &Settings) -> bool {
+        matches!(self.state, State::Websocket(_)) && self.frames_in_read >= settings.websocket_frames_per_read_limit.max(1)
+    }
+
+    /// Parsing of next request waits for job of `Request::spawn_blocking` or queued request of `ConcurrencyLimit`, so responses keep order of requests.
+    fn waits_blocking_job(&self) -> bool {
+        matches!(self.state, State::Http(_))
+            && self.tcp_session.inner.blocking_jobs.load(Ordering::SeqCst) > 0
+            && self.tcp_session.inner.content_callback.lock().is_ok_and(|callback| callback.is_none())
+    }
+
+    /// Continues processing of pipelined requests received while job of `Request::spawn_blocking` was running
+    /// and of websocket frames left after `Settings::websocket_frames_per_read_limit` was reached.
+    pub(crate) fn resume_pending(&mut self, settings: &Settings) {
+        self.frames_in_read = 0;
+        self.process_pending(settings);
+    }
+
+    /// Keeps surplus of processed data for processing by `process_data` after return from current processing.
+    /// The connection is closed if unprocessed data exceeds `Settings::pending_bytes_limit`.
+    fn defer(&mut self, surplus: &[u8], settings: &Settings) {
+        if self.pending.len() + surplus.len() > settings.pending_bytes_limit {
+            if self.tcp_session.is_http_mode() {
+                self.tcp_session.call_http_callback(Err(HttpError::ParseRequestError(RequestError::PipeliningRequestsLimit)));
+            }
+            self.tcp_session.close();
             return;
         }
 
+        self.pending.extend_from_slice(surplus);
+    }
+
+    fn process_chunk(&mut self, data: &[u8], settings: &Settings) {
+        // data received after `TcpSession::shutdown_write` is discarded
+        if self.tcp_session.need_close() || self.tcp_session.linger_deadline().is_some() {
+            return;
+        }
+
+        // detect switching to raw mode
+        if !matches!(self.state, State::Raw) && self.tcp_session.inner.raw_callback.lock().is_ok_and(|callback| callback.is_some()) {
+            if let State::Websocket(_) = self.state {
+                self.tcp_session.inner.worker_counters.websocket_sessions.fetch_sub(1, Ordering::SeqCst);
+            }
+
+            self.state = State::Raw;
+            if let Ok(mut http_request_callback) = self.tcp_session.inner.http_request_callback.lock() {
+                *http_request_callback = None;
+            }
+            if let Ok(mut websocket_callback) = self.tcp_session.inner.websocket_callback.lock() {
+                *websocket_callback = None;
+            }
+            self.tcp_session.inner.is_http_mode.store(false, Ordering::SeqCst);
+        }
+
+        // detect deferred websocket handshake
+        if let State::Http(_) = self.state {
+            if self.tcp_session.inner.deferred_handshake.load(Ordering::SeqCst) {
+                self.state = State::HandshakePending(Vec::new());
+            }
+        }
+
         // detect upgrading to websocket
         if let State::Http(_) = self.state {
             if let Ok(callback) = self.tcp_session.inner.websocket_callback.lock() {
                 if callback.is_some() {
                     self.state = State::Websocket(websocket::Parser::new());
+                    self.tcp_session.inner.worker_counters.websocket_sessions.fetch_add(1, Ordering::SeqCst);
+                    self.tcp_session.inner.coalesced_frame_limit.store(settings.websocket_coalescing.unwrap_or(0), Ordering::SeqCst);
                 }
             }
         }
 
         match &mut self.state {
             State::Http(_) => {
-                let content_callback = self.tcp_session.inner.content_callback.lock()
-                    .unwrap_or_else(|err| { unreachable!(err) });
+                let content_callback = match self.tcp_session.inner.content_callback.lock() {
+                    Ok(content_callback) => content_callback,
+                    Err(_) => {
+                        self.poisoned_lock = true;
+                        self.tcp_session.close();
+                        return;
+                    }
+                };
                 let parse_request = content_callback.is_none();
                 drop(content_callback); // unlock
 
@@ -84,6 +382,18 @@ impl WebSession {
             State::Websocket(_) => {
                 self.on_websocket_read(data, settings);
             }
+            State::HandshakePending(received) => {
+                received.extend_from_slice(data);
+                if received.len() > settings.websocket_payload_limit {
+                    self.tcp_session.close();
+                    return;
+                }
+
+                self.resume_deferred_websocket(settings);
+            }
+            State::Raw => {
+                self.tcp_session.call_raw_callback(data);
+            }
         }
     }
 
@@ -96,49 +406,276 @@ impl WebSession {
                 return;
             }
 
+            if http.skipping_failed_head {
+                match http.request_parser.skip_failed_head(data, SKIPPED_HEAD_LIMIT) {
+                    Ok(Some(surplus)) => {
+                        http.skipping_failed_head = false;
+                        http.first_byte = None;
+                        if !surplus.is_empty() {
+                            self.defer(&surplus, settings);
+                        }
+                    }
+                    Ok(None) => {}
+                    // the response to the failed request is already sent
+                    Err(_) => self.tcp_session.close_when_sent(),
+                }
+                return;
+            }
+
+            let (first_byte, received_at) = *http.first_byte.get_or_insert_with(|| (Instant::now(), SystemTime::now()));
+
             match http.request_parser.push(data, &settings.parse_http_request_settings) {
                 Ok((received_request, surplus)) => {
-                    self.process_received_request(received_request, surplus, settings);
+                    let head_parsed = Instant::now();
+                    let times = RequestTimes { idle_since: http.idle_since, first_byte, received_at, head_parsed };
+                    http.idle_since = head_parsed;
+                    http.first_byte = None;
+                    self.process_received_request(received_request, times, surplus, settings);
                 }
                 Err(parse_err) => {
                     match parse_err {
                         RequestError::Partial => {}
-                        parse_err => {
-                            self.tcp_session.call_http_callback(Err(HttpError::ParseRequestError(parse_err)));
-                            // close anyway
-                            self.tcp_session.close();
-                        }
+                        parse_err if settings.recover_from_parse_errors && parse_err.is_recoverable() => self.recover_from_parse_error(parse_err, settings),
+                        parse_err => self.close_by_parse_error(parse_err, settings),
                     }
                 }
             }
         }
     }
 
-    fn process_received_request(&mut self, received_request: RequestData, surplus: Vec<u8>, settings: &Settings) {
+    /// Responds to the failed request keeping the connection alive and skips the rest of its head, if it's safe.
+    /// Otherwise closes the connection as `close_by_parse_error`.
+    fn recover_from_parse_error(&mut self, parse_err: RequestError, settings: &Settings) {
+        let http = match &mut self.state {
+            State::Http(http) => http,
+            _ => return,
+        };
+
+        let code = parse_err.status_code().filter(|_| settings.respond_to_parse_errors);
+        // response to previous request may be not sent yet, responses must keep order of requests
+        let previous_responded = self.tcp_session.inner.requests_in_flight.load(Ordering::SeqCst) == 0;
+        let skipped = match (code, previous_responded) {
+            (Some(_), true) => http.request_parser.skip_failed_head(&[], SKIPPED_HEAD_LIMIT),
+            _ => Err(SkipHeadError::Content),
+        };
+        let (code, surplus) = match (code, skipped) {
+            (Some(code), Ok(surplus)) => (code, surplus),
+            _ => return self.close_by_parse_error(parse_err, settings),
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(session_id = self.tcp_session.id(), error = ?parse_err, "request parse error, recovering");
+
+        http.first_byte = None;
+        http.skipping_failed_head = surplus.is_none();
+        let rfc7231_date = self.tcp_session.inner.http_date_string.read().map(|date| date.clone()).unwrap_or_default();
+        self.tcp_session.send(&HandlerError::new(code, http_status_code_with_name(code)).keep_alive_response(self.tcp_session.http_version(), &rfc7231_date));
+        self.tcp_session.call_http_callback(Err(HttpError::ParseRequestError(parse_err)));
+
+        if let Some(surplus) = surplus {
+            if !surplus.is_empty() {
+                self.defer(&surplus, settings);
+            }
+        }
+    }
+
+    /// Passes the error to the http callback and closes the connection, with response if it's enabled in settings.
+    fn close_by_parse_error(&self, parse_err: RequestError, settings: &Settings) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(session_id = self.tcp_session.id(), error = ?parse_err, "request parse error");
+
+        let code = parse_err.status_code().filter(|_| settings.respond_to_parse_errors);
+        self.tcp_session.call_http_callback(Err(HttpError::ParseRequestError(parse_err)));
+        // close anyway
+        match code {
+            Some(code) => self.tcp_session.close_by_handler_error(&HandlerError::new(code, http_status_code_with_name(code))),
+            None => self.tcp_session.close(),
+        }
+    }
+
+    /// Responds "413 Payload Too Large" to request with "Content-Length" over `Settings::content_length_limit`
+    /// and closes the connection, after draining of the content if `Settings::rejected_content` allows it.
+    fn reject_content_length(&mut self, received_request: &RequestData, surplus: Vec<u8>, settings: &Settings) {
+        let http = match &mut self.state {
+            State::Http(http) => http,
+            _ => return,
+        };
+
+        let drain_limit = match settings.rejected_content {
+            // the client waiting for "100 Continue" doesn't send content
+            _ if received_request.expects_continue() => 0,
+            // the reset can't be avoided, so the client doesn't wait for draining
+            RejectedContent::Drain(limit) if received_request.content_len() > limit => 0,
+            RejectedContent::Drain(limit) => limit,
+            RejectedContent::Close => 0,
+        };
+        // response to previous request may be not sent yet, responses must keep order of requests
+        let previous_responded = self.tcp_session.inner.requests_in_flight.load(Ordering::SeqCst) == 0;
+        if drain_limit == 0 || !previous_responded || !settings.respond_to_parse_errors {
+            self.close_by_parse_error(RequestError::ContentLengthLimit, settings);
+            return;
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(session_id = self.tcp_session.id(), content_len = received_request.content_len(), "content length over limit, draining");
+
+        match self.tcp_session.inner.content_callback.lock() {
+            Ok(mut content_callback) => *content_callback = Some((Box::new(|_, _| Ok(())), None)),
+            Err(_) => {
+                self.poisoned_lock = true;
+                self.tcp_session.close();
+                return;
+            }
+        }
+
+        http.start_content(received_request.content_len(), false, None);
+        http.close_after_content = true;
+
+        let rfc7231_date = self.tcp_session.inner.http_date_string.read().map(|date| date.clone()).unwrap_or_default();
+        self.tcp_session.send(&HandlerError::new(413, http_status_code_with_name(413)).response(self.tcp_session.http_version(), &rfc7231_date));
+        self.tcp_session.call_http_callback(Err(HttpError::ParseRequestError(RequestError::ContentLengthLimit)));
+
+        self.defer(&surplus, settings);
+    }
+
+    fn process_received_request(&mut self, received_request: RequestData, times: RequestTimes, surplus: Vec<u8>, settings: &Settings) {
+        self.tcp_session.inner.http_1_0.store(received_request.version() == &HttpVersion::Http1_0, Ordering::SeqCst);
+
         if let State::Http(http) = &mut self.state {
             let content_len = received_request.content_len();
+            let chunked = received_request.is_chunked();
+            let has_content = received_request.has_content();
+
+            http.requests_count += 1;
+            let counters = &self.tcp_session.inner.worker_counters;
+            counters.requests_total.fetch_add(1, Ordering::SeqCst);
+            if http.requests_count > 1 {
+                counters.reused_connection_requests_total.fetch_add(1, Ordering::SeqCst);
+            }
 
-            self.tcp_session.call_http_callback(Ok(Request::new(received_request, self.tcp_session.clone())));
+            if !chunked && settings.content_length_limit.is_some_and(|limit| content_len > limit) {
+                self.reject_content_length(&received_request, surplus, settings);
+                return;
+            }
 
-            if let Ok(content_callback) = self.tcp_session.inner.content_callback.lock().as_deref_mut() {
-                let complete = false;
-                if let Some((content_callback, request)) = content_callback {
-                    if content_len == 0 {
-                        let request = request.take();
-                        if content_callback(&[], request).is_err() {
+            #[cfg(feature = "tracing")]
+            let span = tracing::debug_span!("request", session_id = self.tcp_session.id(), request_id = http.requests_count, method = received_request.method(), path = received_request.path());
+            #[cfg(feature = "tracing")]
+            let entered = span.enter();
+
+            let request = Request::new(received_request, self.tcp_session.clone(), http.requests_count, settings.default_response_headers.clone(), settings.content_decompression_limit, settings.verify_content_digest, times, settings.on_timing.clone(), settings.response_transforms.clone(), settings.trust_forwarded_proto, settings.blocking_pool.clone());
+            if let Some(mirror) = &settings.mirror {
+                if mirror.start(self.tcp_session.id(), &request) {
+                    if has_content {
+                        http.mirroring = Some(mirror.clone());
+                    } else {
+                        mirror.send(self.tcp_session.id(), MirrorEvent::End);
+                    }
+                }
+            }
+            let request = match &settings.health_check {
+                Some(health_check) if !has_content => health_check.respond(request),
+                _ => Some(request),
+            };
+            let request = match (&settings.acme_challenges, request) {
+                (Some(acme_challenges), Some(request)) if !has_content => acme_challenges.respond(request),
+                (_, request) => request,
+            };
+            let request = request.and_then(|request| respond_if_not_canonical_host(request, settings));
+            let request = request.and_then(|request| respond_trace_or_connect(request, settings));
+            let request = request.and_then(respond_if_unsupported_expectation);
+            let request = request.and_then(|request| respond_if_overloaded(request, settings));
+            let request = match (&settings.inspector, request) {
+                (Some(inspector), Some(mut request)) if inspector.buffers(content_len, chunked) => {
+                    request.send_continue_if_expected();
+                    // the check and the handler are called after receiving of content
+                    let content = Arc::new(Mutex::new(Vec::with_capacity(content_len)));
+                    let buffer = content.clone();
+                    let limit = inspector.max_buffered_len();
+                    match self.tcp_session.inner.content_callback.lock() {
+                        Ok(mut content_callback) => {
+                            *content_callback = Some((Box::new(move |data, _| {
+                                if let Ok(mut buffer) = buffer.lock() {
+                                    if buffer.len() + data.len() > limit {
+                                        return Err(HandlerError::new(413, http_status_code_with_name(413)).into());
+                                    }
+                                    buffer.extend_from_slice(data);
+                                }
+                                Ok(())
+                            }), None));
+                        }
+                        Err(_) => {
+                            self.poisoned_lock = true;
                             self.tcp_session.close();
                             return;
                         }
                     }
 
-                    http.content_len = content_len;
-                    http.already_read_content_len = 0;
+                    http.inspected = Some((Box::new(request), content));
+                    http.start_content(content_len, chunked, None);
+
+                    self.defer(&surplus, settings);
+                    return;
                 }
+                (Some(inspector), Some(request)) => match inspector.check(&request, None) {
+                    Ok(()) => Some(request),
+                    Err(err) => {
+                        self.tcp_session.close_by_handler_error(&err);
+                        None
+                    }
+                },
+                (_, request) => request,
+            };
 
-                if complete {
-                    *content_callback = None;
-                    http.content_len = 0;
-                    http.already_read_content_len = 0;
+            if let Some(request) = request {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("calling handler");
+
+                self.tcp_session.call_http_callback(Ok(request));
+            }
+
+            #[cfg(feature = "tracing")]
+            drop(entered); // don't include next pipelined requests to the span
+
+            match self.tcp_session.inner.content_callback.lock().as_deref_mut() {
+                Ok(content_callback) => {
+                    let complete = false;
+                    if let Some((content_callback, request)) = content_callback {
+                        if !has_content {
+                            let request = request.take();
+                            if let Err(err) = content_callback(&[], request) {
+                                self.tcp_session.close_by_handler_error(err.as_ref());
+                                return;
+                            }
+                        }
+
+                        http.start_content(content_len, chunked, None);
+                    } else if has_content {
+                        // the handler didn't read content, it must not be parsed as next request
+                        if content_len > settings.discard_unread_content_limit {
+                            self.tcp_session.close_when_sent();
+                            return;
+                        }
+
+                        *content_callback = Some((Box::new(|_, _| Ok(())), None));
+                        http.start_content(content_len, chunked, Some(settings.discard_unread_content_limit));
+                    }
+
+                    if complete {
+                        *content_callback = None;
+                        http.start_content(0, false, None);
+                    }
+                }
+                Err(_) => {
+                    self.poisoned_lock = true;
+                    self.tcp_session.close();
+                    return;
+                }
+            }
+
+            if self.tcp_session.inner.deferred_handshake.load(Ordering::SeqCst) {
+                if let Ok(mut http_request_callback) = self.tcp_session.inner.http_request_callback.lock() {
+                    *http_request_callback = None;
                 }
             }
 
@@ -151,30 +688,103 @@ impl WebSession {
                 }
             }
 
-            if !surplus.is_empty() && !self.tcp_session.need_close() {
-                // here is recursion
-                self.process_data(&surplus, settings);
+            if !self.tcp_session.need_close() {
+                self.defer(&surplus, settings);
+            }
+        }
+    }
+
+    /// Switches to websocket after approval of deferred handshake if frame callback is set,
+    /// then processes data received while waiting.
+    pub(crate) fn resume_deferred_websocket(&mut self, settings: &Settings) {
+        if let State::HandshakePending(received) = &mut self.state {
+            let inner = &self.tcp_session.inner;
+            let frame_callback_is_set = inner.websocket_callback.lock().is_ok_and(|callback| callback.is_some());
+            if inner.handshake_pending.load(Ordering::SeqCst) || !frame_callback_is_set || self.tcp_session.need_close() {
+                return;
+            }
+
+            let received = std::mem::take(received);
+            self.state = State::Websocket(websocket::Parser::new());
+            inner.worker_counters.websocket_sessions.fetch_add(1, Ordering::SeqCst);
+            inner.coalesced_frame_limit.store(settings.websocket_coalescing.unwrap_or(0), Ordering::SeqCst);
+            inner.is_http_mode.store(false, Ordering::SeqCst);
+
+            if !received.is_empty() {
+                self.process_data(&received, settings);
             }
         }
     }
 
     fn read_content(&mut self, data: &[u8], settings: &Settings) {
-        let mut content_callback = self.tcp_session.inner.content_callback.lock()
-            .unwrap_or_else(|err| { unreachable!(err) });
+        let mut content_callback = match self.tcp_session.inner.content_callback.lock() {
+            Ok(content_callback) => content_callback,
+            Err(_) => {
+                self.poisoned_lock = true;
+                self.tcp_session.close();
+                return;
+            }
+        };
 
         if let State::Http(http) = &mut self.state {
-            let mid = http.content_len.checked_sub(http.already_read_content_len)
-                .unwrap_or_else(|| unreachable!())
-                .min(data.len());
+            let mut decoded = Vec::new();
+            let (content, surplus, complete) = match &mut http.chunked {
+                Some(decoder) => match decoder.push(data, &mut decoded) {
+                    Ok(content_end) => {
+                        if http.discard_limit.is_some_and(|limit| decoder.decoded_len() > limit) {
+                            self.tcp_session.close_when_sent();
+                            return;
+                        }
+                        if settings.content_length_limit.is_some_and(|limit| decoder.decoded_len() > limit) {
+                            *content_callback = None;
+                            drop(content_callback); // unlock
+                            self.close_by_parse_error(RequestError::ContentLengthLimit, settings);
+                            return;
+                        }
+
+                        let surplus = &data[content_end.unwrap_or(data.len())..];
+                        (&decoded[..], surplus, content_end.is_some())
+                    }
+                    Err(err) => {
+                        drop(content_callback); // unlock
+                        self.close_by_parse_error(err, settings);
+                        return;
+                    }
+                },
+                None => {
+                    let mid = http.content_len.checked_sub(http.already_read_content_len)
+                        .unwrap_or_else(|| unreachable!())
+                        .min(data.len());
+
+                    let (content, surplus) = data.split_at(mid);
+                    http.already_read_content_len += content.len();
+                    (content, surplus, http.already_read_content_len >= http.content_len)
+                }
+            };
 
-            let (content, surplus) = data.split_at(mid);
-            http.already_read_content_len += content.len();
-            let complete = http.already_read_content_len >= http.content_len;
+            if let Some(mirror) = &http.mirroring {
+                mirror.send(self.tcp_session.id(), MirrorEvent::Content(&data[..data.len() - surplus.len()]));
+                if complete {
+                    mirror.send(self.tcp_session.id(), MirrorEvent::End);
+                    http.mirroring = None;
+                }
+            }
+
+            let trailers = match &mut http.chunked {
+                Some(decoder) if complete => decoder.take_trailers(),
+                _ => Vec::new(),
+            };
+            if let (true, Some((request, _))) = (complete, &mut http.inspected) {
+                request.set_trailers(trailers.clone());
+            }
 
             if let Some((content_callback, request)) = &mut *content_callback {
-                let request = if complete { request.take() } else { None };
-                if content_callback(content, request).is_err() {
-                    self.tcp_session.close();
+                let mut request = if complete { request.take() } else { None };
+                if let Some(request) = &mut request {
+                    request.set_trailers(trailers);
+                }
+                if let Err(err) = content_callback(content, request) {
+                    self.tcp_session.close_by_handler_error(err.as_ref());
                 }
             }
 
@@ -185,15 +795,58 @@ impl WebSession {
             if complete {
                 *content_callback = None;
 
-                http.content_len = 0;
-                http.already_read_content_len = 0;
+                http.start_content(0, false, None);
 
                 drop(content_callback); // unlock
 
-                if !surplus.is_empty() {
-                    // here is recursion
-                    self.process_data(&surplus, settings);
+                if std::mem::take(&mut http.close_after_content) {
+                    // rest of pipelined data isn't processed after rejected request
+                    self.tcp_session.close_when_sent();
+                    return;
                 }
+
+                self.finish_inspection(settings);
+
+                if !self.tcp_session.need_close() {
+                    self.defer(surplus, settings);
+                }
+            }
+        }
+    }
+
+    /// Checks request with received content by `Settings::inspector`, then calls the http callback
+    /// and passes the content to it if it reads content.
+    fn finish_inspection(&mut self, settings: &Settings) {
+        let (request, content) = match &mut self.state {
+            State::Http(http) => match http.inspected.take() {
+                Some(inspected) => inspected,
+                None => return,
+            },
+            _ => return,
+        };
+
+        let content = content.lock().map(|mut content| std::mem::take(&mut *content)).unwrap_or_default();
+        if let Some(inspector) = &settings.inspector {
+            if let Err(err) = inspector.check(&request, Some(&content)) {
+                self.tcp_session.close_by_handler_error(&err);
+                return;
+            }
+        }
+
+        self.tcp_session.call_http_callback(Ok(*request));
+
+        let content_callback = match self.tcp_session.inner.content_callback.lock() {
+            Ok(mut content_callback) => content_callback.take(),
+            Err(_) => {
+                self.poisoned_lock = true;
+                self.tcp_session.close();
+                return;
+            }
+        };
+
+        if let Some((mut content_callback, mut request)) = content_callback {
+            if let Err(err) = content_callback(&content, request.take()) {
+                self.tcp_session.close_by_handler_error(err.as_ref());
             }
         }
     }
@@ -204,12 +857,13 @@ impl WebSession {
                 Ok(result) => {
                     if let Some((frame, surplus)) = result {
                         let frame_is_close = frame.is_close();
+                        self.frames_in_read += 1;
                         self.tcp_session.call_websocket_callback(Ok(&frame));
 
                         if frame_is_close {
                             self.tcp_session.close();
-                        } else if !surplus.is_empty() {
-                            self.process_data(&surplus, settings); // here is recursion
+                        } else {
+                            self.defer(&surplus, settings);
                         }
                     }
                 }
@@ -222,6 +876,24 @@ impl WebSession {
     }
 }
 
+impl Drop for WebSession {
+    fn drop(&mut self) {
+        let worker_counters = &self.tcp_session.inner.worker_counters;
+        worker_counters.active_sessions.fetch_sub(1, Ordering::SeqCst);
+        match &self.state {
+            State::Websocket(_) => {
+                worker_counters.websocket_sessions.fetch_sub(1, Ordering::SeqCst);
+            }
+            State::Http(http) => {
+                if let Some(mirror) = &http.mirroring {
+                    mirror.send(self.tcp_session.id(), MirrorEvent::Aborted);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 /// Settings of incoming data processing.
 #[derive(Clone)]
 pub struct Settings {
@@ -229,6 +901,165 @@ pub struct Settings {
     pub parse_http_request_settings: ParseHttpRequestSettings,
     /// Limit of payload length in websocket frame.
     pub websocket_payload_limit: usize,
+    /// Maximum of websocket frames passed to the callback per readiness event of one connection, so a client sending
+    /// thousands of tiny frames doesn't delay other sessions. The rest is processed on next iteration of the worker,
+    /// the socket isn't read until it's processed. Default 256.
+    pub websocket_frames_per_read_limit: usize,
+    /// If set, frames sent by `Websocket` are written by the worker after processing of current events, consecutive frames
+    /// not longer than this value are joined into one write. Reduces number of writes for chatty websockets. Default None.
+    pub websocket_coalescing: Option<usize>,
+    /// Built-in responder of health check requests. If None, health check requests are passed to the http callback.
+    pub health_check: Option<HealthCheck>,
+    /// Responder of ACME HTTP-01 challenges. If None, challenge requests are passed to the http callback.
+    pub acme_challenges: Option<ChallengeStore>,
+    /// Canonical host of the site, for example "www.example.com" or "example.com:8443". If set, requests with other
+    /// host in "Host" header are redirected to it with "301 Moved Permanently" keeping scheme, path and query,
+    /// HTTP/1.1 requests without "Host" or with invalid one are answered with "400 Bad Request". Default None.
+    pub canonical_host: Option<String>,
+    /// If true, `Request::scheme` and `Request::url` use scheme from "Forwarded" or "X-Forwarded-Proto" header,
+    /// set it only behind reverse proxy that overwrites these headers. Default false.
+    pub trust_forwarded_proto: bool,
+    /// If true, "TRACE" requests are answered with echo of request head (without "Cookie" and "Authorization" headers),
+    /// otherwise with "405 Method Not Allowed". They are never passed to the http callback. Default false.
+    pub trace_echo: bool,
+    /// If true, "CONNECT" requests are answered with "405 Method Not Allowed" without passing to the http callback. Default true.
+    pub reject_connect: bool,
+    /// Headers (name, value) that are added to all responses built by `Response` and `StaticFiles`,
+    /// for example "Server" or "X-Content-Type-Options". Header is not added if response already has header with same name.
+    pub default_response_headers: Arc<Vec<(String, String)>>,
+    /// Transforms of content of responses built by `Response`, for example `GzipTransform::factory`.
+    /// Applied in order after transforms set by `Response::transform`. Default empty.
+    pub response_transforms: Arc<Vec<TransformFactory>>,
+    /// Maximum of received bytes left unprocessed after request or websocket frame, for example pipelined requests.
+    /// If exceeded, the connection is closed. Default 1 MB.
+    pub pending_bytes_limit: usize,
+    /// Limit of length of request content that is read and discarded if the http callback returns without calling
+    /// `Request::read_content`, so that the content is not parsed as next pipelined request.
+    /// If content is longer, the connection is closed after sending of the response. Default 1 MB.
+    pub discard_unread_content_limit: usize,
+    /// Limit of request content length declared in "Content-Length". Request over it isn't passed to the http callback,
+    /// the callback gets `HttpError::ParseRequestError(RequestError::ContentLengthLimit)`, the client gets
+    /// "413 Payload Too Large" with "Connection: close". See `rejected_content`.
+    /// Chunked content is limited by its decoded length: when it exceeds the limit, the callback gets the error and
+    /// the connection is closed after "413 Payload Too Large". Default None.
+    pub content_length_limit: Option<usize>,
+    /// What to do with content of request rejected by `content_length_limit`. Default `RejectedContent::Drain(1_000_000)`.
+    pub rejected_content: RejectedContent,
+    /// Maximum number of reads from socket of one connection per readiness event, so big uploads are received quickly
+    /// but don't starve other connections of the worker. The rest is read on next iteration of the worker. Default 64.
+    pub reads_per_event_limit: usize,
+    /// Maximum of bytes read from socket of one connection per readiness event, see `reads_per_event_limit`. Default 64 KB.
+    pub read_bytes_per_event_limit: usize,
+    /// Initial size of read buffer of each connection. Default 1 KB.
+    pub read_buffer_size: usize,
+    /// Maximum size of read buffer of connection, the buffer grows while the client sends data faster than it's read
+    /// and shrinks back to `read_buffer_size` when the client has nothing more to send. Default 64 KB.
+    pub read_buffer_limit: usize,
+    /// If set, content with "Content-Encoding" gzip or deflate is decompressed by `Request::read_content`,
+    /// the value is limit of length of decompressed content. If it is exceeded, "413 Payload Too Large" is sent
+    /// and the connection is closed. Original encoding is available by `Request::content_encoding`. Default None.
+    pub content_decompression_limit: Option<usize>,
+    /// If true, `Request::read_content` checks received content against "Content-MD5", "Digest" or "Content-Digest"
+    /// header (MD5, SHA-256 or SHA-512) and responds "400 Bad Request" on mismatch. Default false.
+    pub verify_content_digest: bool,
+    /// If true, the client gets response with status by `RequestError::status_code` before closing of the connection
+    /// when request can't be parsed, for example "414 URI Too Long" or "431 Request Header Fields Too Large". Default true.
+    pub respond_to_parse_errors: bool,
+    /// If true, after error in one line of request head (see `RequestError::is_recoverable`) the client gets response
+    /// with the error status, the rest of the head is skipped and the connection is kept alive. The connection is closed anyway
+    /// if `respond_to_parse_errors` is false, the failed request has content or responses to previous requests are not sent yet.
+    /// Default false.
+    pub recover_from_parse_errors: bool,
+    /// Limit of outbound bandwidth of each connection in bytes per second, can be changed for connection by
+    /// `TcpSession::set_bandwidth_limit`. Default None.
+    pub session_bandwidth_limit: Option<u64>,
+    /// Maximum of bytes received on each connection (see `TcpSession::bytes_read`), after exceeding the connection is
+    /// closed with `Event::QuotaExceeded`, for example against endless streaming to websockets. Default None.
+    pub read_quota: Option<u64>,
+    /// Maximum of bytes written to each connection (see `TcpSession::bytes_written`). Default None.
+    pub write_quota: Option<u64>,
+    /// Named groups of websockets for broadcasting, see `Server::websocket_registry`. Default empty registry.
+    pub websocket_registry: WebsocketRegistry,
+    /// Limit of outbound bandwidth of all connections of the server together. Default None.
+    pub global_bandwidth_limit: Option<Arc<Throttle>>,
+    /// Thresholds of worker load after which it sheds load. Default None.
+    pub load_shedding: Option<LoadShedding>,
+    /// Check of requests before the http callback. Default None.
+    pub inspector: Option<Inspector>,
+    /// Called with timing breakdown of each request when its `Response` is written, for access log or latency analysis.
+    /// Summary of timings is also counted in `Stats`. Default None.
+    pub on_timing: Option<TimingCallback>,
+    /// Time for receiving of request head from its first byte. If exceeded, "408 Request Timeout" is sent
+    /// (if `respond_to_parse_errors`) and the connection is closed with `Event::Timeout`. Default None.
+    pub request_head_timeout: Option<Duration>,
+    /// Time after which HTTP connection without unfinished requests, content reading and data waiting for write
+    /// is closed with `Event::Timeout`. Default None.
+    pub idle_timeout: Option<Duration>,
+    /// Detection of clients that don't read responses or websocket frames fast enough, see `Event::SlowConsumer`. Default None.
+    pub slow_consumer: Option<SlowConsumer>,
+    /// Threads for `Request::spawn_blocking`. If None, the closure is called in the worker. Default None.
+    pub blocking_pool: Option<BlockingPool>,
+    /// Duplication of selected requests to other sink, for example file or shadow server. Default None.
+    pub mirror: Option<Mirror>,
+    /// Response to client that starts TLS handshake on plaintext connection, for example browser opened "https://" URL
+    /// with port of HTTP listener. The connection is closed with `Event::ProtocolMismatch`. Default `TlsOnPlaintext::BadRequest`.
+    pub tls_on_plaintext: TlsOnPlaintext,
+}
+
+/// Handling of content of request rejected by `Settings::content_length_limit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectedContent {
+    /// Close the connection right after the response. Unread content makes the OS reset the connection,
+    /// so a client still uploading may see the reset instead of the response.
+    Close,
+    /// Read and discard content up to the number of bytes, then close the connection after the response,
+    /// so the client finishes upload and reads the response. Request with longer "Content-Length" is treated as `Close`.
+    Drain(usize),
+}
+
+/// Response to TLS handshake on plaintext connection, see `Settings::tls_on_plaintext`.
+/// TLS clients can't read plaintext response, but some of them show it, for example curl.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TlsOnPlaintext {
+    /// Close the connection without response.
+    Close,
+    /// "400 Bad Request" with explanation that the port is for plain HTTP.
+    BadRequest,
+    /// "301 Moved Permanently" to the URL, for example "https://example.com/".
+    /// URL with line breaks or control characters isn't sent, the client gets response of `BadRequest`.
+    Redirect(String),
+}
+
+/// Thresholds of slow consumer. When bytes waiting for write of a connection exceed `queued_bytes` longer than `duration`,
+/// the worker emits `Event::SlowConsumer` once, until the queue falls below the threshold again. The connection is not closed,
+/// the application can close it or reduce its updates. See `TcpSession::queued_bytes`.
+#[derive(Clone, Copy, Debug)]
+pub struct SlowConsumer {
+    /// Bytes waiting for write.
+    pub queued_bytes: usize,
+    /// Time during which the queue stays above `queued_bytes`.
+    pub duration: Duration,
+}
+
+/// Thresholds of worker overload. When active sessions or bytes waiting for write of a worker exceed them,
+/// the worker emits `Event::Overloaded(true)` and sheds load until load returns below thresholds.
+#[derive(Clone, Debug)]
+pub struct LoadShedding {
+    /// Maximum of active sessions of worker.
+    pub max_active_sessions: usize,
+    /// Maximum of bytes waiting for write in sessions of worker.
+    pub max_queued_write_bytes: usize,
+    /// What to do while overloaded.
+    pub mode: LoadSheddingMode,
+}
+
+/// Behavior of overloaded worker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadSheddingMode {
+    /// Stop accepting of new connections, they wait in the listen backlog or go to other workers.
+    StopAccepting,
+    /// Accept connections, but respond "503 Service Unavailable" to new requests and close connection.
+    Respond503,
 }
 
 impl Default for Settings {
@@ -236,16 +1067,171 @@ impl Default for Settings {
         Settings {
             parse_http_request_settings: ParseHttpRequestSettings::default(),
             websocket_payload_limit: 16_000_000,
+            websocket_frames_per_read_limit: 256,
+            websocket_coalescing: None,
+            health_check: None,
+            acme_challenges: None,
+            canonical_host: None,
+            trust_forwarded_proto: false,
+            trace_echo: false,
+            reject_connect: true,
+            default_response_headers: Arc::new(Vec::new()),
+            response_transforms: Arc::new(Vec::new()),
+            pending_bytes_limit: 1_000_000,
+            discard_unread_content_limit: 1_000_000,
+            content_length_limit: None,
+            rejected_content: RejectedContent::Drain(1_000_000),
+            reads_per_event_limit: 64,
+            read_bytes_per_event_limit: 65536,
+            read_buffer_size: 1024,
+            read_buffer_limit: 65536,
+            content_decompression_limit: None,
+            verify_content_digest: false,
+            respond_to_parse_errors: true,
+            recover_from_parse_errors: false,
+            session_bandwidth_limit: None,
+            read_quota: None,
+            write_quota: None,
+            websocket_registry: WebsocketRegistry::new(),
+            global_bandwidth_limit: None,
+            load_shedding: None,
+            inspector: None,
+            on_timing: None,
+            request_head_timeout: None,
+            idle_timeout: None,
+            slow_consumer: None,
+            blocking_pool: None,
+            mirror: None,
+            tls_on_plaintext: TlsOnPlaintext::BadRequest,
         }
     }
 }
 
+/// Answers "503 Service Unavailable" and closes the connection if the worker is overloaded and settings require it.
+/// Returns request back if it must be passed to the http callback.
+fn respond_if_overloaded(request: Request, settings: &Settings) -> Option<Request> {
+    match &settings.load_shedding {
+        Some(load_shedding) if load_shedding.mode == LoadSheddingMode::Respond503 => {}
+        _ => return Some(request),
+    }
+
+    let tcp_session = request.tcp_session();
+    if !tcp_session.inner.worker_counters.overloaded.load(Ordering::SeqCst) {
+        return Some(request);
+    }
+
+    tcp_session.close_by_handler_error(&HandlerError::new(503, http_status_code_with_name(503)));
+    None
+}
+
+/// Redirects request to `Settings::canonical_host`, see it. Returns request back if the host is canonical.
+fn respond_if_not_canonical_host(request: Request, settings: &Settings) -> Option<Request> {
+    let canonical_host = match &settings.canonical_host {
+        Some(canonical_host) => canonical_host,
+        None => return Some(request),
+    };
+
+    let host = match request.header_value("Host") {
+        Some(host) => host.trim(),
+        None if *request.version() == HttpVersion::Http1_0 => return Some(request),
+        None => {
+            request.response(400).text("400 Bad Request").send();
+            return None;
+        }
+    };
+
+    let host_name = host_without_port(host);
+    let port = host_name.map(|host_name| host[host_name.len()..].trim_start_matches(':'));
+    let (host_name, port) = match (host_name, port) {
+        (Some(host_name), Some(port)) if port.bytes().all(|ch| ch.is_ascii_digit()) => (host_name, port),
+        _ => {
+            request.response(400).text("400 Bad Request").send();
+            return None;
+        }
+    };
+
+    // port is compared only if it's in canonical host
+    let canonical_has_port = host_without_port(canonical_host).is_some_and(|canonical_name| canonical_name.len() < canonical_host.len());
+    let compared = if canonical_has_port && !port.is_empty() { host } else { host_name };
+    if compared.trim_end_matches('.').eq_ignore_ascii_case(canonical_host) {
+        return Some(request);
+    }
+
+    match path_and_query(&request) {
+        Some(path_and_query) => {
+            let location = format!("{}://{}{}", request.scheme().as_str(), canonical_host, path_and_query);
+            request.response(301).location(&location).send();
+        }
+        None => {
+            request.response(400).text("400 Bad Request").send();
+        }
+    }
+
+    None
+}
+
+/// Answers "417 Expectation Failed" to "Expect" other than "100-continue" (RFC 7231, 5.1.1).
+/// "Expect" of HTTP/1.0 requests is ignored.
+fn respond_if_unsupported_expectation(request: Request) -> Option<Request> {
+    let unsupported = *request.version() == HttpVersion::Http1_1
+        && request.header_value("Expect").is_some_and(|expect| !expect.trim().eq_ignore_ascii_case("100-continue"));
+    if unsupported {
+        request.response(417).text("417 Expectation Failed").close().send();
+        return None;
+    }
+
+    Some(request)
+}
+
+/// Answers "TRACE" and "CONNECT" requests by settings. Returns request back if it must be passed to the http callback.
+fn respond_trace_or_connect(request: Request, settings: &Settings) -> Option<Request> {
+    let reject = match request.method() {
+        "TRACE" => !settings.trace_echo,
+        "CONNECT" if settings.reject_connect => true,
+        _ => return Some(request),
+    };
+
+    if reject {
+        // methods which the server passes to handlers or answers itself
+        let mut allowed = vec!["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS"];
+        if settings.trace_echo {
+            allowed.push("TRACE");
+        }
+        if !settings.reject_connect {
+            allowed.push("CONNECT");
+        }
+
+        request.response(405).allow(&allowed).text("405 Method Not Allowed").send();
+        return None;
+    }
+
+    // echo of TRACE request
+    let mut echo = Vec::with_capacity(request.raw().len());
+    for line in request.raw().split(|ch| *ch == b'\n') {
+        let name = line.split(|ch| *ch == b':').next().unwrap_or(&[]);
+        let sensitive = [&b"Cookie"[..], b"Authorization", b"Proxy-Authorization"].iter()
+            .any(|sensitive| name.eq_ignore_ascii_case(sensitive));
+
+        if !sensitive && !line.is_empty() {
+            echo.extend_from_slice(line);
+            echo.push(b'\n');
+        }
+    }
+
+    request.response(200).content("Content-Type: message/http\r\n", &echo).send();
+    None
+}
+
 /// Current processing processing state depended by current mode (http, websocket).
 enum State {
     /// Tcp connection using for HTTP.
-    Http(HttpState),
+    Http(Box<HttpState>),
     /// Tcp connection using for websocket.
     Websocket(websocket::Parser),
+    /// Received data is passed to the user as is, see `TcpSession::into_raw_mode`.
+    Raw,
+    /// Deferred websocket handshake is waiting for approval, received data is kept.
+    HandshakePending(Vec<u8>),
 }
 
 /// Current http processing state.
@@ -256,6 +1242,43 @@ struct HttpState {
     content_len: usize,
     /// Number of already read bytes of content.
     already_read_content_len: usize,
+    /// Decoder of content with "Transfer-Encoding: chunked", used instead of `content_len`.
+    chunked: Option<ChunkedDecoder>,
+    /// Limit of chunked content that is discarded because the handler didn't read it.
+    discard_limit: Option<usize>,
     /// It's used if connection upgraded to websocket. The parser need to be recreated only after error!
-    pipelining_http_requests_count: u16
+    pipelining_http_requests_count: u16,
+    /// Number of received requests, used as request id in the session.
+    requests_count: u64,
+    /// Request waiting for its content before check by `Settings::inspector`, and the received content.
+    inspected: Option<(Box<Request>, SharedContent)>,
+    /// Moment of creation of session or parsing of previous request.
+    idle_since: Instant,
+    /// Moment and wall clock time of the first byte of request being parsed.
+    first_byte: Option<(Instant, SystemTime)>,
+    /// Rest of head of request with recoverable parse error is skipped, see `Settings::recover_from_parse_errors`.
+    skipping_failed_head: bool,
+    /// Content of rejected request is drained, the connection is closed after it, see `Settings::rejected_content`.
+    close_after_content: bool,
+    /// Mirror of request being received, see `Settings::mirror`.
+    mirroring: Option<Mirror>,
+}
+
+impl HttpState {
+    /// Prepares reading of content of received request, `discard_limit` is set if the content isn't read by the handler.
+    fn start_content(&mut self, content_len: usize, chunked: bool, discard_limit: Option<usize>) {
+        self.content_len = content_len;
+        self.already_read_content_len = 0;
+        self.chunked = if chunked { Some(ChunkedDecoder::new()) } else { None };
+        self.discard_limit = discard_limit;
+    }
 }
+
+/// Content received by worker and taken after receiving.
+type SharedContent = Arc<Mutex<Vec<u8>>>;
+
+/// Message of response to TLS handshake on plaintext connection.
+const TLS_ON_PLAINTEXT_MESSAGE: &str = "400 Bad Request: TLS handshake is received on plain HTTP port, use \"http://\" or HTTPS port";
+
+/// Maximum of bytes of head of failed request skipped before the connection is closed, see `Settings::recover_from_parse_errors`.
+const SKIPPED_HEAD_LIMIT: usize = 65536;
@@ -0,0 +1,553 @@
+use crate::cookie::cookie_date;
+use crate::handler_error::HandlerError;
+use crate::log::{log, Level};
+use crate::mime::mime_type_by_extension;
+use crate::request::Request;
+use crate::response::{http_status_code_with_name, ChunkedResponse};
+use crate::router::HandlerResult;
+use crate::tcp_session::{SendStatus, TcpSession};
+use percent_encoding::{percent_decode, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::borrow::Cow;
+use std::fs::{self, File, Metadata};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Simple WebDAV share (RFC 4918, class 1, without locks) of directory on disk.
+/// Serves OPTIONS, GET, PUT, DELETE, PROPFIND (depth 0 and 1, all properties), MKCOL, COPY and MOVE.
+/// File operations are blocking, so big shares are better served from a thread pool. Files bigger than 64 KiB
+/// are sent chunked, part by part as they are written.
+/// Uploaded file replaces existing one only after its content is received completely.
+#[derive(Clone)]
+pub struct WebDav {
+    /// Directory on disk.
+    root: PathBuf,
+    /// Segments of path of the share in URL.
+    prefix: Vec<String>,
+    /// Only OPTIONS, GET and PROPFIND are allowed.
+    read_only: bool,
+    /// Maximum length of uploaded file.
+    max_file_len: usize,
+}
+
+impl WebDav {
+    /// Share of directory `root` at the root of URL path.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        WebDav { root: root.into(), prefix: Vec::new(), read_only: false, max_file_len: 100_000_000 }
+    }
+
+    /// Path of the share in URL, for example "/dav".
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.split('/').filter(|segment| !segment.is_empty()).map(|segment| segment.to_string()).collect();
+        self
+    }
+
+    /// Disallows modification of files.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Maximum length of file uploaded by PUT, longer upload gets "413 Payload Too Large". Default 100 MB.
+    pub fn max_file_len(mut self, max_file_len: usize) -> Self {
+        self.max_file_len = max_file_len;
+        self
+    }
+
+    /// Responds to the request to the share. Responds "404 Not Found" if path isn't in the share.
+    pub fn handle(&self, request: Request) -> HandlerResult {
+        let prefix: Vec<&str> = self.prefix.iter().map(|segment| segment.as_str()).collect();
+        let segments = match request.tail_after(&prefix) {
+            Some(segments) => self.file_path(&segments),
+            None => {
+                request.response(404).text("404 Not Found").send();
+                return Ok(());
+            }
+        };
+
+        let (file_path, href) = match segments {
+            Some(file_path_and_href) => file_path_and_href,
+            None => {
+                request.response(400).text("400 Bad Request").send();
+                return Ok(());
+            }
+        };
+
+        let allowed = if self.read_only { READ_METHODS } else { ALL_METHODS };
+        if !allowed.contains(&request.method()) {
+            request.response(405).allow(allowed).text("405 Method Not Allowed").send();
+            return Ok(());
+        }
+
+        match request.method() {
+            "OPTIONS" => {
+                request.response(200).headers("DAV: 1\r\n").allow(allowed).send();
+                Ok(())
+            }
+            "GET" => self.get(request, &file_path),
+            "PUT" => self.put(request, file_path),
+            "DELETE" => self.delete(request, &file_path),
+            "PROPFIND" => self.propfind(request, file_path, href),
+            "MKCOL" => self.mkcol(request, &file_path),
+            _ => self.copy_or_move(request, &file_path),
+        }
+    }
+
+    fn get(&self, request: Request, file_path: &Path) -> HandlerResult {
+        match fs::metadata(file_path) {
+            Ok(metadata) if metadata.is_dir() => {
+                request.response(405).allow(&["OPTIONS", "PROPFIND"]).text("405 Method Not Allowed").send();
+                Ok(())
+            }
+            Ok(metadata) => {
+                let mut file = File::open(file_path).map_err(HandlerError::internal)?;
+                let content_type = format!("Content-Type: {}\r\n", content_type_by_path(file_path));
+                if metadata.len() <= FILE_PART_LEN as u64 {
+                    let mut data = Vec::with_capacity(metadata.len() as usize);
+                    file.read_to_end(&mut data).map_err(HandlerError::internal)?;
+                    request.response(200).content(&content_type, &data).send();
+                    return Ok(());
+                }
+
+                // big file is sent chunked, next part is read when previous one is written
+                let tcp_session = request.tcp_session().clone();
+                let chunked = request.response(200).content(&content_type, &[]).chunked();
+                send_file_parts(FileStream { file, chunked, tcp_session });
+                Ok(())
+            }
+            Err(err) => respond_io_error(request, err),
+        }
+    }
+
+    fn put(&self, request: Request, file_path: PathBuf) -> HandlerResult {
+        if file_path.is_dir() {
+            request.response(405).allow(&["OPTIONS", "PROPFIND", "DELETE", "COPY", "MOVE"]).text("405 Method Not Allowed").send();
+            return Ok(());
+        }
+        if !parent_exists(&file_path) {
+            request.response(409).text("409 Conflict").send();
+            return Ok(());
+        }
+
+        if request.content_len() > self.max_file_len {
+            request.response(413).close().text("413 Payload Too Large").send();
+            return Ok(());
+        }
+
+        let existed = file_path.exists();
+        let mut upload = match Upload::create(&file_path, request.tcp_session().id()) {
+            Ok(upload) => Some(upload),
+            Err(err) => return respond_io_error(request, err),
+        };
+
+        let max_file_len = self.max_file_len;
+        request.read_content(move |data, complete| {
+            let current = match upload.as_mut() {
+                Some(upload) => upload,
+                None => return Ok(()),
+            };
+
+            current.len += data.len();
+            if current.len > max_file_len {
+                upload = None; // the temporary file is removed
+                return Err(HandlerError::new(413, http_status_code_with_name(413)).into());
+            }
+            current.file.write_all(data).map_err(HandlerError::internal)?;
+
+            if let Some(request) = complete {
+                if let Some(upload) = upload.take() {
+                    upload.persist(&file_path).map_err(HandlerError::internal)?;
+                }
+                request.response(if existed { 204 } else { 201 }).send();
+            }
+
+            Ok(())
+        });
+        Ok(())
+    }
+
+    fn delete(&self, request: Request, file_path: &Path) -> HandlerResult {
+        if file_path == self.root {
+            request.response(403).text("403 Forbidden").send();
+            return Ok(());
+        }
+
+        let res = match fs::metadata(file_path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(file_path),
+            Ok(_) => fs::remove_file(file_path),
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(()) => {
+                request.response(204).send();
+                Ok(())
+            }
+            Err(err) => respond_io_error(request, err),
+        }
+    }
+
+    fn mkcol(&self, request: Request, file_path: &Path) -> HandlerResult {
+        if request.has_content() {
+            request.response(415).text("415 Unsupported Media Type").send();
+            return Ok(());
+        }
+        if file_path.exists() {
+            request.response(405).allow(&["OPTIONS", "PROPFIND", "DELETE", "COPY", "MOVE"]).text("405 Method Not Allowed").send();
+            return Ok(());
+        }
+        if !parent_exists(file_path) {
+            request.response(409).text("409 Conflict").send();
+            return Ok(());
+        }
+
+        match fs::create_dir(file_path) {
+            Ok(()) => {
+                request.response(201).send();
+                Ok(())
+            }
+            Err(err) => respond_io_error(request, err),
+        }
+    }
+
+    fn copy_or_move(&self, request: Request, file_path: &Path) -> HandlerResult {
+        let destination = match request.header_value("Destination") {
+            Some(destination) => self.destination_path(destination),
+            None => {
+                request.response(400).text("400 Bad Request").send();
+                return Ok(());
+            }
+        };
+        let destination = match destination {
+            Some(destination) => destination,
+            None => {
+                // malformed or on other server
+                request.response(502).text("502 Bad Gateway").send();
+                return Ok(());
+            }
+        };
+
+        let source_is_dir = match fs::metadata(file_path) {
+            Ok(metadata) => metadata.is_dir(),
+            Err(err) => return respond_io_error(request, err),
+        };
+
+        if destination == file_path || destination.starts_with(file_path) || file_path == self.root {
+            request.response(403).text("403 Forbidden").send();
+            return Ok(());
+        }
+        if !parent_exists(&destination) {
+            request.response(409).text("409 Conflict").send();
+            return Ok(());
+        }
+
+        let overwrite = !request.header_value("Overwrite").is_some_and(|overwrite| overwrite.trim().eq_ignore_ascii_case("F"));
+        let existed = destination.exists();
+        if existed && !overwrite {
+            request.response(412).text("412 Precondition Failed").send();
+            return Ok(());
+        }
+
+        let shallow = request.header_value("Depth").is_some_and(|depth| depth.trim() == "0");
+        let res = remove_if_exists(&destination).and_then(|()| {
+            if request.method() == "MOVE" {
+                fs::rename(file_path, &destination)
+            } else if source_is_dir && shallow {
+                fs::create_dir(&destination)
+            } else {
+                copy_recursively(file_path, &destination)
+            }
+        });
+
+        match res {
+            Ok(()) => {
+                request.response(if existed { 204 } else { 201 }).send();
+                Ok(())
+            }
+            Err(err) => respond_io_error(request, err),
+        }
+    }
+
+    fn propfind(&self, request: Request, file_path: PathBuf, href: String) -> HandlerResult {
+        let with_children = match request.header_value("Depth").map(|depth| depth.trim()) {
+            Some("0") => false,
+            Some("1") => true,
+            _ => {
+                let error = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n";
+                request.response(403).content("Content-Type: application/xml; charset=utf-8\r\n", error.as_bytes()).send();
+                return Ok(());
+            }
+        };
+
+        // requested properties are not parsed, all properties are returned
+        request.read_content(move |_, complete| {
+            let request = match complete {
+                Some(request) => request,
+                None => return Ok(()),
+            };
+
+            let metadata = match fs::metadata(&file_path) {
+                Ok(metadata) => metadata,
+                Err(err) => return respond_io_error(request, err),
+            };
+
+            let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+            xml.push_str(&response_xml(&href, &file_path, &metadata));
+            if with_children && metadata.is_dir() {
+                let mut children: Vec<(String, PathBuf)> = fs::read_dir(&file_path)
+                    .map_err(HandlerError::internal)?
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.path())))
+                    .collect();
+                children.sort();
+
+                for (name, child_path) in children {
+                    if let Ok(child_metadata) = fs::metadata(&child_path) {
+                        let child_href = format!("{}{}", href, utf8_percent_encode(&name, HREF_SEGMENT));
+                        xml.push_str(&response_xml(&child_href, &child_path, &child_metadata));
+                    }
+                }
+            }
+            xml.push_str("</D:multistatus>\n");
+
+            request.response(207).content("Content-Type: application/xml; charset=utf-8\r\n", xml.as_bytes()).send();
+            Ok(())
+        });
+        Ok(())
+    }
+
+    /// Path on disk and encoded href of decoded segments of URL path after prefix.
+    /// None if some segment can lead out of the root.
+    fn file_path(&self, segments: &[Cow<'_, str>]) -> Option<(PathBuf, String)> {
+        let mut file_path = self.root.clone();
+        let mut href = String::from("/");
+        for segment in &self.prefix {
+            href.push_str(&utf8_percent_encode(segment, HREF_SEGMENT).to_string());
+            href.push('/');
+        }
+
+        for segment in segments {
+            if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+                return None;
+            }
+
+            file_path.push(segment.as_ref());
+            href.push_str(&utf8_percent_encode(segment, HREF_SEGMENT).to_string());
+            href.push('/');
+        }
+
+        if !segments.is_empty() && !file_path.is_dir() {
+            href.pop();
+        }
+
+        Some((file_path, href))
+    }
+
+    /// Path on disk of "Destination" header value, absolute URI or absolute path. None if it's not in the share.
+    fn destination_path(&self, destination: &str) -> Option<PathBuf> {
+        let path = match destination.find("://") {
+            Some(scheme_end) => {
+                let after_scheme = &destination[scheme_end + 3..];
+                &after_scheme[after_scheme.find('/')?..]
+            }
+            None => destination,
+        };
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+
+        let mut segments = Vec::new();
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            segments.push(percent_decode(segment.as_bytes()).decode_utf8().ok()?);
+        }
+
+        if segments.len() < self.prefix.len() || segments.iter().zip(&self.prefix).any(|(segment, expected)| segment != expected) {
+            return None;
+        }
+
+        let (file_path, _) = self.file_path(&segments[self.prefix.len()..])?;
+        if file_path == self.root {
+            return None;
+        }
+
+        Some(file_path)
+    }
+}
+
+/// Methods of read-only share.
+const READ_METHODS: &[&str] = &["OPTIONS", "GET", "PROPFIND"];
+/// Methods of writable share.
+const ALL_METHODS: &[&str] = &["OPTIONS", "GET", "PUT", "DELETE", "PROPFIND", "MKCOL", "COPY", "MOVE"];
+
+/// Length of part of file read at once, bigger files are sent chunked.
+const FILE_PART_LEN: usize = 64 * 1024;
+
+/// Characters encoded in segments of hrefs, including characters special for XML.
+const HREF_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'&').add(b'\'').add(b'/').add(b'<').add(b'>').add(b'?').add(b'`').add(b'{').add(b'}');
+
+/// Element "response" of multistatus with properties of file or directory.
+fn response_xml(href: &str, file_path: &Path, metadata: &Metadata) -> String {
+    let name = file_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let mut props = format!("<D:displayname>{}</D:displayname>", escape_xml(name));
+    if metadata.is_dir() {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        props.push_str("<D:resourcetype/>");
+        props.push_str(&format!("<D:getcontentlength>{}</D:getcontentlength>", metadata.len()));
+        props.push_str(&format!("<D:getcontenttype>{}</D:getcontenttype>", escape_xml(content_type_by_path(file_path))));
+    }
+    if let Ok(modified) = metadata.modified() {
+        props.push_str(&format!("<D:getlastmodified>{}</D:getlastmodified>", cookie_date(&modified.into())));
+    }
+
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        href, props,
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn content_type_by_path(path: &Path) -> &str {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    mime_type_by_extension(extension)
+}
+
+fn parent_exists(file_path: &Path) -> bool {
+    file_path.parent().is_some_and(|parent| parent.is_dir())
+}
+
+fn remove_if_exists(file_path: &Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(file_path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(file_path),
+        Ok(_) => fs::remove_file(file_path),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Copies file or directory without following of symbolic links, so files outside of the share aren't copied into it.
+/// Symbolic links in directories are skipped, copying of symbolic link itself is forbidden.
+fn copy_recursively(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        return Err(std::io::Error::new(ErrorKind::PermissionDenied, "copying of symbolic link"));
+    }
+    if !metadata.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_symlink() {
+            continue;
+        }
+        copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+    }
+
+    Ok(())
+}
+
+/// File being uploaded by PUT, written to temporary file in the same directory and renamed on completion,
+/// so failed or aborted upload doesn't damage existing file. The temporary file is removed if upload isn't completed.
+struct Upload {
+    file: File,
+    temp_path: PathBuf,
+    /// Number of received bytes.
+    len: usize,
+}
+
+impl Upload {
+    fn create(file_path: &Path, session_id: u64) -> std::io::Result<Self> {
+        let name = file_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let temp_path = file_path.with_file_name(format!(".{}.{}-{}.upload", name, std::process::id(), session_id));
+        let file = File::create(&temp_path)?;
+        Ok(Upload { file, temp_path, len: 0 })
+    }
+
+    /// Replaces file by uploaded one.
+    fn persist(self, file_path: &Path) -> std::io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp_path, file_path)
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        // nothing to remove after rename
+        let _ = fs::remove_file(&self.temp_path);
+    }
+}
+
+/// File sent by GET in parts, so big file isn't loaded into memory.
+struct FileStream {
+    file: File,
+    chunked: ChunkedResponse,
+    tcp_session: TcpSession,
+}
+
+/// Part of file shared with callback of its writing.
+enum PartState {
+    Sending,
+    Written,
+    /// The part isn't written yet, the stream continues in the callback.
+    Waiting(FileStream),
+}
+
+/// Sends parts of file, next part is read when previous one is written. Parts written at once are sent in loop.
+fn send_file_parts(mut stream: FileStream) {
+    let mut part = vec![0; FILE_PART_LEN];
+    loop {
+        let cnt = match stream.file.read(&mut part) {
+            Ok(0) => {
+                stream.chunked.end();
+                return;
+            }
+            Ok(cnt) => cnt,
+            Err(err) => {
+                // content is incomplete, the client must not take it as whole
+                log(Level::Error, format_args!("reading of file for session {} failed: {}", stream.tcp_session.id(), err));
+                stream.tcp_session.close();
+                return;
+            }
+        };
+
+        let state = Arc::new(Mutex::new(PartState::Sending));
+        let callback_state = state.clone();
+        let status = stream.chunked.try_send(&part[..cnt], move |result| {
+            let previous = match callback_state.lock() {
+                Ok(mut state) => std::mem::replace(&mut *state, PartState::Written),
+                Err(_) => return,
+            };
+
+            if let (Ok(()), PartState::Waiting(stream)) = (result, previous) {
+                send_file_parts(stream);
+            }
+        });
+        if let SendStatus::Closed = status {
+            return;
+        }
+
+        let mut part_state = match state.lock() {
+            Ok(part_state) => part_state,
+            Err(_) => return,
+        };
+        if let PartState::Sending = *part_state {
+            *part_state = PartState::Waiting(stream);
+            return;
+        }
+    }
+}
+
+/// Responds to error of file operation, unexpected errors are passed to the server for "500 Internal Server Error".
+fn respond_io_error(request: Request, err: std::io::Error) -> HandlerResult {
+    match err.kind() {
+        ErrorKind::NotFound => request.response(404).text("404 Not Found").send(),
+        ErrorKind::PermissionDenied => request.response(403).text("403 Forbidden").send(),
+        _ => return Err(HandlerError::internal(err).into()),
+    };
+
+    Ok(())
+}
@@ -51,12 +51,20 @@
 // client to server have this bit set to 1.
 
 use sha1::{Digest, Sha1};
-use crate::tcp_session::TcpSession;
+use crate::handler_error::HandlerError;
+use crate::response::http_status_code_with_name;
+use crate::tcp_session::{SendStatus, TcpSession};
+use crate::log::{log, Level};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock, Weak};
 
 pub const CONTINUATION_OPCODE: u8 = 0x0;
 pub const TEXT_OPCODE: u8 = 0x1;
 pub const BINARY_OPCODE: u8 = 0x2;
 pub const CLOSE_OPCODE: u8 = 0x8;
+pub const PING_OPCODE: u8 = 0x9;
+pub const PONG_OPCODE: u8 = 0xA;
 
 #[derive(Clone)]
 pub struct Websocket {
@@ -70,18 +78,102 @@ impl Websocket {
         if let Ok(mut websocket_callback) = self.tcp_session.inner.websocket_callback.lock() {
             *websocket_callback = Some(Box::new(callback));
         }
+
+        if self.tcp_session.inner.deferred_handshake.load(Ordering::SeqCst) {
+            // frames received while the handshake was waiting for approval can be processed now
+            self.tcp_session.wake();
+        }
     }
 
-    /// Send frame.
-    pub fn send(&self, opcode: u8, payload: &[u8]) {
-        self.tcp_session.send(&frame(opcode, payload));
+    /// Send frame. Returns `SendStatus::Closed` if the connection is already closed.
+    pub fn send(&self, opcode: u8, payload: &[u8]) -> SendStatus {
+        self.try_send(opcode, payload, |_| {})
     }
 
-    /// Send frame.
+    /// Send frame. With `Settings::websocket_coalescing` the frame is written after processing of current events of the worker.
     /// # Arguments
-    /// * `res_callback` - function that will be called when the write is finished or socket writing error.
-    pub fn try_send(&self, opcode: u8, payload: &[u8], res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) {
-        self.tcp_session.try_send(&frame(opcode, payload), res_callback);
+    /// * `res_callback` - function that will be called when the write is finished or socket writing error,
+    ///   immediately if the connection is already closed.
+    pub fn try_send(&self, opcode: u8, payload: &[u8], res_callback: impl FnMut(Result<(), std::io::Error>) + Send + 'static) -> SendStatus {
+        if self.tcp_session.inner.coalesced_frame_limit.load(Ordering::SeqCst) > 0 {
+            return self.tcp_session.send_coalesced(frame(opcode, payload), Box::new(res_callback));
+        }
+
+        self.tcp_session.try_send(&frame(opcode, payload), res_callback)
+    }
+
+    /// Send text message in one frame.
+    pub fn send_text(&self, text: &str) -> SendStatus {
+        self.send(TEXT_OPCODE, text.as_bytes())
+    }
+
+    /// Send value serialized to JSON in text frame. Requires "json" feature.
+    #[cfg(feature = "json")]
+    pub fn send_json<T: serde::Serialize + ?Sized>(&self, value: &T) -> Result<SendStatus, serde_json::Error> {
+        Ok(self.send(TEXT_OPCODE, &serde_json::to_vec(value)?))
+    }
+
+    /// Set callback that will be called with every text message instead of frames, see `on_frame`.
+    /// Fragmented messages are assembled, pings are answered with pongs.
+    /// Message longer than `message_limit` is reported as `MessageError::TooLarge` and the connection is closed
+    /// with "1009 Message Too Big" close frame. Binary and not UTF-8 messages are reported as errors without closing.
+    pub fn on_text(&self, message_limit: usize, mut callback: impl FnMut(Result<String, MessageError>, Websocket) -> Result<(), WebsocketError> + Send + 'static) {
+        let mut message = Message { opcode: None, payload: Vec::new() };
+        self.on_frame(move |frame, websocket| {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => return callback(Err(MessageError::Websocket(err)), websocket),
+            };
+
+            match frame.opcode() {
+                PING_OPCODE => {
+                    websocket.send(PONG_OPCODE, frame.payload());
+                    return Ok(());
+                }
+                CONTINUATION_OPCODE if message.opcode.is_none() => return callback(Err(MessageError::UnexpectedContinuation), websocket),
+                CONTINUATION_OPCODE => {}
+                TEXT_OPCODE | BINARY_OPCODE => {
+                    message.opcode = Some(frame.opcode());
+                    message.payload.clear();
+                }
+                _ => return Ok(()),
+            }
+
+            if message.payload.len() + frame.payload().len() > message_limit {
+                message.opcode = None;
+                message.payload = Vec::new();
+                let result = callback(Err(MessageError::TooLarge), websocket.clone());
+                websocket.send(CLOSE_OPCODE, &1009u16.to_be_bytes());
+                websocket.tcp_session.close_when_sent();
+                return result;
+            }
+
+            message.payload.extend_from_slice(frame.payload());
+            if !frame.fin() {
+                return Ok(());
+            }
+
+            let opcode = message.opcode.take();
+            let payload = std::mem::take(&mut message.payload);
+            if opcode == Some(BINARY_OPCODE) {
+                return callback(Err(MessageError::NotText), websocket);
+            }
+
+            match String::from_utf8(payload) {
+                Ok(text) => callback(Ok(text), websocket),
+                Err(err) => callback(Err(MessageError::NotUtf8(err.utf8_error())), websocket),
+            }
+        });
+    }
+
+    /// Same as `on_text` but every text message is deserialized from JSON. Requires "json" feature.
+    /// Message that can't be deserialized is reported as `MessageError::Json` without closing.
+    #[cfg(feature = "json")]
+    pub fn on_json<T: serde::de::DeserializeOwned>(&self, message_limit: usize, mut callback: impl FnMut(Result<T, MessageError>, Websocket) -> Result<(), WebsocketError> + Send + 'static) {
+        self.on_text(message_limit, move |text, websocket| {
+            let value = text.and_then(|text| serde_json::from_str(&text).map_err(MessageError::Json));
+            callback(value, websocket)
+        });
     }
 
     /// Close of client socket. After clossing will be generated `sever::Event::Disconnected`.
@@ -94,11 +186,197 @@ impl Websocket {
         &self.tcp_session
     }
 
+    /// Adds the websocket to named group of registry of the server (see `Server::websocket_registry`),
+    /// for example "room:1". The websocket leaves all groups when its connection is closed,
+    /// closed websocket doesn't join.
+    pub fn join(&self, group: &str) {
+        if let Ok(mut membership) = self.tcp_session.inner.websocket_membership.lock() {
+            // checked under the lock, so the websocket isn't added after `Membership::leave_all`
+            if membership.left || self.tcp_session.is_closed() {
+                return;
+            }
+
+            let registry = match membership.registry.upgrade() {
+                Some(registry) => registry,
+                None => return,
+            };
+
+            if let Ok(mut groups) = registry.write() {
+                groups.entry(group.to_string()).or_default().insert(self.tcp_session.id(), self.clone());
+            }
+            if !membership.groups.iter().any(|joined| joined == group) {
+                membership.groups.push(group.to_string());
+            }
+        }
+    }
+
+    /// Removes the websocket from named group.
+    pub fn leave(&self, group: &str) {
+        if let Ok(mut membership) = self.tcp_session.inner.websocket_membership.lock() {
+            membership.groups.retain(|joined| joined != group);
+            if let Some(registry) = membership.registry.upgrade() {
+                remove_member(&registry, group, self.tcp_session.id());
+            }
+        }
+    }
+
+    /// Names of groups joined by the websocket.
+    pub fn groups(&self) -> Vec<String> {
+        self.tcp_session.inner.websocket_membership.lock().map(|membership| membership.groups.clone()).unwrap_or_default()
+    }
+
     pub(crate) fn new(tcp_session: TcpSession) -> Self {
         Websocket { tcp_session }
     }
 }
 
+/// Named groups of websockets of the server for broadcasting, like chat rooms or topics. See `Server::websocket_registry`
+/// and `Websocket::join`. Can be used in multi-threaded environment after clone, all clones share groups.
+#[derive(Clone, Default)]
+pub struct WebsocketRegistry {
+    /// Members of groups by session id.
+    groups: Arc<RwLock<Groups>>,
+}
+
+type Groups = HashMap<String, HashMap<u64, Websocket>>;
+
+impl WebsocketRegistry {
+    /// Creates registry without groups.
+    pub fn new() -> Self {
+        WebsocketRegistry::default()
+    }
+
+    /// Sends frame to all websockets of group. The frame is made once. Returns number of receivers with open connections.
+    pub fn broadcast_to(&self, group: &str, opcode: u8, payload: &[u8]) -> usize {
+        let members = self.members(group);
+        if members.is_empty() {
+            return 0;
+        }
+
+        let frame = Arc::new(frame(opcode, payload));
+        members.iter().filter(|member| member.tcp_session.send_arc(&frame) == SendStatus::Queued).count()
+    }
+
+    /// Websockets of group.
+    pub fn members(&self, group: &str) -> Vec<Websocket> {
+        self.groups.read()
+            .map(|groups| groups.get(group).map(|members| members.values().cloned().collect()).unwrap_or_default())
+            .unwrap_or_default()
+    }
+
+    /// Names of groups which have members.
+    pub fn groups(&self) -> Vec<String> {
+        self.groups.read().map(|groups| groups.keys().cloned().collect()).unwrap_or_default()
+    }
+}
+
+/// Registry and groups of websocket connection, see `Websocket::join`.
+#[derive(Default)]
+pub(crate) struct Membership {
+    /// Weak, so connections kept in groups don't keep the registry.
+    registry: Weak<RwLock<Groups>>,
+    groups: Vec<String>,
+    /// The connection is closed and left all groups.
+    left: bool,
+}
+
+impl Membership {
+    pub(crate) fn new(registry: &WebsocketRegistry) -> Self {
+        Membership { registry: Arc::downgrade(&registry.groups), groups: Vec::new(), left: false }
+    }
+
+    /// Removes the connection with `session_id` from all joined groups, when the connection is closed.
+    pub(crate) fn leave_all(&mut self, session_id: u64) {
+        self.left = true;
+        if let Some(registry) = self.registry.upgrade() {
+            for group in self.groups.drain(..) {
+                remove_member(&registry, &group, session_id);
+            }
+        }
+    }
+}
+
+fn remove_member(registry: &RwLock<Groups>, group: &str, session_id: u64) {
+    if let Ok(mut groups) = registry.write() {
+        if let Some(members) = groups.get_mut(group) {
+            members.remove(&session_id);
+            if members.is_empty() {
+                groups.remove(group);
+            }
+        }
+    }
+}
+
+/// Websocket handshake waiting for decision of the handler, see `Request::accept_websocket_deferred`.
+/// Can be moved to other thread, for example to check a token in database.
+/// Data received from the client before the decision is kept and processed after `Websocket::on_frame`.
+/// If dropped without decision, the handshake is rejected with 503.
+pub struct HandshakePending {
+    /// None after decision.
+    tcp_session: Option<TcpSession>,
+    /// Value of "Sec-WebSocket-Accept" header.
+    accept: String,
+    /// Subprotocols requested by the client.
+    protocols: Vec<String>,
+}
+
+impl HandshakePending {
+    /// Subprotocols from "Sec-WebSocket-Protocol" header of request.
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Sends handshake response and returns object for work with websocket.
+    /// # Arguments
+    /// * `subprotocol` - one of `protocols` chosen by the server.
+    pub fn approve(mut self, subprotocol: Option<&str>) -> Websocket {
+        let tcp_session = self.tcp_session.take().unwrap_or_else(|| unreachable!());
+        let date = tcp_session.inner.http_date_string.read().map(|date| date.clone()).unwrap_or_default();
+        tcp_session.send(&handshake_response(&self.accept, subprotocol, &date));
+
+        tcp_session.inner.handshake_pending.store(false, Ordering::SeqCst);
+        tcp_session.wake();
+        Websocket::new(tcp_session)
+    }
+
+    /// Sends response with the status code and closes the connection.
+    pub fn reject(mut self, code: u16) {
+        if let Some(tcp_session) = self.tcp_session.take() {
+            tcp_session.close_by_handler_error(&HandlerError::new(code, http_status_code_with_name(code)));
+        }
+    }
+
+    pub(crate) fn new(tcp_session: TcpSession, accept: String, protocols: Vec<String>) -> Self {
+        tcp_session.inner.deferred_handshake.store(true, Ordering::SeqCst);
+        tcp_session.inner.handshake_pending.store(true, Ordering::SeqCst);
+        HandshakePending { tcp_session: Some(tcp_session), accept, protocols }
+    }
+}
+
+impl Drop for HandshakePending {
+    fn drop(&mut self) {
+        if let Some(tcp_session) = self.tcp_session.take() {
+            tcp_session.close_by_handler_error(&HandlerError::new(503, http_status_code_with_name(503)));
+        }
+    }
+}
+
+/// Handshake response "101 Switching Protocols".
+pub(crate) fn handshake_response(accept: &str, protocol: Option<&str>, rfc7231_date: &str) -> Vec<u8> {
+    Vec::from(format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Accept: {}\r\n\
+        {}\
+        Date: {}\r\n\
+        \r\n",
+        accept,
+        protocol.map(|protocol| format!("Sec-WebSocket-Protocol: {}\r\n", protocol)).unwrap_or_default(),
+        rfc7231_date,
+    ))
+}
+
 /// Received websocket frame or error receiving it
 pub type WebsocketResult<'a> = Result<&'a Frame, WebsocketError>;
 
@@ -111,6 +389,32 @@ pub enum WebsocketError {
     ParseFrameError(ParseFrameError),
     /// Register in poll error.
     PollRegisterError(std::io::Error),
+    /// Write to sock error. Connection is closed, frames sent before may be not delivered.
+    WriteError(std::io::Error),
+}
+
+/// Error of receiving message by `Websocket::on_text` or `Websocket::on_json`.
+#[derive(Debug)]
+pub enum MessageError {
+    /// Error of websocket, see `on_frame`.
+    Websocket(WebsocketError),
+    /// Message is longer than the limit, the connection is closed.
+    TooLarge,
+    /// Binary message instead of text.
+    NotText,
+    /// Text message is not UTF-8.
+    NotUtf8(std::str::Utf8Error),
+    /// Continuation frame without first frame of message.
+    UnexpectedContinuation,
+    /// Text message is not JSON of expected type.
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+}
+
+/// Text or binary message assembled from frames.
+struct Message {
+    opcode: Option<u8>,
+    payload: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -125,12 +429,23 @@ pub fn accept_key(sec_websocket_key: &str) -> Result<String, WebsocketHandshakeE
     const MAGIC_STRING_FOR_HANDSHAKE: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
     hasher.update((sec_websocket_key.to_owned() + MAGIC_STRING_FOR_HANDSHAKE).as_bytes());
     let accept_sha1 = hasher.finalize();
-    Ok(base64::encode(&accept_sha1))
+    Ok(base64::encode(accept_sha1))
 }
 
 /// Make vector containing frame based on the specified opcode and payload data.
 pub fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    build_frame(opcode, payload, None)
+}
+
+/// Make vector containing masked frame based on the specified opcode and payload data,
+/// as client must send it to the server. Masking key must be unpredictable (RFC: 6455 section 5.3).
+pub fn masked_frame(opcode: u8, payload: &[u8], masking_key: [u8; 4]) -> Vec<u8> {
+    build_frame(opcode, payload, Some(masking_key))
+}
+
+fn build_frame(opcode: u8, payload: &[u8], masking_key: Option<[u8; 4]>) -> Vec<u8> {
     let data_len = payload.len();
+    let mask_bit = if masking_key.is_some() { 0b1000_0000 } else { 0 };
     const MAX_FRAME_HEADER_LEN: usize = 14;
     let mut result = Vec::with_capacity(MAX_FRAME_HEADER_LEN + data_len);
 
@@ -138,18 +453,23 @@ pub fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
     result.push(first_byte);
 
     if data_len < 126 {
-        result.push(data_len as u8);
+        result.push(data_len as u8 | mask_bit);
     } else if data_len <= u16::MAX as usize {
-        result.push(126);
+        result.push(126 | mask_bit);
         let bytes = (data_len as u64).to_be_bytes();
         result.extend_from_slice(&bytes[6..8]);
     } else {
-        result.push(127);
+        result.push(127 | mask_bit);
         let bytes = (data_len as u64).to_be_bytes();
         result.extend_from_slice(&bytes);
     }
 
-    result.extend_from_slice(payload);
+    if let Some(masking_key) = masking_key {
+        result.extend_from_slice(&masking_key);
+        result.extend(payload.iter().enumerate().map(|(i, ch)| ch ^ masking_key[i % 4]));
+    } else {
+        result.extend_from_slice(payload);
+    }
 
     result
 }
@@ -158,6 +478,8 @@ pub fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
 pub struct Parser {
     state: ParserState,
     frame: Frame,
+    /// Parses frames received by client from server: they must be unmasked.
+    client_mode: bool,
 }
 
 impl Parser {
@@ -166,6 +488,18 @@ impl Parser {
         Parser::default()
     }
 
+    /// Parser of frames received by client from server. Accepts only unmasked frames.
+    /// The parser need to be recreated only after error!
+    pub fn client() -> Self {
+        Parser { client_mode: true, ..Parser::default() }
+    }
+
+    /// Length of data of not complete frame kept until next `parse_yet`.
+    #[cfg(any(test, feature = "fuzz"))]
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.frame.buf.len()
+    }
+
     /// Add incoming data for processing.
     pub fn parse_yet(&mut self, tmp_buf: &[u8], payload_limit: usize) -> Result<Option<(Frame, Vec<u8>)>, ParseFrameError> {
         self.frame.buf.extend_from_slice(tmp_buf);
@@ -192,8 +526,13 @@ impl Parser {
                         let second_byte = self.frame.buf[1];
                         let mask = second_byte & 0b1000_0000;
                         // RFC: 6455 section 5.1: server must disconnect from a client
-                        // if that client sends an unmasked message
-                        if mask == 0 {
+                        // if that client sends an unmasked message, and client must
+                        // disconnect from a server if it sends a masked message
+                        if self.client_mode {
+                            if mask != 0 {
+                                return Err(ParseFrameError::MaskedServerMessage);
+                            }
+                        } else if mask == 0 {
                             return Err(ParseFrameError::UnmaskedClientMaessage);
                         }
 
@@ -215,6 +554,15 @@ impl Parser {
                     break; // need more data
                 }
                 ParserState::ParseMaskingKey => {
+                    if self.client_mode {
+                        // no masking key, payload is right after payload length
+                        self.frame.payload_index = self.frame.masking_key_index;
+                        self.frame.masking_key_index = 0;
+
+                        self.state = ParserState::LoadPayloadData;
+                        continue;
+                    }
+
                     const MASKING_KEY_LEN: usize = 4;
                     if self.frame.buf.len() >= self.frame.masking_key_index + MASKING_KEY_LEN {
                         self.frame.payload_index = self.frame.masking_key_index + MASKING_KEY_LEN;
@@ -276,15 +624,17 @@ impl Parser {
 
                         // mask is checked early. RFC: 6455 section 5.1: server must disconnect
                         // from a client if that client sends an unmasked message
-                        let mut mask = [0; 4];
-                        mask.clone_from_slice(result.mask().unwrap_or_else(|| {
-                            // unreachable code
-                            &[0, 0, 0, 0]
-                        }));
-
-                        // decode
-                        for (i, ch) in result.buf.iter_mut().skip(result.payload_index).enumerate() {
-                            *ch ^= mask[i % 4];
+                        if !self.client_mode {
+                            let mut mask = [0; 4];
+                            mask.clone_from_slice(result.mask().unwrap_or_else(|| {
+                                log(Level::Error, format_args!("websocket frame has no mask after mask check"));
+                                &[0, 0, 0, 0]
+                            }));
+
+                            // decode
+                            for (i, ch) in result.buf.iter_mut().skip(result.payload_index).enumerate() {
+                                *ch ^= mask[i % 4];
+                            }
                         }
 
                         self.state = ParserState::ParseFirstByteWhereFinAndOpcode;
@@ -305,12 +655,13 @@ impl Default for Parser {
         Parser {
             frame: Frame::new(),
             state: ParserState::ParseFirstByteWhereFinAndOpcode,
+            client_mode: false,
         }
     }
 }
 
 /// Parsed websocket frame. See RFC: 6455 section 5.2, Base Framing Protocol.
-/// No mask because server accept only frames where mask==1.
+/// Payload is unmasked. Frames received by server always have mask, frames received by client (see `Parser::client`) never have mask.
 #[derive(Debug)]
 pub struct Frame {
     /// First bit of first byte.
@@ -413,6 +764,8 @@ enum ParserState {
 pub enum ParseFrameError {
     UnsupportedOpcode,
     UnmaskedClientMaessage,
+    /// Client received masked frame from server.
+    MaskedServerMessage,
     PayloadLimit,
 }
 
@@ -431,9 +784,18 @@ impl From<ParseFrameError> for WebsocketError {
 
 impl std::fmt::Display for WebsocketHandshakeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
 impl std::error::Error for WebsocketHandshakeError {
 }
+
+impl std::fmt::Display for MessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for MessageError {
+}
@@ -0,0 +1,410 @@
+use crate::websocket::{accept_key, masked_frame, Frame, Parser, WebsocketError, WebsocketResult, CLOSE_OPCODE};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use rustls::Session;
+use std::sync::{Arc, Mutex};
+
+/// Outbound websocket connection to ws:// or wss:// server. Frames are received in a dedicated thread.
+#[derive(Clone)]
+pub struct WebsocketClient {
+    inner: Arc<InnerWebsocketClient>,
+}
+
+impl WebsocketClient {
+    /// Connects to ws:// url and performs websocket handshake. Blocks until the handshake is finished.
+    pub fn connect(url: &str) -> Result<Self, WebsocketClientError> {
+        let url = Url::parse(url)?;
+        if url.tls {
+            return Err(WebsocketClientError::NoTlsConfig);
+        }
+
+        let tcp_stream = TcpStream::connect((url.host.as_str(), url.port))?;
+        WebsocketClient::handshake(&url, tcp_stream, None)
+    }
+
+    /// Connects to ws:// or wss:// url and performs websocket handshake. Blocks until the handshake is finished.
+    /// # Arguments
+    /// * `tls_config` - client TLS config with root certificates, used for wss:// url.
+    pub fn connect_tls(url: &str, tls_config: Arc<rustls::ClientConfig>) -> Result<Self, WebsocketClientError> {
+        let url = Url::parse(url)?;
+        let tcp_stream = TcpStream::connect((url.host.as_str(), url.port))?;
+        if !url.tls {
+            return WebsocketClient::handshake(&url, tcp_stream, None);
+        }
+
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str(&url.host).map_err(|_| WebsocketClientError::WrongUrl)?;
+        let tls_session = rustls::ClientSession::new(&tls_config, dns_name);
+        WebsocketClient::handshake(&url, tcp_stream, Some(tls_session))
+    }
+
+    /// Set callback that will called every time a frame is received from the server
+    /// or some error such as read socket or parsing frames. Starts receiving of frames.
+    pub fn on_frame(&self, callback: impl FnMut(WebsocketResult, WebsocketClient) -> Result<(), WebsocketError> + Send + 'static) {
+        if let Ok(mut frame_callback) = self.inner.frame_callback.lock() {
+            *frame_callback = Some(Box::new(callback));
+        }
+
+        if !self.inner.receiving.swap(true, Ordering::SeqCst) {
+            let client = self.clone();
+            std::thread::spawn(move || client.receive_frames());
+        }
+    }
+
+    /// Send masked frame.
+    pub fn send(&self, opcode: u8, payload: &[u8]) {
+        let _ = self.try_send(opcode, payload);
+    }
+
+    /// Send masked frame. Blocks until the frame is written, doesn't wait for reading of frames.
+    pub fn try_send(&self, opcode: u8, payload: &[u8]) -> Result<(), std::io::Error> {
+        let frame = masked_frame(opcode, payload, self.inner.masking_key());
+        let mut writer = self.inner.writer.lock().map_err(|_| std::io::Error::other("poisoned lock"))?;
+        match &self.inner.tls_session {
+            Some(tls_session) => {
+                let mut tls_session = tls_session.lock().map_err(|_| std::io::Error::other("poisoned lock"))?;
+                // the session buffers limited amount of data, it's written by parts
+                let mut frame = &frame[..];
+                while !frame.is_empty() {
+                    let cnt = tls_session.write(frame)?;
+                    frame = &frame[cnt..];
+                    while tls_session.wants_write() {
+                        tls_session.write_tls(&mut *writer)?;
+                    }
+                }
+                Ok(())
+            }
+            None => {
+                writer.write_all(&frame)?;
+                writer.flush()
+            }
+        }
+    }
+
+    /// Sends close frame and closes connection.
+    pub fn close(&self) {
+        if !self.inner.closed.swap(true, Ordering::SeqCst) {
+            let _ = self.try_send(CLOSE_OPCODE, &[]);
+            let _ = self.inner.tcp_stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Returns true if the connection is closed by `close`, by the server or by error.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::SeqCst)
+    }
+
+    fn handshake(url: &Url, tcp_stream: TcpStream, mut tls_session: Option<rustls::ClientSession>) -> Result<Self, WebsocketClientError> {
+        let mut writer = tcp_stream.try_clone()?;
+        let mut stream: Box<dyn ClientStream + '_> = match &mut tls_session {
+            Some(tls_session) => Box::new(rustls::Stream::new(tls_session, &mut writer)),
+            None => Box::new(&mut writer),
+        };
+
+        let random = RandomState::new();
+        let mut key = [0; 16];
+        key[..8].copy_from_slice(&random_u64(&random, 0).to_be_bytes());
+        key[8..].copy_from_slice(&random_u64(&random, 1).to_be_bytes());
+        let key = base64::encode(key);
+
+        let request = format!(
+            "GET {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             \r\n",
+            url.path_and_query,
+            url.host_header(),
+            key,
+        );
+        stream.write_all(request.as_bytes())?;
+        stream.flush()?;
+
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+        let head_len = loop {
+            if let Some(index) = response.windows(4).position(|window| window == b"\r\n\r\n") {
+                break index + 4;
+            }
+
+            if response.len() > HANDSHAKE_RESPONSE_LIMIT {
+                return Err(WebsocketClientError::WrongHandshakeResponse);
+            }
+
+            let cnt = stream.read(&mut buf)?;
+            if cnt == 0 {
+                return Err(WebsocketClientError::WrongHandshakeResponse);
+            }
+            response.extend_from_slice(&buf[..cnt]);
+        };
+
+        let head = std::str::from_utf8(&response[..head_len]).map_err(|_| WebsocketClientError::WrongHandshakeResponse)?;
+        let mut lines = head.split("\r\n");
+        if !lines.next().is_some_and(|status_line| status_line.starts_with("HTTP/1.1 101")) {
+            return Err(WebsocketClientError::WrongHandshakeResponse);
+        }
+
+        let expected_accept = accept_key(&key).map_err(|_| WebsocketClientError::WrongHandshakeResponse)?;
+        let accepted = lines.any(|line| {
+            let mut name_value = line.splitn(2, ':');
+            name_value.next().is_some_and(|name| name.trim().eq_ignore_ascii_case("Sec-WebSocket-Accept")) &&
+                name_value.next().is_some_and(|value| value.trim() == expected_accept)
+        });
+        if !accepted {
+            return Err(WebsocketClientError::WrongHandshakeResponse);
+        }
+
+        drop(stream);
+
+        Ok(WebsocketClient {
+            inner: Arc::new(InnerWebsocketClient {
+                writer: Mutex::new(writer),
+                tls_session: tls_session.map(Mutex::new),
+                tcp_stream,
+                frame_callback: Mutex::new(None),
+                received_after_handshake: Mutex::new(response[head_len..].to_vec()),
+                receiving: AtomicBool::new(false),
+                closed: AtomicBool::new(false),
+                random,
+                masking_key_counter: AtomicU64::new(2),
+            }),
+        })
+    }
+
+    /// Reads and parses frames until the connection is closed.
+    fn receive_frames(&self) {
+        let mut parser = Parser::client();
+        let mut data = match self.inner.received_after_handshake.lock() {
+            Ok(mut received) => std::mem::take(&mut *received),
+            Err(_) => Vec::new(),
+        };
+        let mut buf = vec![0; 16384];
+        let mut reader = match self.inner.tcp_stream.try_clone() {
+            Ok(reader) => reader,
+            Err(err) => {
+                self.call_frame_callback(Err(WebsocketError::ReadError(err)));
+                self.close();
+                return;
+            }
+        };
+
+        let mut server_closed = false;
+        loop {
+            loop {
+                match parser.parse_yet(&data, PAYLOAD_LIMIT) {
+                    Ok(Some((frame, surplus))) => {
+                        self.call_frame_callback(Ok(&frame));
+                        data = surplus;
+                    }
+                    Ok(None) => break,
+                    Err(err) => {
+                        self.call_frame_callback(Err(WebsocketError::ParseFrameError(err)));
+                        self.close();
+                        return;
+                    }
+                }
+            }
+
+            if server_closed {
+                self.inner.closed.store(true, Ordering::SeqCst);
+                return;
+            }
+
+            if self.is_closed() {
+                return;
+            }
+
+            data = match self.read_data(&mut reader, &mut buf) {
+                Ok((received, closed)) => {
+                    server_closed = closed;
+                    received
+                }
+                Err(err) => {
+                    if !self.is_closed() {
+                        self.call_frame_callback(Err(WebsocketError::ReadError(err)));
+                        self.close();
+                    }
+                    return;
+                }
+            };
+        }
+    }
+
+    /// Reads data from the server with own stream of reading thread, decrypts it if the connection uses TLS.
+    /// The session is locked only for decryption, so sending isn't blocked by waiting for data.
+    /// Returns received data and true if the server closed the connection.
+    fn read_data(&self, reader: &mut TcpStream, buf: &mut [u8]) -> Result<(Vec<u8>, bool), std::io::Error> {
+        let cnt = reader.read(buf)?;
+        let tls_session = match &self.inner.tls_session {
+            Some(tls_session) => tls_session,
+            None => return Ok((buf[..cnt].to_vec(), cnt == 0)),
+        };
+
+        if cnt == 0 {
+            return Ok((Vec::new(), true));
+        }
+
+        // alerts and other records of the session are written in the same order as frames
+        let mut writer = self.inner.writer.lock().map_err(|_| std::io::Error::other("poisoned lock"))?;
+        let mut tls_session = tls_session.lock().map_err(|_| std::io::Error::other("poisoned lock"))?;
+        let mut received = &buf[..cnt];
+        while !received.is_empty() {
+            tls_session.read_tls(&mut received)?;
+            tls_session.process_new_packets().map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        }
+
+        let mut data = Vec::new();
+        let closed = loop {
+            match tls_session.read(buf) {
+                Ok(0) => break false,
+                Ok(cnt) => data.extend_from_slice(&buf[..cnt]),
+                // "close_notify" is received
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionAborted => break true,
+                Err(err) => return Err(err),
+            }
+        };
+
+        while tls_session.wants_write() {
+            tls_session.write_tls(&mut *writer)?;
+        }
+
+        Ok((data, closed))
+    }
+
+    fn call_frame_callback(&self, frame: Result<&Frame, WebsocketError>) {
+        if let Ok(mut callback) = self.inner.frame_callback.lock() {
+            if let Some(callback) = &mut *callback {
+                if callback(frame, self.clone()).is_err() {
+                    self.close();
+                }
+            }
+        }
+    }
+}
+
+/// Error of connecting to websocket server.
+#[derive(Debug)]
+pub enum WebsocketClientError {
+    /// Url is not ws:// or wss:// url with host.
+    WrongUrl,
+    /// Url is wss:// but connected without TLS config, see `WebsocketClient::connect_tls`.
+    NoTlsConfig,
+    /// Server response isn't "101 Switching Protocols" with valid "Sec-WebSocket-Accept".
+    WrongHandshakeResponse,
+    /// Connect, read or write error.
+    Io(std::io::Error),
+}
+
+/// Parts of ws:// or wss:// url.
+struct Url {
+    tls: bool,
+    host: String,
+    port: u16,
+    path_and_query: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Self, WebsocketClientError> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("ws://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("wss://") {
+            (true, rest)
+        } else {
+            return Err(WebsocketClientError::WrongUrl);
+        };
+
+        let (authority, path_and_query) = match rest.find(['/', '?']) {
+            Some(index) if rest[index..].starts_with('/') => (&rest[..index], rest[index..].to_string()),
+            Some(index) => (&rest[..index], format!("/{}", &rest[index..])),
+            None => (rest, "/".to_string()),
+        };
+
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = match authority.rfind(':') {
+            Some(index) if !authority.ends_with(']') => {
+                let port = authority[index + 1..].parse::<u16>().map_err(|_| WebsocketClientError::WrongUrl)?;
+                (&authority[..index], port)
+            }
+            _ => (authority, default_port),
+        };
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() || host.contains(char::is_whitespace) {
+            return Err(WebsocketClientError::WrongUrl);
+        }
+
+        Ok(Url { tls, host: host.to_string(), port, path_and_query })
+    }
+
+    /// Value of "Host" header.
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == if self.tls { 443 } else { 80 } {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+type ClientFrameCallback = Box<dyn FnMut(WebsocketResult, WebsocketClient) -> Result<(), WebsocketError> + Send>;
+
+/// Plain or TLS stream of client during handshake.
+trait ClientStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> ClientStream for T {}
+
+struct InnerWebsocketClient {
+    /// Stream for writing, reading thread reads with own clone of `tcp_stream`.
+    writer: Mutex<TcpStream>,
+    /// TLS session of wss:// connection, locked by writing and by reading thread for decryption of received data.
+    tls_session: Option<Mutex<rustls::ClientSession>>,
+    /// For shutdown of connection and for cloning of reading stream.
+    tcp_stream: TcpStream,
+    frame_callback: Mutex<Option<ClientFrameCallback>>,
+    /// Data received together with handshake response.
+    received_after_handshake: Mutex<Vec<u8>>,
+    /// Thread of receiving frames is started.
+    receiving: AtomicBool,
+    closed: AtomicBool,
+    /// Randomly seeded hasher for masking keys.
+    random: RandomState,
+    masking_key_counter: AtomicU64,
+}
+
+impl InnerWebsocketClient {
+    /// Unpredictable masking key (RFC: 6455 section 5.3).
+    fn masking_key(&self) -> [u8; 4] {
+        let random = random_u64(&self.random, self.masking_key_counter.fetch_add(1, Ordering::SeqCst));
+        let mut key = [0; 4];
+        key.copy_from_slice(&random.to_be_bytes()[..4]);
+        key
+    }
+}
+
+fn random_u64(random: &RandomState, counter: u64) -> u64 {
+    let mut hasher = random.build_hasher();
+    hasher.write_u64(counter);
+    hasher.finish()
+}
+
+const HANDSHAKE_RESPONSE_LIMIT: usize = 16384;
+const PAYLOAD_LIMIT: usize = 16_000_000;
+
+impl From<std::io::Error> for WebsocketClientError {
+    fn from(err: std::io::Error) -> Self {
+        WebsocketClientError::Io(err)
+    }
+}
+
+impl std::fmt::Display for WebsocketClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for WebsocketClientError {}
@@ -1,15 +1,19 @@
-use crate::server::{Error, Event, Settings, Stopper};
+use crate::server::{AcceptPolicy, ConnectionLimitAction, Error, Event, Settings, Stopper, DRAIN_CHECK_INTERVAL};
+use crate::stats::{Stats, WorkerCounters};
 use crate::tcp_session::TcpSession;
+#[cfg(unix)]
+use crate::tls_handshake::Handshaken;
 
 use mio::net::TcpListener;
 use slab::Slab;
-use std::io::ErrorKind;
+use std::io::Write;
+use std::net::SocketAddr;
 use std::panic;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
-use std::time::Duration;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use crate::web_session;
-use crate::web_session::WebSession;
+use crate::web_session::{LoadSheddingMode, WebSession};
 
 /// Single threaded TCP server designed for use as an HTTP server.
 pub struct Worker {
@@ -32,8 +36,32 @@ pub struct Worker {
     /// For update once per second.
     http_date_string: Arc<RwLock<String>>,
 
-    /// Buffer for read from socket.
-    read_buf: [u8; 1024],
+    /// Statistics counters, can be read from other threads.
+    pub(crate) counters: Arc<WorkerCounters>,
+
+    /// Statistics of all workers of the server, for accept policy.
+    pub(crate) stats: Stats,
+
+    /// Wakes the worker for writing data sent to its sessions from other threads.
+    pub(crate) waker: Arc<Waker>,
+    /// Registration of the waker in poll, must live as long as the worker.
+    _waker_registration: mio::Registration,
+    /// Slab keys of sessions with data sent from other threads.
+    woken_sessions: mpsc::Receiver<mio::Token>,
+    /// Connections which completed TLS handshake in `Settings::tls_handshake_pool`.
+    #[cfg(unix)]
+    handshaken: mpsc::Receiver<Handshaken>,
+    /// Sender for `TlsHandshakePool`.
+    #[cfg(unix)]
+    handshaken_sender: mpsc::Sender<Handshaken>,
+
+    /// Load state seen by the worker last time.
+    load_state: LoadState,
+
+    /// Time of last check of timeouts of sessions.
+    timeouts_checked: Instant,
+    /// Time of last check of slow consumers.
+    slow_consumers_checked: Instant,
 }
 
 impl Worker {
@@ -43,6 +71,12 @@ impl Worker {
 
         mio_poll.register(&tcp_listener, LISTENER_TOKEN, mio::Ready::readable(), mio::PollOpt::level())?;
 
+        let (waker_registration, set_readiness) = mio::Registration::new2();
+        mio_poll.register(&waker_registration, WAKER_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+        let (woken_sessions_sender, woken_sessions) = mpsc::channel();
+        #[cfg(unix)]
+        let (handshaken_sender, handshaken) = mpsc::channel();
+
         const POLL_EVENTS_CNT: usize = 4096;
         const CLIENTS_CAPACITY: usize = 1000000;
 
@@ -57,17 +91,78 @@ impl Worker {
             tcp_listener,
             settings: Settings {
                 tls_config: None,
+                #[cfg(unix)]
+                tls_handshake_pool: None,
                 web_settings: web_session::Settings::default(),
+                accept_policy: AcceptPolicy::default(),
+                connection_limit: None,
+                accept_rate_limit: None,
             },
             stopper,
             http_date_string,
-            read_buf: [0; 1024],
+            counters: Arc::new(WorkerCounters::default()),
+            stats: Stats::default(),
+            waker: Arc::new(Waker { set_readiness, sessions: woken_sessions_sender, throttled: Mutex::new(Vec::new()) }),
+            _waker_registration: waker_registration,
+            woken_sessions,
+            #[cfg(unix)]
+            handshaken,
+            #[cfg(unix)]
+            handshaken_sender,
+            load_state: LoadState { listening: true, connection_limit_reached: false, draining: false, yielding: false },
+            timeouts_checked: Instant::now(),
+            slow_consumers_checked: Instant::now(),
         })
     }
 
     /// Poll mio, process MIO events, read data processing (parse HTTP, etc.), generate events and do some based on user response to event.
-    pub fn poll(&mut self, timeout: Option<Duration>, event_callback: &mut (dyn FnMut(Event))) {
+    pub fn poll(&mut self, timeout: Option<Duration>, event_callback: &mut dyn FnMut(Event) ) {
+        let timeout = if self.resume_throttled() {
+            // check bandwidth limits again soon
+            Some(timeout.map_or(THROTTLE_CHECK_INTERVAL, |timeout| timeout.min(THROTTLE_CHECK_INTERVAL)))
+        } else {
+            timeout
+        };
+
+        let timeout = if self.close_timed_out(event_callback) {
+            Some(timeout.map_or(TIMEOUT_CHECK_INTERVAL, |timeout| timeout.min(TIMEOUT_CHECK_INTERVAL)))
+        } else {
+            timeout
+        };
+
+        let timeout = if self.report_slow_consumers(event_callback) {
+            Some(timeout.map_or(TIMEOUT_CHECK_INTERVAL, |timeout| timeout.min(TIMEOUT_CHECK_INTERVAL)))
+        } else {
+            timeout
+        };
+
+        let timeout = if self.close_lingering() {
+            Some(timeout.map_or(TIMEOUT_CHECK_INTERVAL, |timeout| timeout.min(TIMEOUT_CHECK_INTERVAL)))
+        } else {
+            timeout
+        };
+
         self.remove_if_need_close(event_callback);
+        #[cfg(unix)]
+        while let Some(phase) = self.stopper.take_handoff_phase() {
+            event_callback(Event::Handoff(phase));
+        }
+
+        let timeout = if self.stopper.is_draining() {
+            self.load_state.draining = true;
+            self.check_drain();
+            Some(timeout.map_or(DRAIN_CHECK_INTERVAL, |timeout| timeout.min(DRAIN_CHECK_INTERVAL)))
+        } else {
+            timeout
+        };
+        self.load_state.yielding = !self.may_accept();
+        update_load(&self.settings, &self.counters, &self.stats, &mut self.load_state, &self.mio_poll, &self.tcp_listener, event_callback);
+        let timeout = if !self.load_state.listening && (self.load_state.connection_limit_reached || self.load_state.yielding) {
+            // connections of other workers don't wake this worker when they are closed
+            Some(timeout.map_or(CONNECTION_LIMIT_CHECK_INTERVAL, |timeout| timeout.min(CONNECTION_LIMIT_CHECK_INTERVAL)))
+        } else {
+            timeout
+        };
 
         let poll_res = self.mio_poll.poll(&mut self.events, timeout);
         if let Err(err) = poll_res {
@@ -79,7 +174,7 @@ impl Worker {
     }
 
     /// Run server. See 'poll'.
-    pub fn run(&mut self, event_callback: &mut (dyn FnMut(Event))) {
+    pub fn run(&mut self, event_callback: &mut dyn FnMut(Event) ) {
         loop {
             if self.stopper.need_stop() {
                 break;
@@ -90,68 +185,85 @@ impl Worker {
     }
 
     /// Process MIO events. Register new tcp connections.
-    fn process_mio_events(&mut self, event_callback: &mut (dyn FnMut(Event))) {
-        for event in self.events.iter() {
+    fn process_mio_events(&mut self, event_callback: &mut dyn FnMut(Event) ) {
+        let mut woken = false;
+        // taken for the loop because sessions are added while iterating
+        let events = std::mem::replace(&mut self.events, mio::Events::with_capacity(0));
+        for event in events.iter() {
             match event.token() {
                 LISTENER_TOKEN => {
-                    while let Ok((stream, addr)) = self.tcp_listener.accept() {
-                        let session_id = self.connections_counter.fetch_add(1, Ordering::SeqCst);
-                        let slab_key = self.web_sessions.vacant_entry().key();
+                    loop {
+                        if !self.may_accept() {
+                            // less loaded worker will accept, the listener is deregistered so poll doesn't return it again
+                            self.load_state.yielding = true;
+                            update_load(&self.settings, &self.counters, &self.stats, &mut self.load_state, &self.mio_poll, &self.tcp_listener, event_callback);
+                            break;
+                        }
 
-                        let rustls_session = match &self.settings.tls_config {
-                            Some(tls_config) => Some(Mutex::new(rustls::ServerSession::new(&tls_config))),
-                            None => None,
+                        let connection_limit_reached = update_load(&self.settings, &self.counters, &self.stats, &mut self.load_state, &self.mio_poll, &self.tcp_listener, event_callback);
+                        if !self.load_state.listening {
+                            // other connections wait in backlog
+                            break;
+                        }
+
+                        let (stream, addr) = match self.tcp_listener.accept() {
+                            Ok(accepted) => accepted,
+                            Err(_) => break,
                         };
 
-                        let tcp_session = TcpSession::new(session_id, slab_key, stream, addr, rustls_session, self.mio_poll.clone(), self.http_date_string.clone());
-                        let web_session = WebSession::new(tcp_session.clone());
+                        if connection_limit_reached {
+                            let respond = self.settings.tls_config.is_none()
+                                && self.settings.connection_limit.is_some_and(|limit| limit.action == ConnectionLimitAction::Respond503);
+                            reject_connection(stream, respond);
+                            self.counters.rejected_total.fetch_add(1, Ordering::SeqCst);
+                            continue;
+                        }
 
-                        event_callback(Event::Incoming(tcp_session.clone()));
+                        if self.settings.accept_rate_limit.as_ref().is_some_and(|accept_rate_limit| !accept_rate_limit.allow(addr.ip())) {
+                            #[cfg(feature = "tracing")]
+                            tracing::debug!(addr = %addr, "connection rate limited");
 
-                        if tcp_session.need_close() {
+                            self.counters.rejected_total.fetch_add(1, Ordering::SeqCst);
+                            event_callback(Event::RateLimited { addr });
                             continue;
                         }
 
-                        let register_result;
-                        match tcp_session.inner.mio_stream.lock() {
-                            Ok(stream) => {
-                                register_result = self.mio_poll.register(&*stream, mio::Token(slab_key), mio::Ready::readable(), mio::PollOpt::level());
-                            }
-                            Err(err) => {
-                                let err = std::io::Error::new(ErrorKind::Other, format!("{}", err));
-                                event_callback(Event::Error(Error::RegisterError(err)));
-                                event_callback(Event::Closed(session_id));
-                                continue;
-                            }
+                        #[cfg(unix)]
+                        if let (Some(tls_config), Some(pool)) = (&self.settings.tls_config, &self.settings.tls_handshake_pool) {
+                            // the session is added after the handshake, see `join_handshaken`; until then the connection is counted here
+                            self.counters.active_sessions.fetch_add(1, Ordering::SeqCst);
+                            pool.handshake(stream, addr, rustls::ServerSession::new(tls_config), self.handshaken_sender.clone(), self.waker.clone());
+                            continue;
                         }
 
-                        match register_result {
-                            Ok(()) => {
-                                self.web_sessions.insert(web_session);
-                            }
-                            Err(err) => {
-                                event_callback(Event::Error(Error::RegisterError(err)));
-                                event_callback(Event::Closed(session_id));
-                            }
-                        }
+                        let rustls_session = self.settings.tls_config.as_ref().map(rustls::ServerSession::new);
+                        self.add_session(stream, addr, rustls_session, &[], event_callback);
                     }
                 }
-                mio::Token(token_id) => {
+                WAKER_TOKEN => {
+                    woken = true;
+                }
+                token => {
                     let mut need_remove = None;
 
                     if event.readiness().is_readable() {
-                        // there is a possibility of receiving events on a already removed session if library user cloned stream and not deleted yet
-                        if let Some(session) = self.web_sessions.get_mut(token_id) {
+                        if let Some(session) = session_by_token(&mut self.web_sessions, token) {
                             let session_settings = &self.settings.web_settings;
 
-                            let read_buf = &mut self.read_buf[..];
                             let catch_result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
-                                session.read_stream(session_settings, read_buf);
+                                session.read_stream(session_settings);
                             }));
 
+                            if let Some(err) = session.tcp_session.take_tls_handshake_error() {
+                                event_callback(Event::TlsHandshakeFailed { addr: *session.tcp_session.addr(), err });
+                            }
+
                             if catch_result.is_err() {
                                 need_remove = Some(session.tcp_session.id());
                                 event_callback(Event::Error(Error::Panicked(session.tcp_session.id())));
+                            } else if session.is_poisoned_lock() {
+                                need_remove = Some(session.tcp_session.id());
+                                event_callback(Event::Error(Error::PoisonedLock(session.tcp_session.id())));
                             } else if session.tcp_session.need_close() {
                                 need_remove = Some(session.tcp_session.id());
                             }
@@ -159,7 +271,7 @@ impl Worker {
                     }
 
                     if event.readiness().is_writable() {
-                        if let Some(session) = self.web_sessions.get_mut(token_id) {
+                        if let Some(session) = session_by_token(&mut self.web_sessions, token) {
                             session.tcp_session.send_yet();
 
                             if session.tcp_session.need_close() {
@@ -168,20 +280,260 @@ impl Worker {
                         }
                     }
 
-                    if let Some(session_id) = need_remove {
-                        self.web_sessions.remove(token_id);
-                        event_callback(Event::Closed(session_id));
+                    if need_remove.is_some() {
+                        let session = self.web_sessions.remove(token_slab_key(token));
+                        finish_session(&session, event_callback);
+                    }
+                }
+            }
+        }
+
+        self.events = events;
+
+        if woken {
+            self.write_outboxes(event_callback);
+            #[cfg(unix)]
+            self.join_handshaken(event_callback);
+        }
+    }
+
+    /// Creates session of accepted connection, emits `Event::Incoming` and registers the session in poll.
+    /// `received` is data received before, for example with the last packet of TLS handshake.
+    fn add_session(&mut self, stream: mio::net::TcpStream, addr: SocketAddr, rustls_session: Option<rustls::ServerSession>, received: &[u8], event_callback: &mut dyn FnMut(Event)) {
+        let slab_key = self.web_sessions.vacant_entry().key();
+        if slab_key > MAX_SLAB_KEY {
+            // key doesn't fit to token
+            reject_connection(stream, false);
+            self.counters.rejected_total.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+
+        let session_id = self.connections_counter.fetch_add(1, Ordering::SeqCst);
+        self.counters.accepted_total.fetch_add(1, Ordering::SeqCst);
+        let token = session_token(slab_key, session_id);
+
+        let tcp_session = TcpSession::new(session_id, token, stream, addr, rustls_session.map(Mutex::new), self.mio_poll.clone(), self.http_date_string.clone(), self.counters.clone(), self.settings.web_settings.global_bandwidth_limit.clone(), self.waker.clone());
+        tcp_session.set_bandwidth_limit(self.settings.web_settings.session_bandwidth_limit);
+        tcp_session.set_read_quota(self.settings.web_settings.read_quota);
+        tcp_session.set_write_quota(self.settings.web_settings.write_quota);
+        tcp_session.set_websocket_registry(&self.settings.web_settings.websocket_registry);
+        let mut web_session = WebSession::new(tcp_session.clone());
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(session_id, addr = %addr, tls = self.settings.tls_config.is_some(), "connection accepted");
+
+        event_callback(Event::Incoming(tcp_session.clone()));
+
+        if tcp_session.need_close() {
+            return;
+        }
+
+        let register_result = match tcp_session.inner.mio_stream.lock() {
+            Ok(stream) => {
+                self.mio_poll.register(&*stream, token, mio::Ready::readable(), mio::PollOpt::level())
+            }
+            Err(err) => {
+                let err = std::io::Error::other(format!("{}", err));
+                event_callback(Event::Error(Error::RegisterError(err)));
+                event_callback(Event::Closed(session_id));
+                return;
+            }
+        };
+
+        if let Err(err) = register_result {
+            event_callback(Event::Error(Error::RegisterError(err)));
+            event_callback(Event::Closed(session_id));
+            return;
+        }
+
+        if !received.is_empty() {
+            let session_settings = &self.settings.web_settings;
+            let catch_result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                web_session.process_received(received, session_settings);
+            }));
+
+            if catch_result.is_err() {
+                event_callback(Event::Error(Error::Panicked(session_id)));
+                tcp_session.close();
+            }
+        }
+
+        // closed session is removed by `remove_if_need_close`
+        self.web_sessions.insert(web_session);
+    }
+
+    /// Adds sessions of connections which completed TLS handshake in `TlsHandshakePool`.
+    #[cfg(unix)]
+    fn join_handshaken(&mut self, event_callback: &mut dyn FnMut(Event)) {
+        while let Ok(handshaken) = self.handshaken.try_recv() {
+            // counted by the session from now
+            self.counters.active_sessions.fetch_sub(1, Ordering::SeqCst);
+
+            match handshaken.result {
+                Ok((rustls_session, received)) => {
+                    if self.stopper.need_stop() {
+                        continue;
                     }
+
+                    self.add_session(handshaken.stream, handshaken.addr, Some(rustls_session), &received, event_callback);
+                }
+                Err(err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(addr = %handshaken.addr, error = %err, "tls handshake failed");
+
+                    event_callback(Event::TlsHandshakeFailed { addr: handshaken.addr, err });
+                }
+            }
+        }
+    }
+
+    /// Writes data sent to sessions from other threads, closes sessions waiting for it in `TcpSession::close_when_sent`.
+    /// Also starts websockets after deferred handshakes, see `Request::accept_websocket_deferred`.
+    fn write_outboxes(&mut self, event_callback: &mut dyn FnMut(Event)) {
+        let _ = self.waker.set_readiness.set_readiness(mio::Ready::empty());
+
+        for token in self.woken_sessions.try_iter() {
+            // the session may be closed already
+            if let Some(web_session) = session_by_token(&mut self.web_sessions, token) {
+                web_session.tcp_session.drain_outbox();
+                web_session.tcp_session.close_if_sent();
+
+                let session_settings = &self.settings.web_settings;
+                let catch_result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                    web_session.resume_deferred_websocket(session_settings);
+                    web_session.resume_pending(session_settings);
+                }));
+
+                if catch_result.is_err() {
+                    event_callback(Event::Error(Error::Panicked(web_session.tcp_session.id())));
+                    web_session.tcp_session.close();
                 }
             }
         }
     }
 
+    /// Counts sessions with not written data or not finished requests for `Stopper::drain`.
+    fn check_drain(&self) {
+        let pending = self.web_sessions.iter().filter(|(_, web_session)| web_session.tcp_session.has_pending_work()).count();
+        self.counters.drain_pending.store(pending, Ordering::SeqCst);
+        self.counters.drain_checked.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks accept policy.
+    fn may_accept(&self) -> bool {
+        match self.settings.accept_policy {
+            AcceptPolicy::Shared => true,
+            AcceptPolicy::LeastConnections { slack } => {
+                let active_sessions = self.counters.active_sessions.load(Ordering::SeqCst);
+                self.stats.min_active_sessions().is_none_or(|min| active_sessions <= min + slack)
+            }
+        }
+    }
+
+    /// Continues writing of sessions stopped by bandwidth limit. Returns true if some of them are still waiting.
+    fn resume_throttled(&mut self) -> bool {
+        let tokens = match self.waker.throttled.lock() {
+            Ok(mut throttled) if !throttled.is_empty() => std::mem::take(&mut *throttled),
+            _ => return false,
+        };
+
+        let mut waiting = false;
+        for token in tokens {
+            // the session may be closed already
+            let web_session = match session_by_token(&mut self.web_sessions, token) {
+                Some(web_session) => web_session,
+                None => continue,
+            };
+            let tcp_session = &web_session.tcp_session;
+            if tcp_session.inner.throttled.swap(false, Ordering::SeqCst) {
+                tcp_session.send_yet();
+                waiting |= tcp_session.inner.throttled.load(Ordering::SeqCst);
+            }
+        }
+
+        waiting
+    }
+
+    /// Closes sessions that exceeded timeouts of settings, not more often than `TIMEOUT_CHECK_INTERVAL`.
+    /// Returns true if timeouts are set and the worker must check them again later.
+    fn close_timed_out(&mut self, event_callback: &mut dyn FnMut(Event)) -> bool {
+        let settings = &self.settings.web_settings;
+        if settings.request_head_timeout.is_none() && settings.idle_timeout.is_none() {
+            return false;
+        }
+
+        if self.timeouts_checked.elapsed() < TIMEOUT_CHECK_INTERVAL {
+            return true;
+        }
+        self.timeouts_checked = Instant::now();
+
+        for (_, web_session) in self.web_sessions.iter_mut() {
+            if web_session.tcp_session.need_close() {
+                continue;
+            }
+
+            if let Some(kind) = web_session.close_if_timed_out(settings) {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(session_id = web_session.tcp_session.id(), kind = ?kind, "connection timed out");
+
+                event_callback(Event::Timeout { session_id: web_session.tcp_session.id(), kind });
+            }
+        }
+
+        true
+    }
+
+    /// Closes half-closed sessions whose grace period is over, see `TcpSession::shutdown_write`.
+    /// Returns true if there are half-closed sessions and the worker must check them again later.
+    fn close_lingering(&mut self) -> bool {
+        if self.counters.lingering_sessions.load(Ordering::SeqCst) == 0 {
+            return false;
+        }
+
+        let now = Instant::now();
+        for (_, web_session) in self.web_sessions.iter() {
+            if web_session.tcp_session.linger_deadline().is_some_and(|deadline| deadline <= now) {
+                web_session.tcp_session.close();
+            }
+        }
+
+        true
+    }
+
+    /// Emits `Event::SlowConsumer` for sessions that exceeded threshold of settings, not more often than `TIMEOUT_CHECK_INTERVAL`.
+    /// Returns true if the threshold is set and the worker must check sessions again later.
+    fn report_slow_consumers(&mut self, event_callback: &mut dyn FnMut(Event)) -> bool {
+        let slow_consumer = match &self.settings.web_settings.slow_consumer {
+            Some(slow_consumer) => *slow_consumer,
+            None => return false,
+        };
+
+        if self.slow_consumers_checked.elapsed() < TIMEOUT_CHECK_INTERVAL {
+            return true;
+        }
+        self.slow_consumers_checked = Instant::now();
+
+        for (_, web_session) in self.web_sessions.iter_mut() {
+            if web_session.tcp_session.need_close() {
+                continue;
+            }
+
+            if let Some(queued_bytes) = web_session.check_slow_consumer(&slow_consumer) {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(session_id = web_session.tcp_session.id(), queued_bytes, "slow consumer");
+
+                event_callback(Event::SlowConsumer { session_id: web_session.tcp_session.id(), queued_bytes });
+            }
+        }
+
+        true
+    }
+
     /// Removes sessions that no need.
-    fn remove_if_need_close(&mut self, event_callback: &mut (dyn FnMut(Event))) {
+    fn remove_if_need_close(&mut self, event_callback: &mut dyn FnMut(Event) ) {
         self.web_sessions.retain(|_, web_session| {
             if web_session.tcp_session.need_close() {
-                event_callback(Event::Closed(web_session.tcp_session.id()));
+                finish_session(web_session, event_callback);
                 return false;
             }
 
@@ -190,8 +542,178 @@ impl Worker {
     }
 }
 
+/// Last steps of session removed from worker, emits `Event::Closed`.
+fn finish_session(web_session: &WebSession, event_callback: &mut dyn FnMut(Event)) {
+    let tcp_session = &web_session.tcp_session;
+    // last attempt to write data sent from other threads before closing
+    tcp_session.drain_outbox();
+    tcp_session.report_write_error();
+    tcp_session.leave_websocket_groups();
+    tcp_session.release_socket();
+    tcp_session.release_content_callback();
+    tcp_session.release_write_callbacks();
+    if tcp_session.linger_deadline().is_some() {
+        tcp_session.inner.worker_counters.lingering_sessions.fetch_sub(1, Ordering::SeqCst);
+    }
+    if let Some(kind) = tcp_session.take_exceeded_quota() {
+        event_callback(Event::QuotaExceeded { session_id: tcp_session.id(), kind });
+    }
+    if let Some(kind) = tcp_session.take_protocol_mismatch() {
+        event_callback(Event::ProtocolMismatch { session_id: tcp_session.id(), kind });
+    }
+    event_callback(Event::Closed(tcp_session.id()));
+}
+
+/// Load state of worker.
+struct LoadState {
+    /// Listener is registered in poll.
+    listening: bool,
+    /// Number of connections of the server is at `Settings::connection_limit`.
+    connection_limit_reached: bool,
+    /// New connections are not accepted because of `Stopper::drain`.
+    draining: bool,
+    /// New connections are accepted by less loaded worker, see `AcceptPolicy::LeastConnections`.
+    yielding: bool,
+}
+
+/// Updates overload and connection limit states, informs about their changes and stops or resumes accepting of
+/// connections if it's required by settings. Returns true if the connection limit is reached.
+fn update_load(settings: &Settings, counters: &WorkerCounters, stats: &Stats, load_state: &mut LoadState, mio_poll: &mio::Poll, tcp_listener: &TcpListener, event_callback: &mut dyn FnMut(Event)) -> bool {
+    let overloaded = update_overload(&settings.web_settings, counters, event_callback);
+
+    let connection_limit_reached = settings.connection_limit.is_some_and(|limit| {
+        let connections = stats.active_sessions().unwrap_or_else(|| counters.active_sessions.load(Ordering::SeqCst));
+        connections >= limit.max_connections
+    });
+
+    if load_state.connection_limit_reached != connection_limit_reached {
+        load_state.connection_limit_reached = connection_limit_reached;
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(connection_limit_reached, "connection limit state changed");
+
+        event_callback(Event::ConnectionLimitReached(connection_limit_reached));
+    }
+
+    let pause = load_state.draining
+        || load_state.yielding
+        || (overloaded && stops_accepting(&settings.web_settings))
+        || (connection_limit_reached && settings.connection_limit.is_some_and(|limit| limit.action == ConnectionLimitAction::PauseAccepting));
+
+    if load_state.listening == pause {
+        let register_result = if pause {
+            mio_poll.deregister(tcp_listener)
+        } else {
+            mio_poll.register(tcp_listener, LISTENER_TOKEN, mio::Ready::readable(), mio::PollOpt::level())
+        };
+
+        match register_result {
+            Ok(()) => load_state.listening = !pause,
+            Err(err) => event_callback(Event::Error(Error::RegisterError(err))),
+        }
+    }
+
+    connection_limit_reached
+}
+
+/// Compares load of worker with thresholds of `Settings::load_shedding` and informs about change of overload state.
+/// Returns true if the worker is overloaded.
+fn update_overload(settings: &web_session::Settings, counters: &WorkerCounters, event_callback: &mut dyn FnMut(Event)) -> bool {
+    let load_shedding = match &settings.load_shedding {
+        Some(load_shedding) => load_shedding,
+        None => return false,
+    };
+
+    let overloaded = counters.active_sessions.load(Ordering::SeqCst) > load_shedding.max_active_sessions
+        || counters.queued_write_bytes.load(Ordering::SeqCst) > load_shedding.max_queued_write_bytes;
+
+    if counters.overloaded.swap(overloaded, Ordering::SeqCst) != overloaded {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(overloaded, "worker overload state changed");
+
+        event_callback(Event::Overloaded(overloaded));
+    }
+
+    overloaded
+}
+
+/// Overloaded worker doesn't accept connections.
+fn stops_accepting(settings: &web_session::Settings) -> bool {
+    settings.load_shedding.as_ref().is_some_and(|load_shedding| load_shedding.mode == LoadSheddingMode::StopAccepting)
+}
+
+/// Closes connection accepted above the connection limit, optionally writing "503 Service Unavailable" before.
+fn reject_connection(stream: mio::net::TcpStream, respond: bool) {
+    if respond {
+        // new socket has empty send buffer, so the response is written at once or not at all
+        let _ = (&stream).write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        let _ = stream.shutdown(std::net::Shutdown::Write);
+    }
+}
+
+/// Interval of checking of sessions stopped by bandwidth limit.
+const THROTTLE_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Interval of checking of timeouts of sessions.
+const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval of checking of number of connections while accepting is paused by the connection limit or by accept policy.
+const CONNECTION_LIMIT_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
 /// MIO key of server listener.
 const LISTENER_TOKEN: mio::Token = mio::Token(usize::MAX - 1);
+/// MIO key of waker of worker.
+const WAKER_TOKEN: mio::Token = mio::Token(usize::MAX - 2);
+
+/// Number of low bits of session token with slab key, high bits have generation.
+const SLAB_KEY_BITS: u32 = usize::BITS / 2;
+/// Slab keys are not greater, so session tokens differ from tokens of listener and waker.
+const MAX_SLAB_KEY: usize = (1 << SLAB_KEY_BITS) - 4;
+
+/// Token of session in mio with slab key in low bits and generation (low bits of session id) in high bits,
+/// so delayed event of closed session is not delivered to new session that reuses its slab key.
+pub(crate) fn session_token(slab_key: usize, session_id: u64) -> mio::Token {
+    mio::Token(slab_key | ((session_id as usize) << SLAB_KEY_BITS))
+}
+
+/// Slab key of session token.
+pub(crate) fn token_slab_key(token: mio::Token) -> usize {
+    token.0 & ((1 << SLAB_KEY_BITS) - 1)
+}
+
+/// Session of token if it's not closed and its slab key is not reused by other session.
+fn session_by_token(web_sessions: &mut Slab<WebSession>, token: mio::Token) -> Option<&mut WebSession> {
+    web_sessions.get_mut(token_slab_key(token)).filter(|web_session| web_session.tcp_session.inner.token == token)
+}
+
+/// Wakes the worker for writing data sent to its sessions from other threads.
+pub(crate) struct Waker {
+    set_readiness: mio::SetReadiness,
+    /// Tokens of sessions with data to write.
+    sessions: mpsc::Sender<mio::Token>,
+    /// Tokens of sessions whose writing is stopped by bandwidth limit, see `Worker::resume_throttled`.
+    throttled: Mutex<Vec<mio::Token>>,
+}
+
+impl Waker {
+    pub(crate) fn wake(&self, token: mio::Token) {
+        if self.sessions.send(token).is_ok() {
+            let _ = self.set_readiness.set_readiness(mio::Ready::readable());
+        }
+    }
+
+    /// Session stopped writing by bandwidth limit, the worker resumes it later.
+    pub(crate) fn throttled(&self, token: mio::Token) {
+        if let Ok(mut throttled) = self.throttled.lock() {
+            throttled.push(token);
+        }
+    }
+
+    /// Wakes the worker without writing, for example to check the stopper.
+    pub(crate) fn wake_worker(&self) {
+        let _ = self.set_readiness.set_readiness(mio::Ready::readable());
+    }
+}
 
 /// Returns string date in 7231 format.
 pub fn now_rfc7231_string() -> String {